@@ -0,0 +1,147 @@
+// Measures the throughput of the indexer on a generated collection. Criterion is not among the
+// dependencies, so runs are timed directly. Run with `cargo bench --features bench-index`.
+#![recursion_limit = "256"]
+// Only the index is exercised out of the server modules compiled into this benchmark. Cargo also
+// builds benchmarks with `cfg(test)`, which brings in unit tests whose imports then go unused.
+#![allow(dead_code, unused_imports)]
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+#[cfg(feature = "profile-index")]
+#[macro_use]
+extern crate flamer;
+
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/db/mod.rs"]
+mod db;
+#[path = "../src/ddns.rs"]
+mod ddns;
+#[path = "../src/index/fixture.rs"]
+mod fixture;
+#[path = "../src/index/mod.rs"]
+mod index;
+#[path = "../src/user.rs"]
+mod user;
+#[path = "../src/utils.rs"]
+mod utils;
+#[path = "../src/vfs.rs"]
+mod vfs;
+
+use diesel::prelude::*;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::db::{misc_settings, DB};
+use crate::fixture::{generate_collection, mount_collection};
+use crate::index::UpdateOptions;
+
+const DIRECTORY_COUNT: usize = 200;
+const SONG_COUNT: usize = 20;
+const RUNS: u32 = 5;
+
+// Generous enough for slow machines, this only catches severe regressions
+const FULL_INDEX_TIME_LIMIT: Duration = Duration::from_secs(60);
+
+// Times `operation` over several runs, each against a fresh database where the collection is
+// mounted and `prepare` has been applied
+fn measure<P, O>(name: &str, collection_path: &Path, prepare: P, operation: O) -> Duration
+where
+	P: Fn(&DB),
+	O: Fn(&DB),
+{
+	let output_dir = Path::new("test-output").join("bench-index");
+	std::fs::create_dir_all(&output_dir).unwrap();
+
+	let mut total = Duration::default();
+	let mut slowest = Duration::default();
+	for run in 0..RUNS {
+		let db_path = output_dir.join(format!("{}_{}.sqlite", name, run));
+		if db_path.exists() {
+			std::fs::remove_file(&db_path).unwrap();
+		}
+		let db = DB::new(&db_path).unwrap();
+		mount_collection(&db, collection_path);
+		prepare(&db);
+
+		let start = Instant::now();
+		operation(&db);
+		let elapsed = start.elapsed();
+		total += elapsed;
+		slowest = slowest.max(elapsed);
+	}
+
+	let songs = (DIRECTORY_COUNT * SONG_COUNT) as f64 * f64::from(RUNS);
+	println!("{}: {:.0} songs/s", name, songs / total.as_secs_f64());
+	slowest
+}
+
+// Small batches make the cost of syncing each write to disk stand out
+fn set_fast_writes(db: &DB, fast_writes: bool) {
+	let connection = db.connect().unwrap();
+	diesel::update(misc_settings::table)
+		.set((
+			misc_settings::index_insert_buffer_size.eq(10),
+			misc_settings::index_fast_writes.eq(fast_writes as i32),
+		))
+		.execute(&connection)
+		.unwrap();
+}
+
+fn main() {
+	let collection_path = generate_collection("bench-index", DIRECTORY_COUNT, SONG_COUNT);
+
+	let slowest = measure(
+		"populate",
+		&collection_path,
+		|_| {},
+		|db| {
+			index::populate(db, true, &UpdateOptions::default()).unwrap();
+		},
+	);
+	assert!(
+		slowest < FULL_INDEX_TIME_LIMIT,
+		"Indexing took {:?}, more than {:?}",
+		slowest,
+		FULL_INDEX_TIME_LIMIT
+	);
+
+	// Updates step through the collection on a single thread, apart from tag reads
+	measure(
+		"update",
+		&collection_path,
+		|_| {},
+		|db| {
+			index::update(db, &UpdateOptions::default()).unwrap();
+		},
+	);
+
+	for (name, fast_writes) in &[
+		("populate_small_batches", false),
+		("populate_fast_writes", true),
+	] {
+		measure(
+			name,
+			&collection_path,
+			|db| set_fast_writes(db, *fast_writes),
+			|db| {
+				index::populate(db, true, &UpdateOptions::default()).unwrap();
+			},
+		);
+	}
+
+	// Moving the mount point leaves all but the first album outside of the collection
+	measure(
+		"clean",
+		&collection_path,
+		|db| {
+			index::populate(db, true, &UpdateOptions::default()).unwrap();
+			mount_collection(db, &collection_path.join("Album 000"));
+		},
+		|db| {
+			index::clean(db, &UpdateOptions::default()).unwrap();
+		},
+	);
+}
@@ -0,0 +1,2 @@
+ALTER TABLE songs ADD COLUMN genre TEXT;
+ALTER TABLE directories ADD COLUMN genre TEXT;
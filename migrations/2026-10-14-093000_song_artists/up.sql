@@ -0,0 +1,7 @@
+ALTER TABLE misc_settings ADD COLUMN index_artist_separator TEXT NOT NULL DEFAULT ';';
+CREATE TABLE song_artists (
+	id INTEGER PRIMARY KEY NOT NULL,
+	song_path TEXT NOT NULL,
+	artist TEXT NOT NULL,
+	UNIQUE(song_path, artist) ON CONFLICT IGNORE
+);
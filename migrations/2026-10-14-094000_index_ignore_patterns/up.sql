@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_ignore_patterns TEXT NOT NULL DEFAULT '.*,Thumbs.db,desktop.ini';
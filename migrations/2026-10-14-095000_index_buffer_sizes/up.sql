@@ -0,0 +1,2 @@
+ALTER TABLE misc_settings ADD COLUMN index_insert_buffer_size INTEGER NOT NULL DEFAULT 1000;
+ALTER TABLE misc_settings ADD COLUMN index_clean_buffer_size INTEGER NOT NULL DEFAULT 500;
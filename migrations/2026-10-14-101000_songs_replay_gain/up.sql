@@ -0,0 +1,4 @@
+ALTER TABLE songs ADD COLUMN replay_gain_track_gain REAL;
+ALTER TABLE songs ADD COLUMN replay_gain_track_peak REAL;
+ALTER TABLE songs ADD COLUMN replay_gain_album_gain REAL;
+ALTER TABLE songs ADD COLUMN replay_gain_album_peak REAL;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_follow_symlinks INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+ALTER TABLE directories ADD COLUMN song_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE directories ADD COLUMN total_duration INTEGER NOT NULL DEFAULT 0;
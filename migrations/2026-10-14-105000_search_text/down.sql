@@ -0,0 +1,46 @@
+CREATE TEMPORARY TABLE songs_backup(id, path, parent, track_number, disc_number, title, artist, album_artist, year, album, artwork, duration, genre, file_size, mtime, replay_gain_track_gain, replay_gain_track_peak, replay_gain_album_gain, replay_gain_album_peak, track_total, disc_total);
+INSERT INTO songs_backup SELECT id, path, parent, track_number, disc_number, title, artist, album_artist, year, album, artwork, duration, genre, file_size, mtime, replay_gain_track_gain, replay_gain_track_peak, replay_gain_album_gain, replay_gain_album_peak, track_total, disc_total FROM songs;
+DROP TABLE songs;
+CREATE TABLE songs (
+	id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	parent TEXT NOT NULL,
+	track_number INTEGER,
+	disc_number INTEGER,
+	title TEXT,
+	artist TEXT,
+	album_artist TEXT,
+	year INTEGER,
+	album TEXT,
+	artwork TEXT,
+	duration INTEGER,
+	genre TEXT,
+	file_size BIGINT,
+	mtime BIGINT,
+	replay_gain_track_gain REAL,
+	replay_gain_track_peak REAL,
+	replay_gain_album_gain REAL,
+	replay_gain_album_peak REAL,
+	track_total INTEGER,
+	disc_total INTEGER,
+	UNIQUE(path) ON CONFLICT REPLACE
+);
+INSERT INTO songs SELECT * FROM songs_backup;
+DROP TABLE songs_backup;
+CREATE TEMPORARY TABLE misc_settings_backup(id, auth_secret, index_sleep_duration_seconds, index_album_art_pattern, index_thread_count, index_artist_separator, index_ignore_patterns, index_insert_buffer_size, index_clean_buffer_size, index_follow_symlinks);
+INSERT INTO misc_settings_backup SELECT id, auth_secret, index_sleep_duration_seconds, index_album_art_pattern, index_thread_count, index_artist_separator, index_ignore_patterns, index_insert_buffer_size, index_clean_buffer_size, index_follow_symlinks FROM misc_settings;
+DROP TABLE misc_settings;
+CREATE TABLE misc_settings (
+	id INTEGER PRIMARY KEY NOT NULL CHECK(id = 0),
+	auth_secret BLOB NOT NULL DEFAULT (randomblob(32)),
+	index_sleep_duration_seconds INTEGER NOT NULL,
+	index_album_art_pattern TEXT NOT NULL,
+	index_thread_count INTEGER,
+	index_artist_separator TEXT NOT NULL DEFAULT ';',
+	index_ignore_patterns TEXT NOT NULL DEFAULT '.*,Thumbs.db,desktop.ini',
+	index_insert_buffer_size INTEGER NOT NULL DEFAULT 1000,
+	index_clean_buffer_size INTEGER NOT NULL DEFAULT 500,
+	index_follow_symlinks INTEGER NOT NULL DEFAULT 1
+);
+INSERT INTO misc_settings SELECT * FROM misc_settings_backup;
+DROP TABLE misc_settings_backup;
@@ -0,0 +1,3 @@
+ALTER TABLE misc_settings ADD COLUMN index_normalize_search_text INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE songs ADD COLUMN search_title TEXT;
+ALTER TABLE songs ADD COLUMN search_artist TEXT;
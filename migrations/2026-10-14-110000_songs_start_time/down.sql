@@ -0,0 +1,31 @@
+CREATE TEMPORARY TABLE songs_backup(id, path, parent, track_number, disc_number, title, artist, album_artist, year, album, artwork, duration, genre, file_size, mtime, replay_gain_track_gain, replay_gain_track_peak, replay_gain_album_gain, replay_gain_album_peak, track_total, disc_total, search_title, search_artist);
+INSERT INTO songs_backup SELECT id, path, parent, track_number, disc_number, title, artist, album_artist, year, album, artwork, duration, genre, file_size, mtime, replay_gain_track_gain, replay_gain_track_peak, replay_gain_album_gain, replay_gain_album_peak, track_total, disc_total, search_title, search_artist FROM songs;
+DROP TABLE songs;
+CREATE TABLE songs (
+	id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	parent TEXT NOT NULL,
+	track_number INTEGER,
+	disc_number INTEGER,
+	title TEXT,
+	artist TEXT,
+	album_artist TEXT,
+	year INTEGER,
+	album TEXT,
+	artwork TEXT,
+	duration INTEGER,
+	genre TEXT,
+	file_size BIGINT,
+	mtime BIGINT,
+	replay_gain_track_gain REAL,
+	replay_gain_track_peak REAL,
+	replay_gain_album_gain REAL,
+	replay_gain_album_peak REAL,
+	track_total INTEGER,
+	disc_total INTEGER,
+	search_title TEXT,
+	search_artist TEXT,
+	UNIQUE(path) ON CONFLICT REPLACE
+);
+INSERT INTO songs SELECT * FROM songs_backup;
+DROP TABLE songs_backup;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_fast_writes INTEGER NOT NULL DEFAULT 0;
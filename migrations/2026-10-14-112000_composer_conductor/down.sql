@@ -0,0 +1,52 @@
+CREATE TEMPORARY TABLE directories_backup(id, path, parent, artist, year, album, artwork, date_added, last_indexed, genre, song_count, total_duration);
+INSERT INTO directories_backup SELECT id, path, parent, artist, year, album, artwork, date_added, last_indexed, genre, song_count, total_duration FROM directories;
+DROP TABLE directories;
+CREATE TABLE directories (
+	id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	parent TEXT,
+	artist TEXT,
+	year INTEGER,
+	album TEXT,
+	artwork TEXT,
+	date_added INTEGER DEFAULT 0 NOT NULL,
+	last_indexed INTEGER,
+	genre TEXT,
+	song_count INTEGER NOT NULL DEFAULT 0,
+	total_duration INTEGER NOT NULL DEFAULT 0,
+	UNIQUE(path) ON CONFLICT REPLACE
+);
+INSERT INTO directories SELECT * FROM directories_backup;
+DROP TABLE directories_backup;
+CREATE TEMPORARY TABLE songs_backup(id, path, parent, track_number, disc_number, title, artist, album_artist, year, album, artwork, duration, genre, file_size, mtime, replay_gain_track_gain, replay_gain_track_peak, replay_gain_album_gain, replay_gain_album_peak, track_total, disc_total, search_title, search_artist, start_time);
+INSERT INTO songs_backup SELECT id, path, parent, track_number, disc_number, title, artist, album_artist, year, album, artwork, duration, genre, file_size, mtime, replay_gain_track_gain, replay_gain_track_peak, replay_gain_album_gain, replay_gain_album_peak, track_total, disc_total, search_title, search_artist, start_time FROM songs;
+DROP TABLE songs;
+CREATE TABLE songs (
+	id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	parent TEXT NOT NULL,
+	track_number INTEGER,
+	disc_number INTEGER,
+	title TEXT,
+	artist TEXT,
+	album_artist TEXT,
+	year INTEGER,
+	album TEXT,
+	artwork TEXT,
+	duration INTEGER,
+	genre TEXT,
+	file_size BIGINT,
+	mtime BIGINT,
+	replay_gain_track_gain REAL,
+	replay_gain_track_peak REAL,
+	replay_gain_album_gain REAL,
+	replay_gain_album_peak REAL,
+	track_total INTEGER,
+	disc_total INTEGER,
+	search_title TEXT,
+	search_artist TEXT,
+	start_time INTEGER,
+	UNIQUE(path, start_time) ON CONFLICT REPLACE
+);
+INSERT INTO songs SELECT * FROM songs_backup;
+DROP TABLE songs_backup;
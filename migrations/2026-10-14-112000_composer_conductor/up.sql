@@ -0,0 +1,3 @@
+ALTER TABLE songs ADD COLUMN composer TEXT;
+ALTER TABLE songs ADD COLUMN conductor TEXT;
+ALTER TABLE directories ADD COLUMN composer TEXT;
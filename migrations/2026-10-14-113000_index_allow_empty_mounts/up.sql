@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_allow_empty_mounts INTEGER NOT NULL DEFAULT 0;
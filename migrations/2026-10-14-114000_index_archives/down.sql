@@ -0,0 +1,20 @@
+CREATE TEMPORARY TABLE misc_settings_backup(id, auth_secret, index_sleep_duration_seconds, index_album_art_pattern, index_thread_count, index_artist_separator, index_ignore_patterns, index_insert_buffer_size, index_clean_buffer_size, index_follow_symlinks, index_normalize_search_text, index_fast_writes, index_allow_empty_mounts);
+INSERT INTO misc_settings_backup SELECT id, auth_secret, index_sleep_duration_seconds, index_album_art_pattern, index_thread_count, index_artist_separator, index_ignore_patterns, index_insert_buffer_size, index_clean_buffer_size, index_follow_symlinks, index_normalize_search_text, index_fast_writes, index_allow_empty_mounts FROM misc_settings;
+DROP TABLE misc_settings;
+CREATE TABLE misc_settings (
+	id INTEGER PRIMARY KEY NOT NULL CHECK(id = 0),
+	auth_secret BLOB NOT NULL DEFAULT (randomblob(32)),
+	index_sleep_duration_seconds INTEGER NOT NULL,
+	index_album_art_pattern TEXT NOT NULL,
+	index_thread_count INTEGER,
+	index_artist_separator TEXT NOT NULL DEFAULT ';',
+	index_ignore_patterns TEXT NOT NULL DEFAULT '.*,Thumbs.db,desktop.ini',
+	index_insert_buffer_size INTEGER NOT NULL DEFAULT 1000,
+	index_clean_buffer_size INTEGER NOT NULL DEFAULT 500,
+	index_follow_symlinks INTEGER NOT NULL DEFAULT 1,
+	index_normalize_search_text INTEGER NOT NULL DEFAULT 0,
+	index_fast_writes INTEGER NOT NULL DEFAULT 0,
+	index_allow_empty_mounts INTEGER NOT NULL DEFAULT 0
+);
+INSERT INTO misc_settings SELECT * FROM misc_settings_backup;
+DROP TABLE misc_settings_backup;
@@ -0,0 +1,2 @@
+ALTER TABLE songs ADD COLUMN content_hash TEXT;
+ALTER TABLE misc_settings ADD COLUMN index_compute_audio_hash INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE mount_points ADD COLUMN album_art_pattern TEXT;
@@ -0,0 +1,6 @@
+ALTER TABLE songs ADD COLUMN sort_artist TEXT;
+ALTER TABLE songs ADD COLUMN sort_album TEXT;
+ALTER TABLE songs ADD COLUMN sort_title TEXT;
+ALTER TABLE directories ADD COLUMN sort_artist TEXT;
+ALTER TABLE directories ADD COLUMN sort_album TEXT;
+ALTER TABLE misc_settings ADD COLUMN index_sort_articles TEXT NOT NULL DEFAULT 'The,A,An';
@@ -0,0 +1,7 @@
+CREATE TABLE song_lyrics (
+	id INTEGER PRIMARY KEY NOT NULL,
+	song_path TEXT NOT NULL,
+	lyrics TEXT NOT NULL,
+	has_synced_lyrics INTEGER NOT NULL,
+	UNIQUE(song_path) ON CONFLICT REPLACE
+);
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_various_artists TEXT NOT NULL DEFAULT 'Various Artists';
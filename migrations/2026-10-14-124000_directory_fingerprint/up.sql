@@ -0,0 +1,2 @@
+ALTER TABLE directories ADD COLUMN dir_fingerprint TEXT;
+ALTER TABLE misc_settings ADD COLUMN index_use_directory_fingerprint INTEGER NOT NULL DEFAULT 0;
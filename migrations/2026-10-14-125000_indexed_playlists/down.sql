@@ -0,0 +1,2 @@
+DROP TABLE indexed_playlist_songs;
+DROP TABLE indexed_playlists;
@@ -0,0 +1,16 @@
+CREATE TABLE indexed_playlists (
+	id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	parent TEXT NOT NULL,
+	name TEXT NOT NULL,
+	UNIQUE(path) ON CONFLICT REPLACE
+);
+
+CREATE TABLE indexed_playlist_songs (
+	id INTEGER PRIMARY KEY NOT NULL,
+	playlist_path TEXT NOT NULL,
+	ordering INTEGER NOT NULL,
+	path TEXT NOT NULL,
+	missing INTEGER NOT NULL DEFAULT 0,
+	UNIQUE(playlist_path, ordering) ON CONFLICT REPLACE
+);
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_store_relative_paths INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+ALTER TABLE songs ADD COLUMN format TEXT;
+ALTER TABLE songs ADD COLUMN bitrate INTEGER;
+ALTER TABLE songs ADD COLUMN sample_rate INTEGER;
+ALTER TABLE songs ADD COLUMN bits_per_sample INTEGER;
+ALTER TABLE songs ADD COLUMN channels INTEGER;
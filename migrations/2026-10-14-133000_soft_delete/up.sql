@@ -0,0 +1,2 @@
+ALTER TABLE misc_settings ADD COLUMN index_soft_delete INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE songs ADD COLUMN deleted_at INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_ignore_marker TEXT NOT NULL DEFAULT '.nomedia';
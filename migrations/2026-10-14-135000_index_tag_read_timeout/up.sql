@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_tag_read_timeout_seconds INTEGER NOT NULL DEFAULT 30;
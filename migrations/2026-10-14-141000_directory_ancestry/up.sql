@@ -0,0 +1,2 @@
+ALTER TABLE directories ADD COLUMN depth INTEGER;
+ALTER TABLE directories ADD COLUMN ancestors TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE songs ADD COLUMN mount_name TEXT;
+ALTER TABLE directories ADD COLUMN mount_name TEXT;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_write_retries INTEGER NOT NULL DEFAULT 5;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_disc_folder_pattern TEXT NOT NULL DEFAULT '(?i)^(cd|disc|disk)\s*\d+\b';
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_low_memory INTEGER NOT NULL DEFAULT 0;
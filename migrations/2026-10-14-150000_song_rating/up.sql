@@ -0,0 +1,2 @@
+ALTER TABLE songs ADD COLUMN rating INTEGER;
+ALTER TABLE songs ADD COLUMN tagged_play_count INTEGER;
@@ -0,0 +1,6 @@
+CREATE TABLE index_checkpoint (
+	id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	kind TEXT NOT NULL,
+	UNIQUE(path, kind)
+);
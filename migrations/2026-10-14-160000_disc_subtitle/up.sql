@@ -0,0 +1 @@
+ALTER TABLE songs ADD COLUMN disc_subtitle TEXT;
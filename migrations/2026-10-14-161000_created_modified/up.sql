@@ -0,0 +1,3 @@
+ALTER TABLE directories ADD COLUMN created INTEGER;
+ALTER TABLE directories ADD COLUMN modified INTEGER;
+ALTER TABLE songs ADD COLUMN created BIGINT;
@@ -0,0 +1,12 @@
+CREATE TEMPORARY TABLE mount_points_backup(id, source, name, album_art_pattern);
+INSERT INTO mount_points_backup SELECT id, source, name, album_art_pattern FROM mount_points;
+DROP TABLE mount_points;
+CREATE TABLE mount_points (
+	id INTEGER PRIMARY KEY NOT NULL,
+	source TEXT NOT NULL,
+	name TEXT NOT NULL,
+	album_art_pattern TEXT,
+	UNIQUE(name)
+);
+INSERT INTO mount_points SELECT * FROM mount_points_backup;
+DROP TABLE mount_points_backup;
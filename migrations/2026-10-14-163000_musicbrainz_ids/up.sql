@@ -0,0 +1,6 @@
+ALTER TABLE songs ADD COLUMN isrc TEXT;
+ALTER TABLE songs ADD COLUMN musicbrainz_track_id TEXT;
+ALTER TABLE songs ADD COLUMN musicbrainz_album_id TEXT;
+ALTER TABLE songs ADD COLUMN musicbrainz_artist_id TEXT;
+ALTER TABLE directories ADD COLUMN musicbrainz_album_id TEXT;
+ALTER TABLE directories ADD COLUMN musicbrainz_artist_id TEXT;
@@ -0,0 +1,10 @@
+ALTER TABLE misc_settings ADD COLUMN index_detect_renames INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE index_renames (
+	song_id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	start_time INTEGER,
+	content_hash TEXT,
+	file_size BIGINT,
+	duration INTEGER,
+	title TEXT
+);
@@ -0,0 +1,3 @@
+ALTER TABLE misc_settings ADD COLUMN index_artwork_cache_dir TEXT NOT NULL DEFAULT '';
+ALTER TABLE misc_settings ADD COLUMN index_artwork_max_dimension INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE misc_settings ADD COLUMN index_artwork_format TEXT NOT NULL DEFAULT '';
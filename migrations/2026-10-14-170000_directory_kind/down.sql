@@ -0,0 +1,31 @@
+CREATE TEMPORARY TABLE directories_backup(id, path, parent, artist, year, album, artwork, date_added, last_indexed, genre, song_count, total_duration, composer, sort_artist, sort_album, dir_fingerprint, depth, ancestors, mount_name, created, modified, musicbrainz_album_id, musicbrainz_artist_id);
+INSERT INTO directories_backup SELECT id, path, parent, artist, year, album, artwork, date_added, last_indexed, genre, song_count, total_duration, composer, sort_artist, sort_album, dir_fingerprint, depth, ancestors, mount_name, created, modified, musicbrainz_album_id, musicbrainz_artist_id FROM directories;
+DROP TABLE directories;
+CREATE TABLE directories (
+	id INTEGER PRIMARY KEY NOT NULL,
+	path TEXT NOT NULL,
+	parent TEXT,
+	artist TEXT,
+	year INTEGER,
+	album TEXT,
+	artwork TEXT,
+	date_added INTEGER DEFAULT 0 NOT NULL,
+	last_indexed INTEGER,
+	genre TEXT,
+	song_count INTEGER NOT NULL DEFAULT 0,
+	total_duration INTEGER NOT NULL DEFAULT 0,
+	composer TEXT,
+	sort_artist TEXT,
+	sort_album TEXT,
+	dir_fingerprint TEXT,
+	depth INTEGER,
+	ancestors TEXT,
+	mount_name TEXT,
+	created INTEGER,
+	modified INTEGER,
+	musicbrainz_album_id TEXT,
+	musicbrainz_artist_id TEXT,
+	UNIQUE(path) ON CONFLICT REPLACE
+);
+INSERT INTO directories SELECT * FROM directories_backup;
+DROP TABLE directories_backup;
@@ -0,0 +1,2 @@
+ALTER TABLE songs ADD COLUMN encoder_delay INTEGER;
+ALTER TABLE songs ADD COLUMN encoder_padding INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE songs ADD COLUMN original_date TEXT;
+ALTER TABLE directories ADD COLUMN original_date TEXT;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_min_file_size INTEGER NOT NULL DEFAULT 0;
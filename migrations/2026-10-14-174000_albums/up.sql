@@ -0,0 +1,12 @@
+ALTER TABLE misc_settings ADD COLUMN index_group_albums INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE albums (
+	id INTEGER PRIMARY KEY NOT NULL,
+	musicbrainz_album_id TEXT,
+	directory TEXT,
+	name TEXT,
+	artist TEXT,
+	year INTEGER,
+	artwork TEXT,
+	song_count INTEGER NOT NULL DEFAULT 0,
+	total_duration INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1 @@
+UPDATE misc_settings SET index_extensions = 'aac,aiff,ape,flac,m4a,mp3,mpc,ogg,opus,wav' WHERE index_extensions = 'aac,aiff,ape,flac,m4a,mp3,mpc,ogg,opus,wav,wv';
@@ -0,0 +1,2 @@
+ALTER TABLE misc_settings ADD COLUMN index_resolve_file_symlinks INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE songs ADD COLUMN link_target TEXT;
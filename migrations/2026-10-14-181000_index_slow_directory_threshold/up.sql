@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_slow_directory_threshold_ms INTEGER NOT NULL DEFAULT 0;
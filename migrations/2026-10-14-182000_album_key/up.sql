@@ -0,0 +1,4 @@
+ALTER TABLE songs ADD COLUMN album_key TEXT;
+ALTER TABLE directories ADD COLUMN album_key TEXT;
+CREATE INDEX songs_album_key ON songs(album_key);
+CREATE INDEX directories_album_key ON directories(album_key);
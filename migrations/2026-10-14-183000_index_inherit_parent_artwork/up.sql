@@ -0,0 +1,2 @@
+ALTER TABLE misc_settings ADD COLUMN index_inherit_parent_artwork INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE misc_settings ADD COLUMN index_inherit_artwork_levels INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+ALTER TABLE songs ADD COLUMN lossless BOOLEAN;
+ALTER TABLE songs ADD COLUMN bitrate_mode TEXT;
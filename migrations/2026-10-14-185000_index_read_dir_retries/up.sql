@@ -0,0 +1,2 @@
+ALTER TABLE misc_settings ADD COLUMN index_read_dir_retries INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE misc_settings ADD COLUMN index_read_dir_retry_delay_ms INTEGER NOT NULL DEFAULT 500;
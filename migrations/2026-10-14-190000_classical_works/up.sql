@@ -0,0 +1,3 @@
+ALTER TABLE songs ADD COLUMN work TEXT;
+ALTER TABLE songs ADD COLUMN movement_name TEXT;
+ALTER TABLE songs ADD COLUMN movement_number INTEGER;
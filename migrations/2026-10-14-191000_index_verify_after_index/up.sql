@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_verify_after_index INTEGER NOT NULL DEFAULT 0;
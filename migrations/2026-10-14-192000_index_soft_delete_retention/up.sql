@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_soft_delete_retention_days INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_merge_updates INTEGER NOT NULL DEFAULT 0;
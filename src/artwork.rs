@@ -14,6 +14,7 @@ pub fn read(image_path: &Path) -> Result<DynamicImage> {
 		Some(AudioFormat::MPC) => read_ape(image_path),
 		Some(AudioFormat::OGG) => read_vorbis(image_path),
 		Some(AudioFormat::OPUS) => read_opus(image_path),
+		Some(AudioFormat::WAVPACK) => read_ape(image_path),
 		None => Ok(image::open(image_path)?),
 	}
 }
@@ -12,6 +12,7 @@ use toml;
 
 use crate::db::{ddns_config, misc_settings, mount_points, users, DB};
 use crate::ddns::DDNSConfig;
+use crate::index;
 use crate::user::*;
 use crate::vfs::MountPoint;
 
@@ -21,6 +22,47 @@ pub struct MiscSettings {
 	pub auth_secret: Vec<u8>,
 	pub index_sleep_duration_seconds: i32,
 	pub index_album_art_pattern: String,
+	pub index_thread_count: Option<i32>,
+	pub index_artist_separator: String,
+	pub index_ignore_patterns: String,
+	pub index_insert_buffer_size: i32,
+	pub index_clean_buffer_size: i32,
+	pub index_follow_symlinks: i32,
+	pub index_normalize_search_text: i32,
+	pub index_fast_writes: i32,
+	pub index_allow_empty_mounts: i32,
+	pub index_archives: i32,
+	pub index_compute_audio_hash: i32,
+	pub index_sort_articles: String,
+	pub index_various_artists: String,
+	pub index_use_directory_fingerprint: i32,
+	pub index_store_relative_paths: i32,
+	pub index_tag_read_threads: i32,
+	pub index_soft_delete: i32,
+	pub index_ignore_marker: String,
+	pub index_tag_read_timeout_seconds: i32,
+	pub index_write_retries: i32,
+	pub index_disc_folder_pattern: String,
+	pub index_low_memory: i32,
+	pub index_extensions: String,
+	pub index_atomic: i32,
+	pub index_tag_charset_detection: i32,
+	pub index_max_depth: i32,
+	pub index_detect_renames: i32,
+	pub index_artwork_cache_dir: String,
+	pub index_artwork_max_dimension: i32,
+	pub index_artwork_format: String,
+	pub index_min_file_size: i32,
+	pub index_group_albums: i32,
+	pub index_resolve_file_symlinks: i32,
+	pub index_slow_directory_threshold_ms: i32,
+	pub index_inherit_parent_artwork: i32,
+	pub index_inherit_artwork_levels: i32,
+	pub index_read_dir_retries: i32,
+	pub index_read_dir_retry_delay_ms: i32,
+	pub index_verify_after_index: i32,
+	pub index_soft_delete_retention_days: i32,
+	pub index_merge_updates: i32,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -95,7 +137,7 @@ pub fn read(db: &DB) -> Result<Config> {
 	{
 		use self::mount_points::dsl::*;
 		mount_dirs = mount_points
-			.select((source, name))
+			.select((source, name, album_art_pattern, include_patterns))
 			.get_results(&connection)?;
 		config.mount_dirs = Some(mount_dirs);
 	}
@@ -140,10 +182,22 @@ pub fn amend(db: &DB, new_config: &Config) -> Result<()> {
 	let connection = db.connect()?;
 
 	if let Some(ref mount_dirs) = new_config.mount_dirs {
+		// Entries indexed from mount points which are no longer configured are removed right away,
+		// while the mount points can still tell which entries are theirs
+		let old_mount_names: Vec<String> = mount_points::table
+			.select(mount_points::name)
+			.load(&connection)?;
+		for name in old_mount_names
+			.iter()
+			.filter(|name| !mount_dirs.iter().any(|m| &m.name == *name))
+		{
+			index::forget_mount(db, name)?;
+		}
 		diesel::delete(mount_points::table).execute(&connection)?;
 		diesel::insert_into(mount_points::table)
 			.values(mount_dirs)
 			.execute(&*connection)?; // TODO https://github.com/diesel-rs/diesel/issues/1822
+		index::clear_album_art_pattern_cache();
 	}
 
 	if let Some(ref config_users) = new_config.users {
@@ -205,6 +259,7 @@ pub fn amend(db: &DB, new_config: &Config) -> Result<()> {
 		diesel::update(misc_settings::table)
 			.set(misc_settings::index_album_art_pattern.eq(album_art_pattern))
 			.execute(&connection)?;
+		index::clear_album_art_pattern_cache();
 	}
 
 	if let Some(ref ydns) = new_config.ydns {
@@ -291,6 +346,8 @@ fn test_amend() {
 		mount_dirs: Some(vec![MountPoint {
 			source: "C:\\Music".into(),
 			name: "root".into(),
+			album_art_pattern: None,
+			include_patterns: None,
 		}]),
 		users: Some(vec![ConfigUser {
 			name: "Teddy🐻".into(),
@@ -306,6 +363,8 @@ fn test_amend() {
 		mount_dirs: Some(vec![MountPoint {
 			source: "/home/music".into(),
 			name: "🎵📁".into(),
+			album_art_pattern: None,
+			include_patterns: None,
 		}]),
 		users: Some(vec![ConfigUser {
 			name: "Kermit🐸".into(),
@@ -1,9 +1,16 @@
 use anyhow::*;
+use diesel::connection::SimpleConnection;
 use diesel::r2d2::{self, ConnectionManager, PooledConnection};
 use diesel::sqlite::SqliteConnection;
+use diesel::Connection;
 use diesel::RunQueryDsl;
 use diesel_migrations;
-use std::path::Path;
+use log::warn;
+use std::path::{Path, PathBuf};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 mod schema;
 
@@ -16,8 +23,15 @@ embed_migrations!("migrations");
 #[derive(Clone)]
 pub struct DB {
 	pool: r2d2::Pool<ConnectionManager<SqliteConnection>>,
+	path: PathBuf,
+	// An in-memory database only lives as long as one of its connections is open
+	memory_connection: Option<Arc<Mutex<SqliteConnection>>>,
 }
 
+// Each in-memory database is given a distinct name, which its connections open as a shared cache
+#[cfg(test)]
+static MEMORY_DB_COUNT: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Debug)]
 struct ConnectionCustomizer {}
 impl diesel::r2d2::CustomizeConnection<SqliteConnection, diesel::r2d2::Error>
@@ -35,17 +49,101 @@ impl diesel::r2d2::CustomizeConnection<SqliteConnection, diesel::r2d2::Error>
 		query
 			.execute(connection)
 			.map_err(|e| diesel::r2d2::Error::QueryError(e))?;
+		set_read_uncommitted(connection).map_err(diesel::r2d2::Error::QueryError)?;
 		Ok(())
 	}
 }
 
+// Connections to a shared cache lock the tables they read, which would fail writes from other
+// connections rather than wait for them. Reading uncommitted data avoids these locks and has no
+// effect on databases stored in a file.
+fn set_read_uncommitted(connection: &SqliteConnection) -> diesel::QueryResult<()> {
+	connection.batch_execute("PRAGMA read_uncommitted = true;")
+}
+
+// The journal mode persists in the database file, so it must be explicitly restored
+pub fn set_wal_journal(connection: &SqliteConnection, enabled: bool) -> Result<()> {
+	let journal_mode = if enabled { "WAL" } else { "DELETE" };
+	connection.batch_execute(&format!("PRAGMA journal_mode = {};", journal_mode))?;
+	Ok(())
+}
+
+// These pragmas only apply to the connection they are executed on
+pub fn set_fast_writes(connection: &SqliteConnection, enabled: bool) -> Result<()> {
+	let pragmas = if enabled {
+		"PRAGMA synchronous = NORMAL; PRAGMA temp_store = MEMORY;"
+	} else {
+		"PRAGMA synchronous = FULL; PRAGMA temp_store = DEFAULT;"
+	};
+	connection.batch_execute(pragmas)?;
+	Ok(())
+}
+
+const RETRY_INITIAL_DELAY: Duration = Duration::from_millis(50);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+// Writes which fail because another connection holds a lock are attempted again, waiting twice as
+// long before each new attempt
+pub fn retry_when_busy<T, F>(retries: u32, mut write: F) -> diesel::QueryResult<T>
+where
+	F: FnMut() -> diesel::QueryResult<T>,
+{
+	let mut delay = RETRY_INITIAL_DELAY;
+	let mut attempt = 0;
+	loop {
+		match write() {
+			Err(e) if attempt < retries && is_busy(&e) => {
+				warn!("Database is locked, retrying write in {:?}", delay);
+				std::thread::sleep(delay);
+				delay = (delay * 2).min(RETRY_MAX_DELAY);
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+// Diesel does not tell SQLITE_BUSY and SQLITE_LOCKED apart from other errors, except by message
+fn is_busy(error: &diesel::result::Error) -> bool {
+	match error {
+		diesel::result::Error::DatabaseError(_, info) => {
+			let message = info.message();
+			message.contains("database is locked") || message.contains("database table is locked")
+		}
+		_ => false,
+	}
+}
+
 impl DB {
 	pub fn new(path: &Path) -> Result<DB> {
 		let manager = ConnectionManager::<SqliteConnection>::new(path.to_string_lossy());
 		let pool = diesel::r2d2::Pool::builder()
 			.connection_customizer(Box::new(ConnectionCustomizer {}))
 			.build(manager)?;
-		let db = DB { pool: pool };
+		let db = DB {
+			pool: pool,
+			path: path.to_owned(),
+			memory_connection: None,
+		};
+		db.migrate_up()?;
+		Ok(db)
+	}
+
+	// Nothing is written to disk, and the database is dropped along with the last clone of `DB`
+	#[cfg(test)]
+	pub fn new_in_memory() -> Result<DB> {
+		let number = MEMORY_DB_COUNT.fetch_add(1, Ordering::Relaxed);
+		let uri = format!("file:polaris-memory-{}?mode=memory&cache=shared", number);
+		let memory_connection = SqliteConnection::establish(&uri)?;
+		let manager = ConnectionManager::<SqliteConnection>::new(uri.as_str());
+		let pool = diesel::r2d2::Pool::builder()
+			.connection_customizer(Box::new(ConnectionCustomizer {}))
+			.build(manager)?;
+		let db = DB {
+			pool,
+			path: PathBuf::from(uri),
+			memory_connection: Some(Arc::new(Mutex::new(memory_connection))),
+		};
 		db.migrate_up()?;
 		Ok(db)
 	}
@@ -54,6 +152,14 @@ impl DB {
 		self.pool.get().map_err(Error::new)
 	}
 
+	// Connections outside of the pool are closed when dropped, which leaving WAL mode depends on
+	pub fn connect_unpooled(&self) -> Result<SqliteConnection> {
+		let connection = SqliteConnection::establish(&self.path.to_string_lossy())?;
+		connection.batch_execute("PRAGMA busy_timeout = 60000;")?;
+		set_read_uncommitted(&connection)?;
+		Ok(connection)
+	}
+
 	#[allow(dead_code)]
 	fn migrate_down(&self) -> Result<()> {
 		let connection = self.connect().unwrap();
@@ -112,3 +218,34 @@ fn test_migrations_down() {
 	db.migrate_down().unwrap();
 	db.migrate_up().unwrap();
 }
+
+#[test]
+fn test_retry_when_busy() {
+	use diesel::ExpressionMethods;
+
+	let db = get_test_db("retry_when_busy.sqlite");
+	let locking_connection = db.connect_unpooled().unwrap();
+	locking_connection
+		.batch_execute("BEGIN EXCLUSIVE;")
+		.unwrap();
+	let connection = db.connect_unpooled().unwrap();
+	connection
+		.batch_execute("PRAGMA busy_timeout = 0;")
+		.unwrap();
+	let write = || {
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_sleep_duration_seconds.eq(10))
+			.execute(&connection)
+	};
+
+	// Without retries, the write fails as soon as the database is locked
+	let error = retry_when_busy(0, write).unwrap_err();
+	assert!(is_busy(&error));
+
+	let unlock = std::thread::spawn(move || {
+		std::thread::sleep(Duration::from_millis(100));
+		locking_connection.batch_execute("COMMIT;").unwrap();
+	});
+	assert_eq!(retry_when_busy(5, write).unwrap(), 1);
+	unlock.join().unwrap();
+}
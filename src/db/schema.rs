@@ -1,3 +1,17 @@
+table! {
+	albums (id) {
+		id -> Integer,
+		musicbrainz_album_id -> Nullable<Text>,
+		directory -> Nullable<Text>,
+		name -> Nullable<Text>,
+		artist -> Nullable<Text>,
+		year -> Nullable<Integer>,
+		artwork -> Nullable<Text>,
+		song_count -> Integer,
+		total_duration -> Integer,
+	}
+}
+
 table! {
 	ddns_config (id) {
 		id -> Integer,
@@ -17,6 +31,63 @@ table! {
 		album -> Nullable<Text>,
 		artwork -> Nullable<Text>,
 		date_added -> Integer,
+		last_indexed -> Nullable<Integer>,
+		genre -> Nullable<Text>,
+		song_count -> Integer,
+		total_duration -> Integer,
+		composer -> Nullable<Text>,
+		sort_artist -> Nullable<Text>,
+		sort_album -> Nullable<Text>,
+		dir_fingerprint -> Nullable<Text>,
+		depth -> Nullable<Integer>,
+		ancestors -> Nullable<Text>,
+		mount_name -> Nullable<Text>,
+		created -> Nullable<Integer>,
+		modified -> Nullable<Integer>,
+		musicbrainz_album_id -> Nullable<Text>,
+		musicbrainz_artist_id -> Nullable<Text>,
+		kind -> Nullable<Text>,
+		original_date -> Nullable<Text>,
+		album_key -> Nullable<Text>,
+	}
+}
+
+table! {
+	index_checkpoint (id) {
+		id -> Integer,
+		path -> Text,
+		kind -> Text,
+	}
+}
+
+table! {
+	index_renames (song_id) {
+		song_id -> Integer,
+		path -> Text,
+		start_time -> Nullable<Integer>,
+		content_hash -> Nullable<Text>,
+		file_size -> Nullable<BigInt>,
+		duration -> Nullable<Integer>,
+		title -> Nullable<Text>,
+	}
+}
+
+table! {
+	indexed_playlist_songs (id) {
+		id -> Integer,
+		playlist_path -> Text,
+		ordering -> Integer,
+		path -> Text,
+		missing -> Integer,
+	}
+}
+
+table! {
+	indexed_playlists (id) {
+		id -> Integer,
+		path -> Text,
+		parent -> Text,
+		name -> Text,
 	}
 }
 
@@ -26,6 +97,47 @@ table! {
 		auth_secret -> Binary,
 		index_sleep_duration_seconds -> Integer,
 		index_album_art_pattern -> Text,
+		index_thread_count -> Nullable<Integer>,
+		index_artist_separator -> Text,
+		index_ignore_patterns -> Text,
+		index_insert_buffer_size -> Integer,
+		index_clean_buffer_size -> Integer,
+		index_follow_symlinks -> Integer,
+		index_normalize_search_text -> Integer,
+		index_fast_writes -> Integer,
+		index_allow_empty_mounts -> Integer,
+		index_archives -> Integer,
+		index_compute_audio_hash -> Integer,
+		index_sort_articles -> Text,
+		index_various_artists -> Text,
+		index_use_directory_fingerprint -> Integer,
+		index_store_relative_paths -> Integer,
+		index_tag_read_threads -> Integer,
+		index_soft_delete -> Integer,
+		index_ignore_marker -> Text,
+		index_tag_read_timeout_seconds -> Integer,
+		index_write_retries -> Integer,
+		index_disc_folder_pattern -> Text,
+		index_low_memory -> Integer,
+		index_extensions -> Text,
+		index_atomic -> Integer,
+		index_tag_charset_detection -> Integer,
+		index_max_depth -> Integer,
+		index_detect_renames -> Integer,
+		index_artwork_cache_dir -> Text,
+		index_artwork_max_dimension -> Integer,
+		index_artwork_format -> Text,
+		index_min_file_size -> Integer,
+		index_group_albums -> Integer,
+		index_resolve_file_symlinks -> Integer,
+		index_slow_directory_threshold_ms -> Integer,
+		index_inherit_parent_artwork -> Integer,
+		index_inherit_artwork_levels -> Integer,
+		index_read_dir_retries -> Integer,
+		index_read_dir_retry_delay_ms -> Integer,
+		index_verify_after_index -> Integer,
+		index_soft_delete_retention_days -> Integer,
+		index_merge_updates -> Integer,
 	}
 }
 
@@ -34,6 +146,8 @@ table! {
 		id -> Integer,
 		source -> Text,
 		name -> Text,
+		album_art_pattern -> Nullable<Text>,
+		include_patterns -> Nullable<Text>,
 	}
 }
 
@@ -54,6 +168,23 @@ table! {
 	}
 }
 
+table! {
+	song_artists (id) {
+		id -> Integer,
+		song_path -> Text,
+		artist -> Text,
+	}
+}
+
+table! {
+	song_lyrics (id) {
+		id -> Integer,
+		song_path -> Text,
+		lyrics -> Text,
+		has_synced_lyrics -> Integer,
+	}
+}
+
 table! {
 	songs (id) {
 		id -> Integer,
@@ -68,6 +199,51 @@ table! {
 		album -> Nullable<Text>,
 		artwork -> Nullable<Text>,
 		duration -> Nullable<Integer>,
+		genre -> Nullable<Text>,
+		file_size -> Nullable<BigInt>,
+		mtime -> Nullable<BigInt>,
+		replay_gain_track_gain -> Nullable<Float>,
+		replay_gain_track_peak -> Nullable<Float>,
+		replay_gain_album_gain -> Nullable<Float>,
+		replay_gain_album_peak -> Nullable<Float>,
+		track_total -> Nullable<Integer>,
+		disc_total -> Nullable<Integer>,
+		search_title -> Nullable<Text>,
+		search_artist -> Nullable<Text>,
+		start_time -> Nullable<Integer>,
+		composer -> Nullable<Text>,
+		conductor -> Nullable<Text>,
+		content_hash -> Nullable<Text>,
+		sort_artist -> Nullable<Text>,
+		sort_album -> Nullable<Text>,
+		sort_title -> Nullable<Text>,
+		format -> Nullable<Text>,
+		bitrate -> Nullable<Integer>,
+		sample_rate -> Nullable<Integer>,
+		bits_per_sample -> Nullable<Integer>,
+		channels -> Nullable<Integer>,
+		deleted_at -> Nullable<Integer>,
+		bpm -> Nullable<Integer>,
+		musical_key -> Nullable<Text>,
+		mount_name -> Nullable<Text>,
+		rating -> Nullable<Integer>,
+		tagged_play_count -> Nullable<Integer>,
+		disc_subtitle -> Nullable<Text>,
+		created -> Nullable<BigInt>,
+		isrc -> Nullable<Text>,
+		musicbrainz_track_id -> Nullable<Text>,
+		musicbrainz_album_id -> Nullable<Text>,
+		musicbrainz_artist_id -> Nullable<Text>,
+		encoder_delay -> Nullable<Integer>,
+		encoder_padding -> Nullable<Integer>,
+		original_date -> Nullable<Text>,
+		link_target -> Nullable<Text>,
+		album_key -> Nullable<Text>,
+		lossless -> Nullable<Bool>,
+		bitrate_mode -> Nullable<Text>,
+		work -> Nullable<Text>,
+		movement_name -> Nullable<Text>,
+		movement_number -> Nullable<Integer>,
 	}
 }
 
@@ -88,12 +264,19 @@ joinable!(playlist_songs -> playlists (playlist));
 joinable!(playlists -> users (owner));
 
 allow_tables_to_appear_in_same_query!(
+	albums,
 	ddns_config,
 	directories,
+	index_checkpoint,
+	index_renames,
+	indexed_playlist_songs,
+	indexed_playlists,
 	misc_settings,
 	mount_points,
 	playlist_songs,
 	playlists,
+	song_artists,
+	song_lyrics,
 	songs,
 	users,
 );
@@ -0,0 +1,190 @@
+use anyhow::*;
+use flate2::read::DeflateDecoder;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::utils;
+
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+const CENTRAL_DIRECTORY_HEADER_SIZE: usize = 46;
+const LOCAL_HEADER_SIZE: usize = 30;
+const MAX_COMMENT_SIZE: usize = 65535;
+
+const COMPRESSION_STORED: u16 = 0;
+const COMPRESSION_DEFLATE: u16 = 8;
+
+const ENTRY_SEPARATOR: &str = "#";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveEntry {
+	pub name: String,
+	pub size: u64,
+	compressed_size: u64,
+	compression_method: u16,
+	header_offset: u64,
+}
+
+pub fn is_archive(path: &Path) -> bool {
+	path.extension()
+		.and_then(|e| e.to_str())
+		.map_or(false, |e| e.eq_ignore_ascii_case("zip"))
+}
+
+// Entries are indexed under the path of their archive, as in `album.zip#01.flac`
+pub fn entry_path(archive_path: &str, entry_name: &str) -> String {
+	format!("{}{}{}", archive_path, ENTRY_SEPARATOR, entry_name)
+}
+
+pub fn split_entry_path(path: &str) -> Option<(&str, &str)> {
+	let marker = format!(".zip{}", ENTRY_SEPARATOR);
+	let split = path.to_ascii_lowercase().find(&marker)? + ".zip".len();
+	Some((&path[..split], &path[split + ENTRY_SEPARATOR.len()..]))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes([
+		data[offset],
+		data[offset + 1],
+		data[offset + 2],
+		data[offset + 3],
+	])
+}
+
+// Lists the files of a zip archive from its central directory. Encrypted entries are skipped.
+pub fn list(path: &Path) -> Result<Vec<ArchiveEntry>> {
+	let mut file = fs::File::open(utils::fs_path(path))?;
+	let file_size = file.seek(SeekFrom::End(0))?;
+
+	// The end of central directory record is only followed by the archive comment
+	let tail_size = file_size.min((END_OF_CENTRAL_DIRECTORY_SIZE + MAX_COMMENT_SIZE) as u64);
+	file.seek(SeekFrom::Start(file_size - tail_size))?;
+	let mut tail = vec![0; tail_size as usize];
+	file.read_exact(&mut tail)?;
+	let end = (0..(tail.len() + 1).saturating_sub(END_OF_CENTRAL_DIRECTORY_SIZE))
+		.rev()
+		.find(|&i| read_u32(&tail, i) == END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+		.ok_or_else(|| anyhow!("Missing end of central directory"))?;
+	let entry_count = read_u16(&tail, end + 10) as usize;
+	let directory_size = read_u32(&tail, end + 12);
+	let directory_offset = read_u32(&tail, end + 16);
+	if directory_size == u32::MAX || directory_offset == u32::MAX {
+		bail!("Zip64 archives are not supported");
+	}
+
+	file.seek(SeekFrom::Start(directory_offset as u64))?;
+	let mut directory = vec![0; directory_size as usize];
+	file.read_exact(&mut directory)?;
+
+	let mut entries = Vec::with_capacity(entry_count);
+	let mut offset = 0;
+	for _ in 0..entry_count {
+		if offset + CENTRAL_DIRECTORY_HEADER_SIZE > directory.len()
+			|| read_u32(&directory, offset) != CENTRAL_DIRECTORY_HEADER_SIGNATURE
+		{
+			bail!("Invalid central directory");
+		}
+		let flags = read_u16(&directory, offset + 8);
+		let compression_method = read_u16(&directory, offset + 10);
+		let compressed_size = read_u32(&directory, offset + 20) as u64;
+		let size = read_u32(&directory, offset + 24) as u64;
+		let name_length = read_u16(&directory, offset + 28) as usize;
+		let extra_length = read_u16(&directory, offset + 30) as usize;
+		let comment_length = read_u16(&directory, offset + 32) as usize;
+		let header_offset = read_u32(&directory, offset + 42) as u64;
+
+		let name_start = offset + CENTRAL_DIRECTORY_HEADER_SIZE;
+		let name = directory
+			.get(name_start..name_start + name_length)
+			.ok_or_else(|| anyhow!("Invalid central directory"))?;
+		let name = String::from_utf8_lossy(name).into_owned();
+		offset = name_start + name_length + extra_length + comment_length;
+
+		let is_encrypted = flags & 1 != 0;
+		if is_encrypted || name.ends_with('/') {
+			continue;
+		}
+		entries.push(ArchiveEntry {
+			name,
+			size,
+			compressed_size,
+			compression_method,
+			header_offset,
+		});
+	}
+
+	Ok(entries)
+}
+
+pub fn extract(path: &Path, entry: &ArchiveEntry, destination: &Path) -> Result<()> {
+	let mut file = fs::File::open(utils::fs_path(path))?;
+	file.seek(SeekFrom::Start(entry.header_offset))?;
+	let mut header = [0; LOCAL_HEADER_SIZE];
+	file.read_exact(&mut header)?;
+	if read_u32(&header, 0) != LOCAL_HEADER_SIGNATURE {
+		bail!("Invalid header for `{}`", entry.name);
+	}
+	let name_length = read_u16(&header, 26) as i64;
+	let extra_length = read_u16(&header, 28) as i64;
+	file.seek(SeekFrom::Current(name_length + extra_length))?;
+
+	let mut data = file.take(entry.compressed_size);
+	match entry.compression_method {
+		COMPRESSION_STORED => {
+			io::copy(&mut data, &mut fs::File::create(destination)?)?;
+		}
+		COMPRESSION_DEFLATE => {
+			let mut decoder = DeflateDecoder::new(data);
+			io::copy(&mut decoder, &mut fs::File::create(destination)?)?;
+		}
+		method => bail!("Unsupported compression method {}", method),
+	};
+	Ok(())
+}
+
+#[test]
+fn test_list_and_extract() {
+	let archive_path = Path::new("test-data/archives/sample.zip");
+	let entries = list(archive_path).unwrap();
+	let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+	assert_eq!(
+		names,
+		vec!["01 - Stored.mp3", "02 - Deflated.flac", "notes.txt"]
+	);
+	assert_eq!(entries[0].compression_method, COMPRESSION_STORED);
+	assert_eq!(entries[1].compression_method, COMPRESSION_DEFLATE);
+
+	let mut output_dir = std::path::PathBuf::new();
+	output_dir.push("test-output");
+	output_dir.push("archive-extract");
+	fs::create_dir_all(&output_dir).unwrap();
+	for (entry, original) in entries.iter().zip(&[
+		"test-data/formats/sample.mp3",
+		"test-data/formats/sample.flac",
+	]) {
+		let output_path = output_dir.join(&entry.name);
+		extract(archive_path, entry, &output_path).unwrap();
+		assert_eq!(fs::read(&output_path).unwrap(), fs::read(original).unwrap());
+		assert_eq!(entry.size, fs::metadata(original).unwrap().len());
+	}
+}
+
+#[test]
+fn test_split_entry_path() {
+	assert_eq!(
+		split_entry_path("music/Album.ZIP#01 - Track #1.flac"),
+		Some(("music/Album.ZIP", "01 - Track #1.flac"))
+	);
+	assert_eq!(split_entry_path("music/Track #1.flac"), None);
+	assert_eq!(
+		entry_path("music/album.zip", "01.flac"),
+		"music/album.zip#01.flac"
+	);
+}
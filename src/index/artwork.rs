@@ -0,0 +1,242 @@
+use anyhow::*;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageOutputFormat};
+use log::warn;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::config::MiscSettings;
+use crate::index::metadata;
+use crate::index::{IndexError, UpdateOptions};
+use crate::utils;
+use crate::vfs::VFS;
+
+const IMAGE_HEADER_SIZE: usize = 32;
+
+// Names of cached files are derived from their contents. Unlike the standard library hashers, the
+// digest does not change between compiler releases, which would orphan the whole cache.
+pub fn hash_bytes(parts: &[&[u8]]) -> String {
+	let mut hasher = Sha256::new();
+	for part in parts {
+		hasher.update(part);
+	}
+	format!("{:x}", hasher.finalize())
+}
+
+// Loose album art patterns may match other files, such as the songs themselves. Files without a
+// known image extension are recognized by their first bytes.
+fn is_image(path: &Path) -> bool {
+	if ImageFormat::from_path(path).is_ok() {
+		return true;
+	}
+	let mut header = Vec::with_capacity(IMAGE_HEADER_SIZE);
+	fs::File::open(utils::fs_path(path))
+		.and_then(|f| f.take(IMAGE_HEADER_SIZE as u64).read_to_end(&mut header))
+		.map_or(false, |_| image::guess_format(&header).is_ok())
+}
+
+// Files matching the same pattern are sorted by name, so the choice does not depend on the order in
+// which the file system lists them
+pub fn find(dir: &Path, album_art_patterns: &[Regex]) -> Result<Option<String>> {
+	let mut best_match: Option<(usize, String)> = None;
+	for file in fs::read_dir(utils::fs_path(dir))? {
+		let file = file?;
+		let path_string = match dir.join(file.file_name()).to_str() {
+			Some(p) => p.to_owned(),
+			None => continue,
+		};
+		if let Some(name_string) = file.file_name().to_str() {
+			let priority = match album_art_patterns
+				.iter()
+				.position(|p| p.is_match(name_string))
+			{
+				Some(priority) => priority,
+				None => continue,
+			};
+			let candidate = (priority, path_string);
+			if best_match.as_ref().map_or(true, |best| candidate < *best) {
+				if !is_image(Path::new(&candidate.1)) {
+					warn!(
+						"Skipping artwork candidate `{}` which is not an image",
+						candidate.1
+					);
+					continue;
+				}
+				best_match = Some(candidate);
+			}
+		}
+	}
+	Ok(best_match.map(|(_, path)| path))
+}
+
+// Covers found by a previous update are used again as long as they still exist and match a
+// pattern, which saves listing the directory. Embedded artwork is always looked up again.
+pub fn find_indexed(
+	vfs: &VFS,
+	dir: &Path,
+	stored_artwork: &str,
+	album_art_patterns: &[Regex],
+) -> Option<String> {
+	let artwork = vfs.stored_to_real(stored_artwork);
+	if artwork.parent() != Some(dir) {
+		return None;
+	}
+	let name = artwork.file_name()?.to_str()?;
+	if !album_art_patterns.iter().any(|p| p.is_match(name))
+		|| !utils::fs_path(&artwork).is_file()
+		|| !is_image(&artwork)
+	{
+		return None;
+	}
+	artwork.to_str().map(|a| a.to_owned())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArtworkFormat {
+	Jpeg,
+	Png,
+}
+
+impl ArtworkFormat {
+	fn extension(self) -> &'static str {
+		match self {
+			ArtworkFormat::Jpeg => "jpg",
+			ArtworkFormat::Png => "png",
+		}
+	}
+}
+
+// Cached artwork can be downscaled and re-encoded, which keeps the cache small
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ArtworkEncoding {
+	max_dimension: Option<u32>,
+	format: Option<ArtworkFormat>, // Resized images otherwise keep their format
+}
+
+impl ArtworkEncoding {
+	fn new(settings: &MiscSettings) -> Result<Option<ArtworkEncoding>, IndexError> {
+		let format = match settings.index_artwork_format.trim().to_lowercase().as_str() {
+			"" => None,
+			"jpg" | "jpeg" => Some(ArtworkFormat::Jpeg),
+			"png" => Some(ArtworkFormat::Png),
+			format => return Err(IndexError::ArtworkFormat(format.to_owned())),
+		};
+		let max_dimension = Some(settings.index_artwork_max_dimension)
+			.filter(|d| *d > 0)
+			.map(|d| d as u32);
+		if format.is_none() && max_dimension.is_none() {
+			return Ok(None);
+		}
+		Ok(Some(ArtworkEncoding {
+			max_dimension,
+			format,
+		}))
+	}
+
+	// Re-encoded images are cached apart from the same image encoded differently
+	fn cache_key(&self) -> String {
+		format!(
+			"{}:{}",
+			self.max_dimension.unwrap_or(0),
+			self.format.map_or("", |f| f.extension())
+		)
+	}
+
+	fn get_format(&self, data: &[u8]) -> Result<ArtworkFormat> {
+		Ok(match self.format {
+			Some(format) => format,
+			None => match image::guess_format(data)? {
+				ImageFormat::Png => ArtworkFormat::Png,
+				_ => ArtworkFormat::Jpeg,
+			},
+		})
+	}
+
+	fn encode(&self, data: &[u8], format: ArtworkFormat) -> Result<Vec<u8>> {
+		let mut image = image::load_from_memory(data)?;
+		if let Some(max_dimension) = self.max_dimension {
+			if image.width() > max_dimension || image.height() > max_dimension {
+				image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+			}
+		}
+		let mut encoded = Vec::new();
+		match format {
+			// JPEG has no alpha channel
+			ArtworkFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+				.write_to(&mut encoded, ImageOutputFormat::Jpeg(80))?,
+			ArtworkFormat::Png => image.write_to(&mut encoded, ImageOutputFormat::Png)?,
+		}
+		Ok(encoded)
+	}
+}
+
+// Directory which embedded artwork is extracted to
+pub struct ArtworkCache {
+	path: PathBuf,
+	encoding: Option<ArtworkEncoding>, // Embedded artwork is otherwise cached as it is
+}
+
+impl ArtworkCache {
+	// Embedded artwork is written to the cache directory from the settings, or the one the index
+	// was given on startup. The encoding settings are checked even when there is no cache.
+	pub fn new(
+		settings: &MiscSettings,
+		options: &UpdateOptions,
+	) -> Result<Option<ArtworkCache>, IndexError> {
+		let encoding = ArtworkEncoding::new(settings)?;
+		let cache_dir = settings.index_artwork_cache_dir.trim();
+		let path = if cache_dir.is_empty() {
+			options.artwork_cache.clone()
+		} else {
+			Some(PathBuf::from(cache_dir))
+		};
+		Ok(path.map(|path| ArtworkCache { path, encoding }))
+	}
+
+	pub fn cache_embedded_artwork(&self, song_path: &str) -> Result<Option<String>> {
+		let cache = &self.path;
+		let data = match metadata::read_artwork(Path::new(song_path)) {
+			Some(d) => d,
+			None => return Ok(None),
+		};
+
+		// Images which cannot be re-encoded are cached as they are
+		if let Some(encoding) = self.encoding {
+			let hash = hash_bytes(&[&data, encoding.cache_key().as_bytes()]);
+			let cached = encoding.get_format(&data).and_then(|format| {
+				let cache_path = cache.join(format!("{}.{}", hash, format.extension()));
+				if !cache_path.exists() {
+					let encoded = encoding.encode(&data, format)?;
+					fs::create_dir_all(cache)?;
+					fs::write(&cache_path, &encoded)?;
+				}
+				Ok(cache_path)
+			});
+			match cached {
+				Ok(cache_path) => return Ok(cache_path.to_str().map(|p| p.to_owned())),
+				Err(e) => warn!(
+					"Could not re-encode embedded artwork from `{}`: {}",
+					song_path, e
+				),
+			}
+		}
+
+		let extension = image::guess_format(&data)?
+			.extensions_str()
+			.first()
+			.copied()
+			.unwrap_or("jpg");
+
+		let mut cache_path = cache.to_path_buf();
+		cache_path.push(format!("{}.{}", hash_bytes(&[&data]), extension));
+
+		if !cache_path.exists() {
+			fs::create_dir_all(cache)?;
+			fs::write(&cache_path, &data)?;
+		}
+		Ok(cache_path.to_str().map(|p| p.to_owned()))
+	}
+}
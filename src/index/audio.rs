@@ -0,0 +1,724 @@
+use anyhow::*;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::utils;
+
+const MP3_MAX_FRAME_SEARCH: usize = 64 * 1024;
+
+const MP3_BITRATES_V1: [[u32; 15]; 3] = [
+	[
+		0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+	],
+	[
+		0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+	],
+	[
+		0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+	],
+];
+const MP3_BITRATES_V2: [[u32; 15]; 2] = [
+	[
+		0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+	],
+	[0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+const MP3_SAMPLE_RATES: [[u32; 3]; 3] = [
+	[44100, 48000, 32000],
+	[22050, 24000, 16000],
+	[11025, 12000, 8000],
+];
+
+pub const BITRATE_MODE_CONSTANT: &str = "CBR";
+pub const BITRATE_MODE_AVERAGE: &str = "ABR";
+pub const BITRATE_MODE_VARIABLE: &str = "VBR";
+
+// Properties of the audio stream rather than of its tags. Bitrates are in kbps.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AudioProperties {
+	pub format: Option<String>,
+	pub lossless: Option<bool>,
+	pub bitrate: Option<u32>,
+	pub bitrate_mode: Option<String>, // One of the `BITRATE_MODE_*` values, for lossy formats only
+	pub sample_rate: Option<u32>,
+	pub bits_per_sample: Option<u32>,
+	pub channels: Option<u32>,
+	pub encoder_delay: Option<u32>, // Samples to skip at the start and end for gapless playback
+	pub encoder_padding: Option<u32>,
+}
+
+// Averaged over the whole file, which is how variable bitrates are reported
+pub fn average_bitrate(file_size: u64, duration: f64) -> Option<u32> {
+	if duration > 0.0 {
+		Some((file_size as f64 * 8.0 / duration / 1000.0).round() as u32)
+	} else {
+		None
+	}
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+	Ok(fs::metadata(utils::fs_path(path))?.len())
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> u32 {
+	u16::from_le_bytes([data[offset], data[offset + 1]]) as u32
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes([
+		data[offset],
+		data[offset + 1],
+		data[offset + 2],
+		data[offset + 3],
+	])
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> u32 {
+	u32::from_be_bytes([
+		data[offset],
+		data[offset + 1],
+		data[offset + 2],
+		data[offset + 3],
+	])
+}
+
+// Reads the first valid frame header after the ID3v2 tag
+pub fn read_mp3(path: &Path, duration: Option<f64>) -> Result<AudioProperties> {
+	let mut file = fs::File::open(utils::fs_path(path))?;
+	let mut id3_header = [0; 10];
+	file.read_exact(&mut id3_header)?;
+	let audio_start = if &id3_header[..3] == b"ID3" {
+		// Tag sizes are stored as syncsafe integers, which use 7 bits per byte
+		let size = id3_header[6..10]
+			.iter()
+			.fold(0u64, |size, b| (size << 7) | (*b & 0x7f) as u64);
+		let footer_size = if id3_header[5] & 0x10 != 0 { 10 } else { 0 };
+		10 + size + footer_size
+	} else {
+		0
+	};
+	file.seek(SeekFrom::Start(audio_start))?;
+	let mut data = Vec::new();
+	file.take(MP3_MAX_FRAME_SEARCH as u64)
+		.read_to_end(&mut data)?;
+
+	for (offset, header) in data.windows(4).enumerate() {
+		if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+			continue;
+		}
+		let version = (header[1] >> 3) & 0x03;
+		let layer = (header[1] >> 1) & 0x03;
+		let bitrate_index = (header[2] >> 4) as usize;
+		let sample_rate_index = ((header[2] >> 2) & 0x03) as usize;
+		if version == 1
+			|| layer == 0
+			|| bitrate_index == 0
+			|| bitrate_index == 15
+			|| sample_rate_index == 3
+		{
+			continue;
+		}
+		let layer_index = (3 - layer) as usize;
+		let (frame_bitrate, sample_rate) = match version {
+			3 => (
+				MP3_BITRATES_V1[layer_index][bitrate_index],
+				MP3_SAMPLE_RATES[0][sample_rate_index],
+			),
+			2 | 0 => (
+				MP3_BITRATES_V2[layer_index.min(1)][bitrate_index],
+				MP3_SAMPLE_RATES[if version == 2 { 1 } else { 2 }][sample_rate_index],
+			),
+			_ => continue,
+		};
+		let is_mono = header[3] >> 6 == 3;
+
+		// Random data can look like a frame header, so it only counts when another frame follows
+		let padding = ((header[2] >> 1) & 0x01) as usize;
+		let frame_size = match (layer, version) {
+			(3, _) => (12 * frame_bitrate as usize * 1000 / sample_rate as usize + padding) * 4,
+			(1, 2) | (1, 0) => 72 * frame_bitrate as usize * 1000 / sample_rate as usize + padding,
+			_ => 144 * frame_bitrate as usize * 1000 / sample_rate as usize + padding,
+		};
+		let next_offset = offset + frame_size;
+		if let Some(next_header) = data.get(next_offset..next_offset + 2) {
+			if next_header[0] != 0xff || next_header[1] & 0xe0 != 0xe0 {
+				continue;
+			}
+		}
+
+		// Encoders mark constant bitrate files with an `Info` header in place of the `Xing` header
+		// of variable bitrate files. It follows the side information of the first frame.
+		let side_info_size = match (version == 3, is_mono) {
+			(true, false) => 32,
+			(true, true) | (false, false) => 17,
+			(false, true) => 9,
+		};
+		let info_offset = offset + 4 + side_info_size;
+		let info_header = data.get(info_offset..info_offset + 4);
+		let is_constant_bitrate = info_header == Some(&b"Info"[..]);
+		let encoder_gaps = data.get(info_offset..).and_then(parse_lame_encoder_gaps);
+
+		// Fraunhofer encoders write a `VBRI` header at a fixed offset instead
+		let vbri_offset = offset + 4 + 32;
+		let is_vbri = data.get(vbri_offset..vbri_offset + 4) == Some(&b"VBRI"[..]);
+		let bitrate_mode = match data.get(info_offset..).and_then(parse_lame_bitrate_mode) {
+			Some(mode) => Some(mode),
+			None if is_constant_bitrate => Some(BITRATE_MODE_CONSTANT),
+			None if info_header == Some(&b"Xing"[..]) || is_vbri => Some(BITRATE_MODE_VARIABLE),
+			None => None,
+		};
+		let audio_size = file_size(path)?.saturating_sub(audio_start);
+		let bitrate = if is_constant_bitrate {
+			Some(frame_bitrate)
+		} else {
+			duration.and_then(|d| average_bitrate(audio_size, d))
+		}
+		.or_else(|| Some(frame_bitrate).filter(|b| *b > 0));
+		return Ok(AudioProperties {
+			format: Some(format!("MP{}", 4 - layer)),
+			lossless: Some(false),
+			bitrate,
+			bitrate_mode: bitrate_mode.map(|m| m.to_owned()),
+			sample_rate: Some(sample_rate),
+			bits_per_sample: None,
+			channels: Some(if is_mono { 1 } else { 2 }),
+			encoder_delay: encoder_gaps.map(|(delay, _)| delay),
+			encoder_padding: encoder_gaps.map(|(_, padding)| padding),
+		});
+	}
+
+	bail!("No MPEG frame found")
+}
+
+const LAME_ENCODERS: [&[u8]; 3] = [b"LAME", b"Lavf", b"Lavc"];
+
+// The LAME extension follows the `Xing` or `Info` header, whose size depends on the fields its
+// flags announce
+fn find_lame_tag(data: &[u8]) -> Option<&[u8]> {
+	let header = data.get(..8)?;
+	if &header[..4] != b"Xing" && &header[..4] != b"Info" {
+		return None;
+	}
+	let flags = read_u32_be(header, 4);
+	let field_sizes = [(0x1, 4), (0x2, 4), (0x4, 100), (0x8, 4)];
+	let lame_offset = 8 + field_sizes
+		.iter()
+		.filter(|(flag, _)| flags & flag != 0)
+		.map(|(_, size)| size)
+		.sum::<usize>();
+	let lame = data.get(lame_offset..lame_offset + 24)?;
+	if !LAME_ENCODERS.iter().any(|e| lame.starts_with(e)) {
+		return None;
+	}
+	Some(lame)
+}
+
+// Encoder delay and padding are packed as two 12 bit sample counts
+fn parse_lame_encoder_gaps(data: &[u8]) -> Option<(u32, u32)> {
+	let lame = find_lame_tag(data)?;
+	let delay = (lame[21] as u32) << 4 | (lame[22] as u32) >> 4;
+	let padding = ((lame[22] & 0x0f) as u32) << 8 | lame[23] as u32;
+	Some((delay, padding))
+}
+
+// The low bits of the byte following the encoder version give the method the file was encoded with
+fn parse_lame_bitrate_mode(data: &[u8]) -> Option<&'static str> {
+	match find_lame_tag(data)?[9] & 0x0f {
+		1 | 8 => Some(BITRATE_MODE_CONSTANT),
+		2 | 9 => Some(BITRATE_MODE_AVERAGE),
+		3..=6 => Some(BITRATE_MODE_VARIABLE),
+		_ => None,
+	}
+}
+
+// Monkey's Audio files start with a descriptor since version 3.98, and with the header itself
+// in older versions
+pub fn read_ape(path: &Path) -> Result<AudioProperties> {
+	let mut file = fs::File::open(utils::fs_path(path))?;
+	let mut data = [0; 76];
+	file.read_exact(&mut data)?;
+	if &data[..4] != b"MAC " {
+		bail!("Missing Monkey's Audio header");
+	}
+	let version = read_u16_le(&data, 4);
+
+	let (
+		sample_rate,
+		channels,
+		bits_per_sample,
+		total_frames,
+		final_frame_blocks,
+		blocks_per_frame,
+	) = if version >= 3980 {
+		let header = read_u32_le(&data, 8) as usize;
+		if header + 24 > data.len() {
+			bail!("Invalid Monkey's Audio descriptor");
+		}
+		(
+			read_u32_le(&data, header + 20),
+			read_u16_le(&data, header + 18),
+			read_u16_le(&data, header + 16),
+			read_u32_le(&data, header + 12),
+			read_u32_le(&data, header + 8),
+			read_u32_le(&data, header + 4),
+		)
+	} else {
+		let compression_level = read_u16_le(&data, 6);
+		let format_flags = read_u16_le(&data, 8);
+		let bits_per_sample = if format_flags & 0x01 != 0 {
+			8
+		} else if format_flags & 0x08 != 0 {
+			24
+		} else {
+			16
+		};
+		let blocks_per_frame = if version >= 3950 {
+			73728 * 4
+		} else if version >= 3900 || (version >= 3800 && compression_level == 4000) {
+			73728
+		} else {
+			9216
+		};
+		(
+			read_u32_le(&data, 12),
+			read_u16_le(&data, 10),
+			bits_per_sample,
+			read_u32_le(&data, 24),
+			read_u32_le(&data, 28),
+			blocks_per_frame,
+		)
+	};
+
+	let total_blocks = match total_frames {
+		0 => 0,
+		n => (n as u64 - 1) * blocks_per_frame as u64 + final_frame_blocks as u64,
+	};
+	let bitrate = match sample_rate {
+		0 => None,
+		rate => average_bitrate(file_size(path)?, total_blocks as f64 / rate as f64),
+	};
+	Ok(AudioProperties {
+		format: Some("APE".to_owned()),
+		lossless: Some(true),
+		bitrate,
+		bitrate_mode: None,
+		sample_rate: Some(sample_rate).filter(|r| *r > 0),
+		bits_per_sample: Some(bits_per_sample),
+		channels: Some(channels),
+		encoder_delay: None,
+		encoder_padding: None,
+	})
+}
+
+const WAVPACK_SAMPLE_RATES: [u32; 15] = [
+	6000, 8000, 9600, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000,
+	192000,
+];
+const WAVPACK_MAX_BLOCK_SIZE: u32 = 1024 * 1024;
+const WAVPACK_ID_CHANNEL_INFO: u8 = 0x0d;
+const WAVPACK_ID_SAMPLE_RATE: u8 = 0x27;
+
+// Stream properties along with the duration in seconds, read from the header of the first block.
+// Sample rates outside of the standard table and channel layouts beyond stereo are stored in
+// metadata sub-blocks following the header.
+pub fn read_wavpack(path: &Path) -> Result<(AudioProperties, f64)> {
+	let mut file = fs::File::open(utils::fs_path(path))?;
+	let mut header = [0; 32];
+	file.read_exact(&mut header)?;
+	if &header[..4] != b"wvpk" {
+		bail!("Missing WavPack block header");
+	}
+	let block_size = read_u32_le(&header, 4);
+	if !(24..=WAVPACK_MAX_BLOCK_SIZE).contains(&block_size) {
+		bail!("Invalid WavPack block size");
+	}
+	let mut block = vec![0; block_size as usize - 24];
+	file.read_exact(&mut block)?;
+
+	let flags = read_u32_le(&header, 24);
+	let mut sample_rate = WAVPACK_SAMPLE_RATES
+		.get((flags >> 23 & 0x0f) as usize)
+		.copied();
+	let mut channels = if flags & 0x04 != 0 { 1 } else { 2 };
+	let bits_per_sample = ((flags & 0x03) + 1) * 8;
+	let is_hybrid = flags & 0x08 != 0; // Lossy unless a correction file is played along
+	for (id, data) in WavPackSubBlocks(&block) {
+		match id & 0x3f {
+			WAVPACK_ID_CHANNEL_INFO if !data.is_empty() => channels = data[0] as u32,
+			WAVPACK_ID_SAMPLE_RATE if data.len() >= 3 => {
+				sample_rate = Some(u32::from_le_bytes([data[0], data[1], data[2], 0]))
+			}
+			_ => (),
+		}
+	}
+
+	// All ones in the low 32 bits means the length was unknown when the file was written
+	let total_samples = match read_u32_le(&header, 12) {
+		u32::MAX => None,
+		low => Some((header[11] as u64) << 32 | low as u64),
+	};
+	let duration = match (total_samples, sample_rate) {
+		(Some(samples), Some(rate)) if rate > 0 => samples as f64 / rate as f64,
+		_ => 0.0,
+	};
+	let properties = AudioProperties {
+		format: Some("WavPack".to_owned()),
+		lossless: Some(!is_hybrid),
+		bitrate: average_bitrate(file_size(path)?, duration),
+		bitrate_mode: None,
+		sample_rate: sample_rate.filter(|r| *r > 0),
+		bits_per_sample: Some(bits_per_sample),
+		channels: Some(channels).filter(|c| *c > 0),
+		encoder_delay: None,
+		encoder_padding: None,
+	};
+	Ok((properties, duration))
+}
+
+// Iterates over the metadata sub-blocks of a WavPack block as (id, data) pairs. Sizes are stored in
+// 16 bit words, with a flag marking a trailing padding byte.
+struct WavPackSubBlocks<'a>(&'a [u8]);
+
+impl<'a> Iterator for WavPackSubBlocks<'a> {
+	type Item = (u8, &'a [u8]);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let data = self.0;
+		let id = *data.get(0)?;
+		let (words, header_size) = if id & 0x80 != 0 {
+			let size = data.get(1..4)?;
+			(
+				u32::from_le_bytes([size[0], size[1], size[2], 0]) as usize,
+				4,
+			)
+		} else {
+			(*data.get(1)? as usize, 2)
+		};
+		let size = words * 2;
+		let content = data.get(header_size..header_size + size)?;
+		self.0 = &data[header_size + size..];
+		let length = if id & 0x40 != 0 {
+			size.saturating_sub(1)
+		} else {
+			size
+		};
+		Some((id, &content[..length]))
+	}
+}
+
+const MUSEPACK_SAMPLE_RATES: [u32; 4] = [44100, 48000, 37800, 32000];
+const MUSEPACK_FRAME_SAMPLES: u64 = 1152;
+const MUSEPACK_MAX_HEADER_SEARCH: u64 = 4096;
+
+// Stream properties along with the duration in seconds, from either an SV7 or an SV8 header
+pub fn read_musepack(path: &Path) -> Result<(AudioProperties, f64)> {
+	let file = fs::File::open(utils::fs_path(path))?;
+	let mut data = Vec::new();
+	file.take(MUSEPACK_MAX_HEADER_SEARCH)
+		.read_to_end(&mut data)?;
+	let (sample_rate, channels, total_samples) =
+		parse_musepack_header(&data).ok_or_else(|| anyhow!("Missing Musepack header"))?;
+	let duration = total_samples as f64 / sample_rate as f64;
+	let properties = AudioProperties {
+		format: Some("MPC".to_owned()),
+		lossless: Some(false),
+		bitrate: average_bitrate(file_size(path)?, duration),
+		bitrate_mode: Some(BITRATE_MODE_VARIABLE.to_owned()), // Musepack has no constant bitrate mode
+		sample_rate: Some(sample_rate),
+		bits_per_sample: None,
+		channels: Some(channels),
+		encoder_delay: None,
+		encoder_padding: None,
+	};
+	Ok((properties, duration))
+}
+
+// Returns the sample rate, channel count and number of samples
+fn parse_musepack_header(data: &[u8]) -> Option<(u32, u32, u64)> {
+	if data.starts_with(b"MPCK") {
+		parse_musepack_sv8_header(&data[4..])
+	} else if data.starts_with(b"MP+") && data.len() >= 24 && data[3] & 0x0f == 7 {
+		// Fields are packed into little endian words, read from their most significant bit
+		let frames = read_u32_le(data, 4) as u64;
+		let sample_rate = MUSEPACK_SAMPLE_RATES[(read_u32_le(data, 8) >> 16 & 0x03) as usize];
+		let gapless = read_u32_le(data, 20);
+		let total_samples = if gapless & 0x8000_0000 != 0 && frames > 0 {
+			(frames - 1) * MUSEPACK_FRAME_SAMPLES + (gapless >> 20 & 0x07ff) as u64
+		} else {
+			frames * MUSEPACK_FRAME_SAMPLES
+		};
+		Some((sample_rate, 2, total_samples))
+	} else {
+		None
+	}
+}
+
+// SV8 streams are a sequence of packets made of a two letter key and a size which includes the
+// key and size themselves. The stream header packet holds the properties we need.
+fn parse_musepack_sv8_header(mut data: &[u8]) -> Option<(u32, u32, u64)> {
+	loop {
+		let key = data.get(..2)?;
+		let (size, size_length) = read_musepack_varint(&data[2..])?;
+		let packet = data.get(2 + size_length..size as usize)?;
+		if key == b"SH" {
+			// CRC and stream version precede the sample counts
+			let (samples, length) = read_musepack_varint(packet.get(5..)?)?;
+			let (silence, silence_length) = read_musepack_varint(&packet[5 + length..])?;
+			let properties = packet.get(5 + length + silence_length..)?;
+			if properties.len() < 2 {
+				return None;
+			}
+			let sample_rate = MUSEPACK_SAMPLE_RATES.get((properties[0] >> 5) as usize)?;
+			let channels = (properties[1] >> 4) as u32 + 1;
+			return Some((*sample_rate, channels, samples.saturating_sub(silence)));
+		}
+		data = &data[size as usize..];
+	}
+}
+
+// Big endian groups of 7 bits, where the high bit marks that another byte follows
+fn read_musepack_varint(data: &[u8]) -> Option<(u64, usize)> {
+	let mut value = 0u64;
+	for (i, byte) in data.iter().enumerate().take(9) {
+		value = value << 7 | (byte & 0x7f) as u64;
+		if byte & 0x80 == 0 {
+			return Some((value, i + 1));
+		}
+	}
+	None
+}
+
+// Finds the content range of a box nested within `start..end` along the given path
+fn find_mp4_box(
+	file: &mut fs::File,
+	start: u64,
+	end: u64,
+	path: &[&[u8; 4]],
+) -> Result<Option<(u64, u64)>> {
+	let (name, rest) = match path.split_first() {
+		Some(split) => split,
+		None => return Ok(Some((start, end))),
+	};
+	let mut offset = start;
+	while offset + 8 <= end {
+		let mut header = [0; 16];
+		file.seek(SeekFrom::Start(offset))?;
+		file.read_exact(&mut header[..8])?;
+		let (size, header_size) = match read_u32_be(&header, 0) {
+			0 => (end - offset, 8),
+			1 => {
+				file.read_exact(&mut header[8..])?;
+				let size = u64::from_be_bytes([
+					header[8], header[9], header[10], header[11], header[12], header[13],
+					header[14], header[15],
+				]);
+				(size, 16)
+			}
+			size => (size as u64, 8),
+		};
+		if size < header_size || offset + size > end {
+			bail!("Invalid MP4 box size");
+		}
+		if &header[4..8] == *name {
+			if let Some(range) = find_mp4_box(file, offset + header_size, offset + size, rest)? {
+				return Ok(Some(range));
+			}
+		}
+		offset += size;
+	}
+	Ok(None)
+}
+
+// Reads the first sample description of the first track
+pub fn read_mp4(path: &Path, duration: Option<f64>) -> Result<AudioProperties> {
+	let mut file = fs::File::open(utils::fs_path(path))?;
+	let file_size = file_size(path)?;
+	let (start, end) = find_mp4_box(
+		&mut file,
+		0,
+		file_size,
+		&[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+	)?
+	.ok_or_else(|| anyhow!("Missing MP4 sample description"))?;
+
+	// Version, flags and entry count precede the audio sample entry
+	let mut data = [0; 44];
+	if end - start < data.len() as u64 {
+		bail!("Invalid MP4 sample description");
+	}
+	file.seek(SeekFrom::Start(start))?;
+	file.read_exact(&mut data)?;
+	let (format, is_lossless) = match &data[12..16] {
+		b"mp4a" => ("AAC".to_owned(), false),
+		b"alac" => ("ALAC".to_owned(), true),
+		codec => (String::from_utf8_lossy(codec).to_uppercase(), false),
+	};
+	let channels = u16::from_be_bytes([data[32], data[33]]) as u32;
+	let sample_size = u16::from_be_bytes([data[34], data[35]]) as u32;
+	let sample_rate = read_u32_be(&data, 40) >> 16; // Fixed point 16.16
+
+	Ok(AudioProperties {
+		format: Some(format),
+		lossless: Some(is_lossless),
+		bitrate: duration.and_then(|d| average_bitrate(file_size, d)),
+		bitrate_mode: None,
+		sample_rate: Some(sample_rate).filter(|r| *r > 0),
+		bits_per_sample: Some(sample_size).filter(|_| is_lossless),
+		channels: Some(channels).filter(|c| *c > 0),
+		encoder_delay: None,
+		encoder_padding: None,
+	})
+}
+
+pub fn read_flac(
+	path: &Path,
+	stream_info: &metaflac::block::StreamInfo,
+) -> Result<AudioProperties> {
+	let bitrate = match stream_info.sample_rate {
+		0 => None,
+		rate => average_bitrate(
+			file_size(path)?,
+			stream_info.total_samples as f64 / rate as f64,
+		),
+	};
+	Ok(AudioProperties {
+		format: Some("FLAC".to_owned()),
+		lossless: Some(true),
+		bitrate,
+		bitrate_mode: None,
+		sample_rate: Some(stream_info.sample_rate),
+		bits_per_sample: Some(stream_info.bits_per_sample as u32),
+		channels: Some(stream_info.num_channels as u32),
+		encoder_delay: None,
+		encoder_padding: None,
+	})
+}
+
+#[test]
+fn test_read_mp3() {
+	let properties = read_mp3(Path::new("test-data/formats/sample.mp3"), None).unwrap();
+	assert_eq!(properties.format, Some("MP3".to_owned()));
+	assert_eq!(properties.bitrate, Some(320));
+	assert_eq!(properties.sample_rate, Some(44100));
+	assert_eq!(properties.channels, Some(1));
+	assert_eq!(properties.bits_per_sample, None);
+	assert_eq!(properties.encoder_delay, Some(576));
+	assert_eq!(properties.encoder_padding, Some(2089));
+
+	assert_eq!(average_bitrate(40_000, 1.0), Some(320));
+	assert_eq!(average_bitrate(40_000, 0.0), None);
+	assert!(read_mp3(Path::new("test-data/formats/sample.flac"), None).is_err());
+}
+
+#[test]
+fn test_parse_lame_encoder_gaps() {
+	// A `Xing` header announcing only a frame count, followed by its LAME extension
+	let mut data = b"Xing\0\0\0\x01\0\0\0\x10LAME3.100".to_vec();
+	data.extend_from_slice(&[0; 12]);
+	data.extend_from_slice(&[0x24, 0x08, 0x29]);
+	assert_eq!(parse_lame_encoder_gaps(&data), Some((576, 2089)));
+	assert_eq!(parse_lame_encoder_gaps(&data[..data.len() - 1]), None);
+
+	data[12..16].copy_from_slice(b"Fake");
+	assert_eq!(parse_lame_encoder_gaps(&data), None);
+	assert_eq!(parse_lame_encoder_gaps(b"Info"), None);
+}
+
+#[test]
+fn test_parse_lame_bitrate_mode() {
+	let mut data = b"Info\0\0\0\0LAME3.100".to_vec();
+	data.extend_from_slice(&[0; 15]);
+	assert_eq!(parse_lame_bitrate_mode(&data), None);
+	for (method, mode) in &[
+		(0x01, BITRATE_MODE_CONSTANT),
+		(0x12, BITRATE_MODE_AVERAGE),
+		(0x14, BITRATE_MODE_VARIABLE),
+	] {
+		data[17] = *method;
+		assert_eq!(parse_lame_bitrate_mode(&data), Some(*mode));
+	}
+}
+
+#[test]
+fn test_read_wavpack() {
+	let (properties, duration) = read_wavpack(Path::new("test-data/formats/sample.wv")).unwrap();
+	assert_eq!(properties.format, Some("WavPack".to_owned()));
+	assert_eq!(properties.sample_rate, Some(44100));
+	assert_eq!(properties.bits_per_sample, Some(16));
+	assert_eq!(properties.channels, Some(1));
+	assert!((duration - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_wavpack_sub_blocks() {
+	let block = [
+		WAVPACK_ID_CHANNEL_INFO | 0x40,
+		1,
+		6,
+		0,
+		WAVPACK_ID_SAMPLE_RATE | 0x80 | 0x40,
+		2,
+		0,
+		0,
+		0x00,
+		0x77,
+		0x01,
+		0,
+	];
+	let sub_blocks: Vec<_> = WavPackSubBlocks(&block).collect();
+	assert_eq!(
+		sub_blocks,
+		vec![
+			(WAVPACK_ID_CHANNEL_INFO | 0x40, &[6][..]),
+			(
+				WAVPACK_ID_SAMPLE_RATE | 0x80 | 0x40,
+				&[0x00, 0x77, 0x01][..]
+			)
+		]
+	);
+	assert_eq!(WavPackSubBlocks(&block[..3]).count(), 0);
+}
+
+#[test]
+fn test_read_musepack() {
+	let (properties, duration) = read_musepack(Path::new("test-data/formats/sample.mpc")).unwrap();
+	assert_eq!(properties.format, Some("MPC".to_owned()));
+	assert_eq!(properties.sample_rate, Some(44100));
+	assert_eq!(properties.channels, Some(2));
+	assert!((duration - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_parse_musepack_sv7_header() {
+	let mut data = vec![0; 24];
+	data[..4].copy_from_slice(b"MP+\x17");
+	data[4..8].copy_from_slice(&40u32.to_le_bytes());
+	data[8..12].copy_from_slice(&(1u32 << 16).to_le_bytes());
+	assert_eq!(parse_musepack_header(&data), Some((48000, 2, 40 * 1152)));
+
+	data[20..24].copy_from_slice(&(0x8000_0000u32 | 500 << 20).to_le_bytes());
+	assert_eq!(
+		parse_musepack_header(&data),
+		Some((48000, 2, 39 * 1152 + 500))
+	);
+
+	data[3] = 0x16;
+	assert_eq!(parse_musepack_header(&data), None);
+}
+
+#[test]
+fn test_read_musepack_varint() {
+	assert_eq!(read_musepack_varint(&[0x05]), Some((5, 1)));
+	assert_eq!(read_musepack_varint(&[0x82, 0xd8, 0x44]), Some((44100, 3)));
+	assert_eq!(read_musepack_varint(&[0x82]), None);
+}
+
+#[test]
+fn test_read_ape() {
+	let properties = read_ape(Path::new("test-data/formats/sample.ape")).unwrap();
+	assert_eq!(properties.format, Some("APE".to_owned()));
+	assert_eq!(properties.sample_rate, Some(44100));
+	assert_eq!(properties.bits_per_sample, Some(16));
+	assert_eq!(properties.channels, Some(1));
+}
@@ -0,0 +1,227 @@
+use anyhow::*;
+use std::fs;
+use std::path::Path;
+
+use crate::utils;
+
+pub const FRAMES_PER_SECOND: u32 = 75;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueTrack {
+	pub number: u32,
+	pub title: Option<String>,
+	pub performer: Option<String>,
+	pub start_frame: u32,
+}
+
+impl CueTrack {
+	pub fn start_time(&self) -> u32 {
+		self.start_frame / FRAMES_PER_SECOND
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueFile {
+	pub name: String,
+	pub tracks: Vec<CueTrack>,
+}
+
+impl CueFile {
+	// The last track lasts until the end of the file, whose duration is not part of the cue sheet
+	pub fn track_durations(&self, file_duration: Option<u32>) -> Vec<Option<u32>> {
+		self.tracks
+			.iter()
+			.enumerate()
+			.map(|(i, track)| match self.tracks.get(i + 1) {
+				Some(next) => Some((next.start_frame - track.start_frame) / FRAMES_PER_SECOND),
+				None => file_duration.and_then(|d| d.checked_sub(track.start_time())),
+			})
+			.collect()
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueSheet {
+	pub title: Option<String>,
+	pub performer: Option<String>,
+	pub files: Vec<CueFile>,
+}
+
+pub fn read(path: &Path) -> Result<CueSheet> {
+	let content = fs::read(utils::fs_path(path))?;
+	parse(&String::from_utf8_lossy(&content))
+}
+
+// Values are either quoted strings or the first word of the arguments
+fn parse_value(arguments: &str) -> String {
+	if let Some(quoted) = arguments.strip_prefix('"') {
+		match quoted.find('"') {
+			Some(end) => quoted[..end].to_owned(),
+			None => quoted.to_owned(),
+		}
+	} else {
+		arguments
+			.split_whitespace()
+			.next()
+			.unwrap_or_default()
+			.to_owned()
+	}
+}
+
+// Timestamps are written as minutes:seconds:frames
+fn parse_timestamp(timestamp: &str) -> Result<u32> {
+	let parts = timestamp
+		.split(':')
+		.map(|p| p.parse::<u32>())
+		.collect::<std::result::Result<Vec<_>, _>>()?;
+	match parts.as_slice() {
+		[minutes, seconds, frames] => Ok((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames),
+		_ => bail!("Invalid timestamp `{}`", timestamp),
+	}
+}
+
+pub fn parse(content: &str) -> Result<CueSheet> {
+	let mut sheet = CueSheet::default();
+	let mut in_track = false;
+	let mut has_index = false;
+
+	for line in content.lines() {
+		let line = line.trim_start_matches('\u{feff}').trim();
+		let (command, arguments) = match line.find(char::is_whitespace) {
+			Some(i) => (&line[..i], line[i..].trim()),
+			None => (line, ""),
+		};
+
+		let current_track = sheet
+			.files
+			.last_mut()
+			.and_then(|f| f.tracks.last_mut())
+			.filter(|_| in_track);
+
+		utils::match_ignore_case! {
+			match command {
+				"FILE" => {
+					if in_track && !has_index {
+						bail!("Track listed without a starting index");
+					}
+					sheet.files.push(CueFile {
+						name: parse_value(arguments),
+						tracks: Vec::new(),
+					});
+					in_track = false;
+				},
+				"TRACK" => {
+					if in_track && !has_index {
+						bail!("Track listed without a starting index");
+					}
+					let file = sheet
+						.files
+						.last_mut()
+						.ok_or_else(|| anyhow!("Track listed before any file"))?;
+					let number = parse_value(arguments).parse::<u32>()?;
+					file.tracks.push(CueTrack {
+						number,
+						..Default::default()
+					});
+					in_track = true;
+					has_index = false;
+				},
+				"TITLE" => match current_track {
+					Some(track) => track.title = Some(parse_value(arguments)),
+					None => sheet.title = Some(parse_value(arguments)),
+				},
+				"PERFORMER" => match current_track {
+					Some(track) => track.performer = Some(parse_value(arguments)),
+					None => sheet.performer = Some(parse_value(arguments)),
+				},
+				"INDEX" => {
+					let mut words = arguments.split_whitespace();
+					let index = words.next().unwrap_or_default().parse::<u32>()?;
+					let timestamp = parse_timestamp(words.next().unwrap_or_default())?;
+					let track = current_track.ok_or_else(|| anyhow!("Index listed outside of a track"))?;
+					if index == 1 {
+						track.start_frame = timestamp;
+						has_index = true;
+					}
+				},
+				_ => (),
+			}
+		}
+	}
+
+	if in_track && !has_index {
+		bail!("Track listed without a starting index");
+	}
+
+	if sheet.files.iter().all(|f| f.tracks.is_empty()) {
+		bail!("Cue sheet has no tracks");
+	}
+
+	for file in &sheet.files {
+		if file
+			.tracks
+			.windows(2)
+			.any(|t| t[1].start_frame < t[0].start_frame)
+		{
+			bail!("Tracks of `{}` are not in chronological order", file.name);
+		}
+	}
+
+	Ok(sheet)
+}
+
+#[test]
+fn test_parse() {
+	let content = "\u{feff}REM GENRE Ambient
+PERFORMER \"Some Artist\"
+TITLE \"Some Album\"
+FILE \"Some Album.flac\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"First\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Second\"
+    PERFORMER \"Guest Artist\"
+    INDEX 00 03:29:50
+    INDEX 01 03:30:00
+";
+	let sheet = parse(content).unwrap();
+	assert_eq!(sheet.title, Some("Some Album".to_owned()));
+	assert_eq!(sheet.performer, Some("Some Artist".to_owned()));
+	assert_eq!(sheet.files.len(), 1);
+
+	let file = &sheet.files[0];
+	assert_eq!(file.name, "Some Album.flac");
+	assert_eq!(
+		file.tracks,
+		vec![
+			CueTrack {
+				number: 1,
+				title: Some("First".to_owned()),
+				performer: None,
+				start_frame: 0,
+			},
+			CueTrack {
+				number: 2,
+				title: Some("Second".to_owned()),
+				performer: Some("Guest Artist".to_owned()),
+				start_frame: 210 * 75,
+			},
+		]
+	);
+	assert_eq!(file.tracks[1].start_time(), 210);
+	assert_eq!(file.track_durations(Some(300)), vec![Some(210), Some(90)]);
+	assert_eq!(file.track_durations(None), vec![Some(210), None]);
+}
+
+#[test]
+fn test_parse_malformed() {
+	assert!(parse("").is_err());
+	assert!(parse("TRACK 01 AUDIO\nINDEX 01 00:00:00").is_err());
+	assert!(parse("FILE a.flac WAVE\nTRACK 01 AUDIO\nTITLE \"No index\"").is_err());
+	assert!(parse("FILE a.flac WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00").is_err());
+	assert!(parse(
+		"FILE a.flac WAVE\nTRACK 01 AUDIO\nINDEX 01 01:00:00\nTRACK 02 AUDIO\nINDEX 01 00:00:00"
+	)
+	.is_err());
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::index::metadata::{self, SongTags};
+use crate::index::{MetadataConflict, MetadataField};
+
+// How tags spell out artists and sort keys
+pub struct TagConventions {
+	pub artist_separator: String,
+	pub various_artists: String,
+	pub sort_articles: Vec<String>,
+}
+
+impl TagConventions {
+	// Sort tags from the file take precedence over keys derived from the displayed value
+	pub fn sort_value(&self, sort_tag: &Option<String>, value: &Option<String>) -> Option<String> {
+		match sort_tag {
+			Some(sort_tag) => Some(metadata::fold_search_text(sort_tag.trim())),
+			None => value
+				.as_deref()
+				.map(|v| metadata::sort_key(v, &self.sort_articles)),
+		}
+	}
+}
+
+// Dates agree when one only adds precision to the other, as "2019-07" does to "2019"
+pub fn most_precise_date<'a>(date: &'a str, other: &'a str) -> Option<&'a str> {
+	if date.starts_with(other) {
+		Some(date)
+	} else if other.starts_with(date) {
+		Some(other)
+	} else {
+		None
+	}
+}
+
+// Distinct tag values of the songs within a directory, for reporting conflicts between them
+#[derive(Default)]
+struct DirectoryTagValues(Vec<(MetadataField, Vec<String>)>);
+
+impl DirectoryTagValues {
+	// Album artists take precedence over song artists, as when consolidating the directory
+	fn add_song(&mut self, tags: &SongTags) {
+		let artist = tags.album_artist.as_ref().or_else(|| tags.artist.as_ref());
+		let year = tags.year.map(|y| y.to_string());
+		for (field, value) in &[
+			(MetadataField::Album, tags.album.as_ref()),
+			(MetadataField::Artist, artist),
+			(MetadataField::Year, year.as_ref()),
+			(MetadataField::Genre, tags.genre.as_ref()),
+			(MetadataField::Composer, tags.composer.as_ref()),
+		] {
+			if let Some(value) = value {
+				self.add(*field, value);
+			}
+		}
+	}
+
+	fn add(&mut self, field: MetadataField, value: &str) {
+		match self.0.iter_mut().find(|(f, _)| *f == field) {
+			Some((_, values)) => {
+				if !values.iter().any(|v| v == value) {
+					values.push(value.to_owned());
+				}
+			}
+			None => self.0.push((field, vec![value.to_owned()])),
+		}
+	}
+
+	fn take(&mut self, field: MetadataField) -> Vec<String> {
+		self.0
+			.iter_mut()
+			.find(|(f, _)| *f == field)
+			.map(|(_, values)| std::mem::take(values))
+			.unwrap_or_default()
+	}
+}
+
+// The casing of a value which appears most often, ties going to the first one seen
+#[derive(Default)]
+pub struct CasingTally {
+	counts: Vec<(String, usize)>,
+}
+
+impl CasingTally {
+	pub fn add(&mut self, value: &str) {
+		match self.counts.iter_mut().find(|(v, _)| v == value) {
+			Some((_, count)) => *count += 1,
+			None => self.counts.push((value.to_owned(), 1)),
+		}
+	}
+
+	pub fn most_common(&self) -> Option<String> {
+		let mut most_common: Option<&(String, usize)> = None;
+		for entry in &self.counts {
+			if most_common.map_or(true, |(_, count)| entry.1 > *count) {
+				most_common = Some(entry);
+			}
+		}
+		most_common.map(|(value, _)| value.to_owned())
+	}
+}
+
+// Tags of a directory, taken from the songs within it
+pub struct ConsolidatedTags {
+	pub album: Option<String>,
+	pub artist: Option<String>,
+	pub year: Option<i32>,
+	pub original_date: Option<String>,
+	pub genre: Option<String>,
+	pub composer: Option<String>,
+	pub sort_album: Option<String>,
+	pub sort_artist: Option<String>,
+	pub musicbrainz_album_id: Option<String>,
+	pub musicbrainz_artist_id: Option<String>,
+	pub song_count: i32,
+	pub total_duration: i32,
+}
+
+// Gathers the tags of the songs within a directory. Values which are not consistent across the
+// songs are left out of the directory.
+#[derive(Default)]
+pub struct DirectoryTags {
+	album: Option<String>,
+	year: Option<i32>,
+	original_date: Option<String>,
+	artist_set: Option<BTreeSet<String>>,
+	genre: Option<String>,
+	composer: Option<String>,
+	musicbrainz_album_id: Option<String>,
+	musicbrainz_artist_id: Option<String>,
+	sort_album: Option<String>,
+	sort_artist: Option<String>,
+	is_compilation: bool,
+	tag_values: Option<DirectoryTagValues>, // Only kept when conflicts are reported
+	album_casings: CasingTally,
+	artist_casings: CasingTally,
+	inconsistent_album: bool,
+	inconsistent_year: bool,
+	inconsistent_original_date: bool,
+	inconsistent_artist: bool,
+	inconsistent_genre: bool,
+	inconsistent_composer: bool,
+	inconsistent_musicbrainz_album_id: bool,
+	inconsistent_musicbrainz_artist_id: bool,
+	song_count: i32,
+	total_duration: i32,
+}
+
+impl DirectoryTags {
+	pub fn new(report_conflicts: bool) -> DirectoryTags {
+		DirectoryTags {
+			tag_values: if report_conflicts {
+				Some(DirectoryTagValues::default())
+			} else {
+				None
+			},
+			..Default::default()
+		}
+	}
+
+	pub fn add_song(&mut self, tags: &SongTags, conventions: &TagConventions) {
+		self.song_count += 1;
+		self.total_duration += tags.duration.unwrap_or(0) as i32;
+
+		if let Some(values) = &mut self.tag_values {
+			values.add_song(tags);
+		}
+
+		if tags.year.is_some() {
+			self.inconsistent_year |= self.year.is_some() && self.year != tags.year;
+			self.year = tags.year;
+		}
+
+		if let Some(date) = &tags.original_date {
+			match &self.original_date {
+				Some(directory_date) => match most_precise_date(directory_date, date) {
+					Some(precise_date) => self.original_date = Some(precise_date.to_owned()),
+					None => self.inconsistent_original_date = true,
+				},
+				None => self.original_date = Some(date.clone()),
+			}
+		}
+
+		// Album and artist names which only differ by case are consistent
+		if let Some(album) = &tags.album {
+			self.inconsistent_album |= self
+				.album
+				.as_ref()
+				.map_or(false, |a: &String| a.to_lowercase() != album.to_lowercase());
+			self.album_casings.add(album);
+			self.album = tags.album.as_ref().cloned();
+			self.sort_album = conventions.sort_value(&tags.sort_album, &tags.album);
+		}
+
+		// Artists are compared as sets so that "A; B" and "B; A" are consistent
+		// Sort tags only describe the song artist, not the album artist
+		let (song_artist, song_artist_set, song_sort_artist) = match &tags.album_artist {
+			Some(album_artist) => (
+				tags.album_artist.as_ref(),
+				metadata::split_artist(album_artist, &conventions.artist_separator)
+					.into_iter()
+					.map(|a| a.to_lowercase())
+					.collect::<BTreeSet<_>>(),
+				conventions.sort_value(&None, &tags.album_artist),
+			),
+			None => (
+				tags.artist.as_ref(),
+				tags.artists
+					.iter()
+					.map(|a| a.to_lowercase())
+					.collect::<BTreeSet<_>>(),
+				conventions.sort_value(&tags.sort_artist, &tags.artist),
+			),
+		};
+		if let Some(song_artist) = song_artist {
+			self.inconsistent_artist |=
+				self.artist_set.is_some() && self.artist_set.as_ref() != Some(&song_artist_set);
+			self.artist_casings.add(song_artist);
+			self.artist_set = Some(song_artist_set);
+			self.sort_artist = song_sort_artist;
+		}
+
+		self.is_compilation |= tags.compilation
+			|| tags.album_artist.as_ref().map_or(false, |a| {
+				a.eq_ignore_ascii_case(&conventions.various_artists)
+			});
+
+		if tags.genre.is_some() {
+			self.inconsistent_genre |= self.genre.is_some() && self.genre != tags.genre;
+			self.genre = tags.genre.as_ref().cloned();
+		}
+
+		if tags.composer.is_some() {
+			self.inconsistent_composer |= self.composer.is_some() && self.composer != tags.composer;
+			self.composer = tags.composer.as_ref().cloned();
+		}
+
+		if tags.musicbrainz_album_id.is_some() {
+			self.inconsistent_musicbrainz_album_id |= self.musicbrainz_album_id.is_some()
+				&& self.musicbrainz_album_id != tags.musicbrainz_album_id;
+			self.musicbrainz_album_id = tags.musicbrainz_album_id.as_ref().cloned();
+		}
+
+		if tags.musicbrainz_artist_id.is_some() {
+			self.inconsistent_musicbrainz_artist_id |= self.musicbrainz_artist_id.is_some()
+				&& self.musicbrainz_artist_id != tags.musicbrainz_artist_id;
+			self.musicbrainz_artist_id = tags.musicbrainz_artist_id.as_ref().cloned();
+		}
+	}
+
+	// Conflicts are only gathered when they are reported
+	pub fn take_conflicts(&mut self, path: &Path) -> Vec<MetadataConflict> {
+		let values = match &mut self.tag_values {
+			Some(values) => values,
+			None => return Vec::new(),
+		};
+		let conflicting_fields = [
+			(MetadataField::Album, self.inconsistent_album),
+			(
+				MetadataField::Artist,
+				self.inconsistent_artist && !self.is_compilation,
+			),
+			(MetadataField::Year, self.inconsistent_year),
+			(MetadataField::Genre, self.inconsistent_genre),
+			(MetadataField::Composer, self.inconsistent_composer),
+		];
+		conflicting_fields
+			.iter()
+			.filter(|(_, conflicting)| *conflicting)
+			.map(|(field, _)| MetadataConflict {
+				path: path.to_owned(),
+				field: *field,
+				values: values.take(*field),
+			})
+			.collect()
+	}
+
+	pub fn consolidate(self, conventions: &TagConventions) -> ConsolidatedTags {
+		let mut tags = ConsolidatedTags {
+			album: self.album_casings.most_common(),
+			artist: self.artist_casings.most_common(),
+			year: self.year,
+			original_date: self.original_date,
+			genre: self.genre,
+			composer: self.composer,
+			sort_album: self.sort_album,
+			sort_artist: self.sort_artist,
+			musicbrainz_album_id: self.musicbrainz_album_id,
+			musicbrainz_artist_id: self.musicbrainz_artist_id,
+			song_count: self.song_count,
+			total_duration: self.total_duration,
+		};
+		if self.inconsistent_year {
+			tags.year = None;
+		}
+		if self.inconsistent_original_date {
+			tags.original_date = None;
+		}
+		if self.inconsistent_album {
+			tags.album = None;
+			tags.sort_album = None;
+		}
+		if self.inconsistent_artist {
+			tags.artist = None;
+			tags.sort_artist = None;
+		}
+		// Compilations are listed under a single artist rather than their track artists
+		if self.is_compilation {
+			tags.artist = Some(conventions.various_artists.clone());
+			tags.sort_artist = conventions.sort_value(&None, &tags.artist);
+		}
+		if self.inconsistent_genre {
+			tags.genre = None;
+		}
+		if self.inconsistent_composer {
+			tags.composer = None;
+		}
+		if self.inconsistent_musicbrainz_album_id {
+			tags.musicbrainz_album_id = None;
+		}
+		if self.inconsistent_musicbrainz_artist_id || self.is_compilation {
+			tags.musicbrainz_artist_id = None;
+		}
+		tags
+	}
+}
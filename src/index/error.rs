@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum IndexError {
+	#[error("Index update was cancelled")]
+	Cancelled,
+	#[error("Index update was rolled back after a failed write")]
+	RolledBack,
+	#[error("Could not connect to the database: {0}")]
+	DatabaseConnection(anyhow::Error),
+	#[error("Database error: {0}")]
+	Database(#[from] diesel::result::Error),
+	#[error("Could not resolve mount points: {0}")]
+	VFS(anyhow::Error),
+	#[error("Invalid album art pattern: {0}")]
+	AlbumArtPattern(#[from] regex::Error),
+	#[error("Invalid disc folder pattern: {0}")]
+	DiscFolderPattern(#[source] regex::Error),
+	#[error("Invalid index buffer size: {0}")]
+	BufferSize(i32),
+	#[error("Unsupported artwork format: {0}")]
+	ArtworkFormat(String),
+	#[error("Invalid ignore pattern: {0}")]
+	IgnorePattern(#[from] glob::PatternError),
+	#[error("Invalid include pattern: {0}")]
+	IncludePattern(#[source] glob::PatternError),
+	#[error("`{0}` is not inside a mount point")]
+	NotInMountPoint(PathBuf),
+	#[error("Mount point `{0}` is empty or missing")]
+	EmptyMountPoint(PathBuf),
+	#[error("Could not read mount point `{0}`: {1}")]
+	MountPoint(PathBuf, #[source] std::io::Error),
+	#[error("{0}")]
+	Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for IndexError {
+	fn from(error: anyhow::Error) -> Self {
+		match error.downcast::<IndexError>() {
+			Ok(e) => e,
+			Err(e) => IndexError::Other(e),
+		}
+	}
+}
@@ -0,0 +1,540 @@
+use anyhow::*;
+use diesel::prelude::*;
+#[cfg(feature = "profile-index")]
+use flame;
+use glob::{MatchOptions, Pattern};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time;
+
+use crate::db::{mount_points, DB};
+use crate::index::archive::{self, ArchiveEntry};
+use crate::index::cue;
+use crate::index::insert::{NewIndexedPlaylist, NewIndexedPlaylistSong, NewSongLyrics};
+use crate::index::m3u;
+use crate::index::metadata::{self, SongTags};
+use crate::index::IndexError;
+use crate::utils;
+use crate::vfs::VFS;
+
+// Include patterns are globs relative to a mount point, whose wildcards do not cross directories.
+// A path is included when it or one of its parents matches, or when it leads to paths which could.
+#[derive(Clone, Debug)]
+pub struct IncludePattern {
+	pattern: Pattern,
+	components: Vec<Option<Pattern>>, // `None` for `**`
+}
+
+const INCLUDE_MATCH_OPTIONS: MatchOptions = MatchOptions {
+	case_sensitive: true,
+	require_literal_separator: true,
+	require_literal_leading_dot: false,
+};
+
+impl IncludePattern {
+	fn new(pattern: &str) -> Result<IncludePattern, IndexError> {
+		let components = pattern
+			.split('/')
+			.filter(|c| !c.is_empty())
+			.map(|c| match c {
+				"**" => Ok(None),
+				_ => Pattern::new(c).map(Some),
+			})
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(IndexError::IncludePattern)?;
+		Ok(IncludePattern {
+			pattern: Pattern::new(pattern.trim_matches('/')).map_err(IndexError::IncludePattern)?,
+			components,
+		})
+	}
+
+	fn matches(&self, relative_path: &Path) -> bool {
+		relative_path
+			.ancestors()
+			.filter(|a| a.components().next().is_some())
+			.any(|a| self.pattern.matches_path_with(a, INCLUDE_MATCH_OPTIONS))
+	}
+
+	fn leads_to_match(&self, relative_path: &Path) -> bool {
+		for (index, name) in relative_path.iter().enumerate() {
+			match self.components.get(index) {
+				None => return false,
+				Some(None) => return true,
+				Some(Some(pattern)) => {
+					if !pattern.matches_with(&name.to_string_lossy(), INCLUDE_MATCH_OPTIONS) {
+						return false;
+					}
+				}
+			}
+		}
+		true
+	}
+}
+
+fn parse_include_patterns(patterns: &str) -> Result<Vec<IncludePattern>, IndexError> {
+	patterns
+		.split(',')
+		.map(|p| p.trim())
+		.filter(|p| !p.is_empty())
+		.map(IncludePattern::new)
+		.collect()
+}
+
+// Mount points without include patterns index everything
+pub fn get_mount_include_patterns(
+	db: &DB,
+) -> Result<HashMap<String, Vec<IncludePattern>>, IndexError> {
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	mount_points::table
+		.select((mount_points::name, mount_points::include_patterns))
+		.load::<(String, Option<String>)>(&connection)?
+		.into_iter()
+		.filter_map(|(name, patterns)| patterns.map(|p| (name, p)))
+		.map(|(name, patterns)| Ok((name, parse_include_patterns(&patterns)?)))
+		.filter(|r| r.as_ref().map_or(true, |(_, p)| !p.is_empty()))
+		.collect()
+}
+
+pub fn is_excluded_virtual_path(
+	include_patterns: &HashMap<String, Vec<IncludePattern>>,
+	virtual_path: &Path,
+) -> bool {
+	let mut names = virtual_path.iter();
+	let patterns = match names.next().and_then(|n| n.to_str()) {
+		Some(mount_name) => include_patterns.get(mount_name),
+		None => None,
+	};
+	let relative_path = names.as_path();
+	match patterns {
+		Some(patterns) => !patterns
+			.iter()
+			.any(|p| p.matches(relative_path) || p.leads_to_match(relative_path)),
+		None => false,
+	}
+}
+
+pub fn is_excluded_path(
+	vfs: &VFS,
+	include_patterns: &HashMap<String, Vec<IncludePattern>>,
+	path: &Path,
+) -> bool {
+	!include_patterns.is_empty()
+		&& vfs.real_to_virtual(path).map_or(false, |virtual_path| {
+			is_excluded_virtual_path(include_patterns, &virtual_path)
+		})
+}
+
+pub fn has_allowed_extension(extensions: &[String], path: &Path) -> bool {
+	extensions.is_empty()
+		|| path
+			.extension()
+			.and_then(|e| e.to_str())
+			.map_or(false, |e| {
+				extensions.iter().any(|a| a.eq_ignore_ascii_case(e))
+			})
+}
+
+// Files whose tags an update reads, shared with the listings which must agree with it
+pub fn is_song_file(extensions: &[String], path: &Path) -> bool {
+	!is_cue_sheet(path)
+		&& !is_lyrics_file(path)
+		&& !m3u::is_playlist(path)
+		&& has_allowed_extension(extensions, path)
+}
+
+pub fn is_ignored(ignore_patterns: &[Pattern], name: &OsStr) -> bool {
+	name.to_str()
+		.map_or(false, |n| ignore_patterns.iter().any(|p| p.matches(n)))
+}
+
+fn is_cue_sheet(path: &Path) -> bool {
+	path.extension()
+		.and_then(|e| e.to_str())
+		.map_or(false, |e| e.eq_ignore_ascii_case("cue"))
+}
+
+fn is_lyrics_file(path: &Path) -> bool {
+	path.extension()
+		.and_then(|e| e.to_str())
+		.map_or(false, |e| e.eq_ignore_ascii_case("lrc"))
+}
+
+pub fn is_symlink(path: &Path) -> bool {
+	fs::symlink_metadata(utils::fs_path(path)).map_or(false, |m| m.file_type().is_symlink())
+}
+
+// Unlike `date_added`, this does not fall back to the modification time
+pub fn get_creation_time(metadata: &fs::Metadata) -> Option<i64> {
+	metadata
+		.created()
+		.ok()
+		.and_then(|t| t.duration_since(time::UNIX_EPOCH).ok())
+		.map(|d| d.as_secs() as i64)
+}
+
+// Song file whose tags are yet to be read
+pub struct SongPath {
+	pub path: PathBuf,
+	pub file_size: i64,
+	pub mtime: i64,
+	pub created: Option<i64>,
+}
+
+pub struct SongFile {
+	pub path: String,
+	pub tags: SongTags,
+	pub file_size: i64,
+	pub mtime: i64,
+	pub created: Option<i64>,    // Not every file system records creation times
+	pub start_time: Option<i32>, // For tracks within a file described by a cue sheet
+	pub content_hash: Option<String>,
+}
+
+// Files of a directory, sorted by how they are indexed
+#[derive(Default)]
+pub struct DirectoryFiles {
+	pub sub_directories: Vec<PathBuf>,
+	pub songs: Vec<SongPath>,
+	pub unchanged_songs: Vec<String>, // Songs of unchanged directories, which are not read again
+	pub cue_sheets: Vec<PathBuf>,
+	pub archives: Vec<PathBuf>,
+	pub lyrics: HashMap<PathBuf, PathBuf>, // Indexed by path without extension
+	pub playlists: Vec<PathBuf>,
+}
+
+// Settings deciding which files of a directory are indexed
+pub struct FileFilters {
+	pub ignore_patterns: Vec<Pattern>,
+	pub include_patterns: HashMap<String, Vec<IncludePattern>>, // By mount name
+	pub extensions: Vec<String>,
+	pub min_file_size: u64, // Smaller files are left over by interrupted downloads or copies
+	pub nested_mount_points: HashSet<PathBuf>,
+	pub follow_symlinks: bool,
+	pub index_archives: bool,
+}
+
+impl FileFilters {
+	// Only the songs and sub-directories of unchanged directories are listed
+	pub fn classify(
+		&self,
+		vfs: &VFS,
+		dir: &Path,
+		file_names: Vec<OsString>,
+		up_to_date: bool,
+		add_warning: &dyn Fn(&Path, String),
+	) -> DirectoryFiles {
+		let mut files = DirectoryFiles::default();
+		for file_name in file_names {
+			// Entries are joined to the listed path so that they keep its normal form
+			let file_path = dir.join(file_name);
+
+			#[cfg(feature = "profile-index")]
+			let _guard = flame::start_guard(format!(
+				"file: {}",
+				file_path
+					.as_path()
+					.file_name()
+					.map(|s| { s.to_string_lossy().into_owned() })
+					.unwrap_or("Unknown".to_owned())
+			));
+
+			// Ignored directories are not recursed into
+			if file_path
+				.file_name()
+				.map_or(false, |name| is_ignored(&self.ignore_patterns, name))
+			{
+				continue;
+			}
+			if is_excluded_path(vfs, &self.include_patterns, &file_path) {
+				continue;
+			}
+
+			if utils::fs_path(&file_path).is_dir() {
+				if !self.follow_symlinks && is_symlink(&file_path) {
+					continue;
+				}
+				if !self.nested_mount_points.is_empty()
+					&& self
+						.nested_mount_points
+						.contains(&utils::normalize_path(&file_path))
+				{
+					continue;
+				}
+				files.sub_directories.push(file_path.to_path_buf());
+				continue;
+			}
+
+			if !up_to_date && is_cue_sheet(&file_path) {
+				files.cue_sheets.push(file_path);
+				continue;
+			}
+
+			if !up_to_date && is_lyrics_file(&file_path) {
+				files.lyrics.insert(file_path.with_extension(""), file_path);
+				continue;
+			}
+
+			if !up_to_date && m3u::is_playlist(&file_path) {
+				files.playlists.push(file_path);
+				continue;
+			}
+
+			if !up_to_date && self.index_archives && archive::is_archive(&file_path) {
+				files.archives.push(file_path);
+				continue;
+			}
+
+			if !is_song_file(&self.extensions, &file_path) {
+				continue;
+			}
+
+			if !up_to_date {
+				// Files which cannot be stat'd are skipped
+				let file_metadata = match fs::metadata(utils::fs_path(&file_path)) {
+					Ok(m) => m,
+					Err(e) => {
+						add_warning(&file_path, format!("File metadata error: {}", e));
+						continue;
+					}
+				};
+				if file_metadata.len() < self.min_file_size {
+					add_warning(
+						&file_path,
+						format!(
+							"File was skipped as it is smaller than {} bytes",
+							self.min_file_size
+						),
+					);
+					continue;
+				}
+				let file_mtime = match file_metadata
+					.modified()
+					.map_err(Error::new)
+					.and_then(|m| m.duration_since(time::UNIX_EPOCH).map_err(Error::new))
+				{
+					Ok(d) => d.as_secs() as i64,
+					Err(e) => {
+						add_warning(&file_path, format!("File modified time error: {}", e));
+						continue;
+					}
+				};
+				files.songs.push(SongPath {
+					path: file_path,
+					file_size: file_metadata.len() as i64,
+					mtime: file_mtime,
+					created: get_creation_time(&file_metadata),
+				});
+			} else if let Some(file_path_string) = file_path.to_str() {
+				files.unchanged_songs.push(file_path_string.to_owned());
+			}
+		}
+		files
+	}
+}
+
+// Audio files described by a cue sheet are replaced by one song per track. Files whose cue sheet
+// cannot be parsed are indexed as a single song.
+pub fn split_cue_tracks(
+	song_files: Vec<SongFile>,
+	cue_paths: &[PathBuf],
+	artist_separator: &str,
+	add_warning: &dyn Fn(&Path, String),
+) -> Vec<SongFile> {
+	let mut cue_files = HashMap::new();
+	for cue_path in cue_paths {
+		let sheet = match cue::read(cue_path) {
+			Ok(sheet) => sheet,
+			Err(e) => {
+				add_warning(cue_path, format!("Cue sheet read error: {}", e));
+				continue;
+			}
+		};
+		let directory = cue_path.parent().unwrap_or_else(|| Path::new(""));
+		for file in &sheet.files {
+			let audio_path = directory.join(&file.name).to_string_lossy().into_owned();
+			cue_files.insert(audio_path, (sheet.clone(), file.clone()));
+		}
+	}
+
+	// Sidecar cue sheets take precedence over the ones embedded in audio files
+	let mut split_song_files = Vec::new();
+	for mut song_file in song_files {
+		let embedded_cue = song_file.tags.cue_sheet.take().and_then(|sheet| {
+			let file = sheet.files.first()?.clone();
+			Some((sheet, file))
+		});
+		let (sheet, file) = match cue_files.get(&song_file.path).cloned().or(embedded_cue) {
+			Some(cue) => cue,
+			None => {
+				split_song_files.push(song_file);
+				continue;
+			}
+		};
+		let durations = file.track_durations(song_file.tags.duration);
+		for (track, duration) in file.tracks.iter().zip(durations) {
+			// Sort tags of the file do not apply to values taken from the cue sheet
+			let mut tags = song_file.tags.clone();
+			if let Some(title) = &track.title {
+				tags.title = Some(title.to_owned());
+				tags.sort_title = None;
+			}
+			tags.track_number = Some(track.number);
+			if let Some(album) = &sheet.title {
+				tags.album = Some(album.to_owned());
+				tags.sort_album = None;
+			}
+			tags.duration = duration;
+			if let Some(performer) = track
+				.performer
+				.as_ref()
+				.or_else(|| sheet.performer.as_ref())
+			{
+				tags.sort_artist = None;
+				tags.artist = Some(performer.to_owned());
+				tags.artists = vec![performer.to_owned()];
+				tags.split_artists(artist_separator);
+			}
+			split_song_files.push(SongFile {
+				path: song_file.path.clone(),
+				tags,
+				file_size: song_file.file_size,
+				mtime: song_file.mtime,
+				created: song_file.created,
+				start_time: Some(track.start_time() as i32),
+				content_hash: song_file.content_hash.clone(),
+			});
+		}
+	}
+	split_song_files
+}
+
+// Entries of the given archives which are audio files, along with the modification time of their
+// archive
+pub fn list_archive_songs<'a>(
+	archive_paths: &'a [PathBuf],
+	add_warning: &dyn Fn(&Path, String),
+) -> Vec<(&'a Path, i64, ArchiveEntry)> {
+	let mut entries = Vec::new();
+	for archive_path in archive_paths {
+		let mtime = fs::metadata(utils::fs_path(archive_path))
+			.and_then(|m| m.modified())
+			.map_err(Error::new)
+			.and_then(|m| m.duration_since(time::UNIX_EPOCH).map_err(Error::new));
+		let mtime = match mtime {
+			Ok(d) => d.as_secs() as i64,
+			Err(e) => {
+				add_warning(archive_path, format!("File modified time error: {}", e));
+				continue;
+			}
+		};
+		match archive::list(archive_path) {
+			Ok(archive_entries) => entries.extend(
+				archive_entries
+					.into_iter()
+					.filter(|e| utils::get_audio_format(Path::new(&e.name)).is_some())
+					.map(|e| (archive_path.as_path(), mtime, e)),
+			),
+			Err(e) => add_warning(archive_path, format!("Archive read error: {}", e)),
+		}
+	}
+	entries
+}
+
+pub fn read_playlists(
+	vfs: &VFS,
+	dir: &Path,
+	playlist_paths: &[PathBuf],
+	add_warning: &dyn Fn(&Path, String),
+) -> (Vec<NewIndexedPlaylist>, Vec<NewIndexedPlaylistSong>) {
+	let mut playlists = Vec::new();
+	let mut playlist_songs = Vec::new();
+	let dir_string = match dir.to_str() {
+		Some(s) => s,
+		None => return (playlists, playlist_songs),
+	};
+	for playlist_path in playlist_paths {
+		let (path_string, name) = match (
+			playlist_path.to_str(),
+			playlist_path.file_stem().and_then(|s| s.to_str()),
+		) {
+			(Some(path_string), Some(name)) => (path_string, name),
+			_ => continue,
+		};
+		let entries = match m3u::read(playlist_path) {
+			Ok(entries) => entries,
+			Err(e) => {
+				add_warning(playlist_path, format!("Playlist read error: {}", e));
+				continue;
+			}
+		};
+		playlist_songs.extend(entries.iter().enumerate().map(|(i, entry)| {
+			NewIndexedPlaylistSong {
+				playlist_path: path_string.to_owned(),
+				ordering: i as i32,
+				path: resolve_playlist_entry(vfs, dir, entry),
+			}
+		}));
+		playlists.push(NewIndexedPlaylist {
+			path: path_string.to_owned(),
+			parent: dir_string.to_owned(),
+			name: name.to_owned(),
+		});
+	}
+	(playlists, playlist_songs)
+}
+
+// Entries are resolved to the same form as the paths of indexed songs. Besides paths relative to
+// the playlist, they can be absolute paths within a mount point or virtual paths. URLs and entries
+// which cannot be resolved are kept verbatim.
+fn resolve_playlist_entry(vfs: &VFS, dir: &Path, entry: &str) -> String {
+	if entry.contains("://") {
+		return entry.to_owned();
+	}
+	// Playlists written on Windows use backslashes as separators
+	let entry = if cfg!(windows) {
+		entry.to_owned()
+	} else {
+		entry.replace('\\', "/")
+	};
+	let entry_path = Path::new(&entry);
+
+	let resolved = if entry_path.is_absolute() {
+		vfs.real_to_virtual(entry_path)
+			.and_then(|p| vfs.virtual_to_real(&p))
+			.unwrap_or_else(|_| entry_path.to_owned())
+	} else {
+		let relative_path = utils::normalize_path(&dir.join(entry_path));
+		if relative_path.exists() {
+			relative_path
+		} else {
+			vfs.virtual_to_real(entry_path).unwrap_or(relative_path)
+		}
+	};
+	resolved.to_string_lossy().into_owned()
+}
+
+// Lyrics from a sidecar file take precedence over embedded lyrics
+pub fn read_lyrics(
+	song_path: &str,
+	sidecar_path: Option<&PathBuf>,
+	embedded_lyrics: Option<String>,
+	add_warning: &dyn Fn(&Path, String),
+) -> Option<NewSongLyrics> {
+	let sidecar_lyrics = sidecar_path.and_then(|p| match metadata::read_lyrics_file(p) {
+		Ok(lyrics) => Some(lyrics),
+		Err(e) => {
+			add_warning(p, format!("Lyrics read error: {}", e));
+			None
+		}
+	});
+	sidecar_lyrics
+		.or(embedded_lyrics)
+		.filter(|l| !l.trim().is_empty())
+		.map(|lyrics| NewSongLyrics {
+			song_path: song_path.to_owned(),
+			has_synced_lyrics: metadata::is_synced_lyrics(&lyrics) as i32,
+			lyrics,
+		})
+}
@@ -0,0 +1,34 @@
+use std::path::{Path, PathBuf};
+
+use crate::db::DB;
+use crate::{config, vfs};
+
+// Builds a collection of `directory_count` albums holding `song_count` copies of a small audio file
+pub fn generate_collection(name: &str, directory_count: usize, song_count: usize) -> PathBuf {
+	let collection_path = Path::new("test-output").join(format!("{}-collection", name));
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for d in 0..directory_count {
+		let directory_path = collection_path.join(format!("Album {:03}", d));
+		std::fs::create_dir_all(&directory_path).unwrap();
+		for s in 0..song_count {
+			let song_path = directory_path.join(format!("{:02} - Song.mp3", s + 1));
+			std::fs::copy("test-data/formats/sample.mp3", song_path).unwrap();
+		}
+	}
+	collection_path
+}
+
+pub fn mount_collection(db: &DB, collection_path: &Path) {
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(db, &config).unwrap();
+}
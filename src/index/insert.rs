@@ -0,0 +1,977 @@
+use anyhow::*;
+use crossbeam_channel::{Receiver, Sender};
+use diesel::connection::TransactionManager;
+use diesel::prelude::*;
+use log::error;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::MiscSettings;
+use crate::db::{
+	self, directories, index_checkpoint, index_renames, indexed_playlist_songs, indexed_playlists,
+	playlist_songs, song_artists, song_lyrics, songs, DB,
+};
+use crate::index::{IndexError, IndexProgress, PathChanges, UpdateOptions};
+
+// The low memory mode trades indexing speed for a smaller memory footprint: rows are written in
+// smaller transactions, sub-directories are traversed one at a time instead of in parallel, and
+// traversal waits for insertions to catch up instead of queuing rows without bounds. Each directory
+// on the way down still holds the paths of its sub-directories until they are visited, whether the
+// traversal recurses or steps through them, so this part of memory use grows with the depth and
+// width of the collection.
+const LOW_MEMORY_BUFFER_SIZE: usize = 50;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BufferSizes {
+	pub insert: usize, // Insertions in each transaction
+	pub clean: usize,  // Deletions in each transaction
+}
+
+impl BufferSizes {
+	pub fn new(settings: &MiscSettings) -> Result<BufferSizes, IndexError> {
+		let validate = |size: i32| -> Result<usize, IndexError> {
+			if size < 1 {
+				return Err(IndexError::BufferSize(size));
+			}
+			Ok(size as usize)
+		};
+		let mut buffer_sizes = BufferSizes {
+			insert: validate(settings.index_insert_buffer_size)?,
+			clean: validate(settings.index_clean_buffer_size)?,
+		};
+		if settings.index_low_memory != 0 {
+			buffer_sizes.insert = buffer_sizes.insert.min(LOW_MEMORY_BUFFER_SIZE);
+			buffer_sizes.clean = buffer_sizes.clean.min(LOW_MEMORY_BUFFER_SIZE);
+		}
+		Ok(buffer_sizes)
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WriteSettings {
+	pub fast_writes: bool,
+	pub retries: u32, // Additional attempts at writes which fail because the database is locked
+	pub atomic: bool, // All insertions of an update are committed together
+	pub checkpoints: bool,
+	pub detect_renames: bool,
+}
+
+impl WriteSettings {
+	pub fn new(settings: &MiscSettings) -> WriteSettings {
+		WriteSettings {
+			fast_writes: settings.index_fast_writes != 0,
+			retries: settings.index_write_retries.max(0) as u32,
+			atomic: settings.index_atomic != 0,
+			checkpoints: false,
+			detect_renames: settings.index_detect_renames != 0,
+		}
+	}
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "songs"]
+pub struct NewSong {
+	pub path: String,
+	pub parent: String,
+	pub track_number: Option<i32>,
+	pub disc_number: Option<i32>,
+	pub title: Option<String>,
+	pub artist: Option<String>,
+	pub album_artist: Option<String>,
+	pub year: Option<i32>,
+	pub album: Option<String>,
+	pub artwork: Option<String>,
+	pub duration: Option<i32>,
+	pub genre: Option<String>,
+	pub file_size: Option<i64>,
+	pub mtime: Option<i64>,
+	pub replay_gain_track_gain: Option<f32>,
+	pub replay_gain_track_peak: Option<f32>,
+	pub replay_gain_album_gain: Option<f32>,
+	pub replay_gain_album_peak: Option<f32>,
+	pub track_total: Option<i32>,
+	pub disc_total: Option<i32>,
+	pub search_title: Option<String>,
+	pub search_artist: Option<String>,
+	pub start_time: Option<i32>,
+	pub composer: Option<String>,
+	pub conductor: Option<String>,
+	pub content_hash: Option<String>,
+	pub sort_artist: Option<String>,
+	pub sort_album: Option<String>,
+	pub sort_title: Option<String>,
+	pub format: Option<String>,
+	pub bitrate: Option<i32>,
+	pub sample_rate: Option<i32>,
+	pub bits_per_sample: Option<i32>,
+	pub channels: Option<i32>,
+	pub bpm: Option<i32>,
+	pub musical_key: Option<String>,
+	pub mount_name: Option<String>,
+	pub rating: Option<i32>,
+	pub tagged_play_count: Option<i32>,
+	pub disc_subtitle: Option<String>,
+	pub created: Option<i64>,
+	pub isrc: Option<String>,
+	pub musicbrainz_track_id: Option<String>,
+	pub musicbrainz_album_id: Option<String>,
+	pub musicbrainz_artist_id: Option<String>,
+	pub encoder_delay: Option<i32>,
+	pub encoder_padding: Option<i32>,
+	pub original_date: Option<String>,
+	pub link_target: Option<String>,
+	pub album_key: Option<String>,
+	pub lossless: Option<bool>,
+	pub bitrate_mode: Option<String>,
+	pub work: Option<String>,
+	pub movement_name: Option<String>,
+	pub movement_number: Option<i32>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "song_artists"]
+pub struct NewSongArtist {
+	pub song_path: String,
+	pub artist: String,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "song_lyrics"]
+pub struct NewSongLyrics {
+	pub song_path: String,
+	pub lyrics: String,
+	pub has_synced_lyrics: i32,
+}
+
+// Interrupted updates leave a checkpoint of the directories they fully wrote, which the next update
+// skips. The songs of a directory and the directory itself are written by separate insertion
+// threads, which each record their part within the transaction writing it.
+pub const CHECKPOINT_SONGS: &str = "songs";
+pub const CHECKPOINT_DIRECTORY: &str = "directory";
+
+#[derive(Debug, Insertable)]
+#[table_name = "index_checkpoint"]
+struct NewCheckpoint<'a> {
+	path: &'a str,
+	kind: &'a str,
+}
+
+// Each inserted song adds up to two bound parameters when looking up removed songs
+const RENAME_CANDIDATES_CHUNK_SIZE: usize = 400;
+
+// Songs removed while rename detection is enabled are kept aside until the next traversal of the
+// mount points. New songs with the same content take over their identity, so that they keep their
+// id and their place within playlists.
+#[derive(Debug, Insertable, Queryable)]
+#[table_name = "index_renames"]
+struct RemovedSong {
+	song_id: i32,
+	path: String,
+	start_time: Option<i32>,
+	content_hash: Option<String>,
+	file_size: Option<i64>,
+	duration: Option<i32>,
+	title: Option<String>,
+}
+
+// Fields of a written song which tell whether it is one of the removed songs
+#[derive(Debug, Queryable)]
+struct WrittenSong {
+	path: String,
+	start_time: Option<i32>,
+	content_hash: Option<String>,
+	file_size: Option<i64>,
+	duration: Option<i32>,
+	title: Option<String>,
+}
+
+impl From<&NewSong> for WrittenSong {
+	fn from(song: &NewSong) -> WrittenSong {
+		WrittenSong {
+			path: song.path.clone(),
+			start_time: song.start_time,
+			content_hash: song.content_hash.clone(),
+			file_size: song.file_size,
+			duration: song.duration,
+			title: song.title.clone(),
+		}
+	}
+}
+
+impl RemovedSong {
+	// Without content hashes, songs are told apart by their size, duration and title
+	fn is_same_song(&self, song: &WrittenSong) -> bool {
+		if self.start_time != song.start_time {
+			return false;
+		}
+		match (&self.content_hash, &song.content_hash) {
+			(Some(removed_hash), Some(hash)) => removed_hash == hash,
+			_ => {
+				self.file_size.is_some()
+					&& self.duration.is_some()
+					&& self.title.is_some()
+					&& self.file_size == song.file_size
+					&& self.duration == song.duration
+					&& self.title == song.title
+			}
+		}
+	}
+}
+
+pub fn keep_removed_songs(
+	connection: &SqliteConnection,
+	song_paths: &[&String],
+) -> QueryResult<()> {
+	let removed_songs: Vec<RemovedSong> = songs::table
+		.select((
+			songs::id,
+			songs::path,
+			songs::start_time,
+			songs::content_hash,
+			songs::file_size,
+			songs::duration,
+			songs::title,
+		))
+		.filter(songs::path.eq_any(song_paths))
+		.load(connection)?;
+	diesel::replace_into(index_renames::table)
+		.values(&removed_songs)
+		.execute(connection)?;
+	Ok(())
+}
+
+// Merge updates only remove songs once the songs they found are written, which then claim the
+// removed songs they match
+pub fn claim_removed_songs_at(
+	connection: &SqliteConnection,
+	song_paths: &[String],
+) -> QueryResult<()> {
+	let written_songs: Vec<WrittenSong> = songs::table
+		.select((
+			songs::path,
+			songs::start_time,
+			songs::content_hash,
+			songs::file_size,
+			songs::duration,
+			songs::title,
+		))
+		.filter(songs::path.eq_any(song_paths))
+		.filter(songs::deleted_at.is_null())
+		.load(connection)?;
+	claim_removed_songs(connection, &written_songs)
+}
+
+// Inserted songs matching a removed one are given its id, and playlists follow them to their path
+fn claim_removed_songs(connection: &SqliteConnection, entries: &[WrittenSong]) -> QueryResult<()> {
+	// Only the removed songs which can match one of the entries are loaded
+	let mut removed_songs: Vec<RemovedSong> = Vec::new();
+	for chunk in entries.chunks(RENAME_CANDIDATES_CHUNK_SIZE) {
+		let file_sizes = chunk.iter().filter_map(|s| s.file_size).collect::<Vec<_>>();
+		let content_hashes = chunk
+			.iter()
+			.filter_map(|s| s.content_hash.as_ref())
+			.collect::<Vec<_>>();
+		let candidates: Vec<RemovedSong> = index_renames::table
+			.filter(
+				index_renames::file_size
+					.eq_any(file_sizes)
+					.or(index_renames::content_hash.eq_any(content_hashes)),
+			)
+			.load(connection)?;
+		for candidate in candidates {
+			if !removed_songs.iter().any(|r| r.song_id == candidate.song_id) {
+				removed_songs.push(candidate);
+			}
+		}
+	}
+	removed_songs.sort_by_key(|r| r.song_id);
+	for song in entries {
+		let index = match removed_songs.iter().position(|r| r.is_same_song(song)) {
+			Some(index) => index,
+			None => continue,
+		};
+		let removed_song = removed_songs.remove(index);
+		let query = songs::table
+			.select(songs::id)
+			.filter(songs::path.eq(&song.path))
+			.into_boxed();
+		let query = match song.start_time {
+			Some(start_time) => query.filter(songs::start_time.eq(start_time)),
+			None => query.filter(songs::start_time.is_null()),
+		};
+		let song_id: i32 = query.first(connection)?;
+		if song_id != removed_song.song_id {
+			take_song_id(connection, song_id, removed_song.song_id)?;
+		}
+		if removed_song.path != song.path {
+			diesel::update(
+				playlist_songs::table.filter(playlist_songs::path.eq(&removed_song.path)),
+			)
+			.set(playlist_songs::path.eq(&song.path))
+			.execute(connection)?;
+		}
+		diesel::delete(index_renames::table.find(removed_song.song_id)).execute(connection)?;
+	}
+	Ok(())
+}
+
+// Moves a song to the id of a removed one. Soft deleted songs still hold their id, while the ids
+// of deleted rows can be given to songs inserted since, which are moved out of the way.
+fn take_song_id(connection: &SqliteConnection, song_id: i32, removed_id: i32) -> QueryResult<()> {
+	let holder: Option<Option<i32>> = songs::table
+		.find(removed_id)
+		.select(songs::deleted_at)
+		.first(connection)
+		.optional()?;
+	match holder {
+		None => (),
+		Some(Some(_)) => {
+			diesel::delete(songs::table.find(removed_id)).execute(connection)?;
+		}
+		Some(None) => {
+			let max_id: Option<i32> = songs::table
+				.select(diesel::dsl::max(songs::id))
+				.first(connection)?;
+			let temporary_id = max_id.unwrap_or(0) + 1;
+			diesel::update(songs::table.find(removed_id))
+				.set(songs::id.eq(temporary_id))
+				.execute(connection)?;
+			diesel::update(songs::table.find(song_id))
+				.set(songs::id.eq(removed_id))
+				.execute(connection)?;
+			diesel::update(songs::table.find(temporary_id))
+				.set(songs::id.eq(song_id))
+				.execute(connection)?;
+			return Ok(());
+		}
+	}
+	diesel::update(songs::table.find(song_id))
+		.set(songs::id.eq(removed_id))
+		.execute(connection)?;
+	Ok(())
+}
+
+// The songs of a directory are followed by its checkpoint once they were all sent
+pub enum SongInsertion {
+	Song(NewSong, Vec<NewSongArtist>, Option<NewSongLyrics>),
+	Checkpoint(String),
+}
+
+// Songs along with the rows of other tables which describe them
+#[derive(Default)]
+struct PendingSongs {
+	songs: Vec<NewSong>,
+	artists: Vec<NewSongArtist>,
+	lyrics: Vec<NewSongLyrics>,
+	checkpoints: Vec<String>,
+}
+
+impl PendingSongs {
+	fn push(&mut self, song: NewSong, artists: Vec<NewSongArtist>, lyrics: Option<NewSongLyrics>) {
+		self.songs.push(song);
+		self.artists.extend(artists);
+		self.lyrics.extend(lyrics);
+	}
+
+	fn is_empty(&self) -> bool {
+		self.songs.is_empty() && self.checkpoints.is_empty()
+	}
+
+	fn clear(&mut self) {
+		self.songs.clear();
+		self.artists.clear();
+		self.lyrics.clear();
+		self.checkpoints.clear();
+	}
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "directories"]
+pub struct NewDirectory {
+	pub path: String,
+	pub parent: Option<String>,
+	pub artist: Option<String>,
+	pub year: Option<i32>,
+	pub album: Option<String>,
+	pub artwork: Option<String>,
+	pub date_added: i32,
+	pub last_indexed: Option<i32>,
+	pub genre: Option<String>,
+	pub song_count: i32,
+	pub total_duration: i32,
+	pub composer: Option<String>,
+	pub sort_artist: Option<String>,
+	pub sort_album: Option<String>,
+	pub dir_fingerprint: Option<String>,
+	pub depth: Option<i32>,
+	pub ancestors: Option<String>,
+	pub mount_name: Option<String>,
+	pub created: Option<i32>,
+	pub modified: Option<i32>,
+	pub musicbrainz_album_id: Option<String>,
+	pub musicbrainz_artist_id: Option<String>,
+	pub kind: Option<String>,
+	pub original_date: Option<String>,
+	pub album_key: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "indexed_playlists"]
+pub struct NewIndexedPlaylist {
+	pub path: String,
+	pub parent: String,
+	pub name: String,
+}
+
+// Entries are flagged as missing once all songs have been inserted
+#[derive(Debug, Insertable)]
+#[table_name = "indexed_playlist_songs"]
+pub struct NewIndexedPlaylistSong {
+	pub playlist_path: String,
+	pub ordering: i32,
+	pub path: String,
+}
+
+// Directories along with the playlist files found within them
+#[derive(Default)]
+struct PendingDirectories {
+	directories: Vec<NewDirectory>,
+	playlists: Vec<NewIndexedPlaylist>,
+	playlist_songs: Vec<NewIndexedPlaylistSong>,
+}
+
+impl PendingDirectories {
+	fn push(
+		&mut self,
+		directory: NewDirectory,
+		playlists: Vec<NewIndexedPlaylist>,
+		playlist_songs: Vec<NewIndexedPlaylistSong>,
+	) {
+		self.directories.push(directory);
+		self.playlists.extend(playlists);
+		self.playlist_songs.extend(playlist_songs);
+	}
+
+	fn clear(&mut self) {
+		self.directories.clear();
+		self.playlists.clear();
+		self.playlist_songs.clear();
+	}
+}
+
+pub struct ProgressReporter {
+	sender: Sender<IndexProgress>,
+	directories_visited: AtomicUsize,
+	songs_inserted: AtomicUsize,
+	songs_total: usize,
+	current_directory: Mutex<String>,
+}
+
+impl ProgressReporter {
+	pub fn new(sender: Sender<IndexProgress>, songs_total: usize) -> ProgressReporter {
+		ProgressReporter {
+			sender,
+			directories_visited: AtomicUsize::new(0),
+			songs_inserted: AtomicUsize::new(0),
+			songs_total,
+			current_directory: Mutex::new(String::new()),
+		}
+	}
+
+	pub fn visit_directory(&self, path: &str) {
+		self.directories_visited.fetch_add(1, Ordering::Relaxed);
+		*self.current_directory.lock().unwrap() = path.to_owned();
+		self.report();
+	}
+
+	pub fn insert_songs(&self, count: usize) {
+		self.songs_inserted.fetch_add(count, Ordering::Relaxed);
+		self.report();
+	}
+
+	// Songs of unchanged directories are not written again, but count towards the total
+	pub fn skip_songs(&self, count: usize) {
+		self.insert_songs(count);
+	}
+
+	fn report(&self) {
+		let progress = IndexProgress {
+			directories_visited: self.directories_visited.load(Ordering::Relaxed),
+			songs_inserted: self.songs_inserted.load(Ordering::Relaxed),
+			songs_total: self.songs_total,
+			current_directory: self.current_directory.lock().unwrap().clone(),
+		};
+		// Nobody listening anymore is not an indexing error
+		let _ = self.sender.send(progress);
+	}
+}
+
+// Tags are read on the traversal threads while dedicated threads insert the rows they produce.
+// Rows waiting to be inserted are bounded, so that the traversal waits for insertions to catch up
+// instead of queuing the whole collection in memory. Low memory mode only queues a single batch.
+const INSERTION_QUEUE_BATCHES: usize = 4;
+
+pub fn insertion_channel<T>(
+	low_memory: bool,
+	buffer_sizes: BufferSizes,
+) -> (Sender<T>, Receiver<T>) {
+	let batches = if low_memory {
+		1
+	} else {
+		INSERTION_QUEUE_BATCHES
+	};
+	crossbeam_channel::bounded(buffer_sizes.insert * batches)
+}
+
+// Write pragmas only apply to the connection they are executed on, so insertions use a dedicated
+// connection which is closed at the end of the update.
+fn connect_for_insertions(db: &DB, fast_writes: bool) -> Result<SqliteConnection> {
+	let connection = db.connect_unpooled()?;
+	db::set_fast_writes(&connection, fast_writes)?;
+	Ok(connection)
+}
+
+// Insertions happen on their own connection, unless the update is atomic. Insertions of an atomic
+// update then share a connection on which a single transaction spans all their writes. Each flush
+// is a nested transaction within it.
+#[derive(Clone)]
+pub struct InsertionConnection {
+	connection: Arc<Mutex<SqliteConnection>>,
+	failed: Arc<AtomicBool>, // A write failed, so the atomic transaction can only be rolled back
+}
+
+impl InsertionConnection {
+	pub fn new(db: &DB, write_settings: WriteSettings) -> Result<InsertionConnection> {
+		let connection = connect_for_insertions(db, write_settings.fast_writes)?;
+		if write_settings.atomic {
+			connection
+				.transaction_manager()
+				.begin_transaction(&connection)?;
+		}
+		Ok(InsertionConnection {
+			connection: Arc::new(Mutex::new(connection)),
+			failed: Arc::new(AtomicBool::new(false)),
+		})
+	}
+
+	pub fn write<T, F>(&self, write: F) -> Option<T>
+	where
+		F: FnOnce(&SqliteConnection) -> QueryResult<T>,
+	{
+		let connection = self.connection.lock().unwrap();
+		match write(&connection) {
+			Ok(result) => Some(result),
+			Err(e) => {
+				error!("Database write error: {}", e);
+				self.failed.store(true, Ordering::Relaxed);
+				None
+			}
+		}
+	}
+
+	// Returns whether the writes were committed
+	pub fn end_atomic(self, commit: bool) -> Result<bool, IndexError> {
+		let connection = self.connection.lock().unwrap();
+		let transaction_manager = connection.transaction_manager();
+		if commit && !self.failed.load(Ordering::Relaxed) {
+			transaction_manager.commit_transaction(&*connection)?;
+			Ok(true)
+		} else {
+			transaction_manager.rollback_transaction(&*connection)?;
+			Ok(false)
+		}
+	}
+}
+
+// Songs are soft deleted by flagging them with the given time instead, in which case their artists
+// and lyrics are kept along with them until they are purged
+pub fn delete_songs(
+	connection: &SqliteConnection,
+	song_paths: &[&String],
+	deleted_at: Option<i32>,
+) -> diesel::QueryResult<usize> {
+	if let Some(deleted_at) = deleted_at {
+		return diesel::update(songs::table.filter(songs::path.eq_any(song_paths)))
+			.set(songs::deleted_at.eq(deleted_at))
+			.execute(connection);
+	}
+	let count =
+		diesel::delete(songs::table.filter(songs::path.eq_any(song_paths))).execute(connection)?;
+	diesel::delete(song_artists::table.filter(song_artists::song_path.eq_any(song_paths)))
+		.execute(connection)?;
+	diesel::delete(song_lyrics::table.filter(song_lyrics::song_path.eq_any(song_paths)))
+		.execute(connection)?;
+	Ok(count)
+}
+
+// Removes the playlists found within the given directories
+pub fn delete_playlists(
+	connection: &SqliteConnection,
+	directory_paths: &[&String],
+) -> diesel::QueryResult<()> {
+	let playlist_paths = indexed_playlists::table
+		.select(indexed_playlists::path)
+		.filter(indexed_playlists::parent.eq_any(directory_paths));
+	diesel::delete(
+		indexed_playlist_songs::table
+			.filter(indexed_playlist_songs::playlist_path.eq_any(playlist_paths)),
+	)
+	.execute(connection)?;
+	diesel::delete(
+		indexed_playlists::table.filter(indexed_playlists::parent.eq_any(directory_paths)),
+	)
+	.execute(connection)?;
+	Ok(())
+}
+
+// Flushes return the number of rows inserted
+fn flush_directories(
+	connection: &SqliteConnection,
+	pending: &PendingDirectories,
+	write_settings: WriteSettings,
+	dry_run: bool,
+) -> QueryResult<usize> {
+	if dry_run {
+		return Ok(0);
+	}
+	let write = || {
+		connection.transaction::<_, diesel::result::Error, _>(|| {
+			// Playlists of a directory are replaced along with it
+			let paths = pending
+				.directories
+				.iter()
+				.map(|d| &d.path)
+				.collect::<Vec<_>>();
+			delete_playlists(connection, &paths)?;
+			let count = diesel::insert_into(directories::table)
+				.values(&pending.directories)
+				.execute(connection)?; // TODO https://github.com/diesel-rs/diesel/issues/1822
+			diesel::insert_into(indexed_playlists::table)
+				.values(&pending.playlists)
+				.execute(connection)?;
+			diesel::insert_into(indexed_playlist_songs::table)
+				.values(&pending.playlist_songs)
+				.execute(connection)?;
+			if write_settings.checkpoints {
+				let checkpoints = paths
+					.iter()
+					.map(|path| NewCheckpoint {
+						path,
+						kind: CHECKPOINT_DIRECTORY,
+					})
+					.collect::<Vec<_>>();
+				diesel::insert_or_ignore_into(index_checkpoint::table)
+					.values(&checkpoints)
+					.execute(connection)?;
+			}
+			Ok(count)
+		})
+	};
+	db::retry_when_busy(write_settings.retries, write)
+}
+
+fn flush_songs(
+	connection: &SqliteConnection,
+	pending: &PendingSongs,
+	buffer_sizes: BufferSizes,
+	progress: &Option<Arc<ProgressReporter>>,
+	cleared_paths: &mut HashSet<String>,
+	write_settings: WriteSettings,
+	dry_run: bool,
+) -> QueryResult<usize> {
+	let entries = &pending.songs;
+	if dry_run {
+		if let Some(progress) = progress {
+			progress.insert_songs(entries.len());
+		}
+		return Ok(0);
+	}
+	let write = || {
+		connection.transaction::<_, diesel::result::Error, _>(|| {
+			// Previous entries are cleared once per file, which can span batches when split by a cue sheet.
+			// This also brings back soft deleted songs which reappeared.
+			let paths = entries
+				.iter()
+				.map(|s| &s.path)
+				.filter(|p| !cleared_paths.contains(*p))
+				.collect::<Vec<_>>();
+			for chunk in paths.chunks(buffer_sizes.clean) {
+				delete_songs(connection, chunk, None)?;
+			}
+			let count = diesel::insert_into(songs::table)
+				.values(entries)
+				.execute(connection)?; // TODO https://github.com/diesel-rs/diesel/issues/1822
+			diesel::insert_into(song_artists::table)
+				.values(&pending.artists)
+				.execute(connection)?;
+			diesel::insert_into(song_lyrics::table)
+				.values(&pending.lyrics)
+				.execute(connection)?;
+			if write_settings.detect_renames {
+				let written_songs = entries.iter().map(WrittenSong::from).collect::<Vec<_>>();
+				claim_removed_songs(connection, &written_songs)?;
+			}
+			if write_settings.checkpoints {
+				let checkpoints = pending
+					.checkpoints
+					.iter()
+					.map(|path| NewCheckpoint {
+						path,
+						kind: CHECKPOINT_SONGS,
+					})
+					.collect::<Vec<_>>();
+				diesel::insert_or_ignore_into(index_checkpoint::table)
+					.values(&checkpoints)
+					.execute(connection)?;
+			}
+			Ok(count)
+		})
+	};
+	// Paths of a rolled back batch still have their previous entries
+	let result = db::retry_when_busy(write_settings.retries, write);
+	if result.is_ok() {
+		cleared_paths.extend(entries.iter().map(|s| s.path.clone()));
+		if let Some(progress) = progress {
+			progress.insert_songs(entries.len());
+		}
+	}
+	result
+}
+
+// Gathers directories into batches of `buffer_sizes.insert`, each written in a single transaction.
+// Pending directories are dropped upon cancellation, as are pending songs in `SongWriter`.
+// Directories which did not make it to the database are fully indexed again by the next incremental
+// update.
+pub struct DirectoryWriter {
+	connection: InsertionConnection,
+	buffer_sizes: BufferSizes,
+	existing_paths: HashSet<String>,
+	write_settings: WriteSettings,
+	options: UpdateOptions,
+	pending: PendingDirectories,
+	added: PathChanges,
+	inserted: usize,
+}
+
+impl DirectoryWriter {
+	pub fn new(
+		connection: InsertionConnection,
+		buffer_sizes: BufferSizes,
+		existing_paths: HashSet<String>,
+		write_settings: WriteSettings,
+		options: UpdateOptions,
+	) -> DirectoryWriter {
+		let mut pending = PendingDirectories::default();
+		pending.directories.reserve_exact(buffer_sizes.insert);
+		DirectoryWriter {
+			connection,
+			buffer_sizes,
+			existing_paths,
+			write_settings,
+			options,
+			pending,
+			added: PathChanges::default(),
+			inserted: 0,
+		}
+	}
+
+	pub fn push(
+		&mut self,
+		directory: NewDirectory,
+		playlists: Vec<NewIndexedPlaylist>,
+		playlist_songs: Vec<NewIndexedPlaylistSong>,
+	) {
+		if self.options.is_cancelled() {
+			self.pending.clear();
+			return;
+		}
+		self.pending.push(directory, playlists, playlist_songs);
+	}
+
+	pub fn is_full(&self) -> bool {
+		self.pending.directories.len() >= self.buffer_sizes.insert
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.pending.directories.is_empty()
+	}
+
+	pub fn added(&self) -> &PathChanges {
+		&self.added
+	}
+
+	pub fn flush(&mut self) {
+		if self.is_empty() || self.options.is_cancelled() {
+			self.pending.clear();
+			return;
+		}
+		let existing_paths = &self.existing_paths;
+		for entry in self
+			.pending
+			.directories
+			.iter()
+			.filter(|d| !existing_paths.contains(&d.path))
+		{
+			self.added.record(&entry.path, self.options.dry_run);
+		}
+		let (pending, write_settings, dry_run) =
+			(&self.pending, self.write_settings, self.options.dry_run);
+		match self
+			.connection
+			.write(|connection| flush_directories(connection, pending, write_settings, dry_run))
+		{
+			Some(count) => self.inserted += count,
+			None => error!("Could not insert new directories in database"),
+		}
+		self.pending.clear();
+	}
+
+	// Returns the directories added and the number of rows inserted
+	pub fn finish(mut self) -> (PathChanges, usize) {
+		self.flush();
+		(self.added, self.inserted)
+	}
+}
+
+// Rows received after a cancellation are discarded until the traversal stops sending, as dropping
+// the receiver would fail the sends it is blocked on
+pub fn insert_directories(
+	receiver: Receiver<(
+		NewDirectory,
+		Vec<NewIndexedPlaylist>,
+		Vec<NewIndexedPlaylistSong>,
+	)>,
+	mut writer: DirectoryWriter,
+) -> (PathChanges, usize) {
+	for (directory, playlists, playlist_songs) in receiver.iter() {
+		writer.push(directory, playlists, playlist_songs);
+		if writer.is_full() {
+			writer.flush();
+		}
+	}
+	writer.finish()
+}
+
+// Gathers songs into batches like `DirectoryWriter` does with directories
+pub struct SongWriter {
+	connection: InsertionConnection,
+	buffer_sizes: BufferSizes,
+	existing_paths: HashSet<String>,
+	progress: Option<Arc<ProgressReporter>>,
+	write_settings: WriteSettings,
+	options: UpdateOptions,
+	pending: PendingSongs,
+	cleared_paths: HashSet<String>,
+	added: PathChanges,
+	inserted: usize,
+}
+
+impl SongWriter {
+	pub fn new(
+		connection: InsertionConnection,
+		buffer_sizes: BufferSizes,
+		existing_paths: HashSet<String>,
+		progress: Option<Arc<ProgressReporter>>,
+		write_settings: WriteSettings,
+		options: UpdateOptions,
+	) -> SongWriter {
+		let mut pending = PendingSongs::default();
+		pending.songs.reserve_exact(buffer_sizes.insert);
+		SongWriter {
+			connection,
+			buffer_sizes,
+			existing_paths,
+			progress,
+			write_settings,
+			options,
+			pending,
+			cleared_paths: HashSet::new(),
+			added: PathChanges::default(),
+			inserted: 0,
+		}
+	}
+
+	pub fn push(&mut self, insertion: SongInsertion) {
+		if self.options.is_cancelled() {
+			self.pending.clear();
+			return;
+		}
+		match insertion {
+			SongInsertion::Song(song, artists, lyrics) => self.pending.push(song, artists, lyrics),
+			SongInsertion::Checkpoint(path) => self.pending.checkpoints.push(path),
+		}
+	}
+
+	pub fn is_full(&self) -> bool {
+		self.pending.songs.len() >= self.buffer_sizes.insert
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.pending.is_empty()
+	}
+
+	pub fn added(&self) -> &PathChanges {
+		&self.added
+	}
+
+	pub fn flush(&mut self) {
+		if self.is_empty() || self.options.is_cancelled() {
+			self.pending.clear();
+			return;
+		}
+		let existing_paths = &self.existing_paths;
+		for entry in self
+			.pending
+			.songs
+			.iter()
+			.filter(|s| !existing_paths.contains(&s.path))
+		{
+			self.added.record(&entry.path, self.options.dry_run);
+		}
+		let SongWriter {
+			connection,
+			buffer_sizes,
+			progress,
+			write_settings,
+			options,
+			pending,
+			cleared_paths,
+			..
+		} = self;
+		match connection.write(|connection| {
+			flush_songs(
+				connection,
+				pending,
+				*buffer_sizes,
+				progress,
+				cleared_paths,
+				*write_settings,
+				options.dry_run,
+			)
+		}) {
+			Some(count) => self.inserted += count,
+			None => error!("Could not insert new songs in database"),
+		}
+		self.pending.clear();
+	}
+
+	// Returns the songs added and the number of rows inserted
+	pub fn finish(mut self) -> (PathChanges, usize) {
+		self.flush();
+		(self.added, self.inserted)
+	}
+}
+
+pub fn insert_songs(
+	receiver: Receiver<SongInsertion>,
+	mut writer: SongWriter,
+) -> (PathChanges, usize) {
+	for insertion in receiver.iter() {
+		writer.push(insertion);
+		if writer.is_full() {
+			writer.flush();
+		}
+	}
+	writer.finish()
+}
@@ -0,0 +1,57 @@
+use anyhow::*;
+use std::fs;
+use std::path::Path;
+
+use crate::utils;
+
+pub fn is_playlist(path: &Path) -> bool {
+	path.extension()
+		.and_then(|e| e.to_str())
+		.map_or(false, |e| {
+			e.eq_ignore_ascii_case("m3u") || e.eq_ignore_ascii_case("m3u8")
+		})
+}
+
+pub fn read(path: &Path) -> Result<Vec<String>> {
+	let content = fs::read(utils::fs_path(path))?;
+	Ok(parse(&String::from_utf8_lossy(&content)))
+}
+
+// Lines starting with `#` are either comments or extended directives such as `#EXTINF`, neither
+// of which reference a track
+pub fn parse(content: &str) -> Vec<String> {
+	content
+		.lines()
+		.map(|line| line.trim_start_matches('\u{feff}').trim())
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(|line| line.to_owned())
+		.collect()
+}
+
+#[test]
+fn test_parse() {
+	let content = "\u{feff}#EXTM3U
+#EXTINF:210,Some Artist - First
+01 - First.mp3
+
+#EXTINF:90,Some Artist - Second
+  ../Other Album/02 - Second.flac
+/music/03 - Third.ogg
+";
+	assert_eq!(
+		parse(content),
+		vec![
+			"01 - First.mp3",
+			"../Other Album/02 - Second.flac",
+			"/music/03 - Third.ogg"
+		]
+	);
+	assert!(parse("#EXTM3U\n").is_empty());
+}
+
+#[test]
+fn test_is_playlist() {
+	assert!(is_playlist(Path::new("music/Mix.m3u")));
+	assert!(is_playlist(Path::new("music/Mix.M3U8")));
+	assert!(!is_playlist(Path::new("music/Mix.cue")));
+}
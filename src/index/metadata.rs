@@ -8,47 +8,359 @@ use mp3_duration;
 use mp4ameta;
 use opus_headers;
 use regex::Regex;
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
+use crate::index::audio::{self, AudioProperties};
+use crate::index::cue::{self, CueFile, CueSheet, CueTrack};
 use crate::utils;
 use crate::utils::AudioFormat;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct SongTags {
 	pub disc_number: Option<u32>,
+	pub disc_total: Option<u32>,
 	pub track_number: Option<u32>,
+	pub track_total: Option<u32>,
 	pub title: Option<String>,
 	pub duration: Option<u32>,
 	pub artist: Option<String>,
 	pub album_artist: Option<String>,
 	pub album: Option<String>,
 	pub year: Option<i32>,
+	pub original_date: Option<String>, // ISO 8601, down to the day when tags are that precise
 	pub has_artwork: bool,
+	pub genre: Option<String>,
+	pub artists: Vec<String>,
+	pub replay_gain_track_gain: Option<f32>,
+	pub replay_gain_track_peak: Option<f32>,
+	pub replay_gain_album_gain: Option<f32>,
+	pub replay_gain_album_peak: Option<f32>,
+	pub composer: Option<String>,
+	pub conductor: Option<String>,
+	pub work: Option<String>, // Classical work the song is a movement of, as in "Symphony No. 5"
+	pub movement_name: Option<String>,
+	pub movement_number: Option<u32>,
+	pub sort_artist: Option<String>,
+	pub sort_album: Option<String>,
+	pub sort_title: Option<String>,
+	pub bpm: Option<i32>,
+	pub musical_key: Option<String>,
+	pub disc_subtitle: Option<String>, // Name of the disc within a set, as in "Bonus Tracks"
+	pub isrc: Option<String>,
+	pub musicbrainz_track_id: Option<String>, // Identifies the recording
+	pub musicbrainz_album_id: Option<String>, // Identifies the release
+	pub musicbrainz_artist_id: Option<String>,
+	pub rating: Option<i32>, // From 1 to 5 stars
+	pub tagged_play_count: Option<i32>,
+	pub lyrics: Option<String>,
+	pub compilation: bool,
+	pub cue_sheet: Option<CueSheet>, // Embedded in the file, describing only that file
+	pub audio: AudioProperties,
+}
+
+impl SongTags {
+	fn text_fields(&mut self) -> Vec<&mut String> {
+		let mut fields: Vec<&mut String> = vec![
+			&mut self.title,
+			&mut self.artist,
+			&mut self.album_artist,
+			&mut self.album,
+			&mut self.genre,
+			&mut self.composer,
+			&mut self.conductor,
+			&mut self.work,
+			&mut self.movement_name,
+			&mut self.sort_artist,
+			&mut self.sort_album,
+			&mut self.sort_title,
+			&mut self.musical_key,
+			&mut self.disc_subtitle,
+			&mut self.lyrics,
+		]
+		.into_iter()
+		.filter_map(Option::as_mut)
+		.collect();
+		fields.extend(self.artists.iter_mut());
+		fields
+	}
+
+	pub fn normalize(&mut self) {
+		for value in self.text_fields() {
+			*value = value.nfc().collect();
+		}
+	}
+
+	// Byte order marks are always removed, while charsets are only detected when asked to
+	pub fn clean_text(&mut self, detect_charsets: bool) {
+		for value in self.text_fields() {
+			if value.starts_with('\u{feff}') {
+				*value = value.trim_start_matches('\u{feff}').to_owned();
+			}
+			if detect_charsets {
+				if let Some(repaired) = repair_charset(value) {
+					*value = repaired;
+				}
+			}
+		}
+	}
+
+	pub fn split_artists(&mut self, separator: &str) {
+		let mut artists: Vec<String> = Vec::new();
+		for artist in self.artists.iter().flat_map(|a| split_artist(a, separator)) {
+			if !artists.contains(&artist) {
+				artists.push(artist);
+			}
+		}
+		self.artists = artists;
+	}
+}
+
+// Tags written in another charset than the one they declare are often read as Latin-1, which
+// yields mojibake. Such text is decoded again from its original bytes: as UTF-8 when these are
+// valid UTF-8, or else as Shift-JIS or Windows-1252 when they contain C1 control codes, which
+// genuine Latin-1 text does not use. Returns `None` for text which looks correctly decoded.
+pub fn repair_charset(text: &str) -> Option<String> {
+	if text.is_ascii() || text.chars().any(|c| c as u32 > 0xFF) {
+		return None;
+	}
+	let bytes: Vec<u8> = text.chars().map(|c| c as u8).collect();
+	if let Ok(utf8) = std::str::from_utf8(&bytes) {
+		return Some(utf8.to_owned());
+	}
+	if !bytes.iter().any(|b| (0x80..0xA0).contains(b)) {
+		return None;
+	}
+
+	// Windows-1252 text is mostly ASCII with the odd punctuation mark, which could also pass for
+	// a double byte Shift-JIS character
+	let (shift_jis, _, errors) = encoding_rs::SHIFT_JIS.decode(&bytes);
+	let mostly_non_ascii = bytes.iter().filter(|b| !b.is_ascii()).count() * 2 >= bytes.len();
+	if !errors && mostly_non_ascii && shift_jis.chars().all(|c| c.is_ascii() || is_japanese(c)) {
+		return Some(shift_jis.into_owned());
+	}
+	let (windows_1252, _, _) = encoding_rs::WINDOWS_1252.decode(&bytes);
+	Some(windows_1252.into_owned())
+}
+
+fn is_japanese(c: char) -> bool {
+	matches!(c,
+		'\u{3000}'..='\u{30FF}' // Punctuation, hiragana and katakana
+		| '\u{4E00}'..='\u{9FFF}' // Kanji
+		| '\u{FF00}'..='\u{FFEF}' // Full width and half width forms
+	)
+}
+
+// Lowercase with diacritics removed from latin letters, for accent-insensitive matching
+pub fn fold_search_text(text: &str) -> String {
+	text.nfc()
+		.map(|c| match std::iter::once(c).nfd().next() {
+			Some(base) if base.is_ascii() => base,
+			_ => c,
+		})
+		.filter(|c| !is_combining_mark(*c))
+		.flat_map(|c| c.to_lowercase())
+		.collect()
+}
+
+// Songs and directories of the same album share this key, whatever the case, spacing or accents
+// of their tags. Whitespace is collapsed, which leaves tabs free to separate the two parts.
+pub fn album_key(album_artist: Option<&str>, album: Option<&str>) -> Option<String> {
+	let normalize = |text: &str| {
+		let folded = fold_search_text(text);
+		folded.split_whitespace().collect::<Vec<_>>().join(" ")
+	};
+	let album_artist = normalize(album_artist?);
+	let album = normalize(album?);
+	if album_artist.is_empty() || album.is_empty() {
+		return None;
+	}
+	Some(format!("{}\t{}", album_artist, album))
+}
+
+// Folded text with the first leading article removed, as in "beatles" for "The Beatles".
+// Articles ending with an apostrophe are elided, as in "L'Impératrice".
+pub fn sort_key(text: &str, articles: &[String]) -> String {
+	let folded = fold_search_text(text.trim());
+	for article in articles {
+		let article = fold_search_text(article);
+		if let Some(rest) = folded.strip_prefix(&article) {
+			let rest = if article.ends_with('\'') {
+				rest
+			} else if rest.starts_with(char::is_whitespace) {
+				rest.trim_start()
+			} else {
+				continue;
+			};
+			if !rest.is_empty() {
+				return rest.to_owned();
+			}
+		}
+	}
+	folded
+}
+
+pub fn read_lyrics_file(path: &Path) -> Result<String> {
+	let content = fs::read(utils::fs_path(path))?;
+	Ok(String::from_utf8_lossy(&content)
+		.trim_start_matches('\u{feff}')
+		.to_owned())
+}
+
+// Synced lyrics use the LRC format, where lines start with a timestamp such as `[01:23.45]`
+pub fn is_synced_lyrics(lyrics: &str) -> bool {
+	let timestamp = Regex::new(r"^\[\d+:\d{2}([.:]\d+)?\]").unwrap();
+	lyrics.lines().any(|l| timestamp.is_match(l.trim_start()))
+}
+
+pub fn split_artist(artist: &str, separator: &str) -> Vec<String> {
+	if separator.is_empty() {
+		return vec![artist.trim().to_owned()];
+	}
+	artist
+		.split(separator)
+		.map(|a| a.trim())
+		.filter(|a| !a.is_empty())
+		.map(|a| a.to_owned())
+		.collect()
+}
+
+// Reads the tags of the files found while indexing. Files which are not songs have no tags.
+pub trait MetadataReader: Send + Sync {
+	fn read(&self, path: &Path) -> Result<Option<SongTags>>;
+}
+
+impl fmt::Debug for dyn MetadataReader {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "MetadataReader")
+	}
+}
+
+// Reads the formats supported by Polaris, which other readers can fall back to
+pub struct DefaultMetadataReader;
+
+impl MetadataReader for DefaultMetadataReader {
+	fn read(&self, path: &Path) -> Result<Option<SongTags>> {
+		read(path)
+	}
+}
+
+// Adjusts the tags read from each file before they are indexed, such as to apply naming rules
+#[derive(Clone)]
+pub struct TagTransform(pub Arc<dyn Fn(&mut SongTags) + Send + Sync>);
+
+impl TagTransform {
+	pub fn apply(&self, tags: &mut SongTags) {
+		(self.0)(tags)
+	}
+}
+
+impl fmt::Debug for TagTransform {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "TagTransform")
+	}
 }
 
 #[cfg_attr(feature = "profile-index", flame)]
-pub fn read(path: &Path) -> Option<SongTags> {
+pub fn read(path: &Path) -> Result<Option<SongTags>> {
+	let path = &utils::fs_path(path);
 	let data = match utils::get_audio_format(path) {
-		Some(AudioFormat::APE) => Some(read_ape(path)),
+		Some(AudioFormat::APE) => Some(read_ape(path).map(|tags| SongTags {
+			audio: audio::read_ape(path).unwrap_or_default(),
+			..tags
+		})),
 		Some(AudioFormat::FLAC) => Some(read_flac(path)),
 		Some(AudioFormat::MP3) => Some(read_id3(path)),
 		Some(AudioFormat::MP4) => Some(read_mp4(path)),
-		Some(AudioFormat::MPC) => Some(read_ape(path)),
+		Some(AudioFormat::MPC) => Some(read_ape(path).map(|tags| {
+			let (audio, duration) = audio::read_musepack(path).map_or_else(
+				|_| {
+					let audio = AudioProperties {
+						format: Some("MPC".to_owned()),
+						lossless: Some(false),
+						..Default::default()
+					};
+					(audio, None)
+				},
+				|(audio, duration)| (audio, Some(duration as u32)),
+			);
+			SongTags {
+				duration,
+				audio,
+				..tags
+			}
+		})),
 		Some(AudioFormat::OGG) => Some(read_vorbis(path)),
 		Some(AudioFormat::OPUS) => Some(read_opus(path)),
+		Some(AudioFormat::WAVPACK) => Some(read_ape(path).map(|tags| {
+			let (audio, duration) = audio::read_wavpack(path)
+				.map_or((AudioProperties::default(), None), |(audio, duration)| {
+					(audio, Some(duration as u32))
+				});
+			SongTags {
+				duration,
+				audio,
+				..tags
+			}
+		})),
 		None => None,
 	};
+	data.transpose()
+}
+
+#[cfg_attr(feature = "profile-index", flame)]
+pub fn read_artwork(path: &Path) -> Option<Vec<u8>> {
+	let path = &utils::fs_path(path);
+	let data = match utils::get_audio_format(path) {
+		Some(AudioFormat::FLAC) => read_flac_artwork(path),
+		Some(AudioFormat::MP3) => read_id3_artwork(path),
+		Some(AudioFormat::MP4) => read_mp4_artwork(path),
+		_ => Ok(None),
+	};
 	match data {
-		Some(Ok(d)) => Some(d),
-		Some(Err(e)) => {
-			error!("Error while reading file metadata for '{:?}': {}", path, e);
+		Ok(d) => d,
+		Err(e) => {
+			error!(
+				"Error while reading embedded artwork for '{:?}': {}",
+				path, e
+			);
 			None
 		}
-		None => None,
 	}
 }
 
+fn read_id3_artwork(path: &Path) -> Result<Option<Vec<u8>>> {
+	let tag = id3::Tag::read_from_path(path)?;
+	let front_cover = tag
+		.pictures()
+		.find(|p| p.picture_type == id3::frame::PictureType::CoverFront);
+	Ok(front_cover
+		.or_else(|| tag.pictures().next())
+		.map(|p| p.data.clone()))
+}
+
+fn read_flac_artwork(path: &Path) -> Result<Option<Vec<u8>>> {
+	let tag = metaflac::Tag::read_from_path(path)?;
+	let front_cover = tag
+		.pictures()
+		.find(|p| p.picture_type == metaflac::block::PictureType::CoverFront);
+	Ok(front_cover
+		.or_else(|| tag.pictures().next())
+		.map(|p| p.data.clone()))
+}
+
+fn read_mp4_artwork(path: &Path) -> Result<Option<Vec<u8>>> {
+	let tag = mp4ameta::Tag::read_from_path(path)?;
+	Ok(tag
+		.artwork()
+		.and_then(|d| d.image_data())
+		.map(|d| d.to_vec()))
+}
+
 #[cfg_attr(feature = "profile-index", flame)]
 fn read_id3(path: &Path) -> Result<SongTags> {
 	let tag = {
@@ -65,26 +377,64 @@ fn read_id3(path: &Path) -> Result<SongTags> {
 			}
 		}?
 	};
-	let duration = {
+	let exact_duration = {
 		#[cfg(feature = "profile-index")]
 		let _guard = flame::start_guard("mp3_duration");
-		mp3_duration::from_path(&path)
-			.map(|d| d.as_secs() as u32)
-			.ok()
+		mp3_duration::from_path(&path).ok()
 	};
+	let duration = exact_duration.map(|d| d.as_secs() as u32);
+	let mut audio =
+		audio::read_mp3(path, exact_duration.map(|d| d.as_secs_f64())).unwrap_or_default();
+	// Gapless information written by iTunes takes precedence over the LAME header
+	if let Some((delay, padding)) = read_id3_itunes_smpb(&tag) {
+		audio.encoder_delay = Some(delay);
+		audio.encoder_padding = Some(padding);
+	}
 
 	let artist = tag.artist().map(|s| s.to_string());
+	// ID3v2.4 separates multiple values with null characters
+	let artists = tag
+		.artist()
+		.map(|s| s.split('\0').map(|a| a.to_owned()).collect())
+		.unwrap_or_default();
 	let album_artist = tag.album_artist().map(|s| s.to_string());
 	let album = tag.album().map(|s| s.to_string());
 	let title = tag.title().map(|s| s.to_string());
 	let disc_number = tag.disc();
+	let disc_total = tag.total_discs();
 	let track_number = tag.track();
+	let track_total = tag.total_tracks();
 	let year = tag
 		.year()
 		.map(|y| y as i32)
 		.or_else(|| tag.date_released().and_then(|d| Some(d.year)))
 		.or_else(|| tag.date_recorded().and_then(|d| Some(d.year)));
+	let original_date = tag
+		.date_recorded()
+		.or_else(|| tag.date_released())
+		.map(|d| format_id3_timestamp(&d))
+		.or_else(|| year.map(|y| y.to_string()));
 	let has_artwork = tag.pictures().count() > 0;
+	let genre = tag.genre().map(|s| s.to_string());
+	let composer = read_id3_text(&tag, "TCOM");
+	let conductor = read_id3_text(&tag, "TPE3");
+	// iTunes keeps works in the frame other taggers use for groupings
+	let work = read_id3_extended_text(&tag, "WORK").or_else(|| read_id3_text(&tag, "TIT1"));
+	let movement_name = read_id3_unknown_text(&tag, "MVNM");
+	let movement_number = read_id3_unknown_text(&tag, "MVIN").and_then(|v| parse_x_of_y(&v).0);
+	let sort_artist = read_id3_text(&tag, "TSOP");
+	let sort_album = read_id3_text(&tag, "TSOA");
+	let sort_title = read_id3_text(&tag, "TSOT");
+	let bpm = read_id3_text(&tag, "TBPM").and_then(|v| parse_bpm(&v));
+	let musical_key = read_id3_text(&tag, "TKEY");
+	let disc_subtitle = read_id3_text(&tag, "TSST");
+	let isrc = read_id3_text(&tag, "TSRC");
+	let musicbrainz_track_id = read_id3_unique_file_id(&tag, MUSICBRAINZ_UFID_OWNER);
+	let musicbrainz_album_id = read_id3_extended_text(&tag, "MusicBrainz Album Id");
+	let musicbrainz_artist_id = read_id3_extended_text(&tag, "MusicBrainz Artist Id");
+	let (rating, tagged_play_count) = read_id3_popularimeter(&tag);
+	let lyrics = tag.lyrics().next().map(|l| l.text.clone());
+	let compilation = read_id3_text(&tag, "TCMP").map_or(false, |v| parse_flag(&v));
 
 	Ok(SongTags {
 		artist,
@@ -93,12 +443,165 @@ fn read_id3(path: &Path) -> Result<SongTags> {
 		title,
 		duration,
 		disc_number,
+		disc_total,
 		track_number,
+		track_total,
 		year,
+		original_date,
 		has_artwork,
+		genre,
+		artists,
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer,
+		conductor,
+		work,
+		movement_name,
+		movement_number,
+		sort_artist,
+		sort_album,
+		sort_title,
+		bpm,
+		musical_key,
+		disc_subtitle,
+		isrc,
+		musicbrainz_track_id,
+		musicbrainz_album_id,
+		musicbrainz_artist_id,
+		rating,
+		tagged_play_count,
+		lyrics,
+		compilation,
+		cue_sheet: None,
+		audio,
 	})
 }
 
+// Players identify their own popularimeter frames by an email address, the first one is used
+fn read_id3_popularimeter(tag: &id3::Tag) -> (Option<i32>, Option<i32>) {
+	tag.frames()
+		.filter(|f| f.id() == "POPM")
+		.find_map(|f| match f.content() {
+			id3::Content::Unknown(data) => parse_popularimeter(data),
+			_ => None,
+		})
+		.unwrap_or_default()
+}
+
+// Popularimeter frames hold a null terminated email address, a rating from 1 to 255 (0 meaning
+// unrated) and an optional play counter of four bytes or more. Ratings are converted to stars
+// following the values written by Windows Media Player: 1, 64, 128, 196 and 255.
+fn parse_popularimeter(data: &[u8]) -> Option<(Option<i32>, Option<i32>)> {
+	let email_end = data.iter().position(|b| *b == 0)?;
+	let (rating, counter) = data[email_end + 1..].split_first()?;
+	let rating = match rating {
+		0 => None,
+		1..=63 => Some(1),
+		64..=127 => Some(2),
+		128..=195 => Some(3),
+		196..=254 => Some(4),
+		255 => Some(5),
+	};
+	let play_count = if counter.is_empty() {
+		None
+	} else {
+		let count = counter.iter().fold(0u64, |count, b| {
+			count.saturating_mul(256).saturating_add(*b as u64)
+		});
+		Some(count.min(i32::MAX as u64) as i32)
+	};
+	Some((rating, play_count))
+}
+
+fn read_id3_text(tag: &id3::Tag, frame_id: &str) -> Option<String> {
+	tag.get(frame_id)
+		.and_then(|f| f.content().text())
+		.map(|s| s.to_string())
+}
+
+// Text frames which the tag reader does not know of are left undecoded, behind the byte giving their
+// encoding
+fn read_id3_unknown_text(tag: &id3::Tag, frame_id: &str) -> Option<String> {
+	let data = match tag.get(frame_id)?.content() {
+		id3::Content::Unknown(data) => data,
+		content => return content.text().map(|s| s.to_owned()),
+	};
+	let (encoding, text) = data.split_first()?;
+	let utf16 = |bytes: &[u8], big_endian: bool| {
+		let units = bytes
+			.chunks_exact(2)
+			.map(|c| match big_endian {
+				true => u16::from_be_bytes([c[0], c[1]]),
+				false => u16::from_le_bytes([c[0], c[1]]),
+			})
+			.collect::<Vec<_>>();
+		String::from_utf16_lossy(&units)
+	};
+	let value = match (encoding, text) {
+		(0, _) => text.iter().map(|b| *b as char).collect(),
+		(1, [0xfe, 0xff, rest @ ..]) => utf16(rest, true),
+		(1, [0xff, 0xfe, rest @ ..]) | (1, rest) => utf16(rest, false),
+		(2, _) => utf16(text, true),
+		_ => String::from_utf8_lossy(text).into_owned(),
+	};
+	Some(value.trim_end_matches('\0').to_owned()).filter(|v| !v.is_empty())
+}
+
+// User defined text frames (TXXX) are told apart by their description
+fn read_id3_extended_text(tag: &id3::Tag, description: &str) -> Option<String> {
+	tag.extended_texts()
+		.find(|t| t.description.eq_ignore_ascii_case(description))
+		.map(|t| t.value.clone())
+}
+
+// iTunes stores gapless information in a comment, which some taggers convert to a TXXX frame
+fn read_id3_itunes_smpb(tag: &id3::Tag) -> Option<(u32, u32)> {
+	tag.comments()
+		.find(|c| c.description == ITUNES_SMPB)
+		.map(|c| c.text.clone())
+		.or_else(|| read_id3_extended_text(tag, ITUNES_SMPB))
+		.and_then(|v| parse_itunes_smpb(&v))
+}
+
+const ITUNES_SMPB: &str = "iTunSMPB";
+
+// Fields are hexadecimal numbers, of which the second and third are the encoder delay and padding
+// as in " 00000000 00000210 000003C4 0000000000AB0C3C ...". Malformed values are ignored.
+fn parse_itunes_smpb(value: &str) -> Option<(u32, u32)> {
+	let mut fields = value
+		.split_whitespace()
+		.skip(1)
+		.map(|f| u32::from_str_radix(f, 16).ok());
+	let delay = fields.next()??;
+	let padding = fields.next()??;
+	Some((delay, padding))
+}
+
+const MUSICBRAINZ_UFID_OWNER: &str = "http://musicbrainz.org";
+
+// Unique file identifier frames (UFID) hold a null terminated owner followed by the identifier
+fn read_id3_unique_file_id(tag: &id3::Tag, owner: &str) -> Option<String> {
+	tag.frames()
+		.filter(|f| f.id() == "UFID")
+		.find_map(|f| match f.content() {
+			id3::Content::Unknown(data) => parse_unique_file_id(data, owner),
+			_ => None,
+		})
+}
+
+fn parse_unique_file_id(data: &[u8], owner: &str) -> Option<String> {
+	let owner_end = data.iter().position(|b| *b == 0)?;
+	if &data[..owner_end] != owner.as_bytes() {
+		return None;
+	}
+	std::str::from_utf8(&data[owner_end + 1..])
+		.ok()
+		.map(|id| id.trim_end_matches('\0').to_owned())
+		.filter(|id| !id.is_empty())
+}
+
 fn read_ape_string(item: &ape::Item) -> Option<String> {
 	match item.value {
 		ape::ItemValue::Text(ref s) => Some(s.clone()),
@@ -106,9 +609,9 @@ fn read_ape_string(item: &ape::Item) -> Option<String> {
 	}
 }
 
-fn read_ape_i32(item: &ape::Item) -> Option<i32> {
+fn read_ape_total(item: &ape::Item) -> Option<u32> {
 	match item.value {
-		ape::ItemValue::Text(ref s) => s.parse::<i32>().ok(),
+		ape::ItemValue::Text(ref s) => parse_x_of_y(s).1,
 		_ => None,
 	}
 }
@@ -134,9 +637,48 @@ fn read_ape(path: &Path) -> Result<SongTags> {
 	let album = tag.item("Album").and_then(read_ape_string);
 	let album_artist = tag.item("Album artist").and_then(read_ape_string);
 	let title = tag.item("Title").and_then(read_ape_string);
-	let year = tag.item("Year").and_then(read_ape_i32);
+	let (year, original_date) = tag
+		.item("Year")
+		.and_then(read_ape_string)
+		.map_or((None, None), |v| parse_year_and_date(&v));
 	let disc_number = tag.item("Disc").and_then(read_ape_x_of_y);
+	let disc_total = tag.item("Disc").and_then(read_ape_total);
 	let track_number = tag.item("Track").and_then(read_ape_x_of_y);
+	let track_total = tag.item("Track").and_then(read_ape_total);
+	let genre = tag.item("Genre").and_then(read_ape_string);
+	let composer = tag.item("Composer").and_then(read_ape_string);
+	let conductor = tag.item("Conductor").and_then(read_ape_string);
+	let work = tag
+		.item("Work")
+		.or_else(|| tag.item("Grouping"))
+		.and_then(read_ape_string);
+	let movement_name = tag.item("MovementName").and_then(read_ape_string);
+	let movement_number = tag
+		.item("Movement")
+		.and_then(read_ape_string)
+		.and_then(|v| parse_x_of_y(&v).0);
+	let sort_artist = tag.item("ArtistSort").and_then(read_ape_string);
+	let sort_album = tag.item("AlbumSort").and_then(read_ape_string);
+	let sort_title = tag.item("TitleSort").and_then(read_ape_string);
+	let bpm = tag
+		.item("BPM")
+		.and_then(read_ape_string)
+		.and_then(|v| parse_bpm(&v));
+	let musical_key = tag
+		.item("InitialKey")
+		.or_else(|| tag.item("Key"))
+		.and_then(read_ape_string);
+	let disc_subtitle = tag.item("DiscSubtitle").and_then(read_ape_string);
+	let isrc = tag.item("ISRC").and_then(read_ape_string);
+	let musicbrainz_track_id = tag.item("MUSICBRAINZ_TRACKID").and_then(read_ape_string);
+	let musicbrainz_album_id = tag.item("MUSICBRAINZ_ALBUMID").and_then(read_ape_string);
+	let musicbrainz_artist_id = tag.item("MUSICBRAINZ_ARTISTID").and_then(read_ape_string);
+	let lyrics = tag.item("Lyrics").and_then(read_ape_string);
+	let compilation = tag
+		.item("Compilation")
+		.and_then(read_ape_string)
+		.map_or(false, |v| parse_flag(&v));
+	let artists = artist.iter().cloned().collect();
 	Ok(SongTags {
 		artist,
 		album_artist,
@@ -144,16 +686,73 @@ fn read_ape(path: &Path) -> Result<SongTags> {
 		title,
 		duration: None,
 		disc_number,
+		disc_total,
 		track_number,
+		track_total,
 		year,
+		original_date,
 		has_artwork: false,
+		genre,
+		artists,
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer,
+		conductor,
+		work,
+		movement_name,
+		movement_number,
+		sort_artist,
+		sort_album,
+		sort_title,
+		bpm,
+		musical_key,
+		disc_subtitle,
+		isrc,
+		musicbrainz_track_id,
+		musicbrainz_album_id,
+		musicbrainz_artist_id,
+		rating: None,
+		tagged_play_count: None,
+		lyrics,
+		compilation,
+		cue_sheet: None,
+		audio: AudioProperties::default(),
 	})
 }
 
+// Encoders only fill in the bitrate bounds when managing the bitrate, and set all three to the same
+// value for constant bitrates
+fn vorbis_bitrate_mode(header: &lewton::header::IdentHeader) -> Option<&'static str> {
+	let bounds = (header.bitrate_minimum, header.bitrate_maximum);
+	match bounds {
+		(min, max) if min > 0 && min == max && max == header.bitrate_nominal => {
+			Some(audio::BITRATE_MODE_CONSTANT)
+		}
+		(min, max) if min > 0 || max > 0 => Some(audio::BITRATE_MODE_AVERAGE),
+		_ if header.bitrate_nominal > 0 => Some(audio::BITRATE_MODE_VARIABLE),
+		_ => None,
+	}
+}
+
 #[cfg_attr(feature = "profile-index", flame)]
 fn read_vorbis(path: &Path) -> Result<SongTags> {
 	let file = fs::File::open(path)?;
 	let source = OggStreamReader::new(file)?;
+	let audio = AudioProperties {
+		format: Some("Vorbis".to_owned()),
+		lossless: Some(false),
+		bitrate: Some(source.ident_hdr.bitrate_nominal)
+			.filter(|b| *b > 0)
+			.map(|b| (b / 1000) as u32),
+		bitrate_mode: vorbis_bitrate_mode(&source.ident_hdr).map(|m| m.to_owned()),
+		sample_rate: Some(source.ident_hdr.audio_sample_rate),
+		bits_per_sample: None,
+		channels: Some(source.ident_hdr.audio_channels as u32),
+		encoder_delay: None,
+		encoder_padding: None,
+	};
 
 	let mut tags = SongTags {
 		artist: None,
@@ -162,9 +761,39 @@ fn read_vorbis(path: &Path) -> Result<SongTags> {
 		title: None,
 		duration: None,
 		disc_number: None,
+		disc_total: None,
 		track_number: None,
+		track_total: None,
 		year: None,
+		original_date: None,
 		has_artwork: false,
+		genre: None,
+		artists: Vec::new(),
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer: None,
+		conductor: None,
+		work: None,
+		movement_name: None,
+		movement_number: None,
+		sort_artist: None,
+		sort_album: None,
+		sort_title: None,
+		bpm: None,
+		musical_key: None,
+		disc_subtitle: None,
+		isrc: None,
+		musicbrainz_track_id: None,
+		musicbrainz_album_id: None,
+		musicbrainz_artist_id: None,
+		rating: None,
+		tagged_play_count: None,
+		lyrics: None,
+		compilation: false,
+		cue_sheet: None,
+		audio,
 	};
 
 	for (key, value) in source.comment_hdr.comment_list {
@@ -172,11 +801,63 @@ fn read_vorbis(path: &Path) -> Result<SongTags> {
 			match key {
 				"TITLE" => tags.title = Some(value),
 				"ALBUM" => tags.album = Some(value),
-				"ARTIST" => tags.artist = Some(value),
+				"ARTIST" => {
+					tags.artists.push(value.clone());
+					tags.artist = Some(value);
+				},
 				"ALBUMARTIST" => tags.album_artist = Some(value),
-				"TRACKNUMBER" => tags.track_number = value.parse::<u32>().ok(),
-				"DISCNUMBER" => tags.disc_number = value.parse::<u32>().ok(),
-				"DATE" => tags.year = value.parse::<i32>().ok(),
+				"TRACKNUMBER" => {
+					let (number, total) = parse_x_of_y(&value);
+					tags.track_number = number;
+					tags.track_total = total.or(tags.track_total);
+				},
+				"TRACKTOTAL" => tags.track_total = value.parse::<u32>().ok(),
+				"TOTALTRACKS" => tags.track_total = value.parse::<u32>().ok(),
+				"DISCNUMBER" => {
+					let (number, total) = parse_x_of_y(&value);
+					tags.disc_number = number;
+					tags.disc_total = total.or(tags.disc_total);
+				},
+				"DISCTOTAL" => tags.disc_total = value.parse::<u32>().ok(),
+				"TOTALDISCS" => tags.disc_total = value.parse::<u32>().ok(),
+				"DATE" => {
+					let (year, original_date) = parse_year_and_date(&value);
+					tags.year = year;
+					tags.original_date = original_date;
+				},
+				"GENRE" => tags.genre = Some(value),
+				"COMPOSER" => tags.composer = Some(value),
+				"CONDUCTOR" => tags.conductor = Some(value),
+				"WORK" => tags.work = Some(value),
+				"GROUPING" => {
+					tags.work.get_or_insert(value);
+				},
+				"MOVEMENTNAME" => tags.movement_name = Some(value),
+				"MOVEMENT" => tags.movement_number = parse_x_of_y(&value).0,
+				"ARTISTSORT" => tags.sort_artist = Some(value),
+				"BPM" => tags.bpm = parse_bpm(&value),
+				"INITIALKEY" => tags.musical_key = Some(value),
+				"DISCSUBTITLE" => tags.disc_subtitle = Some(value),
+				"ISRC" => tags.isrc = Some(value),
+				"MUSICBRAINZ_TRACKID" => tags.musicbrainz_track_id = Some(value),
+				"MUSICBRAINZ_ALBUMID" => tags.musicbrainz_album_id = Some(value),
+				"MUSICBRAINZ_ARTISTID" => {
+					tags.musicbrainz_artist_id.get_or_insert(value);
+				},
+				"KEY" => {
+					tags.musical_key.get_or_insert(value);
+				},
+				"ALBUMSORT" => tags.sort_album = Some(value),
+				"TITLESORT" => tags.sort_title = Some(value),
+				"LYRICS" => tags.lyrics = Some(value),
+				"UNSYNCEDLYRICS" => {
+					tags.lyrics.get_or_insert(value);
+				},
+				"COMPILATION" => tags.compilation = parse_flag(&value),
+				"REPLAYGAIN_TRACK_GAIN" => tags.replay_gain_track_gain = parse_replay_gain(&value),
+				"REPLAYGAIN_TRACK_PEAK" => tags.replay_gain_track_peak = parse_replay_gain(&value),
+				"REPLAYGAIN_ALBUM_GAIN" => tags.replay_gain_album_gain = parse_replay_gain(&value),
+				"REPLAYGAIN_ALBUM_PEAK" => tags.replay_gain_album_peak = parse_replay_gain(&value),
 				_ => (),
 			}
 		}
@@ -188,6 +869,19 @@ fn read_vorbis(path: &Path) -> Result<SongTags> {
 #[cfg_attr(feature = "profile-index", flame)]
 fn read_opus(path: &Path) -> Result<SongTags> {
 	let headers = opus_headers::parse_from_path(path)?;
+	// Opus always decodes to 48kHz, whatever the sample rate of the original input. Its header
+	// gives the number of samples to skip at the start, while padding is implied by the stream.
+	let audio = AudioProperties {
+		format: Some("Opus".to_owned()),
+		lossless: Some(false),
+		bitrate: None,
+		bitrate_mode: None,
+		sample_rate: Some(48000),
+		bits_per_sample: None,
+		channels: Some(headers.id.channel_count as u32),
+		encoder_delay: Some(headers.id.pre_skip as u32),
+		encoder_padding: None,
+	};
 
 	let mut tags = SongTags {
 		artist: None,
@@ -196,9 +890,39 @@ fn read_opus(path: &Path) -> Result<SongTags> {
 		title: None,
 		duration: None,
 		disc_number: None,
+		disc_total: None,
 		track_number: None,
+		track_total: None,
 		year: None,
+		original_date: None,
 		has_artwork: false,
+		genre: None,
+		artists: Vec::new(),
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer: None,
+		conductor: None,
+		work: None,
+		movement_name: None,
+		movement_number: None,
+		sort_artist: None,
+		sort_album: None,
+		sort_title: None,
+		bpm: None,
+		musical_key: None,
+		disc_subtitle: None,
+		isrc: None,
+		musicbrainz_track_id: None,
+		musicbrainz_album_id: None,
+		musicbrainz_artist_id: None,
+		rating: None,
+		tagged_play_count: None,
+		lyrics: None,
+		compilation: false,
+		cue_sheet: None,
+		audio,
 	};
 
 	for (key, value) in headers.comments.user_comments {
@@ -206,11 +930,63 @@ fn read_opus(path: &Path) -> Result<SongTags> {
 			match key {
 				"TITLE" => tags.title = Some(value),
 				"ALBUM" => tags.album = Some(value),
-				"ARTIST" => tags.artist = Some(value),
+				"ARTIST" => {
+					tags.artists.push(value.clone());
+					tags.artist = Some(value);
+				},
 				"ALBUMARTIST" => tags.album_artist = Some(value),
-				"TRACKNUMBER" => tags.track_number = value.parse::<u32>().ok(),
-				"DISCNUMBER" => tags.disc_number = value.parse::<u32>().ok(),
-				"DATE" => tags.year = value.parse::<i32>().ok(),
+				"TRACKNUMBER" => {
+					let (number, total) = parse_x_of_y(&value);
+					tags.track_number = number;
+					tags.track_total = total.or(tags.track_total);
+				},
+				"TRACKTOTAL" => tags.track_total = value.parse::<u32>().ok(),
+				"TOTALTRACKS" => tags.track_total = value.parse::<u32>().ok(),
+				"DISCNUMBER" => {
+					let (number, total) = parse_x_of_y(&value);
+					tags.disc_number = number;
+					tags.disc_total = total.or(tags.disc_total);
+				},
+				"DISCTOTAL" => tags.disc_total = value.parse::<u32>().ok(),
+				"TOTALDISCS" => tags.disc_total = value.parse::<u32>().ok(),
+				"DATE" => {
+					let (year, original_date) = parse_year_and_date(&value);
+					tags.year = year;
+					tags.original_date = original_date;
+				},
+				"GENRE" => tags.genre = Some(value),
+				"COMPOSER" => tags.composer = Some(value),
+				"BPM" => tags.bpm = parse_bpm(&value),
+				"INITIALKEY" => tags.musical_key = Some(value),
+				"DISCSUBTITLE" => tags.disc_subtitle = Some(value),
+				"ISRC" => tags.isrc = Some(value),
+				"MUSICBRAINZ_TRACKID" => tags.musicbrainz_track_id = Some(value),
+				"MUSICBRAINZ_ALBUMID" => tags.musicbrainz_album_id = Some(value),
+				"MUSICBRAINZ_ARTISTID" => {
+					tags.musicbrainz_artist_id.get_or_insert(value);
+				},
+				"KEY" => {
+					tags.musical_key.get_or_insert(value);
+				},
+				"CONDUCTOR" => tags.conductor = Some(value),
+				"WORK" => tags.work = Some(value),
+				"GROUPING" => {
+					tags.work.get_or_insert(value);
+				},
+				"MOVEMENTNAME" => tags.movement_name = Some(value),
+				"MOVEMENT" => tags.movement_number = parse_x_of_y(&value).0,
+				"ARTISTSORT" => tags.sort_artist = Some(value),
+				"ALBUMSORT" => tags.sort_album = Some(value),
+				"TITLESORT" => tags.sort_title = Some(value),
+				"LYRICS" => tags.lyrics = Some(value),
+				"UNSYNCEDLYRICS" => {
+					tags.lyrics.get_or_insert(value);
+				},
+				"COMPILATION" => tags.compilation = parse_flag(&value),
+				"REPLAYGAIN_TRACK_GAIN" => tags.replay_gain_track_gain = parse_replay_gain(&value),
+				"REPLAYGAIN_TRACK_PEAK" => tags.replay_gain_track_peak = parse_replay_gain(&value),
+				"REPLAYGAIN_ALBUM_GAIN" => tags.replay_gain_album_gain = parse_replay_gain(&value),
+				"REPLAYGAIN_ALBUM_PEAK" => tags.replay_gain_album_peak = parse_replay_gain(&value),
 				_ => (),
 			}
 		}
@@ -219,23 +995,187 @@ fn read_opus(path: &Path) -> Result<SongTags> {
 	Ok(tags)
 }
 
+// Dates are kept down to the day, as in "2019-07-14", "2019-07" or "2019". Times and fields which
+// are out of range are dropped.
+pub fn parse_date(value: &str) -> Option<String> {
+	let date = value.trim().split(|c| c == 'T' || c == ' ').next()?;
+	let mut fields = date.split('-');
+	let year = fields.next()?;
+	if year.len() != 4 || !year.chars().all(|c| c.is_ascii_digit()) {
+		return None;
+	}
+	let mut date = year.to_owned();
+	for (field, max) in fields.zip(&[12, 31]) {
+		match field.parse::<u32>() {
+			Ok(n) if field.len() == 2 && n >= 1 && n <= *max => {
+				date.push('-');
+				date.push_str(field);
+			}
+			_ => break,
+		}
+	}
+	Some(date)
+}
+
+// Date tags hold either a year or a full date
+fn parse_year_and_date(value: &str) -> (Option<i32>, Option<String>) {
+	let date = parse_date(value);
+	let year = value
+		.trim()
+		.parse::<i32>()
+		.ok()
+		.or_else(|| date.as_ref().and_then(|d| d[..4].parse::<i32>().ok()));
+	(year, date)
+}
+
+fn format_id3_timestamp(timestamp: &id3::Timestamp) -> String {
+	match (timestamp.month, timestamp.day) {
+		(Some(month), Some(day)) => format!("{:04}-{:02}-{:02}", timestamp.year, month, day),
+		(Some(month), None) => format!("{:04}-{:02}", timestamp.year, month),
+		_ => format!("{:04}", timestamp.year),
+	}
+}
+
+// Numbers may be written along with a total, as in "3/12"
+fn parse_x_of_y(value: &str) -> (Option<u32>, Option<u32>) {
+	let mut parts = value.splitn(2, '/');
+	let number = parts.next().and_then(|n| n.trim().parse::<u32>().ok());
+	let total = parts.next().and_then(|t| t.trim().parse::<u32>().ok());
+	(number, total)
+}
+
+fn parse_flag(value: &str) -> bool {
+	let value = value.trim();
+	value == "1" || value.eq_ignore_ascii_case("true")
+}
+
+// Gains are usually written with a unit suffix, as in "-6.5 dB"
+fn parse_replay_gain(value: &str) -> Option<f32> {
+	value
+		.trim()
+		.to_ascii_lowercase()
+		.trim_end_matches("db")
+		.trim()
+		.parse::<f32>()
+		.ok()
+		.filter(|v| v.is_finite())
+}
+
+// Tempos are often written with decimals, such as `128.00`
+fn parse_bpm(value: &str) -> Option<i32> {
+	value
+		.trim()
+		.parse::<f64>()
+		.ok()
+		.filter(|v| v.is_finite() && *v > 0.0)
+		.map(|v| v.round() as i32)
+}
+
+fn read_flac_x_of_y(
+	vorbis: &metaflac::block::VorbisComment,
+	number_key: &str,
+	total_keys: &[&str],
+) -> (Option<u32>, Option<u32>) {
+	let (number, total) = vorbis
+		.get(number_key)
+		.and_then(|v| v.first())
+		.map_or((None, None), |v| parse_x_of_y(v));
+	let total = total.or_else(|| {
+		total_keys
+			.iter()
+			.find_map(|key| vorbis.get(key).and_then(|v| v.first()))
+			.and_then(|v| v.trim().parse::<u32>().ok())
+	});
+	(number, total)
+}
+
+fn read_flac_replay_gain(vorbis: &metaflac::block::VorbisComment, key: &str) -> Option<f32> {
+	vorbis
+		.get(key)
+		.and_then(|v| v.first())
+		.and_then(|v| parse_replay_gain(v))
+}
+
+// Lead-out tracks mark the end of the disc rather than a track
+const FLAC_LEAD_OUT_TRACKS: [u8; 2] = [170, 255];
+
+// Cue sheets are embedded either as the text of a CUESHEET comment, which can name the tracks, or
+// as a CUESHEET block which only has their offsets. Files with a single track are not split.
+fn read_flac_cue_sheet(
+	tag: &metaflac::Tag,
+	vorbis: &metaflac::block::VorbisComment,
+	sample_rate: Option<u32>,
+) -> Option<CueSheet> {
+	let text_sheet = vorbis
+		.get("CUESHEET")
+		.and_then(|v| cue::parse(&v[0]).ok())
+		.and_then(|mut sheet| {
+			sheet.files.truncate(1);
+			Some(sheet).filter(|s| !s.files.is_empty())
+		});
+	let sheet = text_sheet.or_else(|| {
+		let sample_rate = sample_rate.filter(|r| *r > 0)? as u64;
+		let block = tag.get_blocks(metaflac::BlockType::CueSheet).next()?;
+		let block = match block {
+			metaflac::Block::CueSheet(block) => block,
+			_ => return None,
+		};
+		let tracks = block
+			.tracks
+			.iter()
+			.filter(|t| t.is_audio && !FLAC_LEAD_OUT_TRACKS.contains(&t.number))
+			.filter_map(|t| {
+				let index = t
+					.indices
+					.iter()
+					.find(|i| i.point_num == 1)
+					.or_else(|| t.indices.first())?;
+				let start_sample = t.offset + index.offset;
+				Some(CueTrack {
+					number: t.number as u32,
+					start_frame: (start_sample * cue::FRAMES_PER_SECOND as u64 / sample_rate)
+						as u32,
+					..Default::default()
+				})
+			})
+			.collect();
+		Some(CueSheet {
+			files: vec![CueFile {
+				name: String::new(),
+				tracks,
+			}],
+			..Default::default()
+		})
+	})?;
+	Some(sheet).filter(|s| s.files[0].tracks.len() > 1)
+}
+
 #[cfg_attr(feature = "profile-index", flame)]
 fn read_flac(path: &Path) -> Result<SongTags> {
 	let tag = metaflac::Tag::read_from_path(path)?;
 	let vorbis = tag
 		.vorbis_comments()
 		.ok_or(anyhow!("Missing Vorbis comments"))?;
-	let disc_number = vorbis
-		.get("DISCNUMBER")
-		.and_then(|d| d[0].parse::<u32>().ok());
-	let year = vorbis.get("DATE").and_then(|d| d[0].parse::<i32>().ok());
+	let (disc_number, disc_total) =
+		read_flac_x_of_y(vorbis, "DISCNUMBER", &["DISCTOTAL", "TOTALDISCS"]);
+	let (track_number, track_total) =
+		read_flac_x_of_y(vorbis, "TRACKNUMBER", &["TRACKTOTAL", "TOTALTRACKS"]);
+	let (year, original_date) = vorbis
+		.get("DATE")
+		.map_or((None, None), |d| parse_year_and_date(&d[0]));
 	let mut streaminfo = tag.get_blocks(metaflac::BlockType::StreamInfo);
-	let duration = match streaminfo.next() {
-		Some(&metaflac::Block::StreamInfo(ref s)) => {
-			Some((s.total_samples as u32 / s.sample_rate) as u32)
-		}
+	let stream_info = match streaminfo.next() {
+		Some(&metaflac::Block::StreamInfo(ref s)) => Some(s),
 		_ => None,
 	};
+	let cue_sheet = read_flac_cue_sheet(&tag, vorbis, stream_info.map(|s| s.sample_rate));
+	let (duration, audio) = match stream_info {
+		Some(s) => (
+			Some((s.total_samples as u32 / s.sample_rate) as u32),
+			audio::read_flac(path, s).unwrap_or_default(),
+		),
+		None => (None, AudioProperties::default()),
+	};
 	let has_artwork = tag.pictures().count() > 0;
 
 	Ok(SongTags {
@@ -245,15 +1185,72 @@ fn read_flac(path: &Path) -> Result<SongTags> {
 		title: vorbis.title().map(|v| v[0].clone()),
 		duration,
 		disc_number,
-		track_number: vorbis.track(),
+		disc_total,
+		track_number,
+		track_total,
 		year,
+		original_date,
 		has_artwork,
+		genre: vorbis.genre().map(|v| v[0].clone()),
+		artists: vorbis.artist().cloned().unwrap_or_default(),
+		replay_gain_track_gain: read_flac_replay_gain(vorbis, "REPLAYGAIN_TRACK_GAIN"),
+		replay_gain_track_peak: read_flac_replay_gain(vorbis, "REPLAYGAIN_TRACK_PEAK"),
+		replay_gain_album_gain: read_flac_replay_gain(vorbis, "REPLAYGAIN_ALBUM_GAIN"),
+		replay_gain_album_peak: read_flac_replay_gain(vorbis, "REPLAYGAIN_ALBUM_PEAK"),
+		composer: vorbis.get("COMPOSER").map(|v| v[0].clone()),
+		bpm: vorbis.get("BPM").and_then(|v| parse_bpm(&v[0])),
+		musical_key: vorbis
+			.get("INITIALKEY")
+			.or_else(|| vorbis.get("KEY"))
+			.map(|v| v[0].clone()),
+		disc_subtitle: vorbis.get("DISCSUBTITLE").map(|v| v[0].clone()),
+		isrc: vorbis.get("ISRC").map(|v| v[0].clone()),
+		musicbrainz_track_id: vorbis.get("MUSICBRAINZ_TRACKID").map(|v| v[0].clone()),
+		musicbrainz_album_id: vorbis.get("MUSICBRAINZ_ALBUMID").map(|v| v[0].clone()),
+		musicbrainz_artist_id: vorbis.get("MUSICBRAINZ_ARTISTID").map(|v| v[0].clone()),
+		rating: None,
+		tagged_play_count: None,
+		conductor: vorbis.get("CONDUCTOR").map(|v| v[0].clone()),
+		work: vorbis
+			.get("WORK")
+			.or_else(|| vorbis.get("GROUPING"))
+			.map(|v| v[0].clone()),
+		movement_name: vorbis.get("MOVEMENTNAME").map(|v| v[0].clone()),
+		movement_number: vorbis.get("MOVEMENT").and_then(|v| parse_x_of_y(&v[0]).0),
+		sort_artist: vorbis.get("ARTISTSORT").map(|v| v[0].clone()),
+		sort_album: vorbis.get("ALBUMSORT").map(|v| v[0].clone()),
+		sort_title: vorbis.get("TITLESORT").map(|v| v[0].clone()),
+		lyrics: vorbis
+			.get("LYRICS")
+			.or_else(|| vorbis.get("UNSYNCEDLYRICS"))
+			.map(|v| v[0].clone()),
+		compilation: vorbis
+			.get("COMPILATION")
+			.map_or(false, |v| parse_flag(&v[0])),
+		cue_sheet,
+		audio,
 	})
 }
 
 #[cfg_attr(feature = "profile-index", flame)]
 fn read_mp4(path: &Path) -> Result<SongTags> {
 	let mut tag = mp4ameta::Tag::read_from_path(path)?;
+	let artists: Vec<String> = tag.artists().map(|a| a.to_owned()).collect();
+	let read_string = |ident: &[u8; 4]| {
+		tag.string(mp4ameta::Ident(*ident))
+			.next()
+			.map(|s| s.to_owned())
+	};
+	let sort_artist = read_string(b"soar");
+	let sort_album = read_string(b"soal");
+	let sort_title = read_string(b"sonm");
+	let lyrics = read_string(b"\xa9lyr");
+	let work = read_string(b"\xa9wrk").or_else(|| read_string(b"\xa9grp"));
+	let movement_name = read_string(b"\xa9mvn");
+	let movement_number = tag.movement_index().filter(|i| *i > 0).map(|i| i as u32);
+	let compilation = tag.compilation();
+	let audio = audio::read_mp4(path, tag.duration()).unwrap_or_default();
+	let (year, original_date) = tag.year().map_or((None, None), parse_year_and_date);
 
 	Ok(SongTags {
 		artist: tag.take_artist(),
@@ -262,9 +1259,41 @@ fn read_mp4(path: &Path) -> Result<SongTags> {
 		title: tag.take_title(),
 		duration: tag.duration().map(|v| v as u32),
 		disc_number: tag.disc_number().map(|d| d as u32),
+		disc_total: tag.total_discs().map(|d| d as u32),
 		track_number: tag.track_number().map(|d| d as u32),
-		year: tag.year().and_then(|v| v.parse::<i32>().ok()),
+		track_total: tag.total_tracks().map(|d| d as u32),
+		year,
+		original_date,
 		has_artwork: tag.artwork().is_some(),
+		genre: tag.take_genre(),
+		artists,
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer: tag.take_composer(),
+		bpm: tag.bpm().map(|b| b as i32),
+		// iTunes keeps the initial key, disc subtitle, ISRC, MusicBrainz identifiers and gapless
+		// information in freeform atoms, which the tag reader does not support
+		musical_key: None,
+		disc_subtitle: None,
+		isrc: None,
+		musicbrainz_track_id: None,
+		musicbrainz_album_id: None,
+		musicbrainz_artist_id: None,
+		rating: None,
+		tagged_play_count: None,
+		conductor: None,
+		work,
+		movement_name,
+		movement_number,
+		sort_artist,
+		sort_album,
+		sort_title,
+		lyrics,
+		compilation,
+		cue_sheet: None,
+		audio,
 	})
 }
 
@@ -272,68 +1301,725 @@ fn read_mp4(path: &Path) -> Result<SongTags> {
 fn test_read_metadata() {
 	let sample_tags = SongTags {
 		disc_number: Some(3),
+		disc_total: Some(4),
 		track_number: Some(1),
+		track_total: Some(2),
 		title: Some("TEST TITLE".into()),
 		artist: Some("TEST ARTIST".into()),
 		album_artist: Some("TEST ALBUM ARTIST".into()),
 		album: Some("TEST ALBUM".into()),
 		duration: None,
 		year: Some(2016),
+		original_date: Some("2016".to_owned()),
 		has_artwork: false,
+		genre: None,
+		artists: vec!["TEST ARTIST".into()],
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer: None,
+		conductor: None,
+		work: None,
+		movement_name: None,
+		movement_number: None,
+		sort_artist: None,
+		sort_album: None,
+		sort_title: None,
+		bpm: None,
+		musical_key: None,
+		disc_subtitle: None,
+		isrc: None,
+		musicbrainz_track_id: None,
+		musicbrainz_album_id: None,
+		musicbrainz_artist_id: None,
+		rating: None,
+		tagged_play_count: None,
+		lyrics: None,
+		compilation: false,
+		cue_sheet: None,
+		audio: AudioProperties::default(),
+	};
+	let audio = |format: &str, bitrate, sample_rate, bits_per_sample: Option<u32>, channels| {
+		AudioProperties {
+			format: Some(format.to_owned()),
+			lossless: Some(bits_per_sample.is_some()),
+			bitrate,
+			sample_rate: Some(sample_rate),
+			bits_per_sample,
+			channels: Some(channels),
+			..Default::default()
+		}
 	};
 	let flac_sample_tag = SongTags {
 		duration: Some(0),
+		audio: audio("FLAC", Some(685), 44100, Some(16), 1),
 		..sample_tags.clone()
 	};
 	let mp3_sample_tag = SongTags {
 		duration: Some(0),
+		audio: AudioProperties {
+			encoder_delay: Some(576),
+			encoder_padding: Some(2089),
+			bitrate_mode: Some(audio::BITRATE_MODE_CONSTANT.to_owned()),
+			..audio("MP3", Some(320), 44100, None, 1)
+		},
 		..sample_tags.clone()
 	};
 	let m4a_sample_tag = SongTags {
 		duration: Some(0),
+		disc_total: None,
+		track_total: None,
+		audio: audio("AAC", Some(83), 44100, None, 2),
+		..sample_tags.clone()
+	};
+	let ogg_sample_tag = SongTags {
+		audio: AudioProperties {
+			bitrate_mode: Some(audio::BITRATE_MODE_VARIABLE.to_owned()),
+			..audio("Vorbis", Some(239), 44100, None, 1)
+		},
+		..sample_tags.clone()
+	};
+	let opus_sample_tag = SongTags {
+		disc_total: None,
+		track_total: None,
+		genre: Some("silence".into()),
+		audio: AudioProperties {
+			encoder_delay: Some(312),
+			..audio("Opus", None, 48000, None, 1)
+		},
 		..sample_tags.clone()
 	};
+	let ape_sample_tag = SongTags {
+		audio: audio("APE", Some(521), 44100, Some(16), 1),
+		..sample_tags.clone()
+	};
+	let wavpack_sample_tag = SongTags {
+		duration: Some(1),
+		audio: audio("WavPack", Some(2), 44100, Some(16), 1),
+		..sample_tags.clone()
+	};
+	let mpc_sample_tag = SongTags {
+		duration: Some(1),
+		audio: AudioProperties {
+			bitrate_mode: Some(audio::BITRATE_MODE_VARIABLE.to_owned()),
+			..audio("MPC", Some(2), 44100, None, 2)
+		},
+		..sample_tags
+	};
 	assert_eq!(
 		read(Path::new("test-data/formats/sample.mp3")).unwrap(),
-		mp3_sample_tag
+		Some(mp3_sample_tag)
 	);
 	assert_eq!(
 		read(Path::new("test-data/formats/sample.ogg")).unwrap(),
-		sample_tags
+		Some(ogg_sample_tag)
 	);
 	assert_eq!(
 		read(Path::new("test-data/formats/sample.flac")).unwrap(),
-		flac_sample_tag
+		Some(flac_sample_tag)
 	);
 	assert_eq!(
 		read(Path::new("test-data/formats/sample.m4a")).unwrap(),
-		m4a_sample_tag
+		Some(m4a_sample_tag)
 	);
 	assert_eq!(
 		read(Path::new("test-data/formats/sample.opus")).unwrap(),
-		sample_tags
+		Some(opus_sample_tag)
 	);
 	assert_eq!(
 		read(Path::new("test-data/formats/sample.ape")).unwrap(),
-		sample_tags
+		Some(ape_sample_tag)
+	);
+	assert_eq!(
+		read(Path::new("test-data/formats/sample.wv")).unwrap(),
+		Some(wavpack_sample_tag)
+	);
+	assert_eq!(
+		read(Path::new("test-data/formats/sample.mpc")).unwrap(),
+		Some(mpc_sample_tag)
 	);
 }
 
+#[test]
+fn test_split_artists() {
+	let mut tags = SongTags {
+		disc_number: None,
+		disc_total: None,
+		track_number: None,
+		track_total: None,
+		title: None,
+		artist: Some("Artist A; Artist B".into()),
+		album_artist: None,
+		album: None,
+		duration: None,
+		year: None,
+		original_date: None,
+		has_artwork: false,
+		genre: None,
+		artists: vec![
+			"Artist A; Artist B".into(),
+			"Artist C;".into(),
+			"Artist A".into(),
+		],
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer: None,
+		conductor: None,
+		work: None,
+		movement_name: None,
+		movement_number: None,
+		sort_artist: None,
+		sort_album: None,
+		sort_title: None,
+		bpm: None,
+		musical_key: None,
+		disc_subtitle: None,
+		isrc: None,
+		musicbrainz_track_id: None,
+		musicbrainz_album_id: None,
+		musicbrainz_artist_id: None,
+		rating: None,
+		tagged_play_count: None,
+		lyrics: None,
+		compilation: false,
+		cue_sheet: None,
+		audio: AudioProperties::default(),
+	};
+	tags.split_artists(";");
+	assert_eq!(tags.artists, vec!["Artist A", "Artist B", "Artist C"]);
+
+	assert_eq!(split_artist("AC/DC", ";"), vec!["AC/DC"]);
+	assert_eq!(split_artist(" Artist A ", ""), vec!["Artist A"]);
+}
+
+#[test]
+fn test_normalize() {
+	let decomposed = "Beyonce\u{301}";
+	let mut tags = SongTags {
+		disc_number: None,
+		disc_total: None,
+		track_number: None,
+		track_total: None,
+		title: Some(decomposed.into()),
+		artist: Some(decomposed.into()),
+		album_artist: None,
+		album: None,
+		duration: None,
+		year: None,
+		original_date: None,
+		has_artwork: false,
+		genre: None,
+		artists: vec![decomposed.into()],
+		replay_gain_track_gain: None,
+		replay_gain_track_peak: None,
+		replay_gain_album_gain: None,
+		replay_gain_album_peak: None,
+		composer: None,
+		conductor: None,
+		work: None,
+		movement_name: None,
+		movement_number: None,
+		sort_artist: None,
+		sort_album: None,
+		sort_title: None,
+		bpm: None,
+		musical_key: None,
+		disc_subtitle: None,
+		isrc: None,
+		musicbrainz_track_id: None,
+		musicbrainz_album_id: None,
+		musicbrainz_artist_id: None,
+		rating: None,
+		tagged_play_count: None,
+		lyrics: None,
+		compilation: false,
+		cue_sheet: None,
+		audio: AudioProperties::default(),
+	};
+	tags.normalize();
+	assert_eq!(tags.title, Some("Beyonc\u{e9}".into()));
+	assert_eq!(tags.artists, vec!["Beyonc\u{e9}"]);
+
+	assert_eq!(fold_search_text("Beyonc\u{e9}"), "beyonce");
+	assert_eq!(fold_search_text(decomposed), "beyonce");
+	assert_eq!(fold_search_text("シャーベット"), "シャーベット");
+}
+
+#[test]
+fn test_sort_key() {
+	let articles = vec!["The".to_owned(), "A".to_owned(), "L'".to_owned()];
+	assert_eq!(sort_key("The Beatles", &articles), "beatles");
+	assert_eq!(sort_key("\u{c9}milie Simon", &articles), "emilie simon");
+	assert_eq!(
+		sort_key("A Tribe Called Quest", &articles),
+		"tribe called quest"
+	);
+	assert_eq!(sort_key("L'Imp\u{e9}ratrice", &articles), "imperatrice");
+	assert_eq!(
+		sort_key("Theatre of Tragedy", &articles),
+		"theatre of tragedy"
+	);
+	assert_eq!(sort_key("The", &articles), "the");
+	assert_eq!(sort_key("The Beatles", &[]), "the beatles");
+}
+
+#[test]
+fn test_album_key() {
+	assert_eq!(
+		album_key(Some(" Beyonc\u{e9} "), Some("Lemonade")),
+		Some("beyonce\tlemonade".to_owned())
+	);
+	assert_eq!(
+		album_key(Some("BEYONCE"), Some("Lemonade  (Deluxe)")),
+		album_key(Some("Beyonc\u{e9}"), Some("lemonade (deluxe)"))
+	);
+	assert_ne!(
+		album_key(Some("A B"), Some("C")),
+		album_key(Some("A"), Some("B C"))
+	);
+	assert_eq!(album_key(None, Some("Lemonade")), None);
+	assert_eq!(album_key(Some("Beyonc\u{e9}"), None), None);
+	assert_eq!(album_key(Some(" "), Some("Lemonade")), None);
+}
+
+#[test]
+fn test_is_synced_lyrics() {
+	assert!(is_synced_lyrics(
+		"[ar:Some Artist]\n[00:12.00]First line\n[00:17.20]Second"
+	));
+	assert!(is_synced_lyrics("[01:02]Line"));
+	assert!(!is_synced_lyrics("First line\nSecond line"));
+	assert!(!is_synced_lyrics("[Chorus]\nLine"));
+}
+
+#[test]
+fn test_parse_x_of_y() {
+	assert_eq!(parse_x_of_y("3/12"), (Some(3), Some(12)));
+	assert_eq!(parse_x_of_y(" 3 / 12 "), (Some(3), Some(12)));
+	assert_eq!(parse_x_of_y("3"), (Some(3), None));
+	assert_eq!(parse_x_of_y("3/"), (Some(3), None));
+	assert_eq!(parse_x_of_y("/12"), (None, Some(12)));
+}
+
+#[test]
+fn test_parse_replay_gain() {
+	assert_eq!(parse_replay_gain("-6.5 dB"), Some(-6.5));
+	assert_eq!(parse_replay_gain("+1.20dB"), Some(1.2));
+	assert_eq!(parse_replay_gain("0.988525"), Some(0.988525));
+	assert_eq!(parse_replay_gain("loud"), None);
+	assert_eq!(parse_replay_gain("NaN"), None);
+}
+
+#[test]
+fn test_read_embedded_cue_sheet() {
+	use metaflac::block::{CueSheetTrack, CueSheetTrackIndex};
+
+	let directory = Path::new("test-output").join("embedded-cue-sheet");
+	std::fs::create_dir_all(&directory).unwrap();
+	let write_cue_block = |name: &str, offsets: &[u64]| {
+		let path = directory.join(name);
+		std::fs::copy("test-data/formats/sample.flac", &path).unwrap();
+		let mut tracks = offsets
+			.iter()
+			.enumerate()
+			.map(|(i, offset)| CueSheetTrack {
+				offset: *offset,
+				number: i as u8 + 1,
+				indices: vec![CueSheetTrackIndex {
+					offset: 0,
+					point_num: 1,
+				}],
+				..CueSheetTrack::new()
+			})
+			.collect::<Vec<_>>();
+		tracks.push(CueSheetTrack {
+			offset: 44100 * 300,
+			number: 170,
+			..CueSheetTrack::new()
+		});
+		let mut tag = metaflac::Tag::read_from_path(&path).unwrap();
+		tag.push_block(metaflac::Block::CueSheet(metaflac::block::CueSheet {
+			tracks,
+			..metaflac::block::CueSheet::new()
+		}));
+		tag.write_to_path(&path).unwrap();
+		path
+	};
+
+	let path = write_cue_block("block.flac", &[0, 44100 * 60]);
+	let sheet = read(&path).unwrap().unwrap().cue_sheet.unwrap();
+	assert_eq!(sheet.files.len(), 1);
+	let tracks = &sheet.files[0].tracks;
+	assert_eq!(tracks.len(), 2);
+	assert_eq!(tracks[0].number, 1);
+	assert_eq!(tracks[0].start_time(), 0);
+	assert_eq!(tracks[1].number, 2);
+	assert_eq!(tracks[1].start_time(), 60);
+	assert_eq!(tracks[1].title, None);
+
+	// Single tracks are left whole
+	let path = write_cue_block("single.flac", &[0]);
+	assert_eq!(read(&path).unwrap().unwrap().cue_sheet, None);
+
+	// Text cue sheets name their tracks, and take precedence over a cue sheet block
+	let path = write_cue_block("text.flac", &[0, 44100 * 60]);
+	let mut tag = metaflac::Tag::read_from_path(&path).unwrap();
+	tag.set_vorbis(
+		"CUESHEET",
+		vec!["FILE \"disc.wav\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"First\"\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    TITLE \"Second\"\n    INDEX 01 00:10:00\n  TRACK 03 AUDIO\n    TITLE \"Third\"\n    INDEX 01 00:20:00\n"],
+	);
+	tag.write_to_path(&path).unwrap();
+	let sheet = read(&path).unwrap().unwrap().cue_sheet.unwrap();
+	let tracks = &sheet.files[0].tracks;
+	assert_eq!(tracks.len(), 3);
+	assert_eq!(tracks[1].title, Some("Second".to_owned()));
+	assert_eq!(tracks[2].start_time(), 20);
+
+	let tags = read(Path::new("test-data/formats/sample.flac"))
+		.unwrap()
+		.unwrap();
+	assert_eq!(tags.cue_sheet, None);
+}
+
+#[test]
+fn test_read_bpm_and_key() {
+	let directory = Path::new("test-output").join("bpm-and-key");
+	std::fs::create_dir_all(&directory).unwrap();
+	let path = directory.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &path).unwrap();
+	let mut tag = id3::Tag::read_from_path(&path).unwrap();
+	tag.set_text("TBPM", "128.00");
+	tag.set_text("TKEY", "8A");
+	tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+	let tags = read(&path).unwrap().unwrap();
+	assert_eq!(tags.bpm, Some(128));
+	assert_eq!(tags.musical_key, Some("8A".to_owned()));
+}
+
+#[test]
+fn test_read_disc_subtitle() {
+	let directory = Path::new("test-output").join("disc-subtitle");
+	std::fs::create_dir_all(&directory).unwrap();
+	let path = directory.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &path).unwrap();
+	assert_eq!(read(&path).unwrap().unwrap().disc_subtitle, None);
+
+	let mut tag = id3::Tag::read_from_path(&path).unwrap();
+	tag.set_text("TSST", "Bonus Tracks");
+	tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+	let tags = read(&path).unwrap().unwrap();
+	assert_eq!(tags.disc_subtitle, Some("Bonus Tracks".to_owned()));
+}
+
+#[test]
+fn test_read_external_ids() {
+	let directory = Path::new("test-output").join("external-ids");
+	std::fs::create_dir_all(&directory).unwrap();
+
+	let mp3_path = directory.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &mp3_path).unwrap();
+	let mut tag = id3::Tag::read_from_path(&mp3_path).unwrap();
+	tag.set_text("TSRC", "USRC17607839");
+	tag.add_extended_text("MusicBrainz Album Id", "album-id");
+	tag.add_extended_text("MusicBrainz Artist Id", "artist-id");
+	tag.add_frame(id3::Frame::with_content(
+		"UFID",
+		id3::Content::Unknown(b"http://musicbrainz.org\0track-id".to_vec()),
+	));
+	tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+
+	let flac_path = directory.join("sample.flac");
+	std::fs::copy("test-data/formats/sample.flac", &flac_path).unwrap();
+	let mut tag = metaflac::Tag::read_from_path(&flac_path).unwrap();
+	tag.set_vorbis("ISRC", vec!["USRC17607839"]);
+	tag.set_vorbis("MUSICBRAINZ_TRACKID", vec!["track-id"]);
+	tag.set_vorbis("MUSICBRAINZ_ALBUMID", vec!["album-id"]);
+	tag.set_vorbis("MUSICBRAINZ_ARTISTID", vec!["artist-id", "other-artist-id"]);
+	tag.save().unwrap();
+
+	for path in &[mp3_path, flac_path] {
+		let tags = read(path).unwrap().unwrap();
+		assert_eq!(tags.isrc, Some("USRC17607839".to_owned()));
+		assert_eq!(tags.musicbrainz_track_id, Some("track-id".to_owned()));
+		assert_eq!(tags.musicbrainz_album_id, Some("album-id".to_owned()));
+		assert_eq!(tags.musicbrainz_artist_id, Some("artist-id".to_owned()));
+	}
+
+	let tags = read(Path::new("test-data/formats/sample.mp3"))
+		.unwrap()
+		.unwrap();
+	assert_eq!(tags.isrc, None);
+	assert_eq!(tags.musicbrainz_track_id, None);
+}
+
+#[test]
+fn test_read_classical_works() {
+	let directory = Path::new("test-output").join("classical-works");
+	std::fs::create_dir_all(&directory).unwrap();
+
+	// Movement frames are not known to the tag reader, as they are more recent than ID3v2.4
+	let mp3_path = directory.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &mp3_path).unwrap();
+	let mut tag = id3::Tag::read_from_path(&mp3_path).unwrap();
+	tag.add_extended_text("WORK", "Symphony No. 5");
+	let mut movement_name = vec![1, 0xff, 0xfe];
+	for unit in "Allegro con brio".encode_utf16() {
+		movement_name.extend_from_slice(&unit.to_le_bytes());
+	}
+	tag.add_frame(id3::Frame::with_content(
+		"MVNM",
+		id3::Content::Unknown(movement_name),
+	));
+	tag.add_frame(id3::Frame::with_content(
+		"MVIN",
+		id3::Content::Unknown(b"\x031/4\0".to_vec()),
+	));
+	tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+
+	let flac_path = directory.join("sample.flac");
+	std::fs::copy("test-data/formats/sample.flac", &flac_path).unwrap();
+	let mut tag = metaflac::Tag::read_from_path(&flac_path).unwrap();
+	tag.set_vorbis("GROUPING", vec!["Symphony No. 5"]);
+	tag.set_vorbis("MOVEMENTNAME", vec!["Allegro con brio"]);
+	tag.set_vorbis("MOVEMENT", vec!["1"]);
+	tag.save().unwrap();
+
+	for path in &[mp3_path, flac_path] {
+		let tags = read(path).unwrap().unwrap();
+		assert_eq!(tags.work, Some("Symphony No. 5".to_owned()));
+		assert_eq!(tags.movement_name, Some("Allegro con brio".to_owned()));
+		assert_eq!(tags.movement_number, Some(1));
+	}
+
+	let tags = read(Path::new("test-data/formats/sample.mp3"))
+		.unwrap()
+		.unwrap();
+	assert_eq!(tags.work, None);
+	assert_eq!(tags.movement_name, None);
+	assert_eq!(tags.movement_number, None);
+}
+
+#[test]
+fn test_read_full_dates() {
+	let directory = Path::new("test-output").join("full-dates");
+	std::fs::create_dir_all(&directory).unwrap();
+
+	let mp3_path = directory.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &mp3_path).unwrap();
+	let mut tag = id3::Tag::read_from_path(&mp3_path).unwrap();
+	tag.remove("TYER");
+	tag.set_date_recorded(id3::Timestamp {
+		year: 2019,
+		month: Some(7),
+		day: Some(14),
+		hour: None,
+		minute: None,
+		second: None,
+	});
+	tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+
+	let flac_path = directory.join("sample.flac");
+	std::fs::copy("test-data/formats/sample.flac", &flac_path).unwrap();
+	let mut tag = metaflac::Tag::read_from_path(&flac_path).unwrap();
+	tag.set_vorbis("DATE", vec!["2019-07-14"]);
+	tag.save().unwrap();
+
+	for path in &[mp3_path, flac_path] {
+		let tags = read(path).unwrap().unwrap();
+		assert_eq!(tags.year, Some(2019));
+		assert_eq!(tags.original_date, Some("2019-07-14".to_owned()));
+	}
+}
+
+#[test]
+fn test_parse_date() {
+	assert_eq!(parse_date("2019"), Some("2019".to_owned()));
+	assert_eq!(parse_date("2019-07"), Some("2019-07".to_owned()));
+	assert_eq!(parse_date(" 2019-07-14 "), Some("2019-07-14".to_owned()));
+	assert_eq!(
+		parse_date("2019-07-14T20:00:00Z"),
+		Some("2019-07-14".to_owned())
+	);
+	assert_eq!(parse_date("2019-13-01"), Some("2019".to_owned()));
+	assert_eq!(parse_date("2019-7-14"), Some("2019".to_owned()));
+	assert_eq!(parse_date("19"), None);
+	assert_eq!(parse_date("July 2019"), None);
+	assert_eq!(
+		parse_year_and_date("2019-07-14"),
+		(Some(2019), Some("2019-07-14".to_owned()))
+	);
+	assert_eq!(parse_year_and_date("95"), (Some(95), None));
+}
+
+#[test]
+fn test_read_itunes_smpb() {
+	let directory = Path::new("test-output").join("itunes-smpb");
+	std::fs::create_dir_all(&directory).unwrap();
+	let mp3_path = directory.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &mp3_path).unwrap();
+	let mut tag = id3::Tag::read_from_path(&mp3_path).unwrap();
+	tag.add_comment(id3::frame::Comment {
+		lang: "eng".to_owned(),
+		description: ITUNES_SMPB.to_owned(),
+		text: " 00000000 00000840 000001CA 0000000000011F26".to_owned(),
+	});
+	tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+
+	let tags = read(&mp3_path).unwrap().unwrap();
+	assert_eq!(tags.audio.encoder_delay, Some(0x840));
+	assert_eq!(tags.audio.encoder_padding, Some(0x1ca));
+}
+
+#[test]
+fn test_parse_itunes_smpb() {
+	assert_eq!(
+		parse_itunes_smpb(" 00000000 00000210 000003C4 0000000000AB0C3C"),
+		Some((0x210, 0x3c4))
+	);
+	assert_eq!(parse_itunes_smpb(" 00000000 00000210"), None);
+	assert_eq!(parse_itunes_smpb(" 00000000 zz 000003C4"), None);
+	assert_eq!(parse_itunes_smpb(""), None);
+}
+
+#[test]
+fn test_parse_unique_file_id() {
+	let owner = MUSICBRAINZ_UFID_OWNER;
+	assert_eq!(
+		parse_unique_file_id(b"http://musicbrainz.org\0abc", owner),
+		Some("abc".to_owned())
+	);
+	assert_eq!(
+		parse_unique_file_id(b"http://example.com\0abc", owner),
+		None
+	);
+	assert_eq!(
+		parse_unique_file_id(b"http://musicbrainz.org\0", owner),
+		None
+	);
+	assert_eq!(parse_unique_file_id(b"http://musicbrainz.org", owner), None);
+}
+
+#[test]
+fn test_read_popularimeter() {
+	let directory = Path::new("test-output").join("popularimeter");
+	std::fs::create_dir_all(&directory).unwrap();
+	let path = directory.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &path).unwrap();
+	let mut tag = id3::Tag::read_from_path(&path).unwrap();
+	let data = b"player@example.com\0\xc4\0\0\x01\x02".to_vec();
+	tag.add_frame(id3::Frame::with_content(
+		"POPM",
+		id3::Content::Unknown(data),
+	));
+	tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+	let tags = read(&path).unwrap().unwrap();
+	assert_eq!(tags.rating, Some(4));
+	assert_eq!(tags.tagged_play_count, Some(258));
+
+	let tags = read(Path::new("test-data/formats/sample.mp3"))
+		.unwrap()
+		.unwrap();
+	assert_eq!(tags.rating, None);
+	assert_eq!(tags.tagged_play_count, None);
+}
+
+#[test]
+fn test_parse_popularimeter() {
+	assert_eq!(parse_popularimeter(b"\0\xff"), Some((Some(5), None)));
+	assert_eq!(parse_popularimeter(b"a@b\0\x01"), Some((Some(1), None)));
+	assert_eq!(
+		parse_popularimeter(b"a@b\0\0\0\0\0\x07"),
+		Some((None, Some(7)))
+	);
+	assert_eq!(parse_popularimeter(b"a@b\0"), None);
+	assert_eq!(parse_popularimeter(b"a@b"), None);
+}
+
+#[test]
+fn test_parse_bpm() {
+	assert_eq!(parse_bpm("128"), Some(128));
+	assert_eq!(parse_bpm("127.60"), Some(128));
+	assert_eq!(parse_bpm(" 90.4 "), Some(90));
+	assert_eq!(parse_bpm("0"), None);
+	assert_eq!(parse_bpm("fast"), None);
+}
+
+#[test]
+fn test_read_embedded_artwork() {
+	assert!(read_artwork(Path::new("test-data/artwork/sample.mp3")).is_some());
+	assert!(read_artwork(Path::new("test-data/artwork/sample.flac")).is_some());
+	assert!(read_artwork(Path::new("test-data/artwork/sample.m4a")).is_some());
+	assert!(read_artwork(Path::new("test-data/artwork/sample.ogg")).is_none());
+	assert!(read_artwork(Path::new("test-data/formats/sample.mp3")).is_none());
+}
+
 #[test]
 fn test_read_artwork() {
 	assert!(
 		read(Path::new("test-data/artwork/sample.mp3"))
+			.unwrap()
 			.unwrap()
 			.has_artwork
 	);
 	assert!(
 		read(Path::new("test-data/artwork/sample.flac"))
+			.unwrap()
 			.unwrap()
 			.has_artwork
 	);
 	assert!(
 		read(Path::new("test-data/artwork/sample.m4a"))
+			.unwrap()
 			.unwrap()
 			.has_artwork
 	);
 }
+
+#[test]
+fn test_repair_charset() {
+	let as_latin1 = |bytes: &[u8]| bytes.iter().map(|b| *b as char).collect::<String>();
+
+	assert_eq!(
+		repair_charset(&as_latin1("Beyoncé".as_bytes())).as_deref(),
+		Some("Beyoncé")
+	);
+	let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode("ポリリズム");
+	assert_eq!(
+		repair_charset(&as_latin1(&shift_jis)).as_deref(),
+		Some("ポリリズム")
+	);
+	assert_eq!(
+		repair_charset(&as_latin1(b"It\x92s a Sin")).as_deref(),
+		Some("It’s a Sin")
+	);
+
+	assert_eq!(repair_charset("Beyoncé"), None);
+	assert_eq!(repair_charset("Sigur Rós"), None);
+	assert_eq!(repair_charset("ポリリズム"), None);
+	assert_eq!(repair_charset("Khemmis"), None);
+}
+
+#[test]
+fn test_clean_text() {
+	let tags = SongTags {
+		title: Some("\u{feff}Poison".to_owned()),
+		album: Some("Sigur R\u{c3}\u{b3}s".to_owned()),
+		artists: vec!["\u{feff}Alice Cooper".to_owned()],
+		..Default::default()
+	};
+
+	let mut cleaned = tags.clone();
+	cleaned.clean_text(false);
+	assert_eq!(cleaned.title.as_deref(), Some("Poison"));
+	assert_eq!(cleaned.album.as_deref(), Some("Sigur R\u{c3}\u{b3}s"));
+	assert_eq!(cleaned.artists, vec!["Alice Cooper".to_owned()]);
+
+	let mut repaired = tags;
+	repaired.clean_text(true);
+	assert_eq!(repaired.album.as_deref(), Some("Sigur Rós"));
+}
@@ -4,13 +4,25 @@ use diesel::prelude::*;
 #[cfg(feature = "profile-index")]
 use flame;
 use log::error;
+use std::path::Path;
 use std::sync::{Arc, Condvar, Mutex};
 use std::time;
 
 use crate::config::MiscSettings;
 use crate::db::{misc_settings, DB};
-use crate::vfs::VFS;
+use crate::vfs::{VFSSource, VFS};
 
+mod archive;
+mod artwork;
+mod audio;
+mod cue;
+mod directory;
+mod error;
+mod files;
+#[cfg(test)]
+mod fixture;
+mod insert;
+mod m3u;
 mod metadata;
 mod query;
 #[cfg(test)]
@@ -18,6 +30,8 @@ mod test;
 mod types;
 mod update;
 
+pub use self::error::*;
+pub use self::metadata::{DefaultMetadataReader, MetadataReader, SongTags, TagTransform};
 pub use self::query::*;
 pub use self::types::*;
 pub use self::update::*;
@@ -26,12 +40,14 @@ pub fn builder(db: DB) -> IndexBuilder {
 	IndexBuilder {
 		db: db,
 		periodic_updates: true,
+		update_options: UpdateOptions::default(),
 	}
 }
 
 pub struct IndexBuilder {
 	db: DB,
 	periodic_updates: bool,
+	update_options: UpdateOptions,
 }
 
 impl IndexBuilder {
@@ -40,10 +56,16 @@ impl IndexBuilder {
 		self
 	}
 
+	pub fn artwork_cache(mut self, path: &Path) -> IndexBuilder {
+		self.update_options.artwork_cache = Some(path.to_owned());
+		self
+	}
+
 	pub fn build(self) -> Index {
 		let index = Index {
 			pending_reindex: Arc::new((Mutex::new(false), Condvar::new())),
 			db: self.db.clone(),
+			update_options: self.update_options,
 		};
 
 		let commands_index = index.clone();
@@ -66,6 +88,7 @@ impl IndexBuilder {
 pub struct Index {
 	db: DB,
 	pending_reindex: Arc<(Mutex<bool>, Condvar)>,
+	update_options: UpdateOptions,
 }
 
 impl Index {
@@ -76,6 +99,20 @@ impl Index {
 		cvar.notify_one();
 	}
 
+	// Reindexes a single directory of the collection right away, given its virtual path
+	pub fn update_directory(&self, virtual_path: &Path) -> Result<(), IndexError> {
+		let vfs = self.db.get_vfs().map_err(IndexError::VFS)?;
+		let real_path = vfs
+			.virtual_to_real(virtual_path)
+			.map_err(|_| IndexError::NotInMountPoint(virtual_path.to_owned()))?;
+		update_directory(&self.db, &real_path, &self.update_options)
+	}
+
+	// Compares the index against the file system, with the same filters as updates
+	pub fn verify(&self) -> Result<VerifyReport, IndexError> {
+		verify(&self.db, &self.update_options)
+	}
+
 	fn process_commands(&self) {
 		loop {
 			{
@@ -86,7 +123,7 @@ impl Index {
 				}
 				*pending = false;
 			}
-			if let Err(e) = update(&self.db) {
+			if let Err(e) = update(&self.db, &self.update_options) {
 				error!("Error while updating index: {}", e);
 			}
 		}
@@ -7,10 +7,14 @@ use diesel::sql_types;
 use flame;
 use std::path::Path;
 
-use crate::db::{directories, songs, DB};
+use crate::db::{albums, directories, song_artists, song_lyrics, songs, DB};
+use crate::index::metadata;
 use crate::index::*;
 use crate::vfs::VFSSource;
 
+// Rows read at once when iterating over the whole index
+const ITERATION_PAGE_SIZE: i64 = 500;
+
 no_arg_sql_function!(
 	random,
 	sql_types::Integer,
@@ -19,12 +23,12 @@ no_arg_sql_function!(
 
 #[cfg_attr(feature = "profile-index", flame)]
 pub fn virtualize_song(vfs: &VFS, mut song: Song) -> Option<Song> {
-	song.path = match vfs.real_to_virtual(Path::new(&song.path)) {
+	song.path = match vfs.stored_to_virtual(Path::new(&song.path)) {
 		Ok(p) => p.to_string_lossy().into_owned(),
 		_ => return None,
 	};
 	if let Some(artwork_path) = song.artwork {
-		song.artwork = match vfs.real_to_virtual(Path::new(&artwork_path)) {
+		song.artwork = match vfs.stored_to_virtual(Path::new(&artwork_path)) {
 			Ok(p) => Some(p.to_string_lossy().into_owned()),
 			_ => None,
 		};
@@ -34,12 +38,12 @@ pub fn virtualize_song(vfs: &VFS, mut song: Song) -> Option<Song> {
 
 #[cfg_attr(feature = "profile-index", flame)]
 fn virtualize_directory(vfs: &VFS, mut directory: Directory) -> Option<Directory> {
-	directory.path = match vfs.real_to_virtual(Path::new(&directory.path)) {
+	directory.path = match vfs.stored_to_virtual(Path::new(&directory.path)) {
 		Ok(p) => p.to_string_lossy().into_owned(),
 		_ => return None,
 	};
 	if let Some(artwork_path) = directory.artwork {
-		directory.artwork = match vfs.real_to_virtual(Path::new(&artwork_path)) {
+		directory.artwork = match vfs.stored_to_virtual(Path::new(&artwork_path)) {
 			Ok(p) => Some(p.to_string_lossy().into_owned()),
 			_ => None,
 		};
@@ -47,6 +51,97 @@ fn virtualize_directory(vfs: &VFS, mut directory: Directory) -> Option<Directory
 	Some(directory)
 }
 
+// Reads rows a page at a time, in the order of their ids, so that memory use does not grow with
+// the size of the index
+struct PagedRows<T, F> {
+	db: DB,
+	vfs: VFS,
+	load_page: F,
+	virtualize: fn(&VFS, T) -> Option<T>,
+	last_id: i32,
+	page: std::vec::IntoIter<(i32, T)>,
+	done: bool,
+}
+
+impl<T, F> Iterator for PagedRows<T, F>
+where
+	F: FnMut(&SqliteConnection, i32) -> QueryResult<Vec<(i32, T)>>,
+{
+	type Item = Result<T>;
+
+	fn next(&mut self) -> Option<Result<T>> {
+		loop {
+			if let Some((_, row)) = self.page.next() {
+				match (self.virtualize)(&self.vfs, row) {
+					Some(row) => return Some(Ok(row)),
+					None => continue,
+				}
+			}
+			if self.done {
+				return None;
+			}
+			let rows = self
+				.db
+				.connect()
+				.and_then(|c| (self.load_page)(&c, self.last_id).map_err(Error::new));
+			match rows {
+				Ok(rows) => {
+					self.done = rows.len() < ITERATION_PAGE_SIZE as usize;
+					if let Some((id, _)) = rows.last() {
+						self.last_id = *id;
+					}
+					self.page = rows.into_iter();
+				}
+				Err(e) => {
+					self.done = true;
+					return Some(Err(e));
+				}
+			}
+		}
+	}
+}
+
+#[allow(dead_code)]
+pub fn iter_songs(db: &DB) -> Result<impl Iterator<Item = Result<Song>>> {
+	Ok(PagedRows {
+		db: db.clone(),
+		vfs: db.get_vfs()?,
+		load_page: |connection: &SqliteConnection, last_id| {
+			songs::table
+				.select((songs::id, songs::all_columns))
+				.filter(songs::id.gt(last_id))
+				.filter(songs::deleted_at.is_null())
+				.order(songs::id)
+				.limit(ITERATION_PAGE_SIZE)
+				.load(connection)
+		},
+		virtualize: virtualize_song,
+		last_id: i32::MIN,
+		page: Vec::new().into_iter(),
+		done: false,
+	})
+}
+
+#[allow(dead_code)]
+pub fn iter_directories(db: &DB) -> Result<impl Iterator<Item = Result<Directory>>> {
+	Ok(PagedRows {
+		db: db.clone(),
+		vfs: db.get_vfs()?,
+		load_page: |connection: &SqliteConnection, last_id| {
+			directories::table
+				.select((directories::id, directories::all_columns))
+				.filter(directories::id.gt(last_id))
+				.order(directories::id)
+				.limit(ITERATION_PAGE_SIZE)
+				.load(connection)
+		},
+		virtualize: virtualize_directory,
+		last_id: i32::MIN,
+		page: Vec::new().into_iter(),
+		done: false,
+	})
+}
+
 pub fn browse<P>(db: &DB, virtual_path: P) -> Result<Vec<CollectionFile>>
 where
 	P: AsRef<Path>,
@@ -66,11 +161,11 @@ where
 		output.extend(virtual_directories.map(CollectionFile::Directory));
 	} else {
 		// Browse sub-directory
-		let real_path = vfs.virtual_to_real(virtual_path)?;
-		let real_path_string = real_path.as_path().to_string_lossy().into_owned();
+		let stored_path = vfs.virtual_to_stored(virtual_path)?;
+		let stored_path_string = stored_path.as_path().to_string_lossy().into_owned();
 
 		let real_directories: Vec<Directory> = directories::table
-			.filter(directories::parent.eq(&real_path_string))
+			.filter(directories::parent.eq(&stored_path_string))
 			.order(sql::<sql_types::Bool>("path COLLATE NOCASE ASC"))
 			.load(&connection)?;
 		let virtual_directories = real_directories
@@ -79,13 +174,14 @@ where
 		output.extend(virtual_directories.map(CollectionFile::Directory));
 
 		let real_songs: Vec<Song> = songs::table
-			.filter(songs::parent.eq(&real_path_string))
+			.filter(songs::parent.eq(&stored_path_string))
+			.filter(songs::deleted_at.is_null())
 			.order(sql::<sql_types::Bool>("path COLLATE NOCASE ASC"))
 			.load(&connection)?;
 		let virtual_songs = real_songs
 			.into_iter()
 			.filter_map(|s| virtualize_song(&vfs, s));
-		output.extend(virtual_songs.map(CollectionFile::Song));
+		output.extend(virtual_songs.map(|s| CollectionFile::Song(Box::new(s))));
 	}
 
 	Ok(output)
@@ -100,18 +196,22 @@ where
 	let connection = db.connect()?;
 
 	let real_songs: Vec<Song> = if virtual_path.as_ref().parent() != None {
-		let real_path = vfs.virtual_to_real(virtual_path)?;
+		let stored_path = vfs.virtual_to_stored(virtual_path)?;
 		let song_path_filter = {
-			let mut path_buf = real_path.clone();
+			let mut path_buf = stored_path.clone();
 			path_buf.push("%");
 			path_buf.as_path().to_string_lossy().into_owned()
 		};
 		songs
 			.filter(path.like(&song_path_filter))
+			.filter(deleted_at.is_null())
 			.order(path)
 			.load(&connection)?
 	} else {
-		songs.order(path).load(&connection)?
+		songs
+			.filter(deleted_at.is_null())
+			.order(path)
+			.load(&connection)?
 	};
 
 	let virtual_songs = real_songs
@@ -120,6 +220,85 @@ where
 	Ok(virtual_songs.collect::<Vec<_>>())
 }
 
+// Directories above the given one, from its mount point down
+pub fn get_ancestors(db: &DB, virtual_path: &Path) -> Result<Vec<Directory>> {
+	let vfs = db.get_vfs()?;
+	let connection = db.connect()?;
+	let stored_path = vfs.virtual_to_stored(virtual_path)?;
+	let stored_path_string = stored_path.as_path().to_string_lossy();
+
+	let ancestors: Option<String> = directories::table
+		.select(directories::ancestors)
+		.filter(directories::path.eq(stored_path_string))
+		.get_result(&connection)?;
+	let ancestors = match ancestors {
+		Some(a) if !a.is_empty() => a,
+		_ => return Ok(Vec::new()),
+	};
+	let real_directories: Vec<Directory> = directories::table
+		.filter(directories::path.eq_any(ancestors.split(ANCESTORS_SEPARATOR)))
+		.order(directories::depth)
+		.load(&connection)?;
+	let virtual_directories = real_directories
+		.into_iter()
+		.filter_map(|s| virtualize_directory(&vfs, s));
+	Ok(virtual_directories.collect::<Vec<_>>())
+}
+
+#[cfg_attr(feature = "profile-index", flame)]
+fn virtualize_album(vfs: &VFS, mut album: Album) -> Album {
+	album.directory = album
+		.directory
+		.and_then(|p| vfs.stored_to_virtual(Path::new(&p)).ok())
+		.map(|p| p.to_string_lossy().into_owned());
+	album.artwork = album
+		.artwork
+		.and_then(|p| vfs.stored_to_virtual(Path::new(&p)).ok())
+		.map(|p| p.to_string_lossy().into_owned());
+	album
+}
+
+// Albums are only listed when the index groups them
+#[allow(dead_code)]
+pub fn get_albums(db: &DB) -> Result<Vec<Album>> {
+	let vfs = db.get_vfs()?;
+	let connection = db.connect()?;
+	let real_albums: Vec<Album> = albums::table
+		.order((albums::artist, albums::name, albums::id))
+		.load(&connection)?;
+	Ok(real_albums
+		.into_iter()
+		.map(|a| virtualize_album(&vfs, a))
+		.collect())
+}
+
+#[allow(dead_code)]
+pub fn get_album_songs(db: &DB, album_id: i32) -> Result<Vec<Song>> {
+	let vfs = db.get_vfs()?;
+	let connection = db.connect()?;
+	let (musicbrainz_album_id, directory): (Option<String>, Option<String>) = albums::table
+		.select((albums::musicbrainz_album_id, albums::directory))
+		.find(album_id)
+		.get_result(&connection)?;
+	let query = songs::table
+		.filter(songs::deleted_at.is_null())
+		.order((songs::disc_number, songs::track_number, songs::path))
+		.into_boxed();
+	let query = match (musicbrainz_album_id, directory) {
+		(Some(id), _) => query.filter(songs::musicbrainz_album_id.eq(id)),
+		(None, Some(directory)) => query
+			.filter(songs::parent.eq(directory))
+			.filter(songs::musicbrainz_album_id.is_null())
+			.filter(songs::album.is_not_null()),
+		(None, None) => return Ok(Vec::new()),
+	};
+	let real_songs: Vec<Song> = query.load(&connection)?;
+	Ok(real_songs
+		.into_iter()
+		.filter_map(|s| virtualize_song(&vfs, s))
+		.collect())
+}
+
 pub fn get_random_albums(db: &DB, count: i64) -> Result<Vec<Directory>> {
 	use self::directories::dsl::*;
 	let vfs = db.get_vfs()?;
@@ -154,6 +333,7 @@ pub fn search(db: &DB, query: &str) -> Result<Vec<CollectionFile>> {
 	let vfs = db.get_vfs()?;
 	let connection = db.connect()?;
 	let like_test = format!("%{}%", query);
+	let folded_like_test = format!("%{}%", metadata::fold_search_text(query));
 	let mut output = Vec::new();
 
 	// Find dirs with matching path and parent not matching
@@ -174,22 +354,29 @@ pub fn search(db: &DB, query: &str) -> Result<Vec<CollectionFile>> {
 	// Find songs with matching title/album/artist and non-matching parent
 	{
 		use self::songs::dsl::*;
+		let matching_artists = song_artists::table
+			.filter(song_artists::artist.like(&like_test))
+			.select(song_artists::song_path);
 		let real_songs: Vec<Song> = songs
 			.filter(
 				path.like(&like_test)
 					.or(title.like(&like_test))
 					.or(album.like(&like_test))
 					.or(artist.like(&like_test))
-					.or(album_artist.like(&like_test)),
+					.or(album_artist.like(&like_test))
+					.or(search_title.like(&folded_like_test))
+					.or(search_artist.like(&folded_like_test))
+					.or(path.eq_any(matching_artists)),
 			)
 			.filter(parent.not_like(&like_test))
+			.filter(deleted_at.is_null())
 			.load(&connection)?;
 
 		let virtual_songs = real_songs
 			.into_iter()
 			.filter_map(|s| virtualize_song(&vfs, s));
 
-		output.extend(virtual_songs.map(CollectionFile::Song));
+		output.extend(virtual_songs.map(|s| CollectionFile::Song(Box::new(s))));
 	}
 
 	Ok(output)
@@ -198,12 +385,13 @@ pub fn search(db: &DB, query: &str) -> Result<Vec<CollectionFile>> {
 pub fn get_song(db: &DB, virtual_path: &Path) -> Result<Song> {
 	let vfs = db.get_vfs()?;
 	let connection = db.connect()?;
-	let real_path = vfs.virtual_to_real(virtual_path)?;
-	let real_path_string = real_path.as_path().to_string_lossy();
+	let stored_path = vfs.virtual_to_stored(virtual_path)?;
+	let stored_path_string = stored_path.as_path().to_string_lossy();
 
 	use self::songs::dsl::*;
 	let real_song: Song = songs
-		.filter(path.eq(real_path_string))
+		.filter(path.eq(stored_path_string))
+		.filter(deleted_at.is_null())
 		.get_result(&connection)?;
 
 	match virtualize_song(&vfs, real_song) {
@@ -211,3 +399,20 @@ pub fn get_song(db: &DB, virtual_path: &Path) -> Result<Song> {
 		_ => bail!("Missing VFS mapping"),
 	}
 }
+
+pub fn get_lyrics(db: &DB, virtual_path: &Path) -> Result<Option<Lyrics>> {
+	let vfs = db.get_vfs()?;
+	let connection = db.connect()?;
+	let stored_path = vfs.virtual_to_stored(virtual_path)?;
+	let stored_path_string = stored_path.as_path().to_string_lossy();
+
+	let lyrics = song_lyrics::table
+		.filter(song_lyrics::song_path.eq(stored_path_string))
+		.select((song_lyrics::lyrics, song_lyrics::has_synced_lyrics))
+		.get_result::<(String, i32)>(&connection)
+		.optional()?;
+	Ok(lyrics.map(|(lyrics, has_synced_lyrics)| Lyrics {
+		lyrics,
+		has_synced_lyrics: has_synced_lyrics != 0,
+	}))
+}
@@ -1,14 +1,22 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use crate::db;
-use crate::db::{directories, songs};
+use crate::db::{
+	directories, index_checkpoint, index_renames, indexed_playlist_songs, indexed_playlists,
+	misc_settings, song_artists, song_lyrics, songs,
+};
+use crate::index::fixture::{generate_collection, mount_collection};
 use crate::index::*;
+use crate::{config, vfs};
 
 #[test]
 fn test_populate() {
 	let db = db::get_test_db("populate.sqlite");
-	update(&db).unwrap();
-	update(&db).unwrap(); // Check that subsequent updates don't run into conflicts
+	update(&db, &UpdateOptions::default()).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap(); // Check that subsequent updates don't run into conflicts
 
 	let connection = db.connect().unwrap();
 	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
@@ -17,6 +25,3438 @@ fn test_populate() {
 	assert_eq!(all_songs.len(), 13);
 }
 
+#[test]
+fn test_indexer_steps() {
+	let db = db::get_test_db("indexer_steps.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_insert_buffer_size.eq(2),
+				misc_settings::index_clean_buffer_size.eq(2),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	let options = UpdateOptions::default();
+	let mut indexer = Indexer::new(&db, &options);
+	assert_eq!(indexer.state().phase, IndexPhase::Cleaning);
+
+	// Each scanning step visits a single directory and each writing step adds a single batch
+	let mut steps = Vec::new();
+	let mut state = indexer.state();
+	while state.phase != IndexPhase::Done {
+		let next_state = indexer.step().unwrap();
+		assert!(next_state.songs_added - state.songs_added <= 2);
+		assert!(next_state.directories_added - state.directories_added <= 2);
+		steps.push(state.phase);
+		state = next_state;
+	}
+	assert_eq!(state.songs_added, 13);
+	assert_eq!(state.directories_added, 6);
+	let count = |phase| steps.iter().filter(|p| **p == phase).count();
+	assert_eq!(count(IndexPhase::Scanning), 6);
+	assert!(count(IndexPhase::Writing) >= 10);
+	assert!(count(IndexPhase::Cleaning) >= 1);
+	let first_scan = steps.iter().position(|p| *p == IndexPhase::Scanning);
+	let last_clean = steps.iter().rposition(|p| *p == IndexPhase::Cleaning);
+	assert!(last_clean < first_scan);
+
+	// Stepping a finished update does nothing more
+	assert_eq!(indexer.step().unwrap(), state);
+	let summary = indexer.into_summary();
+	assert_eq!(summary.songs_added.count, 13);
+	assert_eq!(summary.stats.songs_inserted, 13);
+
+	// Later updates clean a chunk of entries at a time
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(songs::table)
+			.set(songs::path.eq(songs::path.concat(".missing")))
+			.execute(&connection)
+			.unwrap();
+	}
+	let mut indexer = Indexer::new(&db, &options);
+	let mut state = indexer.state();
+	let mut cleaning_steps = 0;
+	while state.phase == IndexPhase::Cleaning {
+		let next_state = indexer.step().unwrap();
+		assert!(next_state.songs_removed - state.songs_removed <= 2);
+		cleaning_steps += 1;
+		state = next_state;
+	}
+	assert_eq!(state.songs_removed, 13);
+	assert!(cleaning_steps >= 7);
+	while indexer.step().unwrap().phase != IndexPhase::Done {}
+	assert_eq!(indexer.into_summary().stats.songs_deleted, 13);
+}
+
+#[test]
+fn test_populate_single_thread() {
+	let db = db::get_test_db("populate_single_thread.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_thread_count.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(all_directories.len(), 6);
+	assert_eq!(all_songs.len(), 13);
+}
+
+#[test]
+fn test_populate_buffer_sizes() {
+	let db = db::get_test_db("populate_buffer_sizes.sqlite");
+	let set_buffer_sizes = |insert: i32, clean: i32| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_insert_buffer_size.eq(insert),
+				misc_settings::index_clean_buffer_size.eq(clean),
+			))
+			.execute(&connection)
+			.unwrap();
+	};
+
+	set_buffer_sizes(0, 500);
+	let error = update(&db, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::BufferSize(0)));
+
+	set_buffer_sizes(1, 1);
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(all_directories.len(), 6);
+	assert_eq!(all_songs.len(), 13);
+}
+
+// Cancels the update once the first file was read
+struct CancellingReader {
+	cancellation: Arc<AtomicBool>,
+}
+
+impl MetadataReader for CancellingReader {
+	fn read(&self, path: &Path) -> anyhow::Result<Option<SongTags>> {
+		self.cancellation.store(true, Ordering::Relaxed);
+		DefaultMetadataReader.read(path)
+	}
+}
+
+#[test]
+fn test_populate_cancelled_while_inserting() {
+	let db = db::get_test_db("populate_cancelled_while_inserting.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_low_memory.eq(1),
+				misc_settings::index_insert_buffer_size.eq(1),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	let cancellation = Arc::new(AtomicBool::new(false));
+	let options = UpdateOptions {
+		cancellation: Some(cancellation.clone()),
+		metadata_reader: Some(Arc::new(CancellingReader {
+			cancellation: cancellation.clone(),
+		})),
+		..Default::default()
+	};
+	let error = update(&db, &options).unwrap_err();
+	assert!(matches!(error, IndexError::Cancelled));
+}
+
+#[test]
+fn test_populate_low_memory() {
+	let db = db::get_test_db("populate_low_memory.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_low_memory.eq(1),
+				misc_settings::index_insert_buffer_size.eq(1),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(all_directories.len(), 6);
+	assert_eq!(all_songs.len(), 13);
+}
+
+#[test]
+fn test_populate_max_depth() {
+	let db = db::get_test_db("populate_max_depth.sqlite");
+	let set_max_depth = |max_depth: i32| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_max_depth.eq(max_depth))
+			.execute(&connection)
+			.unwrap();
+	};
+	let counts = || -> (i64, i64) {
+		let connection = db.connect().unwrap();
+		let directories = directories::table.count().get_result(&connection).unwrap();
+		let songs = songs::table.count().get_result(&connection).unwrap();
+		(directories, songs)
+	};
+
+	// Albums are two levels below the mount point, under their artist
+	set_max_depth(1);
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (3, 0));
+	assert_eq!(summary.stats.files_skipped_errors, 2);
+	assert_eq!(
+		verify(&db, &UpdateOptions::default()).unwrap(),
+		VerifyReport::default()
+	);
+
+	set_max_depth(0);
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (6, 13));
+
+	// Entries beyond a lowered limit are removed
+	set_max_depth(1);
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (3, 0));
+}
+
+#[test]
+fn test_update_detect_renames() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("renames-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	let old_path = collection_path.join("Before");
+	let new_path = collection_path.join("After");
+	std::fs::create_dir_all(&old_path).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", old_path.join("sample.mp3")).unwrap();
+	// Songs indexed after the moved one keep its id from being given to the new row
+	let other_path = collection_path.join("Other");
+	std::fs::create_dir_all(&other_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.flac",
+		other_path.join("sample.flac"),
+	)
+	.unwrap();
+
+	let db = db::get_test_db("update_detect_renames.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_detect_renames.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let song_id = |path: &Path| -> i32 {
+		let connection = db.connect().unwrap();
+		songs::table
+			.filter(songs::path.eq(path.to_string_lossy().as_ref()))
+			.select(songs::id)
+			.get_result(&connection)
+			.unwrap()
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	let old_id = song_id(&old_path.join("sample.mp3"));
+	let playlist_song = Path::new("root").join("Before").join("sample.mp3");
+	crate::playlist::save_playlist(
+		"renames",
+		"test_user",
+		&[playlist_song.to_string_lossy().into_owned()],
+		&db,
+	)
+	.unwrap();
+
+	// The moved song keeps its id and its place in playlists
+	std::fs::rename(&old_path, &new_path).unwrap();
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_removed.count, 1);
+	assert_eq!(summary.songs_added.count, 1);
+	assert_eq!(song_id(&new_path.join("sample.mp3")), old_id);
+	let playlist = crate::playlist::read_playlist("renames", "test_user", &db).unwrap();
+	assert_eq!(playlist.len(), 1);
+	assert_eq!(
+		Path::new(&playlist[0].path),
+		Path::new("root").join("After").join("sample.mp3")
+	);
+
+	let connection = db.connect().unwrap();
+	let removed_count: i64 = index_renames::table
+		.count()
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(removed_count, 0);
+}
+
+#[test]
+fn test_update_detect_renames_reordered() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("renames-reordered-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	let first_path = collection_path.join("First");
+	std::fs::create_dir_all(&first_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.ogg",
+		first_path.join("sample.ogg"),
+	)
+	.unwrap();
+
+	let db = db::get_test_db("update_detect_renames_reordered.sqlite");
+	mount_collection(&db, &collection_path);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_detect_renames.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let song_id = |path: &Path| -> i32 {
+		let connection = db.connect().unwrap();
+		songs::table
+			.filter(songs::path.eq(path.to_string_lossy().as_ref()))
+			.select(songs::id)
+			.get_result(&connection)
+			.unwrap()
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// The moved songs hold the highest ids, which are given again to the new rows
+	let old_path = collection_path.join("Second");
+	let new_path = collection_path.join("Third");
+	std::fs::create_dir_all(&old_path).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", old_path.join("a.mp3")).unwrap();
+	std::fs::copy("test-data/formats/sample.flac", old_path.join("b.flac")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	let mp3_id = song_id(&old_path.join("a.mp3"));
+	let flac_id = song_id(&old_path.join("b.flac"));
+
+	// Renaming the files reverses the order in which they are inserted
+	std::fs::rename(&old_path, &new_path).unwrap();
+	std::fs::rename(new_path.join("a.mp3"), new_path.join("b.mp3")).unwrap();
+	std::fs::rename(new_path.join("b.flac"), new_path.join("a.flac")).unwrap();
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_removed.count, 2);
+	assert_eq!(summary.songs_added.count, 2);
+	assert_eq!(song_id(&new_path.join("b.mp3")), mp3_id);
+	assert_eq!(song_id(&new_path.join("a.flac")), flac_id);
+
+	let connection = db.connect().unwrap();
+	let song_count: i64 = songs::table.count().get_result(&connection).unwrap();
+	assert_eq!(song_count, 3);
+}
+
+#[test]
+fn test_populate_include_patterns() {
+	let db = db::get_test_db("populate_include_patterns.sqlite");
+	let set_include_patterns = |include_patterns: &str| {
+		let config = config::Config {
+			mount_dirs: Some(vec![vfs::MountPoint {
+				source: "test-data/small-collection".to_owned(),
+				name: "root".to_owned(),
+				album_art_pattern: None,
+				include_patterns: Some(include_patterns.to_owned()),
+			}]),
+			..Default::default()
+		};
+		config::amend(&db, &config).unwrap();
+	};
+	let counts = || -> (i64, i64) {
+		let connection = db.connect().unwrap();
+		let directories = directories::table.count().get_result(&connection).unwrap();
+		let songs = songs::table.count().get_result(&connection).unwrap();
+		(directories, songs)
+	};
+
+	// Directories leading to a match are indexed without their siblings
+	set_include_patterns("Tobokegao/Picnic");
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (3, 7));
+	assert_eq!(
+		verify(&db, &UpdateOptions::default()).unwrap(),
+		VerifyReport::default()
+	);
+
+	set_include_patterns("Khemmis/**, */Picnic*");
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (6, 13));
+
+	// Entries which no longer match are removed
+	set_include_patterns("Tobokegao/Picnic");
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (3, 7));
+
+	set_include_patterns("Tobokegao/[");
+	let error = update(&db, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::IncludePattern(_)));
+}
+
+// The trigger makes insertions fail for songs matching the given path pattern
+fn kill_song_insertions(db: &db::DB, path_pattern: &str) {
+	use diesel::connection::SimpleConnection;
+	let connection = db.connect().unwrap();
+	connection
+		.batch_execute(&format!(
+			"CREATE TRIGGER kill_song_insertions BEFORE INSERT ON song_artists
+			WHEN NEW.song_path LIKE '{}'
+			BEGIN SELECT RAISE(ABORT, 'Insertion killed'); END;",
+			path_pattern
+		))
+		.unwrap();
+}
+
+#[test]
+fn test_populate_failed_flush() {
+	let db = db::get_test_db("populate_failed_flush.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_insert_buffer_size.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	kill_song_insertions(&db, "%Tobokegao%");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// Songs are lost along with their artists, but other batches are kept
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(all_directories.len(), 6);
+	assert!(!all_songs.is_empty());
+	assert!(all_songs.iter().all(|s| !s.path.contains("Tobokegao")));
+}
+
+#[test]
+fn test_populate_atomic() {
+	let db = db::get_test_db("populate_atomic.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_atomic.eq(1),
+				misc_settings::index_insert_buffer_size.eq(1),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	kill_song_insertions(&db, "%Tobokegao%");
+	let error = populate(&db, false, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::RolledBack));
+	{
+		let connection = db.connect().unwrap();
+		let directory_count: i64 = directories::table.count().get_result(&connection).unwrap();
+		let song_count: i64 = songs::table.count().get_result(&connection).unwrap();
+		assert_eq!(directory_count, 0);
+		assert_eq!(song_count, 0);
+	}
+
+	{
+		use diesel::connection::SimpleConnection;
+		let connection = db.connect().unwrap();
+		connection
+			.batch_execute("DROP TRIGGER kill_song_insertions;")
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(all_directories.len(), 6);
+	assert_eq!(all_songs.len(), 13);
+}
+
+// The index benchmark compares the speed of both journal modes
+#[test]
+fn test_populate_fast_writes() {
+	let collection_path = generate_collection("fast-writes", 4, 10);
+	let db = db::get_test_db("populate_fast_writes.sqlite");
+	mount_collection(&db, &collection_path);
+	set_fast_writes(&db, true);
+
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_added.count, 40);
+	assert_eq!(summary.stats.songs_inserted, 40);
+	assert_eq!(summary.directories_added.count, 5);
+
+	// The journal mode persists within the database file, so it must be restored
+	let connection = db.connect().unwrap();
+	assert_eq!(get_journal_mode(&connection), "delete");
+	let song_count: i64 = songs::table.count().get_result(&connection).unwrap();
+	assert_eq!(song_count, 40);
+}
+
+fn set_fast_writes(db: &db::DB, fast_writes: bool) {
+	let connection = db.connect().unwrap();
+	diesel::update(misc_settings::table)
+		.set((
+			misc_settings::index_insert_buffer_size.eq(10),
+			misc_settings::index_fast_writes.eq(fast_writes as i32),
+		))
+		.execute(&connection)
+		.unwrap();
+}
+
+fn get_journal_mode(connection: &diesel::SqliteConnection) -> String {
+	diesel::select(diesel::dsl::sql::<diesel::sql_types::Text>(
+		"journal_mode FROM pragma_journal_mode",
+	))
+	.get_result::<String>(connection)
+	.unwrap()
+	.to_lowercase()
+}
+
+#[test]
+fn test_populate_checkpoint() {
+	let db = db::get_test_db("populate_checkpoint.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let checkpoint_count = || -> i64 {
+		index_checkpoint::table
+			.count()
+			.get_result(&connection)
+			.unwrap()
+	};
+	assert_eq!(checkpoint_count(), 0);
+
+	// Leave the checkpoint of an interrupted update, which fully wrote one directory and only the
+	// songs of another
+	let directory_path = |name: &str| -> String {
+		directories::table
+			.select(directories::path)
+			.filter(directories::path.like(format!("%{}", name)))
+			.get_result(&connection)
+			.unwrap()
+	};
+	let hunted_path = directory_path("Hunted");
+	let picnic_path = directory_path("Picnic");
+	for (path, kind) in &[
+		(&hunted_path, "songs"),
+		(&hunted_path, "directory"),
+		(&picnic_path, "songs"),
+	] {
+		diesel::insert_into(index_checkpoint::table)
+			.values((
+				index_checkpoint::path.eq(path),
+				index_checkpoint::kind.eq(kind),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	diesel::update(songs::table)
+		.set(songs::title.eq("Altered"))
+		.execute(&connection)
+		.unwrap();
+	let altered_parents = || -> Vec<String> {
+		songs::table
+			.select(songs::parent)
+			.filter(songs::title.eq("Altered"))
+			.distinct()
+			.load(&connection)
+			.unwrap()
+	};
+
+	// Only the fully written directory is skipped when resuming
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	assert_eq!(altered_parents(), vec![hunted_path]);
+	assert_eq!(checkpoint_count(), 0);
+
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	assert!(altered_parents().is_empty());
+}
+
+#[test]
+fn test_populate_incremental() {
+	let db = db::get_test_db("populate_incremental.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let altered_title = "Not Candlelight";
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(songs::table.filter(songs::title.eq("Candlelight")))
+			.set(songs::title.eq(altered_title))
+			.execute(&connection)
+			.unwrap();
+	}
+	let count_title = |title: &str| -> usize {
+		let connection = db.connect().unwrap();
+		songs::table
+			.filter(songs::title.eq(title))
+			.load::<Song>(&connection)
+			.unwrap()
+			.len()
+	};
+
+	// Unchanged directories are skipped, so the altered row survives
+	populate(&db, false, &UpdateOptions::default()).unwrap();
+	assert_eq!(count_title(altered_title), 1);
+
+	// A full rescan reads tags again
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	assert_eq!(count_title(altered_title), 0);
+	assert_eq!(count_title("Candlelight"), 1);
+}
+
+#[test]
+fn test_populate_song_artists() {
+	let db = db::get_test_db("populate_song_artists.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let tobokegao_songs: Vec<String> = song_artists::table
+		.filter(song_artists::artist.eq("Tobokegao"))
+		.select(song_artists::song_path)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(tobokegao_songs.len(), 8);
+}
+
+#[test]
+fn test_populate_file_times() {
+	let db = db::get_test_db("populate_file_times.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let seconds = |t: std::io::Result<std::time::SystemTime>| {
+		t.ok()
+			.map(|t| (t.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()) as i64)
+	};
+
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	for directory in all_directories {
+		let metadata = std::fs::metadata(&directory.path).unwrap();
+		assert_eq!(
+			directory.created.map(i64::from),
+			seconds(metadata.created())
+		);
+		assert_eq!(
+			directory.modified.map(i64::from),
+			seconds(metadata.modified())
+		);
+	}
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	for song in all_songs {
+		let metadata = std::fs::metadata(&song.path).unwrap();
+		assert_eq!(song.created, seconds(metadata.created()));
+		assert_eq!(song.mtime, seconds(metadata.modified()));
+	}
+}
+
+#[test]
+fn test_populate_directory_kinds() {
+	let db = db::get_test_db("populate_directory_kinds.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let collection_path = Path::new("test-data").join("small-collection");
+	let connection = db.connect().unwrap();
+	let kind_of = |path: &Path| -> Option<String> {
+		directories::table
+			.select(directories::kind)
+			.filter(directories::path.eq(path.to_string_lossy().as_ref()))
+			.get_result(&connection)
+			.unwrap()
+	};
+	let kind_of = |path: &Path| kind_of(path).unwrap();
+
+	assert_eq!(kind_of(&collection_path), "other");
+	assert_eq!(kind_of(&collection_path.join("Khemmis")), "artist");
+	assert_eq!(
+		kind_of(&collection_path.join("Khemmis").join("Hunted")),
+		"album"
+	);
+	assert_eq!(kind_of(&collection_path.join("Tobokegao")), "artist");
+	assert_eq!(
+		kind_of(&collection_path.join("Tobokegao").join("Picnic")),
+		"album"
+	);
+}
+
+#[test]
+fn test_populate_album_keys() {
+	let db = db::get_test_db("populate_album_keys.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let hunted_path = Path::new("test-data")
+		.join("small-collection")
+		.join("Khemmis")
+		.join("Hunted");
+	let hunted_path = hunted_path.to_string_lossy();
+	let connection = db.connect().unwrap();
+	let directory_key: Option<String> = directories::table
+		.select(directories::album_key)
+		.filter(directories::path.eq(hunted_path.as_ref()))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(directory_key.as_deref(), Some("khemmis\thunted"));
+
+	// Songs are found by key alone, and folders without an album have none
+	let song_count: i64 = songs::table
+		.filter(songs::album_key.eq(&directory_key))
+		.count()
+		.get_result(&connection)
+		.unwrap();
+	let hunted_song_count: i64 = songs::table
+		.filter(songs::parent.eq(hunted_path.as_ref()))
+		.count()
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(song_count, hunted_song_count);
+	let keyless_directories: Vec<Directory> = directories::table
+		.filter(directories::album_key.is_null())
+		.load(&connection)
+		.unwrap();
+	assert!(keyless_directories.iter().all(|d| d.album.is_none()));
+	assert!(!keyless_directories.is_empty());
+}
+
+#[test]
+fn test_populate_progress() {
+	let db = db::get_test_db("populate_progress.sqlite");
+	let (sender, receiver) = crossbeam_channel::unbounded();
+	let options = UpdateOptions {
+		progress: Some(sender),
+		..Default::default()
+	};
+	update(&db, &options).unwrap();
+
+	let reports = receiver.try_iter().collect::<Vec<_>>();
+	assert!(!reports.is_empty());
+	let directories_visited = reports.iter().map(|p| p.directories_visited).max();
+	let songs_inserted = reports.iter().map(|p| p.songs_inserted).max();
+	assert_eq!(directories_visited, Some(6));
+	assert_eq!(songs_inserted, Some(13));
+	assert!(reports.iter().all(|p| p.songs_total == 13));
+	assert!(reports.iter().all(|p| !p.current_directory.is_empty()));
+}
+
+#[test]
+fn test_populate_cancelled() {
+	let db = db::get_test_db("populate_cancelled.sqlite");
+	let cancellation = Arc::new(AtomicBool::new(true));
+	let options = UpdateOptions {
+		cancellation: Some(cancellation.clone()),
+		..Default::default()
+	};
+	let error = update(&db, &options).unwrap_err();
+	assert!(matches!(error, IndexError::Cancelled));
+
+	{
+		let connection = db.connect().unwrap();
+		let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+		assert!(all_directories.is_empty());
+	}
+
+	cancellation.store(false, Ordering::Relaxed);
+	update(&db, &options).unwrap();
+
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(all_directories.len(), 6);
+	assert_eq!(all_songs.len(), 13);
+}
+
+#[test]
+fn test_populate_ignored_files() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("ignored-files-collection");
+	let album_path = collection_path.join("Album");
+	let hidden_path = collection_path.join(".hidden");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[&album_path, &hidden_path] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/artwork/sample.mp3", path.join("sample.mp3")).unwrap();
+	}
+	std::fs::copy(
+		"test-data/artwork/sample.mp3",
+		album_path.join("._sample.mp3"),
+	)
+	.unwrap();
+	std::fs::write(album_path.join("Thumbs.db"), b"").unwrap();
+
+	let db = db::get_test_db("populate_ignored_files.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+
+	let set_ignore_patterns = |patterns: &str| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_ignore_patterns.eq(patterns))
+			.execute(&connection)
+			.unwrap();
+	};
+	let count_entries = || -> (usize, usize) {
+		let connection = db.connect().unwrap();
+		let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+		let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+		(all_directories.len(), all_songs.len())
+	};
+
+	set_ignore_patterns("");
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(count_entries(), (3, 3));
+
+	// Default patterns skip dotfiles, and clean removes entries which are now ignored
+	set_ignore_patterns(".*,Thumbs.db,desktop.ini");
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(count_entries(), (2, 1));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_populate_symlinks() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("symlinks-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::copy(
+		"test-data/artwork/sample.mp3",
+		album_path.join("sample.mp3"),
+	)
+	.unwrap();
+	std::os::unix::fs::symlink("Album", collection_path.join("Link")).unwrap();
+	std::os::unix::fs::symlink("..", album_path.join("Loop")).unwrap();
+
+	let index_collection = |db_name: &str, follow_symlinks: i32| -> Vec<Directory> {
+		let db = db::get_test_db(db_name);
+		let config = config::Config {
+			mount_dirs: Some(vec![vfs::MountPoint {
+				source: collection_path.to_string_lossy().into_owned(),
+				name: "root".to_owned(),
+				album_art_pattern: None,
+				include_patterns: None,
+			}]),
+			..Default::default()
+		};
+		config::amend(&db, &config).unwrap();
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_follow_symlinks.eq(follow_symlinks))
+			.execute(&connection)
+			.unwrap();
+
+		update(&db, &UpdateOptions::default()).unwrap();
+		let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+		assert_eq!(all_songs.len(), 1);
+		directories::table.load(&connection).unwrap()
+	};
+
+	// The looping symlink and the second path to the album are skipped
+	let all_directories = index_collection("populate_symlinks.sqlite", 1);
+	assert_eq!(all_directories.len(), 2);
+
+	let all_directories = index_collection("populate_symlinks_disabled.sqlite", 0);
+	assert_eq!(all_directories.len(), 2);
+	assert!(all_directories.iter().all(|d| !d.path.contains("Link")));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resolve_file_symlinks() {
+	let collection_path = Path::new("test-output").join("resolve-symlinks-collection");
+	let archive_path = Path::new("test-output").join("resolve-symlinks-archive");
+	let album_path = collection_path.join("Album");
+	for path in &[&collection_path, &archive_path] {
+		if path.exists() {
+			std::fs::remove_dir_all(path).unwrap();
+		}
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::create_dir_all(&archive_path).unwrap();
+	std::fs::copy(
+		"test-data/artwork/sample.mp3",
+		archive_path.join("sample.mp3"),
+	)
+	.unwrap();
+	std::fs::copy(
+		"test-data/artwork/sample.mp3",
+		album_path.join("regular.mp3"),
+	)
+	.unwrap();
+	let target_path = std::fs::canonicalize(archive_path.join("sample.mp3")).unwrap();
+	std::os::unix::fs::symlink(&target_path, album_path.join("linked.mp3")).unwrap();
+
+	let db = db::get_test_db("resolve_file_symlinks.sqlite");
+	mount_collection(&db, &collection_path);
+	let connection = db.connect().unwrap();
+	let song_targets = || -> Vec<(String, Option<String>)> {
+		songs::table
+			.select((songs::path, songs::link_target))
+			.order(songs::path)
+			.load(&connection)
+			.unwrap()
+	};
+
+	diesel::update(misc_settings::table)
+		.set(misc_settings::index_resolve_file_symlinks.eq(1))
+		.execute(&connection)
+		.unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// Songs keep the path of their link, which is what the VFS serves
+	let linked_path = album_path.join("linked.mp3").to_string_lossy().into_owned();
+	let regular_path = album_path
+		.join("regular.mp3")
+		.to_string_lossy()
+		.into_owned();
+	assert_eq!(
+		song_targets(),
+		vec![
+			(
+				linked_path.clone(),
+				Some(target_path.to_string_lossy().into_owned())
+			),
+			(regular_path.clone(), None),
+		]
+	);
+
+	// A resolved song exists as long as both its link and its target do
+	let existing_paths = vec![
+		collection_path.clone(),
+		album_path.clone(),
+		target_path,
+		PathBuf::from(&regular_path),
+	];
+	let clean_with_paths = |paths: Vec<PathBuf>| {
+		let options = UpdateOptions {
+			path_existence: Some(Arc::new(FakePathExistence {
+				paths: paths.into_iter().collect(),
+			})),
+			..Default::default()
+		};
+		clean(&db, &options).unwrap()
+	};
+	let mut paths = existing_paths.clone();
+	paths.push(PathBuf::from(&linked_path));
+	let summary = clean_with_paths(paths);
+	assert_eq!(summary.stats.songs_deleted, 0);
+
+	// The link is no longer served by the VFS once it is gone
+	let summary = clean_with_paths(existing_paths);
+	assert_eq!(summary.stats.songs_deleted, 1);
+	assert_eq!(song_targets(), vec![(regular_path.clone(), None)]);
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	assert_eq!(song_targets().len(), 2);
+
+	std::fs::remove_file(archive_path.join("sample.mp3")).unwrap();
+	let summary = clean(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 1);
+	assert_eq!(song_targets(), vec![(regular_path, None)]);
+}
+
+#[test]
+fn test_populate_warnings() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("warnings-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::copy(
+		"test-data/artwork/sample.mp3",
+		album_path.join("sample.mp3"),
+	)
+	.unwrap();
+	std::fs::write(album_path.join("corrupted.mp3"), b"not an mp3").unwrap();
+	#[cfg(unix)]
+	std::os::unix::fs::symlink("missing.mp3", album_path.join("dangling.mp3")).unwrap();
+
+	let db = db::get_test_db("populate_warnings.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+
+	let (sender, receiver) = crossbeam_channel::unbounded();
+	let options = UpdateOptions {
+		warnings: Some(sender),
+		..Default::default()
+	};
+	let summary = update(&db, &options).unwrap();
+	let mut warning_paths = summary
+		.warnings
+		.iter()
+		.map(|w| w.path.clone())
+		.collect::<Vec<_>>();
+	warning_paths.sort();
+	let mut expected_paths = vec![album_path.join("corrupted.mp3")];
+	#[cfg(unix)]
+	expected_paths.push(album_path.join("dangling.mp3"));
+	assert_eq!(warning_paths, expected_paths);
+	assert_eq!(summary.songs_added.count, 1);
+	assert_eq!(summary.stats.files_skipped_errors, expected_paths.len());
+
+	// Warnings are also sent as they occur
+	let mut sent_warnings = receiver.try_iter().collect::<Vec<_>>();
+	sent_warnings.sort_by(|a, b| a.path.cmp(&b.path));
+	let mut summary_warnings = summary.warnings.clone();
+	summary_warnings.sort_by(|a, b| a.path.cmp(&b.path));
+	assert_eq!(sent_warnings, summary_warnings);
+}
+
+#[test]
+fn test_populate_min_file_size() {
+	let collection_path = generate_collection("min-file-size", 1, 2);
+	let album_path = collection_path.join("Album 000");
+	std::fs::write(album_path.join("03 - Song.mp3"), b"").unwrap();
+	let truncated = std::fs::read("test-data/formats/sample.mp3").unwrap();
+	std::fs::write(album_path.join("04 - Song.mp3"), &truncated[..100]).unwrap();
+
+	let db = db::get_test_db("populate_min_file_size.sqlite");
+	mount_collection(&db, &collection_path);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_min_file_size.eq(256))
+			.execute(&connection)
+			.unwrap();
+	}
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_added.count, 2);
+	let mut warning_paths = summary
+		.warnings
+		.iter()
+		.map(|w| w.path.clone())
+		.collect::<Vec<_>>();
+	warning_paths.sort();
+	assert_eq!(
+		warning_paths,
+		vec![
+			album_path.join("03 - Song.mp3"),
+			album_path.join("04 - Song.mp3")
+		]
+	);
+	assert_eq!(
+		verify(&db, &UpdateOptions::default()).unwrap(),
+		VerifyReport::default()
+	);
+}
+
+// Reads titles from file names, without looking at the files
+struct FileNameReader;
+
+impl MetadataReader for FileNameReader {
+	fn read(&self, path: &Path) -> anyhow::Result<Option<SongTags>> {
+		if path.extension().map_or(true, |e| e != "song") {
+			return Ok(None);
+		}
+		Ok(Some(SongTags {
+			title: path.file_stem().map(|s| s.to_string_lossy().into_owned()),
+			..Default::default()
+		}))
+	}
+}
+
+#[test]
+fn test_metadata_reader() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("metadata-reader-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	for name in &["First.song", "Second.song", "notes.txt"] {
+		std::fs::write(collection_path.join(name), b"").unwrap();
+	}
+
+	let db = db::get_test_db("metadata_reader.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq("song"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let options = UpdateOptions {
+		metadata_reader: Some(Arc::new(FileNameReader)),
+		..Default::default()
+	};
+	update(&db, &options).unwrap();
+
+	let connection = db.connect().unwrap();
+	let titles: Vec<Option<String>> = songs::table
+		.select(songs::title)
+		.order(songs::path)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(
+		titles,
+		vec![Some("First".to_owned()), Some("Second".to_owned())]
+	);
+}
+
+// Records which files tags are read from
+#[derive(Default)]
+struct RecordingReader {
+	paths: std::sync::Mutex<Vec<PathBuf>>,
+}
+
+impl MetadataReader for RecordingReader {
+	fn read(&self, path: &Path) -> anyhow::Result<Option<SongTags>> {
+		self.paths.lock().unwrap().push(path.to_owned());
+		DefaultMetadataReader.read(path)
+	}
+}
+
+#[test]
+fn test_populate_extensions() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("extensions-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.mp3",
+		collection_path.join("sample.mp3"),
+	)
+	.unwrap();
+	std::fs::write(collection_path.join("booklet.pdf"), b"").unwrap();
+
+	let db = db::get_test_db("extensions.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let set_extensions = |extensions: &str| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq(extensions))
+			.execute(&connection)
+			.unwrap();
+	};
+	let read_paths = |reader: &RecordingReader| {
+		let mut paths = reader.paths.lock().unwrap().clone();
+		paths.sort();
+		paths
+	};
+	let song_count = || -> i64 {
+		let connection = db.connect().unwrap();
+		songs::table.count().get_result(&connection).unwrap()
+	};
+
+	let reader = Arc::new(RecordingReader::default());
+	let options = UpdateOptions {
+		metadata_reader: Some(reader.clone()),
+		..Default::default()
+	};
+	populate(&db, true, &options).unwrap();
+	assert_eq!(
+		read_paths(&reader),
+		vec![collection_path.join("sample.mp3")]
+	);
+	assert_eq!(song_count(), 1);
+
+	// Without extensions, every file is read
+	set_extensions("");
+	let reader = Arc::new(RecordingReader::default());
+	let options = UpdateOptions {
+		metadata_reader: Some(reader.clone()),
+		..Default::default()
+	};
+	populate(&db, true, &options).unwrap();
+	assert_eq!(
+		read_paths(&reader),
+		vec![
+			collection_path.join("booklet.pdf"),
+			collection_path.join("sample.mp3")
+		]
+	);
+
+	// Songs whose extension is no longer allowed are removed
+	set_extensions(".flac, .OGG");
+	clean(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(song_count(), 0);
+	assert_eq!(
+		verify(&db, &UpdateOptions::default()).unwrap(),
+		VerifyReport::default()
+	);
+}
+
+// Reads the album and artist from the two lines of each file
+struct TextTagReader;
+
+impl MetadataReader for TextTagReader {
+	fn read(&self, path: &Path) -> anyhow::Result<Option<SongTags>> {
+		let content = std::fs::read_to_string(path)?;
+		let mut lines = content.lines().map(|l| l.to_owned());
+		Ok(Some(SongTags {
+			album: lines.next(),
+			artist: lines.next(),
+			..Default::default()
+		}))
+	}
+}
+
+#[test]
+fn test_directory_tag_casing() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("directory-tag-casing-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	for (name, tags) in &[
+		("a.song", "back in black\nac/dc"),
+		("b.song", "Back In Black\nAC/DC"),
+		("c.song", "Back In Black\nAC/DC"),
+	] {
+		std::fs::write(collection_path.join(name), tags).unwrap();
+	}
+
+	let db = db::get_test_db("directory_tag_casing.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq("song"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let options = UpdateOptions {
+		metadata_reader: Some(Arc::new(TextTagReader)),
+		..Default::default()
+	};
+	update(&db, &options).unwrap();
+
+	let connection = db.connect().unwrap();
+	let (album, artist): (Option<String>, Option<String>) = directories::table
+		.select((directories::album, directories::artist))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(album, Some("Back In Black".to_owned()));
+	assert_eq!(artist, Some("AC/DC".to_owned()));
+
+	// Songs keep their own casing
+	let song_albums: Vec<Option<String>> = songs::table
+		.select(songs::album)
+		.order(songs::path)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(song_albums[0], Some("back in black".to_owned()));
+}
+
+#[test]
+fn test_metadata_conflicts() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("metadata-conflicts-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	for (name, tags) in &[
+		("a.song", "First Album\nArtist"),
+		("b.song", "Second Album\nartist"),
+	] {
+		std::fs::write(collection_path.join(name), tags).unwrap();
+	}
+
+	let db = db::get_test_db("metadata_conflicts.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq("song"))
+			.execute(&connection)
+			.unwrap();
+	}
+
+	let options = UpdateOptions {
+		metadata_reader: Some(Arc::new(TextTagReader)),
+		..Default::default()
+	};
+	let summary = populate(&db, true, &options).unwrap();
+	assert!(summary.metadata_conflicts.is_empty());
+
+	let options = UpdateOptions {
+		report_metadata_conflicts: true,
+		..options
+	};
+	let mut summary = populate(&db, true, &options).unwrap();
+	assert_eq!(summary.metadata_conflicts.len(), 1);
+	let conflict = &mut summary.metadata_conflicts[0];
+	conflict.values.sort();
+	assert_eq!(
+		*conflict,
+		MetadataConflict {
+			path: collection_path,
+			field: MetadataField::Album,
+			values: vec!["First Album".to_owned(), "Second Album".to_owned()],
+		}
+	);
+}
+
+#[test]
+fn test_disc_folders() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("disc-folders-collection");
+	let album_path = collection_path.join("Box Set");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for (disc, name, tags) in &[
+		("Disc 1", "a.song", "The Box Set\nBand"),
+		("Disc 1", "b.song", "The Box Set\nBand"),
+		("CD2", "c.song", "the box set\nBand"),
+	] {
+		let disc_path = album_path.join(disc);
+		std::fs::create_dir_all(&disc_path).unwrap();
+		std::fs::write(disc_path.join(name), tags).unwrap();
+	}
+
+	let db = db::get_test_db("disc_folders.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq("song"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let options = UpdateOptions {
+		metadata_reader: Some(Arc::new(TextTagReader)),
+		..Default::default()
+	};
+
+	// Merging discs gives the same result when repeated
+	for _ in 0..2 {
+		update(&db, &options).unwrap();
+		let albums = get_recent_albums(&db, 10).unwrap();
+		assert_eq!(albums.len(), 1);
+		assert_eq!(
+			albums[0].path,
+			Path::new("root").join("Box Set").to_string_lossy()
+		);
+		assert_eq!(albums[0].album, Some("The Box Set".to_owned()));
+		assert_eq!(albums[0].artist, Some("Band".to_owned()));
+		assert_eq!(albums[0].song_count, 3);
+	}
+
+	// Disc folders are indexed as albums when the pattern is disabled
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_disc_folder_pattern.eq(""))
+			.execute(&connection)
+			.unwrap();
+	}
+	populate(&db, true, &options).unwrap();
+	assert_eq!(get_recent_albums(&db, 10).unwrap().len(), 2);
+
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_disc_folder_pattern.eq("("))
+			.execute(&connection)
+			.unwrap();
+	}
+	let error = update(&db, &options).unwrap_err();
+	assert!(matches!(error, IndexError::DiscFolderPattern(_)));
+}
+
+#[test]
+fn test_tag_transform() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("tag-transform-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	for (name, tags) in &[
+		("a.song", "Album [Explicit]\nSinger feat. Guest"),
+		("b.song", "Album\nSinger feat. Guest"),
+	] {
+		std::fs::write(collection_path.join(name), tags).unwrap();
+	}
+
+	let db = db::get_test_db("tag_transform.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq("song"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let options = UpdateOptions {
+		metadata_reader: Some(Arc::new(TextTagReader)),
+		tag_transform: Some(TagTransform(Arc::new(|tags: &mut SongTags| {
+			tags.album = tags.album.as_ref().map(|a| a.replace(" [Explicit]", ""));
+			tags.artist = tags.artist.as_ref().map(|a| a.replace(" feat. ", " ft. "));
+		}))),
+		..Default::default()
+	};
+	update(&db, &options).unwrap();
+
+	let connection = db.connect().unwrap();
+	let songs: Vec<(Option<String>, Option<String>)> = songs::table
+		.select((songs::album, songs::artist))
+		.order(songs::path)
+		.load(&connection)
+		.unwrap();
+	let expected_song = (
+		Some("Album".to_owned()),
+		Some("Singer ft. Guest".to_owned()),
+	);
+	assert_eq!(songs, vec![expected_song.clone(), expected_song.clone()]);
+
+	// Directories are consolidated from the transformed tags
+	let directory: (Option<String>, Option<String>) = directories::table
+		.select((directories::album, directories::artist))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(directory, expected_song);
+}
+
+// Opening a named pipe blocks until something writes to it, like a read which never completes
+#[test]
+#[cfg(unix)]
+fn test_tag_read_timeout() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("tag-read-timeout-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.mp3",
+		collection_path.join("a.mp3"),
+	)
+	.unwrap();
+	for name in &["hanging-1.mp3", "hanging-2.mp3"] {
+		let status = std::process::Command::new("mkfifo")
+			.arg(collection_path.join(name))
+			.status()
+			.unwrap();
+		assert!(status.success());
+	}
+
+	let db = db::get_test_db("tag_read_timeout.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_tag_read_timeout_seconds.eq(1),
+				misc_settings::index_tag_read_threads.eq(1),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+
+	// With a single reading thread, a single read may be stuck before files are no longer read
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_added.count, 1);
+	let mut warning_paths = summary
+		.warnings
+		.iter()
+		.map(|w| w.path.clone())
+		.collect::<Vec<_>>();
+	warning_paths.sort();
+	assert_eq!(
+		warning_paths,
+		vec![
+			collection_path.join("hanging-1.mp3"),
+			collection_path.join("hanging-2.mp3")
+		]
+	);
+	assert!(summary
+		.warnings
+		.iter()
+		.any(|w| w.message.contains("timed out")));
+	assert!(summary
+		.warnings
+		.iter()
+		.any(|w| w.message.contains("still stuck")));
+}
+
+// Takes its time over songs whose name starts with "slow"
+struct SlowReader;
+
+impl MetadataReader for SlowReader {
+	fn read(&self, path: &Path) -> anyhow::Result<Option<SongTags>> {
+		let file_name = path.file_name().unwrap().to_string_lossy();
+		if file_name.starts_with("slow") {
+			std::thread::sleep(std::time::Duration::from_millis(500));
+		}
+		FileNameReader.read(path)
+	}
+}
+
+#[test]
+fn test_slow_directories() {
+	let collection_path = Path::new("test-output").join("slow-directories-collection");
+	let slow_path = collection_path.join("Slow");
+	let fast_path = slow_path.join("Fast");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&fast_path).unwrap();
+	for path in &[
+		slow_path.join("slow.song"),
+		slow_path.join("other.song"),
+		fast_path.join("fast.song"),
+	] {
+		std::fs::write(path, b"").unwrap();
+	}
+
+	let db = db::get_test_db("slow_directories.sqlite");
+	mount_collection(&db, &collection_path);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_extensions.eq("song"),
+				misc_settings::index_slow_directory_threshold_ms.eq(300),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	let options = UpdateOptions {
+		metadata_reader: Some(Arc::new(SlowReader)),
+		..Default::default()
+	};
+
+	// The slow directory does not include the time spent in the one below it
+	let summary = update(&db, &options).unwrap();
+	assert_eq!(summary.songs_added.count, 3);
+	assert_eq!(summary.slow_directories.len(), 1);
+	let slow_directory = &summary.slow_directories[0];
+	assert_eq!(slow_directory.path, slow_path);
+	assert_eq!(slow_directory.file_count, 3);
+	assert!(slow_directory.elapsed >= std::time::Duration::from_millis(500));
+
+	let db = db::get_test_db("slow_directories_disabled.sqlite");
+	mount_collection(&db, &collection_path);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq("song"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let summary = update(&db, &options).unwrap();
+	assert!(summary.slow_directories.is_empty());
+}
+
+#[test]
+fn test_populate_cue_sheets() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("cue-collection");
+	let album_path = collection_path.join("Album");
+	let malformed_path = collection_path.join("Malformed");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::create_dir_all(&malformed_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.flac",
+		album_path.join("album.flac"),
+	)
+	.unwrap();
+	std::fs::write(
+		album_path.join("album.cue"),
+		"PERFORMER \"Cue Artist\"\nTITLE \"Cue Album\"\nFILE \"album.flac\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"First\"\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    TITLE \"Second\"\n    INDEX 01 00:01:00\n",
+	)
+	.unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.flac",
+		malformed_path.join("album.flac"),
+	)
+	.unwrap();
+	std::fs::write(
+		malformed_path.join("album.cue"),
+		"FILE \"album.flac\" WAVE\n",
+	)
+	.unwrap();
+
+	let db = db::get_test_db("populate_cue_sheets.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	let warning_paths = summary
+		.warnings
+		.iter()
+		.map(|w| w.path.clone())
+		.collect::<Vec<_>>();
+	assert_eq!(warning_paths, vec![malformed_path.join("album.cue")]);
+	update(&db, &UpdateOptions::default()).unwrap(); // Check that split tracks are not duplicated
+
+	let connection = db.connect().unwrap();
+	let tracks: Vec<Song> = songs::table
+		.filter(songs::parent.eq(album_path.to_string_lossy().as_ref()))
+		.order(songs::track_number)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(tracks.len(), 2);
+	assert_eq!(tracks[0].title, Some("First".to_owned()));
+	assert_eq!(tracks[0].start_time, Some(0));
+	assert_eq!(tracks[0].duration, Some(1));
+	assert_eq!(tracks[1].title, Some("Second".to_owned()));
+	assert_eq!(tracks[1].start_time, Some(1));
+	assert_eq!(tracks[1].album, Some("Cue Album".to_owned()));
+	assert_eq!(tracks[1].artist, Some("Cue Artist".to_owned()));
+
+	let fallback: Vec<Song> = songs::table
+		.filter(songs::parent.eq(malformed_path.to_string_lossy().as_ref()))
+		.load(&connection)
+		.unwrap();
+	assert_eq!(fallback.len(), 1);
+	assert_eq!(fallback[0].start_time, None);
+}
+
+#[test]
+fn test_populate_cue_sheet_failed_flush() {
+	let collection_path = Path::new("test-output").join("cue-failed-flush-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	let song_path = collection_path.join("album.flac");
+	std::fs::copy("test-data/formats/sample.flac", &song_path).unwrap();
+
+	let db = db::get_test_db("populate_cue_sheet_failed_flush.sqlite");
+	mount_collection(&db, &collection_path);
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// Each track is written in its own batch, and the one of the first track fails
+	std::fs::write(
+		collection_path.join("album.cue"),
+		"FILE \"album.flac\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 01 00:01:00\n",
+	)
+	.unwrap();
+	{
+		use diesel::connection::SimpleConnection;
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_insert_buffer_size.eq(1))
+			.execute(&connection)
+			.unwrap();
+		connection
+			.batch_execute(
+				"CREATE TRIGGER kill_first_track BEFORE INSERT ON songs
+				WHEN NEW.start_time = 0
+				BEGIN SELECT RAISE(ABORT, 'Insertion killed'); END;",
+			)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// The song indexed before the cue sheet is still replaced by the remaining track
+	let connection = db.connect().unwrap();
+	let start_times = songs::table
+		.filter(songs::path.eq(song_path.to_string_lossy().as_ref()))
+		.select(songs::start_time)
+		.load::<Option<i32>>(&connection)
+		.unwrap();
+	assert_eq!(start_times, vec![Some(1)]);
+}
+
+#[test]
+fn test_populate_embedded_cue_sheets() {
+	let collection_path = Path::new("test-output").join("embedded-cue-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	let embedded_cue = "FILE \"disc.wav\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"Embedded First\"\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    TITLE \"Embedded Second\"\n    INDEX 01 00:02:00\n";
+	for name in &["embedded.flac", "sidecar.flac"] {
+		let path = collection_path.join(name);
+		std::fs::copy("test-data/formats/sample.flac", &path).unwrap();
+		let mut tag = metaflac::Tag::read_from_path(&path).unwrap();
+		tag.set_vorbis("CUESHEET", vec![embedded_cue]);
+		tag.write_to_path(&path).unwrap();
+	}
+	std::fs::copy(
+		"test-data/formats/sample.flac",
+		collection_path.join("plain.flac"),
+	)
+	.unwrap();
+	std::fs::write(
+		collection_path.join("sidecar.cue"),
+		"FILE \"sidecar.flac\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"Sidecar First\"\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    TITLE \"Sidecar Second\"\n    INDEX 01 00:01:00\n  TRACK 03 AUDIO\n    TITLE \"Sidecar Third\"\n    INDEX 01 00:03:00\n",
+	)
+	.unwrap();
+
+	let db = db::get_test_db("populate_embedded_cue_sheets.sqlite");
+	mount_collection(&db, &collection_path);
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let tracks_of = |name: &str| -> Vec<(Option<String>, Option<i32>)> {
+		songs::table
+			.select((songs::title, songs::start_time))
+			.filter(songs::path.eq(collection_path.join(name).to_string_lossy().as_ref()))
+			.order(songs::track_number)
+			.load(&connection)
+			.unwrap()
+	};
+	assert_eq!(
+		tracks_of("embedded.flac"),
+		vec![
+			(Some("Embedded First".to_owned()), Some(0)),
+			(Some("Embedded Second".to_owned()), Some(2)),
+		]
+	);
+	assert_eq!(
+		tracks_of("sidecar.flac"),
+		vec![
+			(Some("Sidecar First".to_owned()), Some(0)),
+			(Some("Sidecar Second".to_owned()), Some(1)),
+			(Some("Sidecar Third".to_owned()), Some(3)),
+		]
+	);
+	assert_eq!(
+		tracks_of("plain.flac"),
+		vec![(Some("TEST TITLE".to_owned()), None)]
+	);
+}
+
+#[test]
+fn test_populate_archives() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("archives-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	let archive_path = collection_path.join("album.zip");
+	std::fs::copy("test-data/archives/sample.zip", &archive_path).unwrap();
+
+	let db = db::get_test_db("populate_archives.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let song_paths = || -> Vec<String> {
+		let connection = db.connect().unwrap();
+		songs::table
+			.select(songs::path)
+			.order(songs::path)
+			.load(&connection)
+			.unwrap()
+	};
+
+	// Archives are not indexed by default
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert!(song_paths().is_empty());
+
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_archives.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap(); // Check that songs within archives survive cleaning
+
+	let archive_string = archive_path.to_string_lossy();
+	assert_eq!(
+		song_paths(),
+		vec![
+			format!("{}#01 - Stored.mp3", archive_string),
+			format!("{}#02 - Deflated.flac", archive_string),
+		]
+	);
+
+	let connection = db.connect().unwrap();
+	let song: Song = songs::table
+		.filter(songs::path.eq(format!("{}#02 - Deflated.flac", archive_string)))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(song.parent, collection_path.to_string_lossy().as_ref());
+	assert_eq!(song.title, Some("TEST TITLE".to_owned()));
+	assert_eq!(song.artwork, None);
+}
+
+#[test]
+fn test_populate_content_hash() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("content-hash-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for directory in &["Album", "Favorites"] {
+		let directory_path = collection_path.join(directory);
+		std::fs::create_dir_all(&directory_path).unwrap();
+		std::fs::copy("test-data/formats/sample.mp3", directory_path.join("a.mp3")).unwrap();
+	}
+	std::fs::copy(
+		"test-data/formats/sample.flac",
+		collection_path.join("Album").join("b.flac"),
+	)
+	.unwrap();
+
+	let db = db::get_test_db("populate_content_hash.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let content_hashes = || -> Vec<Option<String>> {
+		let connection = db.connect().unwrap();
+		songs::table
+			.select(songs::content_hash)
+			.order(songs::path)
+			.load(&connection)
+			.unwrap()
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(content_hashes(), vec![None, None, None]);
+
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_compute_audio_hash.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	let hashes = content_hashes();
+	assert!(hashes.iter().all(Option::is_some));
+	assert_eq!(hashes[0], hashes[2]);
+	assert_ne!(hashes[0], hashes[1]);
+}
+
+#[test]
+fn test_populate_audio_properties() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("audio-properties-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	for name in &["sample.flac", "sample.mp3", "sample.opus"] {
+		std::fs::copy(
+			Path::new("test-data/formats").join(name),
+			collection_path.join(name),
+		)
+		.unwrap();
+	}
+
+	let db = db::get_test_db("populate_audio_properties.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let properties: Vec<(
+		Option<String>,
+		Option<i32>,
+		Option<i32>,
+		Option<i32>,
+		Option<i32>,
+	)> = songs::table
+		.select((
+			songs::format,
+			songs::bitrate,
+			songs::sample_rate,
+			songs::bits_per_sample,
+			songs::channels,
+		))
+		.order(songs::path)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(
+		properties,
+		vec![
+			(
+				Some("FLAC".to_owned()),
+				Some(685),
+				Some(44100),
+				Some(16),
+				Some(1)
+			),
+			(
+				Some("MP3".to_owned()),
+				Some(320),
+				Some(44100),
+				None,
+				Some(1)
+			),
+			(Some("Opus".to_owned()), None, Some(48000), None, Some(1)),
+		]
+	);
+
+	let modes: Vec<(Option<bool>, Option<String>)> = songs::table
+		.select((songs::lossless, songs::bitrate_mode))
+		.order(songs::path)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(
+		modes,
+		vec![
+			(Some(true), None),
+			(Some(false), Some("CBR".to_owned())),
+			(Some(false), None),
+		]
+	);
+}
+
+#[test]
+fn test_populate_errors() {
+	let db = db::get_test_db("populate_errors.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_album_art_pattern.eq("("))
+			.execute(&connection)
+			.unwrap();
+	}
+	let error = update(&db, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::AlbumArtPattern(_)));
+
+	let config = config::Config {
+		album_art_pattern: Some("Folder.(jpg|png)".to_owned()),
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: "test-data/missing-collection".to_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let error = update(&db, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::EmptyMountPoint(_)));
+
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_allow_empty_mounts.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let error = update(&db, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::MountPoint(_, _)));
+}
+
+#[test]
+fn test_read_dir_retries() {
+	let db = db::get_test_db("read_dir_retries.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_allow_empty_mounts.eq(1),
+				misc_settings::index_read_dir_retries.eq(2),
+				misc_settings::index_read_dir_retry_delay_ms.eq(10_000),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+
+	// A mount point which is not a directory would fail again, so it is skipped right away
+	let source = Path::new("test-data/formats/sample.mp3");
+	mount_collection(&db, source);
+	let start = std::time::Instant::now();
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert!(start.elapsed() < std::time::Duration::from_secs(10));
+	assert_eq!(summary.warnings.len(), 1);
+	assert_eq!(summary.warnings[0].path, source);
+	assert!(summary.warnings[0]
+		.message
+		.starts_with("Directory read error"));
+}
+
+#[test]
+fn test_update_empty_mount_point() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("empty-mount-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	let song_path = collection_path.join("sample.mp3");
+	std::fs::copy("test-data/formats/sample.mp3", &song_path).unwrap();
+
+	let db = db::get_test_db("update_empty_mount_point.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let count_songs = || -> usize {
+		let connection = db.connect().unwrap();
+		songs::table.load::<Song>(&connection).unwrap().len()
+	};
+
+	// Existing songs are kept when the mount point turns up empty
+	std::fs::remove_file(&song_path).unwrap();
+	let error = update(&db, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::EmptyMountPoint(_)));
+	assert_eq!(count_songs(), 1);
+
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_allow_empty_mounts.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(count_songs(), 0);
+}
+
+#[test]
+fn test_update_dry_run() {
+	let db = db::get_test_db("update_dry_run.sqlite");
+	let count_entries = || -> (usize, usize) {
+		let connection = db.connect().unwrap();
+		let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+		let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+		(all_directories.len(), all_songs.len())
+	};
+	let dry_run = UpdateOptions {
+		dry_run: true,
+		..Default::default()
+	};
+
+	let summary = update(&db, &dry_run).unwrap();
+	assert_eq!(summary.directories_added.count, 6);
+	assert_eq!(summary.songs_added.count, 13);
+	assert_eq!(summary.songs_added.paths.len(), 13);
+	assert_eq!(count_entries(), (0, 0));
+
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_added.count, 13);
+	assert!(summary.songs_added.paths.is_empty());
+	assert_eq!(count_entries(), (6, 13));
+
+	let summary = populate(&db, true, &dry_run).unwrap();
+	assert_eq!(summary, UpdateSummary::default());
+
+	let config = config::Config {
+		mount_dirs: Some(Vec::new()),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	assert_eq!(count_entries(), (0, 0));
+	let summary = update(&db, &dry_run).unwrap();
+	assert_eq!(summary.directories_removed.count, 0);
+	assert_eq!(summary.songs_removed.count, 0);
+}
+
+#[test]
+fn test_clean_missing_files() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("clean-missing-collection");
+	let album_path = collection_path.join("Album");
+	let artist_path = collection_path.join("Artist");
+	let nested_album_path = artist_path.join("Nested Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[&album_path, &nested_album_path] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/artwork/sample.mp3", path.join("a.mp3")).unwrap();
+		std::fs::copy("test-data/artwork/sample.mp3", path.join("b.mp3")).unwrap();
+	}
+
+	let db = db::get_test_db("clean_missing_files.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	std::fs::remove_file(album_path.join("b.mp3")).unwrap();
+	std::fs::remove_dir_all(&artist_path).unwrap();
+	let summary = clean(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_removed.count, 3);
+	assert_eq!(summary.directories_removed.count, 2);
+
+	let connection = db.connect().unwrap();
+	let song_paths: Vec<String> = songs::table.select(songs::path).load(&connection).unwrap();
+	assert_eq!(
+		song_paths,
+		vec![album_path.join("a.mp3").to_string_lossy().into_owned()]
+	);
+	let directory_paths: Vec<String> = directories::table
+		.select(directories::path)
+		.order(directories::path)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(
+		directory_paths,
+		vec![
+			collection_path.to_string_lossy().into_owned(),
+			album_path.to_string_lossy().into_owned(),
+		]
+	);
+}
+
+// Only the listed real paths exist
+struct FakePathExistence {
+	paths: HashSet<PathBuf>,
+}
+
+impl PathExistence for FakePathExistence {
+	fn exists(&self, path: &Path) -> bool {
+		self.paths.contains(path)
+	}
+}
+
+#[test]
+fn test_clean_case_renames() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("case-renames-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(collection_path.join("Album")).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.mp3",
+		collection_path.join("Album").join("Song.mp3"),
+	)
+	.unwrap();
+
+	let db = db::get_test_db("clean_case_renames.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// Only the case of the directory and song changes, which leaves a single entry for each
+	std::fs::rename(
+		collection_path.join("Album").join("Song.mp3"),
+		collection_path.join("Album").join("song.mp3"),
+	)
+	.unwrap();
+	std::fs::rename(collection_path.join("Album"), collection_path.join("album")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let song_paths: Vec<String> = songs::table.select(songs::path).load(&connection).unwrap();
+	let directory_paths: Vec<String> = directories::table
+		.select(directories::path)
+		.filter(directories::parent.is_not_null())
+		.load(&connection)
+		.unwrap();
+	let expected_song = collection_path.join("album").join("song.mp3");
+	let expected_directory = collection_path.join("album");
+	assert_eq!(
+		song_paths,
+		vec![expected_song.to_string_lossy().into_owned()]
+	);
+	assert_eq!(
+		directory_paths,
+		vec![expected_directory.to_string_lossy().into_owned()]
+	);
+}
+
+#[test]
+fn test_clean_path_existence() {
+	let db = db::get_test_db("clean_path_existence.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let picnic_path = Path::new("test-data")
+		.join("small-collection")
+		.join("Tobokegao")
+		.join("Picnic");
+	let candlelight_path = Path::new("test-data")
+		.join("small-collection")
+		.join("Khemmis")
+		.join("Hunted")
+		.join("02 - Candlelight.mp3");
+	let (song_paths, directory_paths) = {
+		let connection = db.connect().unwrap();
+		let song_paths: Vec<String> = songs::table.select(songs::path).load(&connection).unwrap();
+		let directory_paths: Vec<String> = directories::table
+			.select(directories::path)
+			.load(&connection)
+			.unwrap();
+		(song_paths, directory_paths)
+	};
+	let paths = song_paths
+		.iter()
+		.chain(directory_paths.iter())
+		.map(PathBuf::from)
+		.filter(|p| !p.starts_with(&picnic_path) && *p != candlelight_path)
+		.collect();
+	let options = UpdateOptions {
+		path_existence: Some(Arc::new(FakePathExistence { paths })),
+		..Default::default()
+	};
+	let summary = clean(&db, &options).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 8);
+	assert_eq!(summary.stats.directories_deleted, 1);
+
+	let connection = db.connect().unwrap();
+	let remaining_songs: Vec<String> = songs::table.select(songs::path).load(&connection).unwrap();
+	assert_eq!(remaining_songs.len(), 5);
+	assert!(remaining_songs
+		.iter()
+		.all(|p| !Path::new(p).starts_with(&picnic_path) && Path::new(p) != candlelight_path));
+}
+
+#[test]
+fn test_update_stats() {
+	let db = db::get_test_db("update_stats.sqlite");
+	let stats = update(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_inserted, 13);
+	assert_eq!(stats.directories_inserted, 6);
+	assert_eq!(stats.songs_deleted, 0);
+	assert_eq!(stats.files_skipped_errors, 0);
+
+	let config = config::Config {
+		mount_dirs: Some(Vec::new()),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let stats = update(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_inserted, 0);
+	assert_eq!(stats.songs_deleted, 0);
+	assert_eq!(stats.directories_deleted, 0);
+}
+
+#[test]
+fn test_soft_delete() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("soft-delete-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for name in &["a.mp3", "b.mp3"] {
+		std::fs::copy("test-data/formats/sample.mp3", album_path.join(name)).unwrap();
+	}
+
+	let db = db::get_test_db("soft_delete.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_soft_delete.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let deleted_b = || -> Option<i32> {
+		let connection = db.connect().unwrap();
+		songs::table
+			.select(songs::deleted_at)
+			.filter(songs::path.eq(album_path.join("b.mp3").to_string_lossy().as_ref()))
+			.get_result(&connection)
+			.unwrap()
+	};
+	let virtual_b = Path::new("root").join("Album").join("b.mp3");
+
+	// Directories modified within the same second as an update are not considered up to date
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(flatten(&db, "root").unwrap().len(), 2);
+
+	std::fs::remove_file(album_path.join("b.mp3")).unwrap();
+	let stats = update(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_deleted, 1);
+	assert!(deleted_b().is_some());
+	assert_eq!(flatten(&db, "root").unwrap().len(), 1);
+	assert!(get_song(&db, &virtual_b).is_err());
+
+	std::fs::copy("test-data/formats/sample.mp3", album_path.join("b.mp3")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(deleted_b(), None);
+	assert!(get_song(&db, &virtual_b).is_ok());
+
+	std::fs::remove_file(album_path.join("b.mp3")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(purge(&db, std::time::Duration::from_secs(3600)).unwrap(), 0);
+	assert_eq!(purge(&db, std::time::Duration::from_secs(0)).unwrap(), 1);
+	let connection = db.connect().unwrap();
+	let song_count: i64 = songs::table.count().get_result(&connection).unwrap();
+	assert_eq!(song_count, 1);
+}
+
+#[test]
+fn test_soft_delete_retention() {
+	let collection_path = generate_collection("soft-delete-retention", 1, 2);
+	let song_path = collection_path.join("Album 000").join("02 - Song.mp3");
+	let db = db::get_test_db("soft_delete_retention.sqlite");
+	mount_collection(&db, &collection_path);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_soft_delete.eq(1),
+				misc_settings::index_soft_delete_retention_days.eq(1),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	let song_count = || -> i64 {
+		let connection = db.connect().unwrap();
+		songs::table.count().get_result(&connection).unwrap()
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	std::fs::remove_file(&song_path).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(song_count(), 2);
+
+	// Songs deleted longer than the retention ago are purged by the next update
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(songs::table.filter(songs::deleted_at.is_not_null()))
+			.set(songs::deleted_at.eq(0))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(song_count(), 1);
+}
+
+#[test]
+fn test_ignore_marker() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("ignore-marker-collection");
+	let album_path = collection_path.join("Album");
+	let stems_path = collection_path.join("Stems");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[&album_path, &stems_path.join("Drums")] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/formats/sample.mp3", path.join("a.mp3")).unwrap();
+	}
+
+	let db = db::get_test_db("ignore_marker.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let song_paths = || -> Vec<String> {
+		let connection = db.connect().unwrap();
+		songs::table.select(songs::path).load(&connection).unwrap()
+	};
+
+	// Directories modified within the same second as an update are not considered up to date
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(song_paths().len(), 2);
+
+	// Previously indexed content is removed once marked
+	std::fs::write(stems_path.join(".nomedia"), "").unwrap();
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 1);
+	assert_eq!(summary.stats.directories_deleted, 2);
+	assert_eq!(
+		song_paths(),
+		vec![album_path.join("a.mp3").to_string_lossy().into_owned()]
+	);
+
+	std::fs::write(album_path.join(".noindex"), "").unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_ignore_marker.eq(".noindex"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let summary = update_merge(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 1);
+	assert_eq!(
+		song_paths(),
+		vec![stems_path
+			.join("Drums")
+			.join("a.mp3")
+			.to_string_lossy()
+			.into_owned()]
+	);
+}
+
+struct SkipStems {
+	mount_roots: std::sync::Mutex<Vec<PathBuf>>,
+}
+
+impl DirectoryFilter for SkipStems {
+	fn should_index(&self, path: &Path, parent: Option<&Path>) -> bool {
+		if parent.is_none() {
+			self.mount_roots.lock().unwrap().push(path.to_owned());
+		}
+		path.file_name().map_or(true, |n| n != "Stems")
+	}
+}
+
+#[test]
+fn test_directory_filter() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("directory-filter-collection");
+	let album_path = collection_path.join("Album");
+	let stems_path = collection_path.join("Stems");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[&album_path, &stems_path.join("Drums")] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/formats/sample.mp3", path.join("a.mp3")).unwrap();
+	}
+
+	let db = db::get_test_db("directory_filter.sqlite");
+	mount_collection(&db, &collection_path);
+	let song_paths = || -> Vec<String> {
+		let connection = db.connect().unwrap();
+		songs::table.select(songs::path).load(&connection).unwrap()
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(song_paths().len(), 2);
+
+	// Previously indexed content is removed once rejected by the filter
+	let filter = Arc::new(SkipStems {
+		mount_roots: std::sync::Mutex::new(Vec::new()),
+	});
+	let options = UpdateOptions {
+		directory_filter: Some(filter.clone()),
+		..Default::default()
+	};
+	let summary = update(&db, &options).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 1);
+	assert_eq!(summary.stats.directories_deleted, 2);
+	assert_eq!(
+		song_paths(),
+		vec![album_path.join("a.mp3").to_string_lossy().into_owned()]
+	);
+	assert_eq!(verify(&db, &options).unwrap(), VerifyReport::default());
+	let mount_roots = filter.mount_roots.lock().unwrap();
+	assert!(!mount_roots.is_empty());
+	assert!(mount_roots.iter().all(|p| *p == collection_path));
+}
+
+#[test]
+fn test_verify_after_index() {
+	let db = db::get_test_db("verify_after_index.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_verify_after_index.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert!(summary.integrity_violations.is_empty());
+
+	let album_path: PathBuf = ["test-data", "small-collection", "Khemmis", "Hunted"]
+		.iter()
+		.collect();
+	let song_path = album_path.join("05 - Hunted.mp3");
+	let missing_artwork = album_path.join("Missing.jpg");
+	let album_string = album_path.to_string_lossy().into_owned();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(songs::table.filter(songs::path.eq(song_path.to_string_lossy().as_ref())))
+			.set(songs::parent.eq("test-data/missing-directory"))
+			.execute(&connection)
+			.unwrap();
+		diesel::update(directories::table.filter(directories::path.eq(&album_string)))
+			.set((
+				directories::parent.eq(&album_string),
+				directories::artwork.eq(missing_artwork.to_string_lossy().as_ref()),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+	assert_eq!(
+		check_integrity(&db).unwrap(),
+		vec![
+			IntegrityViolation::OrphanedSong(song_path),
+			IntegrityViolation::DirectoryOwnParent(album_path.clone()),
+			IntegrityViolation::MissingArtwork {
+				entry: album_path,
+				artwork: missing_artwork,
+			},
+		]
+	);
+}
+
+#[test]
+fn test_verify() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("verify-collection");
+	let album_path = collection_path.join("Album");
+	let new_album_path = collection_path.join("New Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for name in &["a.mp3", "b.mp3", "c.mp3"] {
+		std::fs::copy("test-data/formats/sample.mp3", album_path.join(name)).unwrap();
+	}
+
+	let db = db::get_test_db("verify.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(
+		verify(&db, &UpdateOptions::default()).unwrap(),
+		VerifyReport::default()
+	);
+
+	// Files written in a later second have a different modification time
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	std::fs::copy("test-data/formats/sample.mp3", album_path.join("a.mp3")).unwrap();
+	std::fs::remove_file(album_path.join("b.mp3")).unwrap();
+	std::fs::create_dir_all(&new_album_path).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", new_album_path.join("d.mp3")).unwrap();
+
+	let report = verify(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(report.missing_songs, vec![album_path.join("b.mp3")]);
+	assert_eq!(report.missing_directories, Vec::<PathBuf>::new());
+	assert_eq!(report.unindexed_songs, vec![new_album_path.join("d.mp3")]);
+	assert_eq!(report.unindexed_directories, vec![new_album_path.clone()]);
+	assert_eq!(report.modified_songs, vec![album_path.join("a.mp3")]);
+
+	let connection = db.connect().unwrap();
+	let song_count: i64 = songs::table.count().get_result(&connection).unwrap();
+	assert_eq!(song_count, 3);
+}
+
+#[test]
+fn test_update_merge() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("update-merge-collection");
+	let album_a_path = collection_path.join("Album A");
+	let album_b_path = collection_path.join("Album B");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[&album_a_path, &album_b_path] {
+		std::fs::create_dir_all(path).unwrap();
+		for name in &["a.mp3", "b.mp3"] {
+			std::fs::copy("test-data/formats/sample.mp3", path.join(name)).unwrap();
+		}
+	}
+
+	let db = db::get_test_db("update_merge.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_insert_buffer_size.eq(1),
+				misc_settings::index_clean_buffer_size.eq(1),
+			))
+			.execute(&connection)
+			.unwrap();
+	}
+
+	// Directories modified within the same second as an update are not considered up to date
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	let stats = update_merge(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_inserted, 4);
+	assert_eq!(stats.directories_inserted, 3);
+
+	// Songs of unchanged directories are not read again, but are still kept
+	let stats = update_merge(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_inserted, 0);
+	assert_eq!(stats.songs_deleted, 0);
+	assert_eq!(stats.directories_deleted, 0);
+
+	std::fs::remove_dir_all(&album_b_path).unwrap();
+	std::fs::remove_file(album_a_path.join("b.mp3")).unwrap();
+	let summary = update_merge(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 3);
+	assert_eq!(summary.stats.directories_deleted, 1);
+
+	let connection = db.connect().unwrap();
+	let song_paths: Vec<String> = songs::table.select(songs::path).load(&connection).unwrap();
+	assert_eq!(
+		song_paths,
+		vec![album_a_path.join("a.mp3").to_string_lossy().into_owned()]
+	);
+	let directory_count: i64 = directories::table.count().get_result(&connection).unwrap();
+	assert_eq!(directory_count, 2);
+	let artist_count: i64 = song_artists::table.count().get_result(&connection).unwrap();
+	assert_eq!(artist_count, 1);
+}
+
+#[test]
+fn test_update_merge_setting() {
+	let collection_path = generate_collection("merge-setting", 2, 2);
+	let db = db::get_test_db("update_merge_setting.sqlite");
+	mount_collection(&db, &collection_path);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_merge_updates.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let options = UpdateOptions::default();
+	let summary = update(&db, &options).unwrap();
+	assert_eq!(summary.songs_added.count, 4);
+
+	std::fs::remove_dir_all(collection_path.join("Album 001")).unwrap();
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	// Entries are removed once the traversal is over
+	let mut indexer = Indexer::new(&db, &options);
+	let mut states = vec![indexer.state()];
+	while states.last().unwrap().phase != IndexPhase::Done {
+		states.push(indexer.step().unwrap());
+	}
+	let first_removal = states.iter().position(|s| s.songs_removed > 0).unwrap();
+	assert!(states[first_removal..]
+		.iter()
+		.all(|s| s.phase != IndexPhase::Scanning));
+	assert!(states[..first_removal]
+		.iter()
+		.any(|s| s.phase == IndexPhase::Scanning));
+	let state = states.last().unwrap();
+	assert_eq!(state.songs_removed, 2);
+	assert_eq!(state.directories_removed, 1);
+	let stats = indexer.into_summary().stats;
+	assert_eq!(stats.songs_deleted, 2);
+	assert_eq!(stats.directories_deleted, 1);
+}
+
+fn set_merge_updates(db: &db::DB) {
+	let connection = db.connect().unwrap();
+	diesel::update(misc_settings::table)
+		.set(misc_settings::index_merge_updates.eq(1))
+		.execute(&connection)
+		.unwrap();
+}
+
+#[test]
+fn test_update_merge_extensions() {
+	let collection_path = generate_collection("merge-extensions", 1, 2);
+	let db = db::get_test_db("update_merge_extensions.sqlite");
+	mount_collection(&db, &collection_path);
+	set_merge_updates(&db);
+	let song_count = || -> i64 {
+		let connection = db.connect().unwrap();
+		songs::table.count().get_result(&connection).unwrap()
+	};
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(song_count(), 2);
+
+	// Songs whose extension is no longer allowed are removed, although their files still exist
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_extensions.eq(".flac"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 2);
+	assert_eq!(song_count(), 0);
+}
+
+#[test]
+fn test_update_merge_max_depth() {
+	let db = db::get_test_db("update_merge_max_depth.sqlite");
+	set_merge_updates(&db);
+	let set_max_depth = |max_depth: i32| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_max_depth.eq(max_depth))
+			.execute(&connection)
+			.unwrap();
+	};
+	let counts = || -> (i64, i64) {
+		let connection = db.connect().unwrap();
+		let directories = directories::table.count().get_result(&connection).unwrap();
+		let songs = songs::table.count().get_result(&connection).unwrap();
+		(directories, songs)
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (6, 13));
+
+	// Entries beyond a lowered limit are removed
+	set_max_depth(1);
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (3, 0));
+}
+
+#[test]
+fn test_update_merge_link_target() {
+	let collection_path = Path::new("test-output").join("merge-link-target-collection");
+	let archive_path = Path::new("test-output").join("merge-link-target-archive");
+	for path in &[&collection_path, &archive_path] {
+		if path.exists() {
+			std::fs::remove_dir_all(path).unwrap();
+		}
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	std::fs::create_dir_all(&archive_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.mp3",
+		archive_path.join("sample.mp3"),
+	)
+	.unwrap();
+	let target_path = std::fs::canonicalize(archive_path.join("sample.mp3")).unwrap();
+	let linked_path = collection_path.join("linked.mp3");
+	std::os::unix::fs::symlink(&target_path, &linked_path).unwrap();
+
+	let db = db::get_test_db("update_merge_link_target.sqlite");
+	mount_collection(&db, &collection_path);
+	set_merge_updates(&db);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_resolve_file_symlinks.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// A resolved song is removed once its target is gone, even while its link is still found
+	std::fs::remove_file(&target_path).unwrap();
+	let options = UpdateOptions {
+		path_existence: Some(Arc::new(FakePathExistence {
+			paths: vec![collection_path.clone(), linked_path]
+				.into_iter()
+				.collect(),
+		})),
+		..Default::default()
+	};
+	let summary = update(&db, &options).unwrap();
+	assert_eq!(summary.stats.songs_deleted, 1);
+}
+
+#[test]
+fn test_update_merge_detect_renames() {
+	let collection_path = Path::new("test-output").join("merge-renames-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	let old_path = collection_path.join("Before");
+	let new_path = collection_path.join("After");
+	std::fs::create_dir_all(&old_path).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", old_path.join("sample.mp3")).unwrap();
+	let other_path = collection_path.join("Other");
+	std::fs::create_dir_all(&other_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.flac",
+		other_path.join("sample.flac"),
+	)
+	.unwrap();
+
+	let db = db::get_test_db("update_merge_detect_renames.sqlite");
+	mount_collection(&db, &collection_path);
+	set_merge_updates(&db);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_detect_renames.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let song_id = |path: &Path| -> i32 {
+		let connection = db.connect().unwrap();
+		songs::table
+			.filter(songs::path.eq(path.to_string_lossy().as_ref()))
+			.select(songs::id)
+			.get_result(&connection)
+			.unwrap()
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	let old_id = song_id(&old_path.join("sample.mp3"));
+
+	// The moved song is written before the old one is removed, and still takes over its id
+	std::fs::rename(&old_path, &new_path).unwrap();
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_removed.count, 1);
+	assert_eq!(summary.songs_added.count, 1);
+	assert_eq!(song_id(&new_path.join("sample.mp3")), old_id);
+
+	let connection = db.connect().unwrap();
+	let removed_count: i64 = index_renames::table
+		.count()
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(removed_count, 0);
+}
+
+#[test]
+fn test_directory_fingerprint() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("directory-fingerprint-collection");
+	let album_path = collection_path.join("Album");
+	let retitled_path = collection_path.join("retitled.mp3");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", album_path.join("a.mp3")).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", &retitled_path).unwrap();
+	let mut tag = id3::Tag::read_from_path(&retitled_path).unwrap();
+	tag.set_title("New Title");
+	tag.write_to_path(&retitled_path, id3::Version::Id3v24)
+		.unwrap();
+
+	let db = db::get_test_db("directory_fingerprint.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: album_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let get_title = || -> Option<String> {
+		let connection = db.connect().unwrap();
+		songs::table
+			.select(songs::title)
+			.get_result(&connection)
+			.unwrap()
+	};
+
+	// Directories modified within the same second as an update are not considered up to date
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_title(), Some("TEST TITLE".to_owned()));
+
+	// Overwriting a file does not change the modification time of its directory
+	std::fs::copy(&retitled_path, album_path.join("a.mp3")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_title(), Some("TEST TITLE".to_owned()));
+
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_use_directory_fingerprint.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let stats = update(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_inserted, 1);
+	assert_eq!(get_title(), Some("New Title".to_owned()));
+
+	let stats = update(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_inserted, 0);
+	assert_eq!(stats.directories_inserted, 0);
+}
+
+#[test]
+fn test_playlist_discovery() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("playlist-discovery-collection");
+	let album_path = collection_path.join("Album");
+	let playlist_path = album_path.join("Mix.m3u");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for name in &["a.mp3", "b.mp3"] {
+		std::fs::copy("test-data/formats/sample.mp3", album_path.join(name)).unwrap();
+	}
+	std::fs::write(
+		&playlist_path,
+		"#EXTM3U\n#EXTINF:1,Some Artist - B\nb.mp3\n../Album/a.mp3\nroot\\Album\\b.mp3\nmissing.mp3\nhttp://example.com/stream.mp3\n",
+	)
+	.unwrap();
+
+	let db = db::get_test_db("playlist_discovery.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+
+	let get_playlists = || -> Vec<(String, String, String)> {
+		let connection = db.connect().unwrap();
+		indexed_playlists::table
+			.select((
+				indexed_playlists::path,
+				indexed_playlists::parent,
+				indexed_playlists::name,
+			))
+			.load(&connection)
+			.unwrap()
+	};
+	let get_entries = || -> Vec<(String, i32)> {
+		let connection = db.connect().unwrap();
+		indexed_playlist_songs::table
+			.select((
+				indexed_playlist_songs::path,
+				indexed_playlist_songs::missing,
+			))
+			.order(indexed_playlist_songs::ordering)
+			.load(&connection)
+			.unwrap()
+	};
+	let song_path = |name: &str| album_path.join(name).to_string_lossy().into_owned();
+
+	// Directories modified within the same second as an update are not considered up to date
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(
+		get_playlists(),
+		vec![(
+			playlist_path.to_string_lossy().into_owned(),
+			album_path.to_string_lossy().into_owned(),
+			"Mix".to_owned()
+		)]
+	);
+	assert_eq!(
+		get_entries(),
+		vec![
+			(song_path("b.mp3"), 0),
+			(song_path("a.mp3"), 0),
+			(song_path("b.mp3"), 0),
+			(song_path("missing.mp3"), 1),
+			("http://example.com/stream.mp3".to_owned(), 1),
+		]
+	);
+
+	// Unchanged directories keep their playlists
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_entries().len(), 5);
+
+	std::fs::remove_file(album_path.join("a.mp3")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_entries()[1], (song_path("a.mp3"), 1));
+
+	std::fs::remove_file(&playlist_path).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert!(get_playlists().is_empty());
+	assert!(get_entries().is_empty());
+}
+
+#[cfg(windows)]
+#[test]
+fn test_long_paths() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("long-paths-collection");
+	let album_path = (0..12).fold(collection_path.clone(), |path, i| {
+		path.join(format!("Deeply Nested Directory {:02}", i))
+	});
+	let song_path = album_path.join("sample.mp3");
+	let fs_path = |path: &Path| crate::utils::fs_path(path).into_owned();
+	assert!(
+		std::env::current_dir()
+			.unwrap()
+			.join(&song_path)
+			.as_os_str()
+			.len() > 260
+	);
+	if collection_path.exists() {
+		std::fs::remove_dir_all(fs_path(&collection_path)).unwrap();
+	}
+	std::fs::create_dir_all(fs_path(&album_path)).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", fs_path(&song_path)).unwrap();
+
+	let db = db::get_test_db("long_paths.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(songs.len(), 1);
+	assert_eq!(songs[0].path, song_path.to_string_lossy().as_ref());
+	assert_eq!(songs[0].title, Some("TEST TITLE".to_owned()));
+}
+
+#[test]
+fn test_nested_mount_points() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("nested-mounts-collection");
+	let classical_path = collection_path.join("Classical");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[collection_path.join("Album"), classical_path.join("Bach")] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/formats/sample.mp3", path.join("a.mp3")).unwrap();
+	}
+
+	let config = config::Config {
+		mount_dirs: Some(vec![
+			vfs::MountPoint {
+				source: collection_path.to_string_lossy().into_owned(),
+				name: "root".to_owned(),
+				album_art_pattern: None,
+				include_patterns: None,
+			},
+			vfs::MountPoint {
+				source: classical_path.to_string_lossy().into_owned(),
+				name: "classical".to_owned(),
+				album_art_pattern: None,
+				include_patterns: None,
+			},
+		]),
+		..Default::default()
+	};
+	for (db_name, store_relative_paths) in &[
+		("nested_mount_points.sqlite", 0),
+		("nested_mount_points_relative.sqlite", 1),
+	] {
+		let db = db::get_test_db(db_name);
+		config::amend(&db, &config).unwrap();
+		{
+			let connection = db.connect().unwrap();
+			diesel::update(misc_settings::table)
+				.set(misc_settings::index_store_relative_paths.eq(store_relative_paths))
+				.execute(&connection)
+				.unwrap();
+		}
+		update(&db, &UpdateOptions::default()).unwrap();
+
+		let connection = db.connect().unwrap();
+		let song_count: i64 = songs::table.count().get_result(&connection).unwrap();
+		assert_eq!(song_count, 2);
+
+		let mut root_paths = browse(&db, Path::new(""))
+			.unwrap()
+			.into_iter()
+			.map(|f| match f {
+				CollectionFile::Directory(d) => d.path,
+				CollectionFile::Song(s) => s.path,
+			})
+			.collect::<Vec<_>>();
+		root_paths.sort();
+		assert_eq!(root_paths, vec!["classical".to_owned(), "root".to_owned()]);
+		let outer_paths = browse(&db, Path::new("root"))
+			.unwrap()
+			.into_iter()
+			.map(|f| match f {
+				CollectionFile::Directory(d) => d.path,
+				CollectionFile::Song(s) => s.path,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			outer_paths,
+			vec![Path::new("root")
+				.join("Album")
+				.to_string_lossy()
+				.into_owned()]
+		);
+		let song = get_song(&db, &Path::new("classical").join("Bach").join("a.mp3")).unwrap();
+		assert_eq!(
+			song.path,
+			Path::new("classical")
+				.join("Bach")
+				.join("a.mp3")
+				.to_string_lossy()
+		);
+	}
+}
+
+#[test]
+fn test_relative_paths() {
+	let mut output_path = PathBuf::new();
+	output_path.push("test-output");
+	output_path.push("relative-paths");
+	let collection_path = output_path.join("collection");
+	let moved_collection_path = output_path.join("moved-collection");
+	let album_path = collection_path.join("Album");
+	if output_path.exists() {
+		std::fs::remove_dir_all(&output_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for name in &["a.mp3", "b.mp3"] {
+		std::fs::copy("test-data/formats/sample.mp3", album_path.join(name)).unwrap();
+	}
+
+	let db = db::get_test_db("relative_paths.sqlite");
+	let mount = |source: &Path| config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: source.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &mount(&collection_path)).unwrap();
+	let get_song_paths = || -> Vec<String> {
+		let connection = db.connect().unwrap();
+		songs::table
+			.select(songs::path)
+			.order(songs::path)
+			.load(&connection)
+			.unwrap()
+	};
+	let virtual_album_path = Path::new("root").join("Album");
+	let virtual_song_path =
+		|name: &str| virtual_album_path.join(name).to_string_lossy().into_owned();
+
+	// Directories modified within the same second as an update are not considered up to date
+	std::thread::sleep(std::time::Duration::from_secs(1));
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(
+		get_song_paths(),
+		vec![
+			album_path.join("a.mp3").to_string_lossy().into_owned(),
+			album_path.join("b.mp3").to_string_lossy().into_owned(),
+		]
+	);
+
+	// Existing entries are converted rather than indexed again
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_store_relative_paths.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	let stats = update(&db, &UpdateOptions::default()).unwrap().stats;
+	assert_eq!(stats.songs_inserted, 0);
+	assert_eq!(
+		get_song_paths(),
+		vec![virtual_song_path("a.mp3"), virtual_song_path("b.mp3")]
+	);
+	let songs = flatten(&db, &virtual_album_path).unwrap();
+	assert_eq!(songs[0].path, virtual_song_path("a.mp3"));
+	assert!(get_song(&db, Path::new(&virtual_song_path("b.mp3"))).is_ok());
+
+	std::fs::remove_file(album_path.join("b.mp3")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_song_paths(), vec![virtual_song_path("a.mp3")]);
+
+	// Moving the collection only requires its mount point to be updated
+	std::fs::rename(&collection_path, &moved_collection_path).unwrap();
+	config::amend(&db, &mount(&moved_collection_path)).unwrap();
+	clean(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_song_paths(), vec![virtual_song_path("a.mp3")]);
+	assert!(get_song(&db, Path::new(&virtual_song_path("a.mp3"))).is_ok());
+}
+
+#[test]
+fn test_update_directory() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("update-directory-collection");
+	let album_a_path = collection_path.join("Album A");
+	let album_b_path = collection_path.join("Album B");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[&album_a_path, &album_b_path] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/artwork/sample.mp3", path.join("sample.mp3")).unwrap();
+	}
+
+	let db = db::get_test_db("update_directory.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	std::fs::remove_file(album_a_path.join("sample.mp3")).unwrap();
+	std::fs::copy(
+		"test-data/artwork/sample.flac",
+		album_b_path.join("sample.flac"),
+	)
+	.unwrap();
+	update_directory(&db, &album_b_path, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let song_parents: Vec<String> = songs::table
+		.select(songs::parent)
+		.order(songs::path)
+		.load(&connection)
+		.unwrap();
+	let album_a = album_a_path.to_string_lossy().into_owned();
+	let album_b = album_b_path.to_string_lossy().into_owned();
+	// Album A was not part of the update so its stale entry remains
+	assert_eq!(
+		song_parents,
+		vec![album_a, album_b.clone(), album_b.clone()]
+	);
+
+	let directory: Directory = directories::table
+		.filter(directories::path.eq(&album_b))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(
+		directory.parent,
+		Some(collection_path.to_string_lossy().into_owned())
+	);
+	assert_eq!(directory.song_count, 2);
+
+	let error =
+		update_directory(&db, Path::new("test-data"), &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::NotInMountPoint(_)));
+}
+
+#[test]
+fn test_tag_read_threads() {
+	let mut album_path = PathBuf::new();
+	album_path.push("test-data");
+	album_path.push("small-collection");
+	album_path.push("Tobokegao");
+	album_path.push("Picnic");
+
+	let db = db::get_test_db("tag_read_threads.sqlite");
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_tag_read_threads.eq(3))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// Songs are inserted in the order of their file names
+	let mut listed_paths: Vec<String> = std::fs::read_dir(&album_path)
+		.unwrap()
+		.map(|f| album_path.join(f.unwrap().file_name()))
+		.filter(|p| p.extension().map_or(false, |e| e == "mp3"))
+		.map(|p| p.to_string_lossy().into_owned())
+		.collect();
+	listed_paths.sort();
+	let connection = db.connect().unwrap();
+	let song_paths: Vec<String> = songs::table
+		.select(songs::path)
+		.filter(songs::parent.eq(album_path.to_string_lossy().as_ref()))
+		.order(songs::id)
+		.load(&connection)
+		.unwrap();
+	assert_eq!(song_paths.len(), 7);
+	assert_eq!(song_paths, listed_paths);
+}
+
 #[test]
 fn test_metadata() {
 	let mut target = PathBuf::new();
@@ -32,7 +3472,7 @@ fn test_metadata() {
 	artwork_path.push("Folder.png");
 
 	let db = db::get_test_db("metadata.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 
 	let connection = db.connect().unwrap();
 	let songs: Vec<Song> = songs::table
@@ -54,6 +3494,700 @@ fn test_metadata() {
 		song.artwork,
 		Some(artwork_path.to_string_lossy().into_owned())
 	);
+	let file_metadata = std::fs::metadata(&song_path).unwrap();
+	assert_eq!(song.file_size, Some(file_metadata.len() as i64));
+	assert!(song.mtime.is_some());
+}
+
+#[test]
+fn test_directory_totals() {
+	let mut album_path = PathBuf::new();
+	album_path.push("test-data");
+	album_path.push("small-collection");
+	album_path.push("Tobokegao");
+	album_path.push("Picnic");
+	let album_path = album_path.to_string_lossy().into_owned();
+
+	let db = db::get_test_db("directory_totals.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let directory: Directory = directories::table
+		.filter(directories::path.eq(&album_path))
+		.get_result(&connection)
+		.unwrap();
+	let songs: Vec<Song> = songs::table
+		.filter(songs::parent.eq(&album_path))
+		.load(&connection)
+		.unwrap();
+	assert_eq!(directory.song_count, 7);
+	assert_eq!(
+		directory.total_duration,
+		songs.iter().filter_map(|s| s.duration).sum::<i32>()
+	);
+}
+
+#[test]
+fn test_mount_album_art_patterns() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("mount-album-art-patterns-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for mount_name in &["classical", "pop"] {
+		let album_path = collection_path.join(mount_name).join("Album");
+		std::fs::create_dir_all(&album_path).unwrap();
+		std::fs::copy(
+			"test-data/formats/sample.flac",
+			album_path.join("sample.flac"),
+		)
+		.unwrap();
+		for name in &["folder.png", "booklet.png"] {
+			std::fs::copy("test-data/artwork/Folder.png", album_path.join(name)).unwrap();
+		}
+	}
+
+	let db = db::get_test_db("mount_album_art_patterns.sqlite");
+	let mount_dirs = |classical_pattern: &str| {
+		vec![
+			vfs::MountPoint {
+				source: collection_path
+					.join("classical")
+					.to_string_lossy()
+					.into_owned(),
+				name: "classical".to_owned(),
+				album_art_pattern: Some(classical_pattern.to_owned()),
+				include_patterns: None,
+			},
+			vfs::MountPoint {
+				source: collection_path.join("pop").to_string_lossy().into_owned(),
+				name: "pop".to_owned(),
+				album_art_pattern: None,
+				include_patterns: None,
+			},
+		]
+	};
+	let config = config::Config {
+		album_art_pattern: Some(r"^folder\.png$".to_owned()),
+		mount_dirs: Some(mount_dirs(r"^booklet\.png$")),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let get_artwork = |mount_name: &str| -> Option<String> {
+		let album_path = collection_path.join(mount_name).join("Album");
+		let connection = db.connect().unwrap();
+		directories::table
+			.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+			.select(directories::artwork)
+			.get_result(&connection)
+			.unwrap()
+	};
+	let expected_artwork = |mount_name: &str, name: &str| {
+		let artwork_path = collection_path.join(mount_name).join("Album").join(name);
+		Some(artwork_path.to_string_lossy().into_owned())
+	};
+	assert_eq!(
+		get_artwork("classical"),
+		expected_artwork("classical", "booklet.png")
+	);
+	assert_eq!(get_artwork("pop"), expected_artwork("pop", "folder.png"));
+
+	let config = config::Config {
+		mount_dirs: Some(mount_dirs("(")),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let error = populate(&db, true, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::AlbumArtPattern(_)));
+}
+
+#[test]
+fn test_artwork_priority() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("artwork-priority-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.flac",
+		album_path.join("sample.flac"),
+	)
+	.unwrap();
+	for name in &["folder.png", "folder.jpg", "back.png"] {
+		std::fs::copy("test-data/artwork/Folder.png", album_path.join(name)).unwrap();
+	}
+
+	let db = db::get_test_db("artwork_priority.sqlite");
+	let config = config::Config {
+		album_art_pattern: Some(r"^cover\.png$, ^folder\.(jpg|png)$".to_owned()),
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+
+	let get_artwork = || -> Option<String> {
+		let connection = db.connect().unwrap();
+		directories::table
+			.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+			.select(directories::artwork)
+			.get_result(&connection)
+			.unwrap()
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	let expected_artwork = album_path.join("folder.jpg");
+	assert_eq!(
+		get_artwork(),
+		Some(expected_artwork.to_string_lossy().into_owned())
+	);
+
+	// Artwork which still exists is kept, unless directories are scanned again
+	std::fs::copy("test-data/artwork/Folder.png", album_path.join("cover.png")).unwrap();
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	assert_eq!(
+		get_artwork(),
+		Some(expected_artwork.to_string_lossy().into_owned())
+	);
+	let options = UpdateOptions {
+		rescan_artwork: true,
+		..Default::default()
+	};
+	populate(&db, true, &options).unwrap();
+	let expected_artwork = album_path.join("cover.png");
+	assert_eq!(
+		get_artwork(),
+		Some(expected_artwork.to_string_lossy().into_owned())
+	);
+
+	// Patterns are compiled again when the setting changes
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_album_art_pattern.eq(r"^back\.png$"))
+			.execute(&connection)
+			.unwrap();
+	}
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	let expected_artwork = album_path.join("back.png");
+	assert_eq!(
+		get_artwork(),
+		Some(expected_artwork.to_string_lossy().into_owned())
+	);
+}
+
+#[test]
+fn test_inherit_parent_artwork() {
+	let collection_path = Path::new("test-output").join("inherit-artwork-collection");
+	let mount_path = collection_path.join("Music");
+	let artist_path = mount_path.join("Artist");
+	let album_path = artist_path.join("Album");
+	let deep_album_path = artist_path.join("Live").join("Deep");
+	let other_path = mount_path.join("Other");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[&album_path, &deep_album_path, &other_path] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/formats/sample.mp3", path.join("a.mp3")).unwrap();
+	}
+	// Covers outside of the mount point are never used
+	for path in &[&collection_path, &artist_path] {
+		std::fs::copy("test-data/artwork/Folder.png", path.join("Folder.png")).unwrap();
+	}
+	let artist_artwork = artist_path
+		.join("Folder.png")
+		.to_string_lossy()
+		.into_owned();
+
+	let index_collection = |db_name: &str, inherit: i32, levels: i32| -> Vec<Option<String>> {
+		let db = db::get_test_db(db_name);
+		mount_collection(&db, &mount_path);
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_inherit_parent_artwork.eq(inherit),
+				misc_settings::index_inherit_artwork_levels.eq(levels),
+			))
+			.execute(&connection)
+			.unwrap();
+		update(&db, &UpdateOptions::default()).unwrap();
+		[&album_path, &deep_album_path, &other_path]
+			.iter()
+			.map(|path| {
+				directories::table
+					.select(directories::artwork)
+					.filter(directories::path.eq(path.to_string_lossy().as_ref()))
+					.get_result(&connection)
+					.unwrap()
+			})
+			.collect()
+	};
+
+	assert_eq!(
+		index_collection("inherit_parent_artwork_disabled.sqlite", 0, 2),
+		vec![None, None, None]
+	);
+	assert_eq!(
+		index_collection("inherit_parent_artwork.sqlite", 1, 1),
+		vec![Some(artist_artwork.clone()), None, None]
+	);
+	assert_eq!(
+		index_collection("inherit_parent_artwork_levels.sqlite", 1, 2),
+		vec![Some(artist_artwork.clone()), Some(artist_artwork), None]
+	);
+}
+
+#[test]
+fn test_artwork_must_be_image() {
+	let collection_path = Path::new("test-output").join("artwork-image-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", album_path.join("Cover.mp3")).unwrap();
+
+	let db = db::get_test_db("artwork_must_be_image.sqlite");
+	mount_collection(&db, &collection_path);
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_album_art_pattern.eq("(?i)cover"))
+			.execute(&connection)
+			.unwrap();
+	}
+	let get_artwork = || -> Option<String> {
+		let connection = db.connect().unwrap();
+		directories::table
+			.select(directories::artwork)
+			.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+			.get_result(&connection)
+			.unwrap()
+	};
+
+	// The song matches the pattern but is not used as artwork
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_artwork(), None);
+
+	// Images without an image extension are recognized by their contents
+	std::fs::copy("test-data/artwork/Folder.png", album_path.join("cover_art")).unwrap();
+	let options = UpdateOptions {
+		rescan_artwork: true,
+		..Default::default()
+	};
+	populate(&db, true, &options).unwrap();
+	assert_eq!(
+		get_artwork(),
+		Some(album_path.join("cover_art").to_string_lossy().into_owned())
+	);
+}
+
+#[test]
+fn test_mount_names() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("mount-names-collection");
+	let classical_path = collection_path.join("Classical");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[collection_path.join("Album"), classical_path.join("Bach")] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/formats/sample.mp3", path.join("a.mp3")).unwrap();
+	}
+
+	let db = db::get_test_db("mount_names.sqlite");
+	config::amend(
+		&db,
+		&config::Config {
+			mount_dirs: Some(vec![
+				vfs::MountPoint {
+					source: collection_path.to_string_lossy().into_owned(),
+					name: "root".to_owned(),
+					album_art_pattern: None,
+					include_patterns: None,
+				},
+				vfs::MountPoint {
+					source: classical_path.to_string_lossy().into_owned(),
+					name: "classical".to_owned(),
+					album_art_pattern: None,
+					include_patterns: None,
+				},
+			]),
+			..Default::default()
+		},
+	)
+	.unwrap();
+
+	let check_mount_names = || {
+		let song = get_song(&db, &Path::new("root").join("Album").join("a.mp3")).unwrap();
+		assert_eq!(song.mount_name, Some("root".to_owned()));
+		let song = get_song(&db, &Path::new("classical").join("Bach").join("a.mp3")).unwrap();
+		assert_eq!(song.mount_name, Some("classical".to_owned()));
+
+		let connection = db.connect().unwrap();
+		let mut mount_names: Vec<Option<String>> = directories::table
+			.select(directories::mount_name)
+			.load(&connection)
+			.unwrap();
+		mount_names.sort();
+		assert_eq!(
+			mount_names,
+			vec![
+				Some("classical".to_owned()),
+				Some("classical".to_owned()),
+				Some("root".to_owned()),
+				Some("root".to_owned()),
+			]
+		);
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	check_mount_names();
+
+	// Entries indexed before mount names were recorded are filled in by the next update
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(songs::table)
+			.set(songs::mount_name.eq(None::<String>))
+			.execute(&connection)
+			.unwrap();
+		diesel::update(directories::table)
+			.set(directories::mount_name.eq(None::<String>))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+	check_mount_names();
+}
+
+#[test]
+fn test_forget_mount() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("forget-mount-collection");
+	let rock_path = collection_path.join("Rock");
+	let jazz_path = collection_path.join("Jazz");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for path in &[rock_path.join("Album"), jazz_path.join("Album")] {
+		std::fs::create_dir_all(path).unwrap();
+		std::fs::copy("test-data/formats/sample.mp3", path.join("a.mp3")).unwrap();
+		std::fs::copy("test-data/formats/sample.flac", path.join("b.flac")).unwrap();
+	}
+
+	let db = db::get_test_db("forget_mount.sqlite");
+	let mount = |source: &Path, name: &str| vfs::MountPoint {
+		source: source.to_string_lossy().into_owned(),
+		name: name.to_owned(),
+		album_art_pattern: None,
+		include_patterns: None,
+	};
+	config::amend(
+		&db,
+		&config::Config {
+			mount_dirs: Some(vec![mount(&rock_path, "rock"), mount(&jazz_path, "jazz")]),
+			..Default::default()
+		},
+	)
+	.unwrap();
+	let mount_names = || -> Vec<Option<String>> {
+		let connection = db.connect().unwrap();
+		let mut names: Vec<Option<String>> = songs::table
+			.select(songs::mount_name)
+			.load(&connection)
+			.unwrap();
+		names.extend(
+			directories::table
+				.select(directories::mount_name)
+				.load::<Option<String>>(&connection)
+				.unwrap(),
+		);
+		names.sort();
+		names.dedup();
+		names
+	};
+
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(forget_mount(&db, "jazz").unwrap(), 4);
+	assert_eq!(mount_names(), vec![Some("rock".to_owned())]);
+	assert_eq!(forget_mount(&db, "jazz").unwrap(), 0);
+
+	// The mount point is still configured, so the next update indexes it again
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(
+		mount_names(),
+		vec![Some("jazz".to_owned()), Some("rock".to_owned())]
+	);
+
+	// Mount points removed from the configuration are forgotten without waiting for an update
+	config::amend(
+		&db,
+		&config::Config {
+			mount_dirs: Some(vec![mount(&rock_path, "rock")]),
+			..Default::default()
+		},
+	)
+	.unwrap();
+	assert_eq!(mount_names(), vec![Some("rock".to_owned())]);
+}
+
+#[test]
+fn test_composer_and_conductor() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("composer-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for (name, conductor) in &[("a.mp3", "Conductor A"), ("b.mp3", "Conductor B")] {
+		let song_path = album_path.join(name);
+		std::fs::copy("test-data/formats/sample.mp3", &song_path).unwrap();
+		let mut tag = id3::Tag::read_from_path(&song_path).unwrap();
+		tag.set_text("TCOM", "Some Composer");
+		tag.set_text("TPE3", *conductor);
+		tag.set_text("TIT1", "Symphony No. 5");
+		tag.write_to_path(&song_path, id3::Version::Id3v24).unwrap();
+	}
+
+	let db = db::get_test_db("composer_and_conductor.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let songs: Vec<Song> = songs::table.order(songs::path).load(&connection).unwrap();
+	assert_eq!(songs.len(), 2);
+	assert_eq!(songs[0].composer, Some("Some Composer".to_owned()));
+	assert_eq!(songs[0].conductor, Some("Conductor A".to_owned()));
+	assert_eq!(songs[1].conductor, Some("Conductor B".to_owned()));
+	assert_eq!(songs[0].work, Some("Symphony No. 5".to_owned()));
+	assert_eq!(songs[0].movement_number, None);
+
+	let directory: Directory = directories::table
+		.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(directory.composer, Some("Some Composer".to_owned()));
+}
+
+#[test]
+fn test_sort_keys() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("sort-keys-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for (name, artist) in &[("a.mp3", "The Beatles"), ("b.mp3", "\u{c9}milie Simon")] {
+		let song_path = album_path.join(name);
+		std::fs::copy("test-data/formats/sample.mp3", &song_path).unwrap();
+		let mut tag = id3::Tag::read_from_path(&song_path).unwrap();
+		tag.set_artist(*artist);
+		tag.set_album_artist("The Beatles");
+		tag.set_album("A Hard Day's Night");
+		if *name == "b.mp3" {
+			tag.set_text("TSOP", "Simon, \u{c9}milie");
+			tag.set_text("TSOT", "Custom Title");
+		}
+		tag.write_to_path(&song_path, id3::Version::Id3v24).unwrap();
+	}
+
+	let db = db::get_test_db("sort_keys.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let songs: Vec<Song> = songs::table.order(songs::path).load(&connection).unwrap();
+	assert_eq!(songs.len(), 2);
+	assert_eq!(songs[0].sort_artist, Some("beatles".to_owned()));
+	assert_eq!(songs[0].sort_album, Some("hard day's night".to_owned()));
+	assert_eq!(songs[0].sort_title, Some("test title".to_owned()));
+	assert_eq!(songs[1].sort_artist, Some("simon, emilie".to_owned()));
+	assert_eq!(songs[1].sort_title, Some("custom title".to_owned()));
+
+	let directory: Directory = directories::table
+		.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(directory.sort_artist, Some("beatles".to_owned()));
+	assert_eq!(directory.sort_album, Some("hard day's night".to_owned()));
+
+	diesel::update(misc_settings::table)
+		.set(misc_settings::index_sort_articles.eq(""))
+		.execute(&connection)
+		.unwrap();
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	let directory: Directory = directories::table
+		.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(directory.sort_artist, Some("the beatles".to_owned()));
+}
+
+#[test]
+fn test_compilations() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("compilations-collection");
+	let flagged_path = collection_path.join("Flagged");
+	let marked_path = collection_path.join("Marked");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for album_path in &[&flagged_path, &marked_path] {
+		std::fs::create_dir_all(album_path).unwrap();
+		for (name, artist) in &[("a.mp3", "Artist A"), ("b.mp3", "Artist B")] {
+			let song_path = album_path.join(name);
+			std::fs::copy("test-data/formats/sample.mp3", &song_path).unwrap();
+			let mut tag = id3::Tag::read_from_path(&song_path).unwrap();
+			tag.set_artist(*artist);
+			if *album_path == &flagged_path {
+				tag.remove("TPE2");
+				if *name == "a.mp3" {
+					tag.set_text("TCMP", "1");
+				}
+			} else {
+				tag.set_album_artist("various artists");
+			}
+			tag.write_to_path(&song_path, id3::Version::Id3v24).unwrap();
+		}
+	}
+
+	let db = db::get_test_db("compilations.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let get_artist = |album_path: &Path| -> Option<String> {
+		let connection = db.connect().unwrap();
+		directories::table
+			.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+			.select(directories::artist)
+			.get_result(&connection)
+			.unwrap()
+	};
+	assert_eq!(
+		get_artist(&flagged_path),
+		Some("Various Artists".to_owned())
+	);
+	assert_eq!(get_artist(&marked_path), Some("Various Artists".to_owned()));
+
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_various_artists.eq("VA"))
+			.execute(&connection)
+			.unwrap();
+	}
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_artist(&flagged_path), Some("VA".to_owned()));
+	assert_eq!(get_artist(&marked_path), Some("various artists".to_owned()));
+}
+
+#[test]
+fn test_lyrics() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("lyrics-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for name in &["a.mp3", "b.mp3", "c.mp3"] {
+		let song_path = album_path.join(name);
+		std::fs::copy("test-data/formats/sample.mp3", &song_path).unwrap();
+		if *name != "c.mp3" {
+			let mut tag = id3::Tag::read_from_path(&song_path).unwrap();
+			tag.add_lyrics(id3::frame::Lyrics {
+				lang: "eng".to_owned(),
+				description: "".to_owned(),
+				text: "Embedded lyrics".to_owned(),
+			});
+			tag.write_to_path(&song_path, id3::Version::Id3v24).unwrap();
+		}
+	}
+	std::fs::write(album_path.join("a.lrc"), "[00:01.00]Sidecar lyrics").unwrap();
+
+	let db = db::get_test_db("lyrics.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let get_lyrics = || -> Vec<(String, String, i32)> {
+		let connection = db.connect().unwrap();
+		song_lyrics::table
+			.select((
+				song_lyrics::song_path,
+				song_lyrics::lyrics,
+				song_lyrics::has_synced_lyrics,
+			))
+			.order(song_lyrics::song_path)
+			.load(&connection)
+			.unwrap()
+	};
+	let song_path = |name: &str| album_path.join(name).to_string_lossy().into_owned();
+	assert_eq!(
+		get_lyrics(),
+		vec![
+			(song_path("a.mp3"), "[00:01.00]Sidecar lyrics".to_owned(), 1),
+			(song_path("b.mp3"), "Embedded lyrics".to_owned(), 0),
+		]
+	);
+
+	std::fs::remove_file(album_path.join("b.mp3")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(get_lyrics().len(), 1);
 }
 
 #[test]
@@ -66,7 +4200,7 @@ fn test_embedded_artwork() {
 	song_path.push("07 - なぜ (Why).mp3");
 
 	let db = db::get_test_db("artwork.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 
 	let connection = db.connect().unwrap();
 	let songs: Vec<Song> = songs::table
@@ -79,13 +4213,185 @@ fn test_embedded_artwork() {
 	assert_eq!(song.artwork, Some(song_path.to_string_lossy().into_owned()));
 }
 
+#[test]
+fn test_embedded_directory_artwork() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("embedded-directory-artwork-collection");
+	let album_path = collection_path.join("Album");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::copy("test-data/formats/sample.ogg", album_path.join("01.ogg")).unwrap();
+	std::fs::copy("test-data/artwork/sample.mp3", album_path.join("02.mp3")).unwrap();
+
+	let db = db::get_test_db("embedded_directory_artwork.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let directory: Directory = directories::table
+		.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(
+		directory.artwork,
+		Some(album_path.join("02.mp3").to_string_lossy().into_owned())
+	);
+}
+
+#[test]
+fn test_cached_embedded_artwork() {
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("cached-embedded-artwork-collection");
+	let mut album_path = collection_path.clone();
+	album_path.push("Album");
+	let mut cache_path = PathBuf::new();
+	cache_path.push("test-output");
+	cache_path.push("cached-embedded-artwork-cache");
+	for path in &[&collection_path, &cache_path] {
+		if path.exists() {
+			std::fs::remove_dir_all(path).unwrap();
+		}
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	for name in &["sample.mp3", "sample.flac"] {
+		std::fs::copy(
+			Path::new("test-data/artwork").join(name),
+			album_path.join(name),
+		)
+		.unwrap();
+	}
+
+	let db = db::get_test_db("cached_embedded_artwork.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+
+	let options = UpdateOptions {
+		artwork_cache: Some(cache_path.clone()),
+		..Default::default()
+	};
+	update(&db, &options).unwrap();
+
+	let connection = db.connect().unwrap();
+	let songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(songs.len(), 2);
+	for song in &songs {
+		let artwork = song.artwork.as_ref().unwrap();
+		assert!(Path::new(artwork).starts_with(&cache_path));
+		assert!(Path::new(artwork).exists());
+	}
+
+	let directory: Directory = directories::table
+		.filter(directories::path.eq(album_path.to_string_lossy().as_ref()))
+		.get_result(&connection)
+		.unwrap();
+	assert_eq!(directory.artwork, songs[0].artwork);
+}
+
+#[test]
+fn test_encoded_embedded_artwork() {
+	use image::GenericImageView;
+
+	let mut collection_path = PathBuf::new();
+	collection_path.push("test-output");
+	collection_path.push("encoded-embedded-artwork-collection");
+	let album_path = collection_path.join("Album");
+	let mut cache_path = PathBuf::new();
+	cache_path.push("test-output");
+	cache_path.push("encoded-embedded-artwork-cache");
+	for path in &[&collection_path, &cache_path] {
+		if path.exists() {
+			std::fs::remove_dir_all(path).unwrap();
+		}
+	}
+	std::fs::create_dir_all(&album_path).unwrap();
+	std::fs::copy(
+		"test-data/artwork/sample.mp3",
+		album_path.join("sample.mp3"),
+	)
+	.unwrap();
+
+	let db = db::get_test_db("encoded_embedded_artwork.sqlite");
+	let config = config::Config {
+		mount_dirs: Some(vec![vfs::MountPoint {
+			source: collection_path.to_string_lossy().into_owned(),
+			name: "root".to_owned(),
+			album_art_pattern: None,
+			include_patterns: None,
+		}]),
+		..Default::default()
+	};
+	config::amend(&db, &config).unwrap();
+	let set_encoding = |max_dimension: i32, format: &str| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_artwork_cache_dir.eq(cache_path.to_string_lossy().as_ref()),
+				misc_settings::index_artwork_max_dimension.eq(max_dimension),
+				misc_settings::index_artwork_format.eq(format),
+			))
+			.execute(&connection)
+			.unwrap();
+	};
+	let get_artwork = || -> PathBuf {
+		let connection = db.connect().unwrap();
+		let artwork: Option<String> = songs::table
+			.select(songs::artwork)
+			.get_result(&connection)
+			.unwrap();
+		PathBuf::from(artwork.unwrap())
+	};
+
+	// The cache directory from the settings is used without one in the update options
+	set_encoding(8, "png");
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	let artwork = get_artwork();
+	assert!(artwork.starts_with(&cache_path));
+	assert_eq!(artwork.extension().unwrap(), "png");
+	let (width, height) = image::open(&artwork).unwrap().dimensions();
+	assert!(width <= 8 && height <= 8);
+
+	set_encoding(0, "jpeg");
+	populate(&db, true, &UpdateOptions::default()).unwrap();
+	let artwork = get_artwork();
+	assert_eq!(artwork.extension().unwrap(), "jpg");
+	assert_eq!(
+		image::guess_format(&std::fs::read(&artwork).unwrap()).unwrap(),
+		image::ImageFormat::Jpeg
+	);
+
+	set_encoding(0, "webp");
+	let error = populate(&db, true, &UpdateOptions::default()).unwrap_err();
+	assert!(matches!(error, IndexError::ArtworkFormat(_)));
+}
+
 #[test]
 fn test_browse_top_level() {
 	let mut root_path = PathBuf::new();
 	root_path.push("root");
 
 	let db = db::get_test_db("browse_top_level.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 	let results = browse(&db, Path::new("")).unwrap();
 
 	assert_eq!(results.len(), 1);
@@ -106,7 +4412,7 @@ fn test_browse() {
 	tobokegao_path.push("Tobokegao");
 
 	let db = db::get_test_db("browse.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 	let results = browse(&db, Path::new("root")).unwrap();
 
 	assert_eq!(results.len(), 2);
@@ -120,10 +4426,39 @@ fn test_browse() {
 	}
 }
 
+#[test]
+fn test_iter_songs_and_directories() {
+	let db = db::get_test_db("iter_songs_and_directories.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let songs = iter_songs(&db)
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+	assert_eq!(songs.len(), 13);
+	assert!(songs.iter().all(|s| s.path.starts_with("root")));
+	let paths = songs.iter().map(|s| s.path.clone()).collect::<HashSet<_>>();
+	let flattened = flatten(&db, Path::new("root")).unwrap();
+	assert_eq!(
+		paths,
+		flattened
+			.into_iter()
+			.map(|s| s.path)
+			.collect::<HashSet<_>>()
+	);
+
+	let directories = iter_directories(&db)
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+	assert_eq!(directories.len(), 6);
+	assert!(directories.iter().any(|d| d.path == "root"));
+}
+
 #[test]
 fn test_flatten() {
 	let db = db::get_test_db("flatten.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 
 	// Flatten all
 	let results = flatten(&db, Path::new("root")).unwrap();
@@ -149,7 +4484,7 @@ fn test_flatten() {
 #[test]
 fn test_random() {
 	let db = db::get_test_db("random.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 	let results = get_random_albums(&db, 1).unwrap();
 	assert_eq!(results.len(), 1);
 }
@@ -157,16 +4492,53 @@ fn test_random() {
 #[test]
 fn test_recent() {
 	let db = db::get_test_db("recent.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 	let results = get_recent_albums(&db, 2).unwrap();
 	assert_eq!(results.len(), 2);
 	assert!(results[0].date_added >= results[1].date_added);
 }
 
+#[test]
+fn test_get_ancestors() {
+	let db = db::get_test_db("get_ancestors.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+	let hunted_path = Path::new("root").join("Khemmis").join("Hunted");
+	let ancestor_paths = || -> Vec<(String, Option<i32>)> {
+		get_ancestors(&db, &hunted_path)
+			.unwrap()
+			.into_iter()
+			.map(|d| (d.path, d.depth))
+			.collect()
+	};
+	let expected_paths = vec![
+		("root".to_owned(), Some(0)),
+		(
+			Path::new("root")
+				.join("Khemmis")
+				.to_string_lossy()
+				.into_owned(),
+			Some(1),
+		),
+	];
+	assert_eq!(ancestor_paths(), expected_paths);
+	assert!(get_ancestors(&db, Path::new("root")).unwrap().is_empty());
+
+	// Ancestors are computed again when stored paths are converted
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_store_relative_paths.eq(1))
+			.execute(&connection)
+			.unwrap();
+	}
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(ancestor_paths(), expected_paths);
+}
+
 #[test]
 fn test_get_song() {
 	let db = db::get_test_db("get_song.sqlite");
-	update(&db).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
 
 	let mut song_path = PathBuf::new();
 	song_path.push("root");
@@ -177,3 +4549,289 @@ fn test_get_song() {
 	let song = get_song(&db, &song_path).unwrap();
 	assert_eq!(song.title.unwrap(), "Candlelight");
 }
+
+#[test]
+fn test_populate_generated_collection() {
+	let collection_path = generate_collection("generated", 20, 10);
+	let db = db::get_test_db("populate_generated_collection.sqlite");
+	mount_collection(&db, &collection_path);
+
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_added.count, 200);
+	assert_eq!(summary.directories_added.count, 21);
+}
+
+#[test]
+fn test_populate_in_memory() {
+	let db = db::DB::new_in_memory().unwrap();
+	let config = config::parse_toml_file(Path::new("test-data/config.toml")).unwrap();
+	config::amend(&db, &config).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	let connection = db.connect().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&connection).unwrap();
+	assert_eq!(all_directories.len(), 6);
+	assert_eq!(all_songs.len(), 13);
+
+	// The database name is not mistaken for a file name
+	let files = std::fs::read_dir(".").unwrap();
+	assert!(!files
+		.map(|f| f.unwrap().file_name())
+		.any(|name| name.to_string_lossy().starts_with("file:")));
+}
+
+#[test]
+fn test_populate_original_dates() {
+	let collection_path = Path::new("test-output").join("original-dates-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	std::fs::create_dir_all(&collection_path).unwrap();
+	let set_dates = |dates: &[&str]| {
+		for (i, date) in dates.iter().enumerate() {
+			let song_path = collection_path.join(format!("{:02}.flac", i + 1));
+			std::fs::copy("test-data/formats/sample.flac", &song_path).unwrap();
+			let mut tag = metaflac::Tag::read_from_path(&song_path).unwrap();
+			tag.set_vorbis("DATE", vec![*date]);
+			tag.save().unwrap();
+		}
+	};
+	let db = db::get_test_db("populate_original_dates.sqlite");
+	mount_collection(&db, &collection_path);
+	let directory_date = || -> Option<String> {
+		let connection = db.connect().unwrap();
+		directories::table
+			.select(directories::original_date)
+			.filter(directories::parent.is_null())
+			.get_result(&connection)
+			.unwrap()
+	};
+
+	// The most precise date is kept when the others only lack precision
+	set_dates(&["2019", "2019-07-14", "2019-07"]);
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(directory_date(), Some("2019-07-14".to_owned()));
+
+	set_dates(&["2019-07-14", "2019-08"]);
+	std::fs::remove_file(collection_path.join("03.flac")).unwrap();
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(directory_date(), None);
+}
+
+#[test]
+fn test_count_indexable() {
+	let db = db::get_test_db("count_indexable.sqlite");
+	assert_eq!(count_indexable(&db, &UpdateOptions::default()).unwrap(), 13);
+	let summary = update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(summary.songs_added.count, 13);
+
+	// Files are counted following the same rules as updates
+	{
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_ignore_patterns.eq("Khemmis"))
+			.execute(&connection)
+			.unwrap();
+	}
+	assert_eq!(count_indexable(&db, &UpdateOptions::default()).unwrap(), 8);
+
+	let options = UpdateOptions {
+		cancellation: Some(Arc::new(AtomicBool::new(true))),
+		..Default::default()
+	};
+	assert!(matches!(
+		count_indexable(&db, &options),
+		Err(IndexError::Cancelled)
+	));
+}
+
+#[test]
+fn test_count_indexable_filters() {
+	let collection_path = generate_collection("count-indexable-filters", 2, 2);
+	let album_path = collection_path.join("Album 000");
+	std::fs::write(album_path.join("03 - Song.mp3"), b"").unwrap();
+	std::fs::copy(
+		"test-data/archives/sample.zip",
+		album_path.join("sample.zip"),
+	)
+	.unwrap();
+	let stems_path = collection_path.join("Album 001").join("Stems");
+	std::fs::create_dir_all(&stems_path).unwrap();
+	std::fs::copy("test-data/formats/sample.mp3", stems_path.join("stem.mp3")).unwrap();
+
+	let db = db::get_test_db("count_indexable_filters.sqlite");
+	mount_collection(&db, &collection_path);
+	assert_eq!(count_indexable(&db, &UpdateOptions::default()).unwrap(), 6);
+
+	let set_setting = |min_file_size: i32, archives: i32| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set((
+				misc_settings::index_min_file_size.eq(min_file_size),
+				misc_settings::index_archives.eq(archives),
+			))
+			.execute(&connection)
+			.unwrap();
+	};
+	set_setting(256, 0);
+	assert_eq!(count_indexable(&db, &UpdateOptions::default()).unwrap(), 5);
+
+	let options = UpdateOptions {
+		directory_filter: Some(Arc::new(SkipStems {
+			mount_roots: std::sync::Mutex::new(Vec::new()),
+		})),
+		..Default::default()
+	};
+	assert_eq!(count_indexable(&db, &options).unwrap(), 4);
+
+	// Archives count for the songs within them
+	set_setting(256, 1);
+	assert_eq!(count_indexable(&db, &options).unwrap(), 6);
+}
+
+#[test]
+fn test_populate_progress_unchanged() {
+	let db = db::get_test_db("populate_progress_unchanged.sqlite");
+	update(&db, &UpdateOptions::default()).unwrap();
+
+	// Songs of unchanged directories are not inserted again, but still make progress
+	let (sender, receiver) = crossbeam_channel::unbounded();
+	let options = UpdateOptions {
+		progress: Some(sender),
+		..Default::default()
+	};
+	let summary = update(&db, &options).unwrap();
+	assert_eq!(summary.stats.songs_inserted, 0);
+	let reports = receiver.try_iter().collect::<Vec<_>>();
+	let songs_inserted = reports.iter().map(|p| p.songs_inserted).max();
+	assert_eq!(songs_inserted, Some(13));
+	assert!(reports.iter().all(|p| p.songs_total == 13));
+}
+
+#[test]
+fn test_group_albums() {
+	let collection_path = Path::new("test-output").join("group-albums-collection");
+	if collection_path.exists() {
+		std::fs::remove_dir_all(&collection_path).unwrap();
+	}
+	for disc in &["CD1", "CD2"] {
+		let disc_path = collection_path.join("Album").join(disc);
+		std::fs::create_dir_all(&disc_path).unwrap();
+		let song_path = disc_path.join("sample.flac");
+		std::fs::copy("test-data/formats/sample.flac", &song_path).unwrap();
+		let mut tag = metaflac::Tag::read_from_path(&song_path).unwrap();
+		tag.set_vorbis("MUSICBRAINZ_ALBUMID", vec!["album-id"]);
+		tag.save().unwrap();
+	}
+	let other_path = collection_path.join("Other");
+	std::fs::create_dir_all(&other_path).unwrap();
+	std::fs::copy(
+		"test-data/formats/sample.mp3",
+		other_path.join("sample.mp3"),
+	)
+	.unwrap();
+
+	let db = db::get_test_db("group_albums.sqlite");
+	mount_collection(&db, &collection_path);
+	let set_group_albums = |enabled: bool| {
+		let connection = db.connect().unwrap();
+		diesel::update(misc_settings::table)
+			.set(misc_settings::index_group_albums.eq(enabled as i32))
+			.execute(&connection)
+			.unwrap();
+	};
+
+	set_group_albums(true);
+	update(&db, &UpdateOptions::default()).unwrap();
+	let albums = get_albums(&db).unwrap();
+	assert_eq!(albums.len(), 2);
+	let split_album = albums
+		.iter()
+		.find(|a| a.musicbrainz_album_id.is_some())
+		.unwrap();
+	assert_eq!(split_album.song_count, 2);
+	assert_eq!(split_album.directory, None);
+	let songs = get_album_songs(&db, split_album.id).unwrap();
+	assert_eq!(
+		songs.iter().map(|s| s.path.clone()).collect::<Vec<_>>(),
+		vec![
+			Path::new("root/Album/CD1/sample.flac").to_string_lossy(),
+			Path::new("root/Album/CD2/sample.flac").to_string_lossy()
+		]
+	);
+
+	// Songs without an identifier are grouped by directory
+	let directory_album = albums
+		.iter()
+		.find(|a| a.musicbrainz_album_id.is_none())
+		.unwrap();
+	assert_eq!(
+		directory_album.directory,
+		Some(Path::new("root/Other").to_string_lossy().into_owned())
+	);
+	assert_eq!(get_album_songs(&db, directory_album.id).unwrap().len(), 1);
+
+	set_group_albums(false);
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert!(get_albums(&db).unwrap().is_empty());
+}
+
+#[test]
+fn test_clean_removed_mount_point() {
+	let kept_path = generate_collection("kept-mount", 1, 2);
+	let removed_path = generate_collection("removed-mount", 2, 1);
+	let db = db::get_test_db("clean_removed_mount_point.sqlite");
+	let mount = |mount_points: &[(&Path, &str)]| {
+		let config = config::Config {
+			mount_dirs: Some(
+				mount_points
+					.iter()
+					.map(|(source, name)| vfs::MountPoint {
+						source: source.to_string_lossy().into_owned(),
+						name: (*name).to_owned(),
+						album_art_pattern: None,
+						include_patterns: None,
+					})
+					.collect(),
+			),
+			..Default::default()
+		};
+		config::amend(&db, &config).unwrap();
+	};
+	let counts = || -> (i64, i64) {
+		let connection = db.connect().unwrap();
+		let directories = directories::table.count().get_result(&connection).unwrap();
+		let songs = songs::table.count().get_result(&connection).unwrap();
+		(directories, songs)
+	};
+	mount(&[(&kept_path, "kept"), (&removed_path, "removed")]);
+	update(&db, &UpdateOptions::default()).unwrap();
+	assert_eq!(counts(), (5, 4));
+
+	// Files of the removed mount point still exist, but are no longer part of the collection
+	mount(&[(&kept_path, "kept")]);
+	assert_eq!(counts(), (2, 2));
+	let options = UpdateOptions {
+		path_existence: Some(Arc::new(FakePathExistence {
+			paths: [
+				kept_path.clone(),
+				kept_path.join("Album 000"),
+				kept_path.join("Album 000").join("01 - Song.mp3"),
+				kept_path.join("Album 000").join("02 - Song.mp3"),
+				removed_path.clone(),
+				removed_path.join("Album 000"),
+				removed_path.join("Album 000").join("01 - Song.mp3"),
+				removed_path.join("Album 001"),
+				removed_path.join("Album 001").join("01 - Song.mp3"),
+			]
+			.iter()
+			.cloned()
+			.collect(),
+		})),
+		..Default::default()
+	};
+	let summary = clean(&db, &options).unwrap();
+	assert_eq!(summary.songs_removed.count, 0);
+	assert_eq!(counts(), (2, 2));
+}
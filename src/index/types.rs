@@ -1,4 +1,7 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::db::songs;
 
@@ -19,6 +22,55 @@ pub struct Song {
 	pub album: Option<String>,
 	pub artwork: Option<String>,
 	pub duration: Option<i32>,
+	pub genre: Option<String>,
+	pub file_size: Option<i64>,
+	pub mtime: Option<i64>,
+	pub replay_gain_track_gain: Option<f32>,
+	pub replay_gain_track_peak: Option<f32>,
+	pub replay_gain_album_gain: Option<f32>,
+	pub replay_gain_album_peak: Option<f32>,
+	pub track_total: Option<i32>,
+	pub disc_total: Option<i32>,
+	#[serde(skip_serializing, skip_deserializing)]
+	pub search_title: Option<String>,
+	#[serde(skip_serializing, skip_deserializing)]
+	pub search_artist: Option<String>,
+	pub start_time: Option<i32>,
+	pub composer: Option<String>,
+	pub conductor: Option<String>,
+	pub content_hash: Option<String>,
+	pub sort_artist: Option<String>,
+	pub sort_album: Option<String>,
+	pub sort_title: Option<String>,
+	pub format: Option<String>,
+	pub bitrate: Option<i32>,
+	pub sample_rate: Option<i32>,
+	pub bits_per_sample: Option<i32>,
+	pub channels: Option<i32>,
+	#[serde(skip_serializing, skip_deserializing)]
+	pub deleted_at: Option<i32>,
+	pub bpm: Option<i32>,
+	pub musical_key: Option<String>,
+	pub mount_name: Option<String>,
+	pub rating: Option<i32>,
+	pub tagged_play_count: Option<i32>,
+	pub disc_subtitle: Option<String>,
+	pub created: Option<i64>, // Null when the file system does not record creation times
+	pub isrc: Option<String>,
+	pub musicbrainz_track_id: Option<String>,
+	pub musicbrainz_album_id: Option<String>,
+	pub musicbrainz_artist_id: Option<String>,
+	pub encoder_delay: Option<i32>, // In samples, for gapless playback
+	pub encoder_padding: Option<i32>,
+	pub original_date: Option<String>, // As in "2019-07-14", or only as precise as the tags
+	#[serde(skip_serializing, skip_deserializing)]
+	pub link_target: Option<String>, // Real path of a symlinked file, when those are resolved
+	pub album_key: Option<String>,
+	pub lossless: Option<bool>,       // Null when the format is unknown
+	pub bitrate_mode: Option<String>, // One of "CBR", "ABR" or "VBR", null when it cannot be told
+	pub work: Option<String>,
+	pub movement_name: Option<String>,
+	pub movement_number: Option<i32>,
 }
 
 #[derive(Debug, PartialEq, Queryable, Serialize, Deserialize)]
@@ -33,10 +85,185 @@ pub struct Directory {
 	pub album: Option<String>,
 	pub artwork: Option<String>,
 	pub date_added: i32,
+	#[serde(skip_serializing, skip_deserializing)]
+	pub last_indexed: Option<i32>,
+	pub genre: Option<String>,
+	pub song_count: i32,
+	pub total_duration: i32,
+	pub composer: Option<String>,
+	pub sort_artist: Option<String>,
+	pub sort_album: Option<String>,
+	#[serde(skip_serializing, skip_deserializing)]
+	pub dir_fingerprint: Option<String>,
+	pub depth: Option<i32>,
+	#[serde(skip_serializing, skip_deserializing)]
+	pub ancestors: Option<String>,
+	pub mount_name: Option<String>,
+	pub created: Option<i32>, // Null when the file system does not record creation times
+	pub modified: Option<i32>,
+	pub musicbrainz_album_id: Option<String>, // Shared by all songs of the directory
+	pub musicbrainz_artist_id: Option<String>,
+	pub kind: Option<String>, // One of "album", "artist" or "other", null until classified
+	pub original_date: Option<String>, // Most precise date which all songs agree with
+	pub album_key: Option<String>, // Shared with the songs of the album
+}
+
+// Songs of an album either share its MusicBrainz identifier or, lacking one, its directory
+#[derive(Debug, PartialEq, Queryable, Serialize, Deserialize)]
+pub struct Album {
+	pub id: i32,
+	pub musicbrainz_album_id: Option<String>,
+	pub directory: Option<String>,
+	pub name: Option<String>,
+	pub artist: Option<String>,
+	pub year: Option<i32>,
+	pub artwork: Option<String>,
+	pub song_count: i32,
+	pub total_duration: i32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lyrics {
+	pub lyrics: String,
+	pub has_synced_lyrics: bool,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum CollectionFile {
 	Directory(Directory),
-	Song(Song),
+	Song(Box<Song>),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathChanges {
+	pub count: usize,
+	pub paths: Vec<String>, // Only collected during dry runs
+}
+
+impl PathChanges {
+	pub fn record(&mut self, path: &str, keep_path: bool) {
+		self.count += 1;
+		if keep_path {
+			self.paths.push(path.to_owned());
+		}
+	}
+}
+
+// Problems which do not interrupt the index update, such as an unreadable file
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexWarning {
+	pub path: PathBuf,
+	pub message: String,
+}
+
+// Tags whose values differ between the songs of a directory are left out of the directory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetadataField {
+	Album,
+	Artist,
+	Year,
+	Genre,
+	Composer,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetadataConflict {
+	pub path: PathBuf,
+	pub field: MetadataField,
+	pub values: Vec<String>, // Distinct values, in the order songs were read
+}
+
+// Directories which took longer than `index_slow_directory_threshold_ms` to index. The time spent
+// in their sub-directories is not included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowDirectory {
+	pub path: PathBuf,
+	pub elapsed: Duration,
+	pub file_count: usize,
+}
+
+// Entries which break the invariants of the index, as real paths. Only collected when
+// `index_verify_after_index` is set.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IntegrityViolation {
+	OrphanedSong(PathBuf), // Whose parent directory is not indexed
+	DirectoryOwnParent(PathBuf),
+	MissingArtwork { entry: PathBuf, artwork: PathBuf },
+}
+
+// Row counts reported by the database, where updated entries count as inserted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexStats {
+	pub songs_inserted: usize,
+	pub songs_deleted: usize,
+	pub directories_inserted: usize,
+	pub directories_deleted: usize,
+	pub files_skipped_errors: usize,
+	pub elapsed: Duration,
+}
+
+impl fmt::Display for IndexStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} songs inserted, {} songs deleted, {} directories inserted, {} directories deleted, {} files skipped after errors, in {:.3} seconds",
+			self.songs_inserted,
+			self.songs_deleted,
+			self.directories_inserted,
+			self.directories_deleted,
+			self.files_skipped_errors,
+			self.elapsed.as_secs_f32()
+		)
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UpdateSummary {
+	pub songs_added: PathChanges,
+	pub songs_removed: PathChanges,
+	pub directories_added: PathChanges,
+	pub directories_removed: PathChanges,
+	pub warnings: Vec<IndexWarning>,
+	pub metadata_conflicts: Vec<MetadataConflict>, // Only collected when requested
+	pub slow_directories: Vec<SlowDirectory>,      // Only collected when a threshold is set
+	pub integrity_violations: Vec<IntegrityViolation>,
+	pub stats: IndexStats,
+}
+
+// Differences between the index and the file system, as real paths
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct VerifyReport {
+	pub missing_songs: Vec<PathBuf>,
+	pub missing_directories: Vec<PathBuf>,
+	pub unindexed_songs: Vec<PathBuf>,
+	pub unindexed_directories: Vec<PathBuf>,
+	pub modified_songs: Vec<PathBuf>,
+	pub unreadable_directories: Vec<PathBuf>, // Whose indexed contents are not reported as missing
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexPhase {
+	Cleaning,
+	Scanning, // Visiting directories and reading their files
+	Writing,  // Writing batches of rows, and what derives from them once all are written
+	Done,
+}
+
+// Phase an `Indexer` runs on its next step, along with the changes made by its previous steps
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexerState {
+	pub phase: IndexPhase,
+	pub songs_added: usize,
+	pub songs_removed: usize,
+	pub directories_added: usize,
+	pub directories_removed: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexProgress {
+	pub directories_visited: usize,
+	pub songs_inserted: usize, // Includes the songs of unchanged directories, which are skipped
+	// Song files found before the update started reading tags, see `count_indexable`
+	pub songs_total: usize,
+	pub current_directory: String,
 }
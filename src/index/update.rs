@@ -1,472 +1,4073 @@
 use anyhow::*;
 use crossbeam_channel::{Receiver, Sender};
 use diesel;
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
 #[cfg(feature = "profile-index")]
 use flame;
-use log::{error, info};
+use glob::Pattern;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
 use rayon::prelude::*;
 use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time;
 
 use crate::config::MiscSettings;
-use crate::db::{directories, misc_settings, songs, DB};
+use crate::db::{
+	self, albums, directories, index_checkpoint, index_renames, indexed_playlist_songs,
+	misc_settings, mount_points, songs, DB,
+};
+use crate::index::archive::{self, ArchiveEntry};
+use crate::index::artwork::{self, ArtworkCache};
+use crate::index::directory::{most_precise_date, CasingTally, DirectoryTags, TagConventions};
+use crate::index::files::{
+	self, get_creation_time, get_mount_include_patterns, has_allowed_extension, is_excluded_path,
+	is_excluded_virtual_path, is_ignored, is_song_file, is_symlink, DirectoryFiles, FileFilters,
+	IncludePattern, SongFile, SongPath,
+};
+use crate::index::insert::{
+	claim_removed_songs_at, delete_playlists, delete_songs, insert_directories, insert_songs,
+	insertion_channel, keep_removed_songs, BufferSizes, DirectoryWriter, InsertionConnection,
+	NewDirectory, NewIndexedPlaylist, NewIndexedPlaylistSong, NewSong, NewSongArtist,
+	NewSongLyrics, ProgressReporter, SongInsertion, SongWriter, WriteSettings,
+	CHECKPOINT_DIRECTORY, CHECKPOINT_SONGS,
+};
 use crate::index::metadata;
-use crate::vfs::VFSSource;
-use metadata::SongTags;
+use crate::index::{
+	IndexError, IndexPhase, IndexProgress, IndexStats, IndexWarning, IndexerState,
+	IntegrityViolation, MetadataConflict, PathChanges, SlowDirectory, UpdateSummary, VerifyReport,
+};
+use crate::utils;
+use crate::vfs::{VFSSource, VFS};
+use metadata::{DefaultMetadataReader, MetadataReader, SongTags, TagTransform};
 
-const INDEX_BUILDING_INSERT_BUFFER_SIZE: usize = 1000; // Insertions in each transaction
-const INDEX_BUILDING_CLEAN_BUFFER_SIZE: usize = 500; // Insertions in each transaction
+#[derive(Clone, Debug, Default)]
+pub struct UpdateOptions {
+	pub artwork_cache: Option<PathBuf>,
+	pub metadata_reader: Option<Arc<dyn MetadataReader>>, // Defaults to `DefaultMetadataReader`
+	pub path_existence: Option<Arc<dyn PathExistence>>,   // Defaults to the file system
+	pub directory_filter: Option<Arc<dyn DirectoryFilter>>, // Defaults to `AllowAllDirectories`
+	pub tag_transform: Option<TagTransform>,
+	pub report_metadata_conflicts: bool,
+	pub rescan_artwork: bool, // Looks for artwork again in directories which already have some
+	pub progress: Option<Sender<IndexProgress>>,
+	pub warnings: Option<Sender<IndexWarning>>, // Receives warnings as they occur
+	pub cancellation: Option<Arc<AtomicBool>>,
+	pub dry_run: bool,
+}
 
-pub fn update(db: &DB) -> Result<()> {
-	let start = time::Instant::now();
+impl UpdateOptions {
+	pub fn is_cancelled(&self) -> bool {
+		self.cancellation
+			.as_ref()
+			.map_or(false, |c| c.load(Ordering::Relaxed))
+	}
+
+	pub fn check_cancellation(&self) -> Result<(), IndexError> {
+		if self.is_cancelled() {
+			return Err(IndexError::Cancelled);
+		}
+		Ok(())
+	}
+}
+
+// Tells whether indexed files still exist when removing missing entries, which tests can fake
+pub trait PathExistence: Send + Sync {
+	fn exists(&self, path: &Path) -> bool;
+}
+
+impl fmt::Debug for dyn PathExistence {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "PathExistence")
+	}
+}
+
+// Decides which directories get indexed, for rules which ignore patterns cannot express. Rejected
+// directories are skipped along with their descendants, and removed from the index if they were
+// part of it. Mount points have no parent.
+pub trait DirectoryFilter: Send + Sync {
+	fn should_index(&self, path: &Path, parent: Option<&Path>) -> bool;
+}
+
+impl fmt::Debug for dyn DirectoryFilter {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "DirectoryFilter")
+	}
+}
+
+pub struct AllowAllDirectories;
+
+impl DirectoryFilter for AllowAllDirectories {
+	fn should_index(&self, _: &Path, _: Option<&Path>) -> bool {
+		true
+	}
+}
+
+fn get_ignore_patterns(settings: &MiscSettings) -> Result<Vec<Pattern>, IndexError> {
+	settings
+		.index_ignore_patterns
+		.split(',')
+		.map(|p| p.trim())
+		.filter(|p| !p.is_empty())
+		.map(|p| Ok(Pattern::new(p)?))
+		.collect()
+}
+
+fn get_ignore_marker(settings: &MiscSettings) -> Option<String> {
+	Some(settings.index_ignore_marker.trim())
+		.filter(|m| !m.is_empty())
+		.map(|m| m.to_owned())
+}
+
+// Compiled album art patterns, by the setting they were parsed from. Updates in quick succession
+// do not compile them again, while a changed setting is compiled on its next use.
+static ALBUM_ART_PATTERNS: Lazy<RwLock<HashMap<String, Vec<Regex>>>> =
+	Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Album art patterns are listed by order of preference
+fn parse_album_art_patterns(patterns: &str) -> Result<Vec<Regex>, IndexError> {
+	if let Some(regexes) = ALBUM_ART_PATTERNS.read().unwrap().get(patterns) {
+		return Ok(regexes.clone());
+	}
+	let regexes = patterns
+		.split(',')
+		.map(|p| p.trim())
+		.filter(|p| !p.is_empty())
+		.map(|p| Ok(Regex::new(p)?))
+		.collect::<Result<Vec<_>, IndexError>>()?;
+	ALBUM_ART_PATTERNS
+		.write()
+		.unwrap()
+		.insert(patterns.to_owned(), regexes.clone());
+	Ok(regexes)
+}
+
+// Drops the patterns compiled from settings which are no longer in use
+pub fn clear_album_art_pattern_cache() {
+	ALBUM_ART_PATTERNS.write().unwrap().clear();
+}
+
+fn get_album_art_patterns(settings: &MiscSettings) -> Result<Vec<Regex>, IndexError> {
+	parse_album_art_patterns(&settings.index_album_art_pattern)
+}
+
+// Mount points without their own album art pattern use the global one
+fn get_mount_album_art_patterns(db: &DB) -> Result<HashMap<String, Vec<Regex>>, IndexError> {
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	mount_points::table
+		.select((mount_points::name, mount_points::album_art_pattern))
+		.load::<(String, Option<String>)>(&connection)?
+		.into_iter()
+		.filter_map(|(name, pattern)| pattern.map(|p| (name, p)))
+		.map(|(name, pattern)| Ok((name, parse_album_art_patterns(&pattern)?)))
+		.collect()
+}
+
+fn get_disc_folder_pattern(settings: &MiscSettings) -> Result<Option<Regex>, IndexError> {
+	let pattern = settings.index_disc_folder_pattern.trim();
+	if pattern.is_empty() {
+		return Ok(None);
+	}
+	Regex::new(pattern)
+		.map(Some)
+		.map_err(IndexError::DiscFolderPattern)
+}
+
+fn get_sort_articles(settings: &MiscSettings) -> Vec<String> {
+	settings
+		.index_sort_articles
+		.split(',')
+		.map(|a| a.trim())
+		.filter(|a| !a.is_empty())
+		.map(|a| a.to_owned())
+		.collect()
+}
+
+// Only files with one of these extensions are read for tags. An empty list lets every file through,
+// which custom metadata readers may rely on.
+fn get_extensions(settings: &MiscSettings) -> Vec<String> {
+	settings
+		.index_extensions
+		.split(',')
+		.map(|e| e.trim().trim_start_matches('.').to_lowercase())
+		.filter(|e| !e.is_empty())
+		.collect()
+}
+
+// Directories deeper than this below their mount point are not indexed, which keeps a mount point
+// covering the entire file system from being traversed. There is no limit unless it is set.
+fn get_max_depth(settings: &MiscSettings) -> Option<usize> {
+	if settings.index_max_depth <= 0 {
+		None
+	} else {
+		Some(settings.index_max_depth as usize)
+	}
+}
+
+// Mount points are at depth 0
+fn get_depth(vfs: &VFS, real_path: &Path) -> Option<usize> {
+	vfs.real_to_virtual(real_path)
+		.ok()
+		.map(|p| p.components().count().saturating_sub(1))
+}
+
+fn is_too_deep(vfs: &VFS, max_depth: Option<usize>, real_path: &Path) -> bool {
+	match (max_depth, get_depth(vfs, real_path)) {
+		(Some(max_depth), Some(depth)) => depth > max_depth,
+		_ => false,
+	}
+}
+
+// Number of files in a directory along with the sum of their sizes and modification times
+fn get_directory_fingerprint(path: &Path) -> Result<String> {
+	let mut file_count: u64 = 0;
+	let mut total_size: u64 = 0;
+	let mut total_mtime: u64 = 0;
+	for entry in fs::read_dir(utils::fs_path(path))? {
+		let metadata = fs::metadata(entry?.path())?;
+		if metadata.is_dir() {
+			continue;
+		}
+		let mtime = metadata.modified()?.duration_since(time::UNIX_EPOCH)?;
+		file_count += 1;
+		total_size = total_size.wrapping_add(metadata.len());
+		total_mtime = total_mtime.wrapping_add(mtime.as_nanos() as u64);
+	}
+	Ok(format!("{}-{}-{}", file_count, total_size, total_mtime))
+}
+
+// Mount points within another mount point are traversed on their own, so the enclosing mount
+// point skips over them
+fn get_nested_mount_points(vfs: &VFS) -> HashSet<PathBuf> {
+	let targets = vfs
+		.get_mount_points()
+		.values()
+		.map(|t| utils::normalize_path(t))
+		.collect::<Vec<_>>();
+	targets
+		.iter()
+		.filter(|t| targets.iter().any(|o| o != *t && t.starts_with(o)))
+		.cloned()
+		.collect()
+}
+
+fn get_file_filters(
+	settings: &MiscSettings,
+	vfs: &VFS,
+	include_patterns: HashMap<String, Vec<IncludePattern>>,
+) -> Result<FileFilters, IndexError> {
+	Ok(FileFilters {
+		ignore_patterns: get_ignore_patterns(settings)?,
+		include_patterns,
+		extensions: get_extensions(settings),
+		min_file_size: settings.index_min_file_size.max(0) as u64,
+		nested_mount_points: get_nested_mount_points(vfs),
+		follow_symlinks: settings.index_follow_symlinks != 0,
+		index_archives: settings.index_archives != 0,
+	})
+}
+
+// Mount names are not matched, only the files and directories within a mount point
+fn is_ignored_virtual_path(ignore_patterns: &[Pattern], virtual_path: &Path) -> bool {
+	virtual_path
+		.iter()
+		.skip(1)
+		.any(|name| is_ignored(ignore_patterns, name))
+}
+
+pub fn update(db: &DB, options: &UpdateOptions) -> Result<UpdateSummary, IndexError> {
 	info!("Beginning library index update");
-	clean(db)?;
-	populate(db)?;
-	info!(
-		"Library index update took {} seconds",
-		start.elapsed().as_millis() as f32 / 1000.0
-	);
+	let mut indexer = Indexer::new(db, options);
+	while indexer.step()?.phase != IndexPhase::Done {}
+	let summary = indexer.into_summary();
+	info!("Library index update complete: {}", summary.stats);
 	#[cfg(feature = "profile-index")]
 	flame::dump_html(&mut fs::File::create("index-flame-graph.html").unwrap()).unwrap();
-	Ok(())
+	Ok(summary)
 }
 
-#[derive(Debug, Insertable)]
-#[table_name = "songs"]
-struct NewSong {
-	path: String,
-	parent: String,
-	track_number: Option<i32>,
-	disc_number: Option<i32>,
-	title: Option<String>,
-	artist: Option<String>,
-	album_artist: Option<String>,
-	year: Option<i32>,
-	album: Option<String>,
-	artwork: Option<String>,
-	duration: Option<i32>,
+// Runs an update a bounded amount of work at a time: each step checks a chunk of entries to clean,
+// visits a single directory or writes a single batch. Nothing runs between steps, so callers can
+// report progress or stop the update there. Tags may still be read in parallel within a step.
+pub struct Indexer<'a> {
+	db: &'a DB,
+	options: &'a UpdateOptions,
+	start: time::Instant,
+	merge: bool,
+	stage: IndexerStage<'a>,
+	removed: UpdateSummary,
+	added: UpdateSummary,
 }
 
-#[derive(Debug, Insertable)]
-#[table_name = "directories"]
-struct NewDirectory {
-	path: String,
-	parent: Option<String>,
-	artist: Option<String>,
-	year: Option<i32>,
-	album: Option<String>,
-	artwork: Option<String>,
-	date_added: i32,
+// Merge updates traverse first and remove what they did not see afterwards, while other updates
+// clean first
+enum IndexerStage<'a> {
+	Start,
+	Cleaning(Box<Cleaner<'a>>),
+	Populating(Box<Population>),
+	RemovingUnseen(Box<UnseenRemoval>, Box<PopulationEnd>),
+	Completing(Box<PopulationEnd>),
+	Done,
+}
+
+impl<'a> Indexer<'a> {
+	pub fn new(db: &'a DB, options: &'a UpdateOptions) -> Indexer<'a> {
+		Indexer {
+			db,
+			options,
+			start: time::Instant::now(),
+			merge: false,
+			stage: IndexerStage::Start,
+			removed: UpdateSummary::default(),
+			added: UpdateSummary::default(),
+		}
+	}
+
+	// Steps after an error do nothing
+	pub fn step(&mut self) -> Result<IndexerState, IndexError> {
+		self.stage = match std::mem::replace(&mut self.stage, IndexerStage::Done) {
+			IndexerStage::Start => {
+				let settings: MiscSettings = {
+					let connection = self.db.connect().map_err(IndexError::DatabaseConnection)?;
+					misc_settings::table.get_result(&connection)?
+				};
+				self.merge = settings.index_merge_updates != 0;
+				if self.merge {
+					self.populate()?
+				} else {
+					IndexerStage::Cleaning(Box::new(Cleaner::new(self.db, None, self.options)?))
+				}
+			}
+			IndexerStage::Cleaning(mut cleaner) => {
+				if cleaner.step()? {
+					IndexerStage::Cleaning(cleaner)
+				} else {
+					self.removed = cleaner.finish()?;
+					self.populate()?
+				}
+			}
+			IndexerStage::Populating(mut population) => match population.phase() {
+				IndexPhase::Scanning => {
+					population.scan_directory();
+					IndexerStage::Populating(population)
+				}
+				_ if !population.is_written() => {
+					population.write_batch();
+					IndexerStage::Populating(population)
+				}
+				_ => {
+					let (added, end) = population.finish(self.options)?;
+					let mut end = Box::new(end);
+					self.added = added;
+					match end.take_unseen_removal(self.db, self.options)? {
+						Some(removal) => IndexerStage::RemovingUnseen(Box::new(removal), end),
+						None => IndexerStage::Completing(end),
+					}
+				}
+			},
+			IndexerStage::RemovingUnseen(mut removal, end) => {
+				if removal.step(self.db, &end.settings, &mut self.removed, self.options)? {
+					IndexerStage::RemovingUnseen(removal, end)
+				} else {
+					IndexerStage::Completing(end)
+				}
+			}
+			IndexerStage::Completing(end) => {
+				end.complete(self.db, &mut self.added, self.options)?;
+				clear_checkpoint(self.db, self.options)?;
+				self.verify_and_purge()?;
+				IndexerStage::Done
+			}
+			IndexerStage::Done => IndexerStage::Done,
+		};
+		Ok(self.state())
+	}
+
+	fn populate(&self) -> Result<IndexerStage<'a>, IndexError> {
+		let (targets, indexed_directories) = get_mount_targets(self.db, false, self.options)?;
+		let population = Population::new(
+			self.db,
+			targets,
+			indexed_directories,
+			self.merge,
+			true,
+			false,
+			self.options,
+		)?;
+		Ok(IndexerStage::Populating(Box::new(population)))
+	}
+
+	fn verify_and_purge(&mut self) -> Result<(), IndexError> {
+		let settings: MiscSettings = {
+			let connection = self.db.connect().map_err(IndexError::DatabaseConnection)?;
+			misc_settings::table.get_result(&connection)?
+		};
+		if settings.index_verify_after_index != 0 && !self.options.dry_run {
+			self.added.integrity_violations = check_integrity(self.db)?;
+		}
+		if settings.index_soft_delete_retention_days > 0 && !self.options.dry_run {
+			let retention = time::Duration::from_secs(
+				settings.index_soft_delete_retention_days as u64 * 24 * 60 * 60,
+			);
+			let purged = purge(self.db, retention)?;
+			if purged > 0 {
+				info!("Purged {} songs past their soft delete retention", purged);
+			}
+		}
+		Ok(())
+	}
+
+	pub fn state(&self) -> IndexerState {
+		let phase = match &self.stage {
+			IndexerStage::Start | IndexerStage::Cleaning(_) | IndexerStage::RemovingUnseen(..) => {
+				IndexPhase::Cleaning
+			}
+			IndexerStage::Populating(population) => population.phase(),
+			IndexerStage::Completing(_) => IndexPhase::Writing,
+			IndexerStage::Done => IndexPhase::Done,
+		};
+		let (songs_removed, directories_removed) = match &self.stage {
+			IndexerStage::Cleaning(cleaner) => {
+				(cleaner.songs_removed(), cleaner.directories_removed())
+			}
+			_ => (
+				self.removed.songs_removed.count,
+				self.removed.directories_removed.count,
+			),
+		};
+		let (songs_added, directories_added) = match &self.stage {
+			IndexerStage::Populating(population) => {
+				(population.songs_added(), population.directories_added())
+			}
+			_ => (
+				self.added.songs_added.count,
+				self.added.directories_added.count,
+			),
+		};
+		IndexerState {
+			phase,
+			songs_added,
+			songs_removed,
+			directories_added,
+			directories_removed,
+		}
+	}
+
+	pub fn into_summary(self) -> UpdateSummary {
+		let stats = IndexStats {
+			songs_inserted: self.added.stats.songs_inserted,
+			songs_deleted: self.removed.stats.songs_deleted,
+			directories_inserted: self.added.stats.directories_inserted,
+			directories_deleted: self.removed.stats.directories_deleted,
+			files_skipped_errors: self.added.stats.files_skipped_errors,
+			elapsed: self.start.elapsed(),
+		};
+		UpdateSummary {
+			songs_added: self.added.songs_added,
+			directories_added: self.added.directories_added,
+			songs_removed: self.removed.songs_removed,
+			directories_removed: self.removed.directories_removed,
+			warnings: self.added.warnings,
+			metadata_conflicts: self.added.metadata_conflicts,
+			slow_directories: self.added.slow_directories,
+			integrity_violations: self.added.integrity_violations,
+			stats,
+		}
+	}
+}
+
+// Directories holding songs of a single album are albums, directories holding nothing but albums
+// are artists and everything else is a generic folder
+const DIRECTORY_KIND_ALBUM: &str = "album";
+const DIRECTORY_KIND_ARTIST: &str = "artist";
+const DIRECTORY_KIND_OTHER: &str = "other";
+
+// Paths found during a merge update, which removes the entries that were not found
+enum SeenPath {
+	Song(String),
+	ReadSong(String), // Read again during the update, so it may take over a removed song
+	Directory(String),
+}
+
+struct UpdateSenders {
+	directories: Sender<DirectoryInsertion>,
+	songs: Sender<SongInsertion>,
+	seen: Option<Sender<SeenPath>>,
+}
+
+type TagReadJob = (PathBuf, Sender<Result<Option<SongTags>>>, Arc<AtomicBool>);
+
+// Reads tags on a fixed set of threads, so that reads which take too long can be abandoned. Tag
+// readers cannot be interrupted, so an abandoned read keeps its thread until it completes. Once
+// too many reads are stuck this way, files are skipped without being read.
+struct TimedTagReader {
+	jobs: Sender<TagReadJob>,
+	timeout: time::Duration,
+	stuck_reads: Arc<AtomicUsize>,
+	max_stuck_reads: usize,
+}
+
+impl TimedTagReader {
+	// Threads beyond the concurrent reads are spare ones, taking over from stuck threads
+	fn new(
+		metadata_reader: Arc<dyn MetadataReader>,
+		timeout: time::Duration,
+		concurrent_reads: usize,
+	) -> Result<TimedTagReader> {
+		let (jobs, receiver) = crossbeam_channel::unbounded::<TagReadJob>();
+		let stuck_reads = Arc::new(AtomicUsize::new(0));
+		let concurrent_reads = concurrent_reads.max(1);
+		let max_stuck_reads = concurrent_reads;
+		for _ in 0..(concurrent_reads + max_stuck_reads) {
+			let receiver = receiver.clone();
+			let metadata_reader = metadata_reader.clone();
+			let stuck_reads = stuck_reads.clone();
+			std::thread::Builder::new()
+				.name("Tag reader".to_owned())
+				.spawn(move || {
+					for (path, sender, claimed) in receiver.iter() {
+						let _ = sender.send(metadata_reader.read(&path));
+						// The read was abandoned if it was claimed first
+						if claimed.swap(true, Ordering::SeqCst) {
+							stuck_reads.fetch_sub(1, Ordering::SeqCst);
+						}
+					}
+				})?;
+		}
+		Ok(TimedTagReader {
+			jobs,
+			timeout,
+			stuck_reads,
+			max_stuck_reads,
+		})
+	}
+
+	fn read(&self, path: &Path) -> Result<Option<SongTags>> {
+		if self.stuck_reads.load(Ordering::SeqCst) >= self.max_stuck_reads {
+			bail!(
+				"Tag read was skipped while {} previous reads are still stuck",
+				self.max_stuck_reads
+			);
+		}
+		let (sender, receiver) = crossbeam_channel::bounded(1);
+		let claimed = Arc::new(AtomicBool::new(false));
+		self.jobs
+			.send((path.to_owned(), sender, claimed.clone()))
+			.map_err(Error::new)?;
+		match receiver.recv_timeout(self.timeout) {
+			Ok(result) => result,
+			Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+				self.stuck_reads.fetch_add(1, Ordering::SeqCst);
+				if !claimed.swap(true, Ordering::SeqCst) {
+					bail!(
+						"Tag read timed out after {} seconds",
+						self.timeout.as_secs()
+					)
+				}
+				// The read completed in the meantime
+				self.stuck_reads.fetch_sub(1, Ordering::SeqCst);
+				receiver.recv().map_err(Error::new)?
+			}
+			Err(e) => Err(Error::new(e)),
+		}
+	}
 }
 
 struct IndexUpdater {
-	directory_sender: Sender<NewDirectory>,
-	song_sender: Sender<NewSong>,
-	album_art_pattern: Regex,
+	senders: UpdateSenders,
+	vfs: VFS,
+	album_art_patterns: Vec<Regex>,
+	artwork_cache: Option<ArtworkCache>,
+	conventions: TagConventions,
+	file_filters: FileFilters,
+	ignore_marker: Option<String>,
+	max_depth: Option<usize>,
+	resolve_file_symlinks: bool,
+	inherit_artwork_levels: usize, // Zero unless `index_inherit_parent_artwork` is set
+	normalize_search_text: bool,
+	tag_charset_detection: bool,
+	compute_audio_hash: bool,
+	tag_read_pool: rayon::ThreadPool,
+	timed_tag_reader: Option<TimedTagReader>,
+	metadata_reader: Arc<dyn MetadataReader>,
+	directory_filter: Arc<dyn DirectoryFilter>,
+	tag_transform: Option<TagTransform>,
+	low_memory: bool,
+	visited_directories: Mutex<HashMap<PathBuf, PathBuf>>,
+	warnings: Mutex<Vec<IndexWarning>>,
+	metadata_conflicts: Mutex<Vec<MetadataConflict>>,
+	slow_directory_threshold: Option<time::Duration>,
+	read_dir_retries: u32, // Additional attempts at listing directories, for flaky network shares
+	read_dir_retry_delay: time::Duration,
+	slow_directories: Mutex<Vec<SlowDirectory>>,
+	indexed_directories: HashMap<String, IndexedDirectory>,
+	use_directory_fingerprint: bool,
+	index_start: i32,
+	progress: Option<Arc<ProgressReporter>>,
+	options: UpdateOptions,
+}
+
+impl IndexUpdater {
+	#[cfg_attr(feature = "profile-index", flame)]
+	fn new(
+		settings: &MiscSettings,
+		vfs: VFS,
+		include_patterns: HashMap<String, Vec<IncludePattern>>,
+		indexed_directories: HashMap<String, IndexedDirectory>,
+		senders: UpdateSenders,
+		progress: Option<Arc<ProgressReporter>>,
+		options: &UpdateOptions,
+	) -> Result<IndexUpdater, IndexError> {
+		let index_start = time::SystemTime::now()
+			.duration_since(time::UNIX_EPOCH)
+			.map_err(Error::new)?
+			.as_secs() as i32;
+		let metadata_reader = options
+			.metadata_reader
+			.clone()
+			.unwrap_or_else(|| Arc::new(DefaultMetadataReader));
+		let tag_read_pool = rayon::ThreadPoolBuilder::new()
+			.num_threads(settings.index_tag_read_threads.max(0) as usize)
+			.build()
+			.map_err(Error::new)?;
+		let timed_tag_reader = match settings.index_tag_read_timeout_seconds {
+			t if t > 0 => Some(TimedTagReader::new(
+				metadata_reader.clone(),
+				time::Duration::from_secs(t as u64),
+				tag_read_pool.current_num_threads(),
+			)?),
+			_ => None,
+		};
+		Ok(IndexUpdater {
+			senders,
+			file_filters: get_file_filters(settings, &vfs, include_patterns)?,
+			vfs,
+			album_art_patterns: get_album_art_patterns(settings)?,
+			artwork_cache: ArtworkCache::new(settings, options)?,
+			conventions: TagConventions {
+				artist_separator: settings.index_artist_separator.clone(),
+				various_artists: settings.index_various_artists.clone(),
+				sort_articles: get_sort_articles(settings),
+			},
+			ignore_marker: get_ignore_marker(settings),
+			max_depth: get_max_depth(settings),
+			resolve_file_symlinks: settings.index_resolve_file_symlinks != 0,
+			inherit_artwork_levels: match settings.index_inherit_parent_artwork {
+				0 => 0,
+				_ => settings.index_inherit_artwork_levels.max(0) as usize,
+			},
+			normalize_search_text: settings.index_normalize_search_text != 0,
+			tag_charset_detection: settings.index_tag_charset_detection != 0,
+			compute_audio_hash: settings.index_compute_audio_hash != 0,
+			tag_read_pool,
+			timed_tag_reader,
+			metadata_reader,
+			directory_filter: options
+				.directory_filter
+				.clone()
+				.unwrap_or_else(|| Arc::new(AllowAllDirectories)),
+			tag_transform: options.tag_transform.clone(),
+			low_memory: settings.index_low_memory != 0,
+			visited_directories: Mutex::new(HashMap::new()),
+			warnings: Mutex::new(Vec::new()),
+			metadata_conflicts: Mutex::new(Vec::new()),
+			slow_directory_threshold: Some(settings.index_slow_directory_threshold_ms)
+				.filter(|t| *t > 0)
+				.map(|t| time::Duration::from_millis(t as u64)),
+			slow_directories: Mutex::new(Vec::new()),
+			read_dir_retries: settings.index_read_dir_retries.max(0) as u32,
+			read_dir_retry_delay: time::Duration::from_millis(
+				settings.index_read_dir_retry_delay_ms.max(0) as u64,
+			),
+			indexed_directories,
+			use_directory_fingerprint: settings.index_use_directory_fingerprint != 0,
+			index_start,
+			progress,
+			options: options.clone(),
+		})
+	}
+
+	#[cfg_attr(feature = "profile-index", flame)]
+	fn push_song(
+		&self,
+		mut song: NewSong,
+		mut artists: Vec<NewSongArtist>,
+		mut lyrics: Option<NewSongLyrics>,
+	) -> Result<()> {
+		song.path = self.stored_path(&song.path);
+		song.parent = self.stored_path(&song.parent);
+		song.artwork = song.artwork.map(|a| self.stored_path(&a));
+		for artist in &mut artists {
+			artist.song_path = song.path.clone();
+		}
+		if let Some(lyrics) = &mut lyrics {
+			lyrics.song_path = song.path.clone();
+		}
+		self.mark_seen(SeenPath::ReadSong(song.path.clone()))?;
+		self.senders
+			.songs
+			.send(SongInsertion::Song(song, artists, lyrics))
+			.map_err(Error::new)
+	}
+
+	fn push_checkpoint(&self, directory_path: &str) -> Result<()> {
+		self.senders
+			.songs
+			.send(SongInsertion::Checkpoint(self.stored_path(directory_path)))
+			.map_err(Error::new)
+	}
+
+	#[cfg_attr(feature = "profile-index", flame)]
+	fn push_directory(
+		&self,
+		mut directory: NewDirectory,
+		mut playlists: Vec<NewIndexedPlaylist>,
+		mut playlist_songs: Vec<NewIndexedPlaylistSong>,
+	) -> Result<()> {
+		directory.path = self.stored_path(&directory.path);
+		directory.parent = directory.parent.map(|p| self.stored_path(&p));
+		directory.artwork = directory.artwork.map(|a| self.stored_path(&a));
+		for playlist in &mut playlists {
+			playlist.path = self.stored_path(&playlist.path);
+			playlist.parent = directory.path.clone();
+		}
+		for playlist_song in &mut playlist_songs {
+			playlist_song.playlist_path = self.stored_path(&playlist_song.playlist_path);
+			playlist_song.path = self.stored_path(&playlist_song.path);
+		}
+		self.mark_seen(SeenPath::Directory(directory.path.clone()))?;
+		self.senders
+			.directories
+			.send((directory, playlists, playlist_songs))
+			.map_err(Error::new)
+	}
+
+	// Symlinked files can be tracked by their target, which may lie outside of any mount point and
+	// is therefore kept as a real path
+	fn get_link_target(&self, path: &Path) -> Option<String> {
+		if !self.resolve_file_symlinks || !is_symlink(path) {
+			return None;
+		}
+		fs::canonicalize(utils::fs_path(path))
+			.ok()
+			.map(|p| utils::strip_fs_prefix(p).to_string_lossy().into_owned())
+	}
+
+	// Paths are gathered from the file system and converted to their stored form when sent
+	fn stored_path(&self, real_path: &str) -> String {
+		self.vfs
+			.real_to_stored(real_path)
+			.to_string_lossy()
+			.into_owned()
+	}
+
+	fn mark_seen(&self, path: SeenPath) -> Result<()> {
+		match &self.senders.seen {
+			Some(sender) => sender.send(path).map_err(Error::new),
+			None => Ok(()),
+		}
+	}
+
+	// Files whose read times out are skipped and indexing carries on
+	fn read_tags(&self, path: &Path) -> Result<Option<SongTags>> {
+		match &self.timed_tag_reader {
+			Some(timed_tag_reader) => timed_tag_reader.read(path),
+			None => self.metadata_reader.read(path),
+		}
+	}
+
+	fn add_warning(&self, path: &Path, message: String) {
+		warn!("{} (`{}`)", message, path.display());
+		let warning = IndexWarning {
+			path: path.to_owned(),
+			message,
+		};
+		if let Some(sender) = &self.options.warnings {
+			sender.send(warning.clone()).ok();
+		}
+		self.warnings.lock().unwrap().push(warning);
+	}
+
+	// Returns the artwork of a directory along with the embedded artwork extracted from its songs,
+	// by song path
+	fn get_directory_artwork(
+		&self,
+		path: &Path,
+		indexed_directory: Option<&IndexedDirectory>,
+		song_files: &[SongFile],
+		album_art_patterns: &[Regex],
+		mount_name: &str,
+	) -> (Option<String>, HashMap<String, String>) {
+		#[cfg(feature = "profile-index")]
+		let _guard = flame::start_guard("artwork");
+		let indexed_artwork = indexed_directory
+			.and_then(|d| d.artwork.as_ref())
+			.and_then(|a| artwork::find_indexed(&self.vfs, path, a, album_art_patterns));
+		let mut directory_artwork = match indexed_artwork {
+			Some(artwork) => Some(artwork),
+			None => match artwork::find(path, album_art_patterns) {
+				Ok(artwork) => artwork,
+				Err(e) => {
+					self.add_warning(path, format!("Artwork read error: {}", e));
+					None
+				}
+			},
+		};
+
+		// Without a sidecar image, embedded artwork is extracted to the cache (when there is one)
+		let mut cached_artwork = HashMap::new();
+		let artwork_cache = self
+			.artwork_cache
+			.as_ref()
+			.filter(|_| !self.options.dry_run);
+		if let (None, Some(cache)) = (&directory_artwork, artwork_cache) {
+			for song_file in song_files.iter().filter(|s| s.tags.has_artwork) {
+				let file_path_string = &song_file.path;
+				match cache.cache_embedded_artwork(file_path_string) {
+					Ok(Some(p)) => {
+						cached_artwork.insert(file_path_string.to_owned(), p);
+					}
+					Ok(None) => (),
+					Err(e) => error!(
+						"Could not cache embedded artwork from `{}`: {}",
+						file_path_string, e
+					),
+				}
+			}
+			directory_artwork = song_files
+				.iter()
+				.find_map(|s| cached_artwork.get(&s.path).cloned());
+		}
+
+		// Otherwise the directory uses the first embedded cover which can actually be read
+		if directory_artwork.is_none() && artwork_cache.is_none() {
+			directory_artwork = song_files
+				.iter()
+				.filter(|s| s.tags.has_artwork)
+				.find(|s| metadata::read_artwork(Path::new(&s.path)).is_some())
+				.map(|s| s.path.to_owned());
+		}
+
+		// Failing that, a cover shared by the directories above
+		if directory_artwork.is_none() {
+			directory_artwork = self.get_inherited_artwork(path, album_art_patterns, mount_name);
+		}
+		(directory_artwork, cached_artwork)
+	}
+
+	// Libraries may keep a single cover in an artist folder for all of its albums. Directories are
+	// only looked up within the same mount point.
+	fn get_inherited_artwork(
+		&self,
+		dir: &Path,
+		album_art_patterns: &[Regex],
+		mount_name: &str,
+	) -> Option<String> {
+		dir.ancestors()
+			.skip(1)
+			.take(self.inherit_artwork_levels)
+			.take_while(|ancestor| {
+				self.vfs
+					.real_to_virtual(ancestor)
+					.ok()
+					.and_then(|v| get_mount_name(&v))
+					.map_or(false, |name| name == mount_name)
+			})
+			.find_map(
+				|ancestor| match artwork::find(ancestor, album_art_patterns) {
+					Ok(artwork) => artwork,
+					Err(e) => {
+						self.add_warning(ancestor, format!("Artwork read error: {}", e));
+						None
+					}
+				},
+			)
+	}
+
+	fn populate_directory(
+		&self,
+		parent: Option<&Path>,
+		path: &Path,
+		album_art_patterns: &[Regex],
+		mount_name: &str,
+	) -> Result<()> {
+		let sub_directories = self.index_directory(parent, path, album_art_patterns, mount_name)?;
+		self.populate_sub_directories(path, sub_directories, album_art_patterns, mount_name)
+	}
+
+	// Indexes the files of a single directory and returns the sub-directories to visit next
+	fn index_directory(
+		&self,
+		parent: Option<&Path>,
+		path: &Path,
+		album_art_patterns: &[Regex],
+		mount_name: &str,
+	) -> Result<Vec<PathBuf>> {
+		#[cfg(feature = "profile-index")]
+		let _guard = flame::start_guard(format!(
+			"dir: {}",
+			path.file_name()
+				.map(|s| { s.to_string_lossy().into_owned() })
+				.unwrap_or("Unknown".to_owned())
+		));
+		let start = time::Instant::now();
+
+		// Extract path and parent path
+		let parent_string = parent.and_then(|p| p.to_str()).map(|s| s.to_owned());
+		self.options.check_cancellation()?;
+
+		if !self.directory_filter.should_index(path, parent) {
+			return Ok(Vec::new());
+		}
+
+		let path_string = path.to_str().ok_or(anyhow!("Invalid directory path"))?;
+
+		if let Some(marker) = &self.ignore_marker {
+			if has_ignore_marker(path, marker) {
+				return Ok(Vec::new());
+			}
+		}
+
+		// Directories reachable through several paths (via symlinks) are only indexed once
+		let canonical_path = fs::canonicalize(utils::fs_path(path))?;
+		{
+			let mut visited_directories = self.visited_directories.lock().unwrap();
+			if let Some(visited_path) = visited_directories.get(&canonical_path) {
+				warn!(
+					"Skipping `{}` which resolves to already visited directory `{}`",
+					path.display(),
+					visited_path.display()
+				);
+				return Ok(Vec::new());
+			}
+			visited_directories.insert(canonical_path, path.to_owned());
+		}
+
+		if let Some(progress) = &self.progress {
+			progress.visit_directory(path_string);
+		}
+
+		// Find date added
+		let metadata = {
+			#[cfg(feature = "profile-index")]
+			let _guard = flame::start_guard("metadata");
+			fs::metadata(utils::fs_path(path))?
+		};
+		let created = {
+			#[cfg(feature = "profile-index")]
+			let _guard = flame::start_guard("created_date");
+			metadata
+				.created()
+				.or_else(|_| metadata.modified())?
+				.duration_since(time::UNIX_EPOCH)?
+				.as_secs() as i32
+		};
+		let modified = metadata
+			.modified()?
+			.duration_since(time::UNIX_EPOCH)?
+			.as_secs() as i32;
+		let creation_time = get_creation_time(&metadata).map(|t| t as i32);
+
+		// Directories which have not changed since the last index only need their sub-directories
+		// visited. Fingerprints also catch changes which do not affect the directory mtime.
+		let fingerprint = if self.use_directory_fingerprint {
+			Some(get_directory_fingerprint(path)?)
+		} else {
+			None
+		};
+		let indexed_directory = self.indexed_directories.get(&self.stored_path(path_string));
+		let up_to_date = match (indexed_directory, &fingerprint) {
+			(Some(indexed), _) if indexed.checkpointed => true,
+			(Some(indexed), Some(fingerprint)) => indexed.fingerprint.as_ref() == Some(fingerprint),
+			(Some(indexed), None) => indexed
+				.last_indexed
+				.map_or(false, |last_indexed| modified < last_indexed),
+			(None, _) => false,
+		};
+
+		let file_names = match self.list_directory(path) {
+			Some(file_names) => file_names,
+			None => return Ok(Vec::new()),
+		};
+		let file_count = file_names.len();
+
+		let add_warning = |path: &Path, message| self.add_warning(path, message);
+		let DirectoryFiles {
+			sub_directories,
+			songs,
+			unchanged_songs,
+			cue_sheets,
+			archives,
+			lyrics,
+			playlists,
+		} = self
+			.file_filters
+			.classify(&self.vfs, path, file_names, up_to_date, &add_warning);
+
+		if up_to_date {
+			// Songs of unchanged directories are not read again, but still exist
+			if let Some(progress) = &self.progress {
+				progress.skip_songs(unchanged_songs.len());
+			}
+			for song_path in unchanged_songs {
+				self.mark_seen(SeenPath::Song(self.stored_path(&song_path)))?;
+			}
+			self.mark_seen(SeenPath::Directory(self.stored_path(path_string)))?;
+			self.record_directory_time(path, start.elapsed(), file_count);
+			return Ok(self.sub_directories_to_visit(path, sub_directories));
+		}
+
+		let song_files = self.read_song_files(songs, &cue_sheets, &archives);
+		let (directory_artwork, cached_artwork) = self.get_directory_artwork(
+			path,
+			indexed_directory,
+			&song_files,
+			album_art_patterns,
+			mount_name,
+		);
+
+		let mut directory_tags = DirectoryTags::new(self.options.report_metadata_conflicts);
+		for song_file in song_files {
+			directory_tags.add_song(&song_file.tags, &self.conventions);
+
+			let artwork_path = if let Some(cached) = cached_artwork.get(&song_file.path).cloned() {
+				Some(cached)
+			} else if song_file.tags.has_artwork {
+				Some(song_file.path.to_owned())
+			} else {
+				directory_artwork.as_ref().cloned()
+			};
+			let sidecar_lyrics = lyrics.get(&Path::new(&song_file.path).with_extension(""));
+
+			let (song, song_artists, song_lyrics) = self.get_song_rows(
+				song_file,
+				path_string,
+				mount_name,
+				artwork_path,
+				sidecar_lyrics,
+			);
+			self.push_song(song, song_artists, song_lyrics)?;
+		}
+
+		// Insert directory
+		let conflicts = directory_tags.take_conflicts(path);
+		if !conflicts.is_empty() {
+			self.metadata_conflicts.lock().unwrap().extend(conflicts);
+		}
+		let tags = directory_tags.consolidate(&self.conventions);
+		let (depth, ancestors) = match get_ancestry(&self.vfs, path) {
+			Some((depth, ancestors)) => (Some(depth), Some(ancestors)),
+			None => (None, None),
+		};
+
+		// Artist folders are only recognized once their subdirectories are written
+		let kind = if tags.song_count > 0 && tags.album.is_some() {
+			DIRECTORY_KIND_ALBUM
+		} else {
+			DIRECTORY_KIND_OTHER
+		};
+		let album_key = metadata::album_key(tags.artist.as_deref(), tags.album.as_deref());
+
+		let directory = NewDirectory {
+			path: path_string.to_owned(),
+			parent: parent_string,
+			artwork: directory_artwork,
+			album: tags.album,
+			artist: tags.artist,
+			year: tags.year,
+			date_added: created,
+			last_indexed: Some(self.index_start),
+			genre: tags.genre,
+			song_count: tags.song_count,
+			total_duration: tags.total_duration,
+			composer: tags.composer,
+			sort_artist: tags.sort_artist,
+			sort_album: tags.sort_album,
+			dir_fingerprint: fingerprint,
+			depth,
+			ancestors,
+			mount_name: Some(mount_name.to_owned()),
+			created: creation_time,
+			modified: Some(modified),
+			musicbrainz_album_id: tags.musicbrainz_album_id,
+			musicbrainz_artist_id: tags.musicbrainz_artist_id,
+			kind: Some(kind.to_owned()),
+			original_date: tags.original_date,
+			album_key,
+		};
+
+		let (playlists, playlist_songs) =
+			files::read_playlists(&self.vfs, path, &playlists, &add_warning);
+		self.push_directory(directory, playlists, playlist_songs)?;
+		self.push_checkpoint(path_string)?;
+		self.record_directory_time(path, start.elapsed(), file_count);
+
+		Ok(self.sub_directories_to_visit(path, sub_directories))
+	}
+
+	// Entries are visited in a stable order, so that the traversal does not depend on the file
+	// system
+	fn list_directory(&self, path: &Path) -> Option<Vec<OsString>> {
+		let read_dir = || fs::read_dir(utils::fs_path(path));
+		let files = match utils::retry_io(
+			path,
+			self.read_dir_retries,
+			self.read_dir_retry_delay,
+			read_dir,
+		) {
+			Ok(files) => files,
+			Err(e) => {
+				self.add_warning(path, format!("Directory read error: {}", e));
+				return None;
+			}
+		};
+		let mut file_names = Vec::new();
+		for file in files {
+			match file {
+				Ok(f) => file_names.push(f.file_name()),
+				Err(e) => {
+					self.add_warning(path, format!("File read error: {}", e));
+					break;
+				}
+			}
+		}
+		file_names.sort();
+		Some(file_names)
+	}
+
+	// Tags are read on a dedicated pool which caps the number of files open at once. Results are
+	// collected in directory order, which keeps the index deterministic.
+	fn read_song_files(
+		&self,
+		song_paths: Vec<SongPath>,
+		cue_paths: &[PathBuf],
+		archive_paths: &[PathBuf],
+	) -> Vec<SongFile> {
+		let song_metadata = |song_path: SongPath| -> Option<SongFile> {
+			#[cfg(feature = "profile-index")]
+			let _guard = flame::start_guard("song_metadata");
+
+			let SongPath {
+				path,
+				file_size,
+				mtime,
+				created,
+			} = song_path;
+			let tags = match self.read_tags(&path) {
+				Ok(tags) => tags,
+				Err(e) => {
+					self.add_warning(&path, format!("Metadata read error: {}", e));
+					None
+				}
+			};
+			path.to_str().and_then(|file_path_string| {
+				tags.map(|tags| SongFile {
+					path: file_path_string.to_owned(),
+					tags: self.process_tags(tags),
+					file_size,
+					mtime,
+					created,
+					start_time: None,
+					content_hash: self.hash_content(&path),
+				})
+			})
+		};
+		let song_files = self.tag_read_pool.install(|| {
+			song_paths
+				.into_par_iter()
+				.filter_map(song_metadata)
+				.collect::<Vec<_>>()
+		});
+		let add_warning = |path: &Path, message| self.add_warning(path, message);
+		let mut song_files = files::split_cue_tracks(
+			song_files,
+			cue_paths,
+			&self.conventions.artist_separator,
+			&add_warning,
+		);
+		song_files.extend(self.read_archives(archive_paths));
+		song_files
+	}
+
+	fn get_song_rows(
+		&self,
+		song_file: SongFile,
+		parent: &str,
+		mount_name: &str,
+		artwork: Option<String>,
+		sidecar_lyrics: Option<&PathBuf>,
+	) -> (NewSong, Vec<NewSongArtist>, Option<NewSongLyrics>) {
+		let SongFile {
+			path: file_path_string,
+			mut tags,
+			file_size,
+			mtime,
+			created: song_created,
+			start_time,
+			content_hash,
+		} = song_file;
+
+		let song_artists = tags
+			.artists
+			.iter()
+			.map(|artist| NewSongArtist {
+				song_path: file_path_string.to_owned(),
+				artist: artist.to_owned(),
+			})
+			.collect();
+
+		let (search_title, search_artist) = if self.normalize_search_text {
+			(
+				tags.title.as_deref().map(metadata::fold_search_text),
+				tags.artist.as_deref().map(metadata::fold_search_text),
+			)
+		} else {
+			(None, None)
+		};
+
+		// Lyrics describe a whole file, so songs split from a cue sheet do not get any
+		let add_warning = |path: &Path, message| self.add_warning(path, message);
+		let lyrics = match start_time {
+			Some(_) => None,
+			None => files::read_lyrics(
+				&file_path_string,
+				sidecar_lyrics,
+				tags.lyrics.take(),
+				&add_warning,
+			),
+		};
+
+		let sort_artist = self.conventions.sort_value(&tags.sort_artist, &tags.artist);
+		let sort_album = self.conventions.sort_value(&tags.sort_album, &tags.album);
+		let sort_title = self.conventions.sort_value(&tags.sort_title, &tags.title);
+		let album_key = metadata::album_key(
+			tags.album_artist
+				.as_deref()
+				.or_else(|| tags.artist.as_deref()),
+			tags.album.as_deref(),
+		);
+
+		let song = NewSong {
+			path: file_path_string.to_owned(),
+			parent: parent.to_owned(),
+			disc_number: tags.disc_number.map(|n| n as i32),
+			track_number: tags.track_number.map(|n| n as i32),
+			title: tags.title,
+			duration: tags.duration.map(|n| n as i32),
+			artist: tags.artist,
+			album_artist: tags.album_artist,
+			album: tags.album,
+			year: tags.year,
+			artwork,
+			genre: tags.genre,
+			file_size: Some(file_size),
+			mtime: Some(mtime),
+			replay_gain_track_gain: tags.replay_gain_track_gain,
+			replay_gain_track_peak: tags.replay_gain_track_peak,
+			replay_gain_album_gain: tags.replay_gain_album_gain,
+			replay_gain_album_peak: tags.replay_gain_album_peak,
+			track_total: tags.track_total.map(|n| n as i32),
+			disc_total: tags.disc_total.map(|n| n as i32),
+			search_title,
+			search_artist,
+			start_time,
+			composer: tags.composer,
+			conductor: tags.conductor,
+			content_hash,
+			sort_artist,
+			sort_album,
+			sort_title,
+			format: tags.audio.format,
+			bitrate: tags.audio.bitrate.map(|n| n as i32),
+			sample_rate: tags.audio.sample_rate.map(|n| n as i32),
+			bits_per_sample: tags.audio.bits_per_sample.map(|n| n as i32),
+			channels: tags.audio.channels.map(|n| n as i32),
+			bpm: tags.bpm,
+			musical_key: tags.musical_key,
+			mount_name: Some(mount_name.to_owned()),
+			rating: tags.rating,
+			tagged_play_count: tags.tagged_play_count,
+			disc_subtitle: tags.disc_subtitle,
+			created: song_created,
+			isrc: tags.isrc,
+			musicbrainz_track_id: tags.musicbrainz_track_id,
+			musicbrainz_album_id: tags.musicbrainz_album_id,
+			musicbrainz_artist_id: tags.musicbrainz_artist_id,
+			encoder_delay: tags.audio.encoder_delay.map(|n| n as i32),
+			encoder_padding: tags.audio.encoder_padding.map(|n| n as i32),
+			original_date: tags.original_date,
+			link_target: self.get_link_target(Path::new(&file_path_string)),
+			album_key,
+			lossless: tags.audio.lossless,
+			bitrate_mode: tags.audio.bitrate_mode,
+			work: tags.work,
+			movement_name: tags.movement_name,
+			movement_number: tags.movement_number.map(|n| n as i32),
+		};
+
+		(song, song_artists, lyrics)
+	}
+
+	fn record_directory_time(&self, path: &Path, elapsed: time::Duration, file_count: usize) {
+		match self.slow_directory_threshold {
+			Some(threshold) if elapsed >= threshold => (),
+			_ => return,
+		}
+		warn!(
+			"Indexing `{}` took {:.3} seconds for {} files",
+			path.display(),
+			elapsed.as_secs_f32(),
+			file_count
+		);
+		self.slow_directories.lock().unwrap().push(SlowDirectory {
+			path: path.to_owned(),
+			elapsed,
+			file_count,
+		});
+	}
+
+	// Copies of a song share the same hash, which lets clients group them
+	fn hash_content(&self, path: &Path) -> Option<String> {
+		if !self.compute_audio_hash {
+			return None;
+		}
+		let mut file = fs::File::open(utils::fs_path(path)).ok()?;
+		let mut hasher = Sha256::new();
+		std::io::copy(&mut file, &mut hasher).ok()?;
+		Some(format!("{:x}", hasher.finalize()))
+	}
+
+	fn process_tags(&self, mut tags: SongTags) -> SongTags {
+		tags.clean_text(self.tag_charset_detection);
+		if let Some(transform) = &self.tag_transform {
+			transform.apply(&mut tags);
+		}
+		if self.normalize_search_text {
+			tags.normalize();
+		}
+		tags.split_artists(&self.conventions.artist_separator);
+		tags
+	}
+
+	// Audio files within archives are listed alongside the other songs of the directory
+	fn read_archives(&self, archive_paths: &[PathBuf]) -> Vec<SongFile> {
+		let add_warning = |path: &Path, message| self.add_warning(path, message);
+		files::list_archive_songs(archive_paths, &add_warning)
+			.into_par_iter()
+			.filter_map(|(archive_path, mtime, entry)| {
+				self.read_archive_entry(archive_path, mtime, entry)
+			})
+			.collect()
+	}
+
+	// Tag readers work on files, so entries are extracted to a temporary location first
+	fn read_archive_entry(
+		&self,
+		archive_path: &Path,
+		mtime: i64,
+		entry: ArchiveEntry,
+	) -> Option<SongFile> {
+		let song_path = archive::entry_path(archive_path.to_str()?, &entry.name);
+		let extension = Path::new(&entry.name).extension()?.to_string_lossy();
+		let temp_path = std::env::temp_dir().join(format!(
+			"polaris-archive-{}.{}",
+			artwork::hash_bytes(&[song_path.as_bytes()]),
+			extension
+		));
+
+		let tags = archive::extract(archive_path, &entry, &temp_path)
+			.and_then(|_| self.read_tags(&temp_path));
+		let content_hash = self.hash_content(&temp_path);
+		let _ = fs::remove_file(&temp_path);
+		let mut tags = match tags {
+			Ok(tags) => tags?,
+			Err(e) => {
+				self.add_warning(
+					Path::new(&song_path),
+					format!("Archive entry read error: {}", e),
+				);
+				return None;
+			}
+		};
+
+		// Embedded artwork cannot be read back from a path within an archive
+		tags.has_artwork = false;
+		Some(SongFile {
+			path: song_path,
+			tags: self.process_tags(tags),
+			file_size: entry.size as i64,
+			mtime,
+			created: None,
+			start_time: None,
+			content_hash,
+		})
+	}
+
+	fn check_mount_point(&self, path: &Path) -> Result<(), IndexError> {
+		utils::retry_io(
+			path,
+			self.read_dir_retries,
+			self.read_dir_retry_delay,
+			|| fs::metadata(utils::fs_path(path)),
+		)
+		.map(|_| ())
+		.map_err(|e| IndexError::MountPoint(path.to_owned(), e))
+	}
+
+	// Dropping the updater also drops the senders of the rows it found
+	fn into_summary(self) -> UpdateSummary {
+		let warnings = self.warnings.into_inner().unwrap();
+		UpdateSummary {
+			stats: IndexStats {
+				files_skipped_errors: warnings.len(),
+				..Default::default()
+			},
+			warnings,
+			metadata_conflicts: self.metadata_conflicts.into_inner().unwrap(),
+			slow_directories: self.slow_directories.into_inner().unwrap(),
+			..Default::default()
+		}
+	}
+
+	fn sub_directories_to_visit(&self, path: &Path, sub_directories: Vec<PathBuf>) -> Vec<PathBuf> {
+		if !sub_directories.is_empty() {
+			if let Some(max_depth) = self.max_depth {
+				if get_depth(&self.vfs, path).map_or(false, |depth| depth >= max_depth) {
+					self.add_warning(
+						path,
+						format!(
+							"Sub-directories were not indexed beyond the maximum depth of {}",
+							max_depth
+						),
+					);
+					return Vec::new();
+				}
+			}
+		}
+		sub_directories
+	}
+
+	// Errors within a sub-directory are recorded as warnings so the rest of the tree gets indexed
+	fn check_sub_directory<T>(&self, sub_directory: &Path, result: Result<T>) -> Result<Option<T>> {
+		match result {
+			Err(e) if !matches!(e.downcast_ref(), Some(IndexError::Cancelled)) => {
+				self.add_warning(sub_directory, e.to_string());
+				Ok(None)
+			}
+			result => result.map(Some),
+		}
+	}
+
+	fn populate_sub_directories(
+		&self,
+		path: &Path,
+		sub_directories: Vec<PathBuf>,
+		album_art_patterns: &[Regex],
+		mount_name: &str,
+	) -> Result<()> {
+		let populate = |sub_directory: PathBuf| {
+			let result =
+				self.populate_directory(Some(path), &sub_directory, album_art_patterns, mount_name);
+			self.check_sub_directory(&sub_directory, result).map(|_| ())
+		};
+		// Propagate cancellation to the caller. Low memory mode visits sub-directories in order, but
+		// still holds all of their paths meanwhile.
+		if self.low_memory {
+			sub_directories.into_iter().try_for_each(populate)
+		} else {
+			sub_directories.into_par_iter().try_for_each(populate)
+		}
+	}
+}
+
+// Reindexes a single directory subtree, identified by its real path
+pub fn update_directory(db: &DB, path: &Path, options: &UpdateOptions) -> Result<(), IndexError> {
+	let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+	let virtual_path = vfs
+		.real_to_virtual(path)
+		.map_err(|_| IndexError::NotInMountPoint(path.to_owned()))?;
+	// Paths are stored in the same form as when traversing from the mount point
+	let path = vfs
+		.virtual_to_real(&virtual_path)
+		.map_err(|_| IndexError::NotInMountPoint(path.to_owned()))?;
+
+	clean_entries(db, Some(&path), options)?;
+
+	let settings: MiscSettings = {
+		let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+		misc_settings::table.get_result(&connection)?
+	};
+	let ignore_patterns = get_ignore_patterns(&settings)?;
+	let include_patterns = get_mount_include_patterns(db)?;
+	if !path.is_dir()
+		|| is_ignored_virtual_path(&ignore_patterns, &virtual_path)
+		|| is_excluded_virtual_path(&include_patterns, &virtual_path)
+	{
+		return Ok(());
+	}
+
+	// Mount point roots have no parent in the index
+	let parent = if virtual_path.components().count() > 1 {
+		path.parent().map(|p| p.to_owned())
+	} else {
+		None
+	};
+	let mount_name = get_mount_name(&virtual_path).unwrap_or_default();
+	let album_art_patterns = get_mount_album_art_patterns(db)?.remove(&mount_name);
+	let target = PopulateTarget {
+		parent,
+		path,
+		album_art_patterns,
+		mount_name,
+	};
+	populate_targets(db, vec![target], HashMap::new(), false, false, options)?;
+	Ok(())
+}
+
+#[allow(dead_code)]
+#[cfg_attr(feature = "profile-index", flame)]
+pub fn clean(db: &DB, options: &UpdateOptions) -> Result<UpdateSummary, IndexError> {
+	clean_entries(db, None, options)
+}
+
+enum Listing {
+	Entries(HashMap<OsString, bool>), // Whether each entry is a symlink
+	Missing,
+	Unreadable,
+}
+
+// Existence checks against the contents of parent directories, which are read once each. This
+// saves a lot of round-trips on network mounts compared to checking every path individually.
+struct ParentListings {
+	listings: HashMap<PathBuf, Listing>,
+	case_insensitive_roots: Vec<PathBuf>, // Mount points on file systems which ignore case
+}
+
+impl ParentListings {
+	fn new(case_insensitive_roots: Vec<PathBuf>) -> ParentListings {
+		ParentListings {
+			listings: HashMap::new(),
+			case_insensitive_roots,
+		}
+	}
+
+	// Reads the parents of the given paths which were not read yet. Under mount points which
+	// ignore case, the parents of their ancestors are read too.
+	fn add<I, P>(&mut self, paths: I)
+	where
+		I: Iterator<Item = P>,
+		P: AsRef<Path>,
+	{
+		let mut levels: BTreeMap<usize, HashSet<PathBuf>> = BTreeMap::new();
+		for path in paths {
+			let path = path.as_ref();
+			let mut parents = path.parent().into_iter().collect::<Vec<_>>();
+			if let Some(root) = self
+				.case_insensitive_roots
+				.iter()
+				.find(|root| path.starts_with(root))
+			{
+				parents.extend(
+					path.ancestors()
+						.skip(2)
+						.take_while(|ancestor| ancestor.starts_with(root)),
+				);
+			}
+			for parent in parents {
+				if !self.listings.contains_key(parent) {
+					levels
+						.entry(parent.components().count())
+						.or_default()
+						.insert(parent.to_path_buf());
+				}
+			}
+		}
+
+		// Parents are read from the top down so that entire missing subtrees can be skipped
+		for (_, parents) in levels {
+			let listings = &self.listings;
+			let level_listings = parents
+				.into_par_iter()
+				.map(|parent| {
+					let listing = if Self::has_missing_ancestor(listings, &parent) {
+						Listing::Missing
+					} else {
+						Self::read_listing(&parent)
+					};
+					(parent, listing)
+				})
+				.collect::<Vec<_>>();
+			self.listings.extend(level_listings);
+		}
+	}
+
+	fn has_missing_ancestor(listings: &HashMap<PathBuf, Listing>, path: &Path) -> bool {
+		path.ancestors()
+			.skip(1)
+			.any(|a| matches!(listings.get(a), Some(Listing::Missing)))
+	}
+
+	fn read_listing(path: &Path) -> Listing {
+		if path.as_os_str().is_empty() {
+			return Listing::Unreadable;
+		}
+		let read_dir = match fs::read_dir(utils::fs_path(path)) {
+			Ok(read_dir) => read_dir,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Listing::Missing,
+			Err(_) => return Listing::Unreadable,
+		};
+		let entries = read_dir
+			.map(|entry| {
+				let entry = entry?;
+				Ok((entry.file_name(), entry.file_type()?.is_symlink()))
+			})
+			.collect::<std::io::Result<HashMap<_, _>>>();
+		match entries {
+			Ok(entries) => Listing::Entries(entries),
+			Err(_) => Listing::Unreadable,
+		}
+	}
+}
+
+impl ParentListings {
+	// Names are compared exactly, so that a path with a different case is missing from the listing
+	// of its parent, even if the file system would find it
+	fn is_listed(&self, path: &Path) -> bool {
+		let listing = path.parent().and_then(|p| self.listings.get(p));
+		match (listing, path.file_name()) {
+			(Some(Listing::Entries(entries)), Some(name)) => entries.contains_key(name),
+			_ => true,
+		}
+	}
+}
+
+impl PathExistence for ParentListings {
+	// Symlinks and paths whose parent could not be read are checked individually
+	fn exists(&self, path: &Path) -> bool {
+		let listing = path.parent().and_then(|p| self.listings.get(p));
+		let exists = match (listing, path.file_name()) {
+			(Some(Listing::Entries(entries)), Some(name)) => match entries.get(name) {
+				Some(false) => true,
+				Some(true) => utils::fs_path(path).exists(),
+				None => false,
+			},
+			(Some(Listing::Missing), _) => false,
+			_ => utils::fs_path(path).exists(),
+		};
+
+		// Directories can be listed through a path with a different case when case is ignored, so
+		// each directory between the mount point and the path must also be listed with its case.
+		// Otherwise renaming a directory would leave its songs under both names.
+		match self
+			.case_insensitive_roots
+			.iter()
+			.find(|root| path.starts_with(root))
+		{
+			Some(root) if exists => path
+				.ancestors()
+				.skip(1)
+				.take_while(|ancestor| ancestor != root && ancestor.starts_with(root))
+				.all(|ancestor| self.is_listed(ancestor)),
+			_ => exists,
+		}
+	}
+}
+
+// Songs within archives exist as long as their archive does
+fn strip_archive_entry(path: &Path) -> &Path {
+	path.to_str()
+		.and_then(archive::split_entry_path)
+		.map_or(path, |(archive_path, _)| Path::new(archive_path))
+}
+
+fn is_outside_collection(
+	vfs: &VFS,
+	ignore_patterns: &[Pattern],
+	include_patterns: &HashMap<String, Vec<IncludePattern>>,
+	path: &Path,
+) -> bool {
+	vfs.real_to_virtual(path).map_or(true, |p| {
+		is_ignored_virtual_path(ignore_patterns, &p)
+			|| is_excluded_virtual_path(include_patterns, &p)
+	})
+}
+
+// Directories above another one are listed from the mount point down, by their stored paths
+pub const ANCESTORS_SEPARATOR: &str = "\n";
+
+// Depth of a directory below its mount point, along with its ancestors
+fn get_ancestry(vfs: &VFS, real_path: &Path) -> Option<(i32, String)> {
+	let virtual_path = vfs.real_to_virtual(real_path).ok()?;
+	let mut ancestors = virtual_path
+		.ancestors()
+		.skip(1)
+		.filter(|a| a.components().count() > 0)
+		.map(|a| {
+			vfs.virtual_to_real(a)
+				.map(|r| vfs.real_to_stored(r).to_string_lossy().into_owned())
+		})
+		.collect::<Result<Vec<_>>>()
+		.ok()?;
+	ancestors.reverse();
+	Some((ancestors.len() as i32, ancestors.join(ANCESTORS_SEPARATOR)))
+}
+
+// Directories indexed before their ancestry was recorded, or whose paths were converted
+fn fill_directory_ancestry(db: &DB, vfs: &VFS) -> Result<(), IndexError> {
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let paths: Vec<String> = directories::table
+		.select(directories::path)
+		.filter(directories::depth.is_null())
+		.load(&connection)?;
+	if paths.is_empty() {
+		return Ok(());
+	}
+	connection.transaction::<_, diesel::result::Error, _>(|| {
+		for path in &paths {
+			if let Some((depth, ancestors)) = get_ancestry(vfs, &vfs.stored_to_real(path)) {
+				diesel::update(directories::table.filter(directories::path.eq(path)))
+					.set((
+						directories::depth.eq(depth),
+						directories::ancestors.eq(ancestors),
+					))
+					.execute(&connection)?;
+			}
+		}
+		Ok(())
+	})?;
+	Ok(())
+}
+
+// Virtual paths start with the name of their mount point
+fn get_mount_name(virtual_path: &Path) -> Option<String> {
+	virtual_path
+		.components()
+		.next()
+		.map(|c| c.as_os_str().to_string_lossy().into_owned())
+}
+
+fn get_stored_mount_name(vfs: &VFS, stored_path: &str) -> Option<String> {
+	let virtual_path = vfs.real_to_virtual(vfs.stored_to_real(stored_path)).ok()?;
+	get_mount_name(&virtual_path)
+}
+
+// Entries indexed before their mount point was recorded. Songs are looked up by their parent
+// directory, which they share with their siblings.
+fn fill_mount_names(db: &DB, vfs: &VFS) -> Result<(), IndexError> {
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let directory_paths: Vec<String> = directories::table
+		.select(directories::path)
+		.filter(directories::mount_name.is_null())
+		.load(&connection)?;
+	let song_parents: Vec<String> = songs::table
+		.select(songs::parent)
+		.filter(songs::mount_name.is_null())
+		.distinct()
+		.load(&connection)?;
+	if directory_paths.is_empty() && song_parents.is_empty() {
+		return Ok(());
+	}
+	connection.transaction::<_, diesel::result::Error, _>(|| {
+		for path in &directory_paths {
+			if let Some(mount_name) = get_stored_mount_name(vfs, path) {
+				diesel::update(directories::table.filter(directories::path.eq(path)))
+					.set(directories::mount_name.eq(mount_name))
+					.execute(&connection)?;
+			}
+		}
+		for parent in &song_parents {
+			if let Some(mount_name) = get_stored_mount_name(vfs, parent) {
+				diesel::update(songs::table.filter(songs::parent.eq(parent)))
+					.set(songs::mount_name.eq(mount_name))
+					.execute(&connection)?;
+			}
+		}
+		Ok(())
+	})?;
+	Ok(())
+}
+
+// Relative paths of entries which were indexed through an enclosing mount point, before a nested
+// mount point took them over
+fn is_stored_elsewhere(vfs: &VFS, stored_path: &Path, real_path: &Path) -> bool {
+	vfs.real_to_stored(real_path) != stored_path
+}
+
+// Directories holding the ignore marker are left out of the index along with their descendants
+fn has_ignore_marker(path: &Path, marker: &str) -> bool {
+	utils::fs_path(&path.join(marker)).exists()
+}
+
+// Returns the stored paths of the given directories which hold the ignore marker
+fn find_marked_directories(
+	vfs: &VFS,
+	marker: Option<&str>,
+	directory_paths: &[String],
+) -> HashSet<PathBuf> {
+	match marker {
+		Some(marker) => directory_paths
+			.par_iter()
+			.filter(|p| has_ignore_marker(&vfs.stored_to_real(p), marker))
+			.map(PathBuf::from)
+			.collect(),
+		None => HashSet::new(),
+	}
+}
+
+// Returns the stored paths of the given directories which the filter rejects
+fn find_filtered_directories(
+	vfs: &VFS,
+	filter: Option<&dyn DirectoryFilter>,
+	directory_paths: &[String],
+) -> HashSet<PathBuf> {
+	let filter = match filter {
+		Some(filter) => filter,
+		None => return HashSet::new(),
+	};
+	let mount_points = vfs.get_mount_points();
+	directory_paths
+		.par_iter()
+		.filter(|p| {
+			let real_path = vfs.stored_to_real(p);
+			let parent = match mount_points.values().any(|m| *m == real_path) {
+				true => None,
+				false => real_path.parent(),
+			};
+			!filter.should_index(&real_path, parent)
+		})
+		.map(PathBuf::from)
+		.collect()
+}
+
+fn is_marked(marked_directories: &HashSet<PathBuf>, path: &Path) -> bool {
+	!marked_directories.is_empty() && path.ancestors().any(|a| marked_directories.contains(a))
+}
+
+// A mount point which is empty or missing usually means that a drive failed to mount, in which
+// case cleaning would wipe its songs from the index
+fn check_mount_points(vfs: &VFS, settings: &MiscSettings) -> Result<(), IndexError> {
+	if settings.index_allow_empty_mounts != 0 {
+		return Ok(());
+	}
+	let retries = settings.index_read_dir_retries.max(0) as u32;
+	let delay = time::Duration::from_millis(settings.index_read_dir_retry_delay_ms.max(0) as u64);
+	for target in vfs.get_mount_points().values() {
+		let is_empty = match utils::retry_io(target, retries, delay, || {
+			fs::read_dir(utils::fs_path(target))
+		}) {
+			Ok(mut entries) => entries.next().is_none(),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+			Err(e) => return Err(IndexError::MountPoint(target.to_owned(), e)),
+		};
+		if is_empty {
+			return Err(IndexError::EmptyMountPoint(target.to_owned()));
+		}
+	}
+	Ok(())
+}
+
+// Columns of the index which hold paths in their stored form
+const STORED_PATH_COLUMNS: &[(&str, &str)] = &[
+	("songs", "path"),
+	("songs", "parent"),
+	("songs", "artwork"),
+	("directories", "path"),
+	("directories", "parent"),
+	("directories", "artwork"),
+	("song_artists", "song_path"),
+	("song_lyrics", "song_path"),
+	("playlist_songs", "path"),
+	("indexed_playlists", "path"),
+	("indexed_playlists", "parent"),
+	("indexed_playlist_songs", "playlist_path"),
+	("indexed_playlist_songs", "path"),
+];
+
+#[derive(QueryableByName)]
+struct StoredPath {
+	#[sql_type = "diesel::sql_types::Text"]
+	path: String,
+}
+
+// Paths stored in the other form, from before `index_store_relative_paths` was toggled
+fn convert_stored_path(vfs: &VFS, store_relative_paths: bool, path: &str) -> Option<String> {
+	let converted = if store_relative_paths {
+		vfs.real_to_virtual(path)
+	} else {
+		vfs.virtual_to_real(path)
+	};
+	converted
+		.ok()
+		.map(|p| p.to_string_lossy().into_owned())
+		.filter(|p| p != path)
+}
+
+// Converts the whole index when the stored form of paths changed. Mount point roots are always
+// indexed, so they tell whether the rest of the index needs converting.
+fn convert_stored_paths(db: &DB, vfs: &VFS) -> Result<(), IndexError> {
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let store_relative_paths: i32 = misc_settings::table
+		.select(misc_settings::index_store_relative_paths)
+		.get_result(&connection)?;
+	let store_relative_paths = store_relative_paths != 0;
+	let roots: Vec<String> = directories::table
+		.select(directories::path)
+		.filter(directories::parent.is_null())
+		.load(&connection)?;
+	if roots
+		.iter()
+		.all(|p| convert_stored_path(vfs, store_relative_paths, p).is_none())
+	{
+		return Ok(());
+	}
+
+	info!("Converting paths stored in the index");
+	connection.transaction::<_, diesel::result::Error, _>(|| {
+		for (table, column) in STORED_PATH_COLUMNS {
+			let paths: Vec<StoredPath> = diesel::sql_query(format!(
+				"SELECT DISTINCT {1} AS path FROM {0} WHERE {1} IS NOT NULL",
+				table, column
+			))
+			.load(&connection)?;
+			for StoredPath { path } in paths {
+				if let Some(converted) = convert_stored_path(vfs, store_relative_paths, &path) {
+					diesel::sql_query(format!(
+						"UPDATE {0} SET {1} = ? WHERE {1} = ?",
+						table, column
+					))
+					.bind::<diesel::sql_types::Text, _>(converted)
+					.bind::<diesel::sql_types::Text, _>(path)
+					.execute(&connection)?;
+				}
+			}
+		}
+		// Ancestors hold several paths each, so they are computed again instead
+		diesel::update(directories::table)
+			.set((
+				directories::depth.eq(None::<i32>),
+				directories::ancestors.eq(None::<String>),
+			))
+			.execute(&connection)?;
+		Ok(())
+	})?;
+	Ok(())
+}
+
+// Directories holding the ignore marker or rejected by the directory filter, among the given ones
+fn find_excluded_directories(
+	vfs: &VFS,
+	settings: &MiscSettings,
+	options: &UpdateOptions,
+	directory_paths: &[String],
+) -> HashSet<PathBuf> {
+	let ignore_marker = get_ignore_marker(settings);
+	let mut excluded_directories =
+		find_marked_directories(vfs, ignore_marker.as_deref(), directory_paths);
+	excluded_directories.extend(find_filtered_directories(
+		vfs,
+		options.directory_filter.as_deref(),
+		directory_paths,
+	));
+	excluded_directories
+}
+
+// Checks each path against the file system, for when parent listings are not read up front
+struct FileSystemExistence;
+
+impl PathExistence for FileSystemExistence {
+	fn exists(&self, path: &Path) -> bool {
+		utils::fs_path(path).exists()
+	}
+}
+
+// Decides which indexed entries no longer belong to the collection. Clean passes and merge updates
+// both remove entries through it, so that they keep the same ones.
+struct Retention<'a> {
+	vfs: &'a VFS,
+	ignore_patterns: Vec<Pattern>,
+	include_patterns: HashMap<String, Vec<IncludePattern>>,
+	extensions: Vec<String>,
+	max_depth: Option<usize>,
+	marked_directories: &'a HashSet<PathBuf>, // Holding the ignore marker or rejected by the filter
+	path_existence: &'a dyn PathExistence,
+}
+
+impl<'a> Retention<'a> {
+	fn new(
+		db: &DB,
+		vfs: &'a VFS,
+		settings: &MiscSettings,
+		marked_directories: &'a HashSet<PathBuf>,
+		path_existence: &'a dyn PathExistence,
+	) -> Result<Retention<'a>, IndexError> {
+		Ok(Retention {
+			vfs,
+			ignore_patterns: get_ignore_patterns(settings)?,
+			include_patterns: get_mount_include_patterns(db)?,
+			extensions: get_extensions(settings),
+			max_depth: get_max_depth(settings),
+			marked_directories,
+			path_existence,
+		})
+	}
+
+	fn is_song_missing(&self, path: &Path, link_target: Option<&str>) -> bool {
+		let too_deep = path.parent().map_or(false, |parent| {
+			is_too_deep(self.vfs, self.max_depth, &self.vfs.stored_to_real(parent))
+		});
+		self.is_missing(path, link_target)
+			|| !has_allowed_extension(&self.extensions, path)
+			|| too_deep
+	}
+
+	fn is_directory_missing(&self, path: &Path) -> bool {
+		self.is_missing(path, None)
+			|| is_too_deep(self.vfs, self.max_depth, &self.vfs.stored_to_real(path))
+	}
+
+	// Songs with a resolved symlink exist as long as both the link and its target do
+	fn is_missing(&self, path: &Path, link_target: Option<&str>) -> bool {
+		let full_real_path = self.vfs.stored_to_real(path);
+		let real_path = strip_archive_entry(&full_real_path);
+		let exists = self.path_existence.exists(real_path)
+			&& link_target.map_or(true, |target| self.path_existence.exists(Path::new(target)));
+		let outside_collection = is_outside_collection(
+			self.vfs,
+			&self.ignore_patterns,
+			&self.include_patterns,
+			real_path,
+		);
+		!self.vfs.is_mounted(path)
+			|| !exists || outside_collection
+			|| is_marked(&self.marked_directories, path)
+			|| is_stored_elsewhere(self.vfs, path, &full_real_path)
+	}
+}
+
+// Songs removed while rename detection is enabled are kept aside, for the songs found in their place
+// to claim them
+fn remove_songs(
+	connection: &SqliteConnection,
+	settings: &MiscSettings,
+	song_paths: &[&String],
+	deleted_at: Option<i32>,
+) -> QueryResult<usize> {
+	if settings.index_detect_renames != 0 {
+		keep_removed_songs(connection, song_paths)?;
+	}
+	delete_songs(connection, song_paths, deleted_at)
+}
+
+fn clean_entries(
+	db: &DB,
+	root: Option<&Path>,
+	options: &UpdateOptions,
+) -> Result<UpdateSummary, IndexError> {
+	let mut cleaner = Cleaner::new(db, root, options)?;
+	while cleaner.step()? {}
+	cleaner.finish()
+}
+
+// Removes the entries of missing files, one chunk of `buffer_sizes.clean` entries at a time. The
+// parent listings and excluded directories which a chunk needs are read along with it, and kept
+// for the following chunks.
+struct Cleaner<'a> {
+	db: &'a DB,
+	options: &'a UpdateOptions,
+	vfs: VFS,
+	settings: MiscSettings,
+	buffer_sizes: BufferSizes,
+	deleted_at: Option<i32>,
+	songs: Vec<(String, Option<String>)>,
+	directories: Vec<String>,
+	songs_checked: usize,
+	directories_checked: usize,
+	checks: CleanChecks,
+	summary: UpdateSummary,
+}
+
+// What the chunks checked so far needed to know about the file system
+struct CleanChecks {
+	listings: Option<ParentListings>, // Unless the options check existence
+	indexed_directories: HashSet<PathBuf>,
+	checked_directories: HashSet<PathBuf>, // For the ignore marker and the directory filter
+	excluded_directories: HashSet<PathBuf>,
+}
+
+impl<'a> Cleaner<'a> {
+	// Only entries under `root` are considered when it is specified
+	fn new(
+		db: &'a DB,
+		root: Option<&Path>,
+		options: &'a UpdateOptions,
+	) -> Result<Cleaner<'a>, IndexError> {
+		let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+		let settings: MiscSettings = {
+			let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+			misc_settings::table.get_result(&connection)?
+		};
+		if root.is_none() {
+			check_mount_points(&vfs, &settings)?;
+			convert_stored_paths(db, &vfs)?;
+		}
+		let buffer_sizes = BufferSizes::new(&settings)?;
+		let deleted_at = get_soft_delete_time(&settings)?;
+
+		let root_filter = root.map(|r| {
+			let stored_root = vfs.real_to_stored(r);
+			let mut path_buf = stored_root.clone();
+			path_buf.push("%");
+			(
+				stored_root.to_string_lossy().into_owned(),
+				path_buf.to_string_lossy().into_owned(),
+			)
+		});
+
+		let songs: Vec<(String, Option<String>)>;
+		let directories: Vec<String>;
+		{
+			let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+			songs = match &root_filter {
+				Some((_, children)) => songs::table
+					.select((songs::path, songs::link_target))
+					.filter(songs::path.like(children))
+					.filter(songs::deleted_at.is_null())
+					.load(&connection)?,
+				None => songs::table
+					.select((songs::path, songs::link_target))
+					.filter(songs::deleted_at.is_null())
+					.load(&connection)?,
+			};
+			directories = match &root_filter {
+				Some((root, children)) => directories::table
+					.select(directories::path)
+					.filter(
+						directories::path
+							.eq(root)
+							.or(directories::path.like(children)),
+					)
+					.load(&connection)?,
+				None => directories::table
+					.select(directories::path)
+					.load(&connection)?,
+			};
+		}
+
+		let listings = match &options.path_existence {
+			Some(_) => None,
+			None => {
+				let case_insensitive_roots = vfs
+					.get_mount_points()
+					.values()
+					.filter(|real_path| utils::is_case_insensitive(real_path))
+					.cloned()
+					.collect();
+				Some(ParentListings::new(case_insensitive_roots))
+			}
+		};
+		let checks = CleanChecks {
+			listings,
+			indexed_directories: directories.iter().map(PathBuf::from).collect(),
+			checked_directories: HashSet::new(),
+			excluded_directories: HashSet::new(),
+		};
+
+		Ok(Cleaner {
+			db,
+			options,
+			vfs,
+			settings,
+			buffer_sizes,
+			deleted_at,
+			songs,
+			directories,
+			songs_checked: 0,
+			directories_checked: 0,
+			checks,
+			summary: UpdateSummary::default(),
+		})
+	}
+
+	fn songs_removed(&self) -> usize {
+		self.summary.songs_removed.count
+	}
+
+	fn directories_removed(&self) -> usize {
+		self.summary.directories_removed.count
+	}
+
+	// Checks the next chunk of songs, or of directories once all songs were checked. Returns
+	// whether entries remain to be checked.
+	fn step(&mut self) -> Result<bool, IndexError> {
+		self.options.check_cancellation()?;
+		if self.songs_checked < self.songs.len() {
+			let end = (self.songs_checked + self.buffer_sizes.clean).min(self.songs.len());
+			let chunk = &self.songs[self.songs_checked..end];
+			self.checks.prepare(
+				&self.vfs,
+				&self.settings,
+				self.options,
+				chunk.iter().map(|(path, _)| path),
+			);
+			let retention = Retention::new(
+				self.db,
+				&self.vfs,
+				&self.settings,
+				&self.checks.excluded_directories,
+				self.checks.path_existence(self.options),
+			)?;
+			let missing_songs = chunk
+				.par_iter()
+				.filter(|(song_path, link_target)| {
+					retention.is_song_missing(Path::new(song_path), link_target.as_deref())
+				})
+				.map(|(song_path, _)| song_path)
+				.collect::<Vec<_>>();
+			for song_path in &missing_songs {
+				self.summary
+					.songs_removed
+					.record(song_path, self.options.dry_run);
+			}
+			if !self.options.dry_run && !missing_songs.is_empty() {
+				let connection = self.db.connect().map_err(IndexError::DatabaseConnection)?;
+				self.summary.stats.songs_deleted +=
+					remove_songs(&connection, &self.settings, &missing_songs, self.deleted_at)?;
+			}
+			self.songs_checked = end;
+			return Ok(true);
+		}
+
+		if self.directories_checked < self.directories.len() {
+			let end =
+				(self.directories_checked + self.buffer_sizes.clean).min(self.directories.len());
+			let chunk = &self.directories[self.directories_checked..end];
+			self.checks
+				.prepare(&self.vfs, &self.settings, self.options, chunk.iter());
+			let retention = Retention::new(
+				self.db,
+				&self.vfs,
+				&self.settings,
+				&self.checks.excluded_directories,
+				self.checks.path_existence(self.options),
+			)?;
+			let missing_directories = chunk
+				.par_iter()
+				.filter(|directory_path| retention.is_directory_missing(Path::new(directory_path)))
+				.collect::<Vec<_>>();
+			for directory_path in &missing_directories {
+				self.summary
+					.directories_removed
+					.record(directory_path, self.options.dry_run);
+			}
+			if !self.options.dry_run && !missing_directories.is_empty() {
+				let connection = self.db.connect().map_err(IndexError::DatabaseConnection)?;
+				self.summary.stats.directories_deleted += diesel::delete(
+					directories::table.filter(directories::path.eq_any(&missing_directories)),
+				)
+				.execute(&connection)?;
+				delete_playlists(&connection, &missing_directories)?;
+			}
+			self.directories_checked = end;
+		}
+
+		Ok(self.directories_checked < self.directories.len())
+	}
+
+	fn finish(self) -> Result<UpdateSummary, IndexError> {
+		if !self.options.dry_run {
+			let connection = self.db.connect().map_err(IndexError::DatabaseConnection)?;
+			flag_missing_playlist_songs(&connection)?;
+		}
+		Ok(self.summary)
+	}
+}
+
+impl CleanChecks {
+	// Entries of removed mount points are deleted whether or not their files still exist, so their
+	// parents are not listed
+	fn prepare<I, P>(
+		&mut self,
+		vfs: &VFS,
+		settings: &MiscSettings,
+		options: &UpdateOptions,
+		stored_paths: I,
+	) where
+		I: Iterator<Item = P> + Clone,
+		P: AsRef<str>,
+	{
+		if let Some(listings) = &mut self.listings {
+			listings.add(
+				stored_paths
+					.clone()
+					.filter(|p| vfs.is_mounted(p.as_ref()))
+					.map(|p| vfs.stored_to_real(p.as_ref())),
+			);
+		}
+
+		// Directories are excluded along with their descendants, so the indexed ancestors of each
+		// entry are checked
+		if get_ignore_marker(settings).is_none() && options.directory_filter.is_none() {
+			return;
+		}
+		let mut unchecked_directories = Vec::new();
+		for path in stored_paths {
+			for ancestor in Path::new(path.as_ref()).ancestors() {
+				if self.indexed_directories.contains(ancestor)
+					&& self.checked_directories.insert(ancestor.to_owned())
+				{
+					unchecked_directories.push(ancestor.to_string_lossy().into_owned());
+				}
+			}
+		}
+		self.excluded_directories.extend(find_excluded_directories(
+			vfs,
+			settings,
+			options,
+			&unchecked_directories,
+		));
+	}
+
+	fn path_existence<'a>(&'a self, options: &'a UpdateOptions) -> &'a dyn PathExistence {
+		match (&self.listings, &options.path_existence) {
+			(Some(listings), _) => listings,
+			(None, Some(path_existence)) => path_existence.as_ref(),
+			(None, None) => &FileSystemExistence,
+		}
+	}
+}
+
+// Contents of the mount points as a populate pass would find them, along with modification times
+#[derive(Default)]
+struct DiskListing {
+	directories: HashSet<PathBuf>,
+	songs: HashMap<PathBuf, Option<i64>>,
+	archives: HashMap<PathBuf, Option<i64>>,
+	unreadable_directories: HashSet<PathBuf>,
+}
+
+impl DiskListing {
+	fn extend(&mut self, other: DiskListing) {
+		self.directories.extend(other.directories);
+		self.songs.extend(other.songs);
+		self.archives.extend(other.archives);
+		self.unreadable_directories
+			.extend(other.unreadable_directories);
+	}
+
+	fn get_mtime(&self, path: &Path) -> Option<Option<i64>> {
+		self.songs
+			.get(path)
+			.or_else(|| self.archives.get(path))
+			.copied()
+	}
+
+	fn is_unreadable(&self, path: &Path) -> bool {
+		path.ancestors()
+			.any(|a| self.unreadable_directories.contains(a))
+	}
+}
+
+// Lists the mount points with the same filters as an update, so that files which are skipped on
+// purpose do not show up as unindexed
+struct DiskWalker<'a> {
+	vfs: &'a VFS,
+	ignore_patterns: Vec<Pattern>,
+	include_patterns: HashMap<String, Vec<IncludePattern>>,
+	ignore_marker: Option<String>,
+	extensions: Vec<String>,
+	max_depth: Option<usize>,
+	min_file_size: u64,
+	directory_filter: Option<&'a dyn DirectoryFilter>,
+	follow_symlinks: bool,
+	index_archives: bool,
+	visited_directories: Mutex<HashSet<PathBuf>>,
+}
+
+impl DiskWalker<'_> {
+	fn walk(&self, parent: Option<&Path>, path: &Path) -> DiskListing {
+		let mut listing = DiskListing::default();
+		if let Some(filter) = self.directory_filter {
+			if !filter.should_index(path, parent) {
+				return listing;
+			}
+		}
+		if let Some(marker) = &self.ignore_marker {
+			if has_ignore_marker(path, marker) {
+				return listing;
+			}
+		}
+		if let Ok(canonical_path) = fs::canonicalize(utils::fs_path(path)) {
+			if !self
+				.visited_directories
+				.lock()
+				.unwrap()
+				.insert(canonical_path)
+			{
+				return listing;
+			}
+		}
+		let entries = match fs::read_dir(utils::fs_path(path)) {
+			Ok(entries) => entries,
+			Err(_) => {
+				listing.unreadable_directories.insert(path.to_owned());
+				return listing;
+			}
+		};
+		listing.directories.insert(path.to_owned());
+
+		let mut sub_directories = Vec::new();
+		for entry in entries {
+			let name = match entry {
+				Ok(entry) => entry.file_name(),
+				Err(_) => {
+					listing.unreadable_directories.insert(path.to_owned());
+					continue;
+				}
+			};
+			if is_ignored(&self.ignore_patterns, &name) {
+				continue;
+			}
+			let file_path = path.join(name);
+			if is_excluded_path(self.vfs, &self.include_patterns, &file_path) {
+				continue;
+			}
+			if utils::fs_path(&file_path).is_dir() {
+				if self.follow_symlinks || !is_symlink(&file_path) {
+					sub_directories.push(file_path);
+				}
+				continue;
+			}
+			let is_archive = self.index_archives && archive::is_archive(&file_path);
+			if !is_archive && !is_song_file(&self.extensions, &file_path) {
+				continue;
+			}
+			let metadata = fs::metadata(utils::fs_path(&file_path));
+			if !is_archive
+				&& metadata
+					.as_ref()
+					.map_or(false, |m| m.len() < self.min_file_size)
+			{
+				continue;
+			}
+			let files = match is_archive {
+				true => &mut listing.archives,
+				false => &mut listing.songs,
+			};
+			let mtime = metadata
+				.and_then(|m| m.modified())
+				.ok()
+				.and_then(|m| m.duration_since(time::UNIX_EPOCH).ok())
+				.map(|d| d.as_secs() as i64);
+			files.insert(file_path, mtime);
+		}
+
+		if let Some(max_depth) = self.max_depth {
+			if get_depth(self.vfs, path).map_or(false, |depth| depth >= max_depth) {
+				return listing;
+			}
+		}
+		let sub_listings = sub_directories
+			.par_iter()
+			.map(|d| self.walk(Some(path), d))
+			.collect::<Vec<_>>();
+		for sub_listing in sub_listings {
+			listing.extend(sub_listing);
+		}
+		listing
+	}
+}
+
+// Checks that every song belongs to an indexed directory, that no directory is its own parent and
+// that artwork paths point to existing files. Soft deleted songs may outlive their directory.
+pub fn check_integrity(db: &DB) -> Result<Vec<IntegrityViolation>, IndexError> {
+	let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let mut violations = Vec::new();
+
+	let orphaned_songs: Vec<String> = songs::table
+		.select(songs::path)
+		.filter(songs::deleted_at.is_null())
+		.filter(songs::parent.ne_all(directories::table.select(directories::path)))
+		.order(songs::path)
+		.load(&connection)?;
+	violations.extend(
+		orphaned_songs
+			.iter()
+			.map(|p| IntegrityViolation::OrphanedSong(vfs.stored_to_real(p))),
+	);
+
+	let own_parents: Vec<String> = directories::table
+		.select(directories::path)
+		.filter(directories::parent.eq(directories::path.nullable()))
+		.order(directories::path)
+		.load(&connection)?;
+	violations.extend(
+		own_parents
+			.iter()
+			.map(|p| IntegrityViolation::DirectoryOwnParent(vfs.stored_to_real(p))),
+	);
+
+	let mut artworks: Vec<(String, Option<String>)> = songs::table
+		.select((songs::path, songs::artwork))
+		.filter(songs::deleted_at.is_null())
+		.filter(songs::artwork.is_not_null())
+		.load(&connection)?;
+	artworks.extend(
+		directories::table
+			.select((directories::path, directories::artwork))
+			.filter(directories::artwork.is_not_null())
+			.load::<(String, Option<String>)>(&connection)?,
+	);
+	artworks.sort();
+	let mut existing_artworks = HashMap::new();
+	for (entry, artwork) in artworks {
+		let artwork = match artwork {
+			Some(artwork) => vfs.stored_to_real(artwork),
+			None => continue,
+		};
+		let exists = *existing_artworks
+			.entry(artwork.clone())
+			.or_insert_with(|| utils::fs_path(&artwork).is_file());
+		if !exists {
+			violations.push(IntegrityViolation::MissingArtwork {
+				entry: vfs.stored_to_real(entry),
+				artwork,
+			});
+		}
+	}
+
+	if !violations.is_empty() {
+		warn!(
+			"Index integrity check found {} violations",
+			violations.len()
+		);
+	}
+	Ok(violations)
+}
+
+// Compares the index against the file system without modifying either. Songs within archives
+// are checked against the archive holding them.
+pub fn verify(db: &DB, options: &UpdateOptions) -> Result<VerifyReport, IndexError> {
+	let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let settings: MiscSettings = misc_settings::table.get_result(&connection)?;
+	let walker = DiskWalker {
+		vfs: &vfs,
+		ignore_patterns: get_ignore_patterns(&settings)?,
+		include_patterns: get_mount_include_patterns(db)?,
+		ignore_marker: get_ignore_marker(&settings),
+		extensions: get_extensions(&settings),
+		max_depth: get_max_depth(&settings),
+		min_file_size: settings.index_min_file_size.max(0) as u64,
+		directory_filter: options.directory_filter.as_deref(),
+		follow_symlinks: settings.index_follow_symlinks != 0,
+		index_archives: settings.index_archives != 0,
+		visited_directories: Mutex::new(HashSet::new()),
+	};
+	let mut listing = DiskListing::default();
+	for target in vfs.get_mount_points().values() {
+		listing.extend(walker.walk(None, Path::new(target)));
+	}
+
+	let indexed_songs: Vec<(String, Option<i64>)> = songs::table
+		.select((songs::path, songs::mtime))
+		.filter(songs::deleted_at.is_null())
+		.load(&connection)?;
+	let indexed_directories: Vec<String> = directories::table
+		.select(directories::path)
+		.load(&connection)?;
+
+	let mut report = VerifyReport::default();
+
+	// Tracks split by a cue sheet share the path of their file
+	let indexed_songs: BTreeMap<PathBuf, Option<i64>> = indexed_songs
+		.into_iter()
+		.map(|(path, mtime)| (vfs.stored_to_real(&path), mtime))
+		.collect();
+	for (path, mtime) in &indexed_songs {
+		let file_path = strip_archive_entry(path);
+		match listing.get_mtime(file_path) {
+			Some(disk_mtime) => {
+				if mtime.is_some() && disk_mtime.is_some() && *mtime != disk_mtime {
+					report.modified_songs.push(path.clone());
+				}
+			}
+			None if !listing.is_unreadable(file_path) => report.missing_songs.push(path.clone()),
+			None => (),
+		}
+	}
+	let mut unindexed_songs = listing
+		.songs
+		.keys()
+		.filter(|p| !indexed_songs.contains_key(*p))
+		.cloned()
+		.collect::<Vec<_>>();
+	unindexed_songs.sort();
+	report.unindexed_songs = unindexed_songs;
+
+	let indexed_directories: BTreeSet<PathBuf> = indexed_directories
+		.iter()
+		.map(|p| vfs.stored_to_real(p))
+		.collect();
+	report.missing_directories = indexed_directories
+		.iter()
+		.filter(|p| !listing.directories.contains(*p) && !listing.is_unreadable(p))
+		.cloned()
+		.collect();
+	let mut unindexed_directories = listing
+		.directories
+		.iter()
+		.filter(|p| !indexed_directories.contains(*p))
+		.cloned()
+		.collect::<Vec<_>>();
+	unindexed_directories.sort();
+	report.unindexed_directories = unindexed_directories;
+
+	let mut unreadable_directories = listing
+		.unreadable_directories
+		.into_iter()
+		.collect::<Vec<_>>();
+	unreadable_directories.sort();
+	report.unreadable_directories = unreadable_directories;
+
+	Ok(report)
+}
+
+// Removes the songs which were soft deleted longer than `older_than` ago, returning how many.
+// Updates call this with the retention from the settings.
+pub fn purge(db: &DB, older_than: time::Duration) -> Result<usize, IndexError> {
+	let settings: MiscSettings = {
+		let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+		misc_settings::table.get_result(&connection)?
+	};
+	let buffer_sizes = BufferSizes::new(&settings)?;
+	let now = time::SystemTime::now()
+		.duration_since(time::UNIX_EPOCH)
+		.map_err(Error::new)?;
+	let cutoff = now.as_secs().saturating_sub(older_than.as_secs()) as i32;
+
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let purged_songs: Vec<String> = songs::table
+		.select(songs::path)
+		.filter(songs::deleted_at.le(cutoff))
+		.load(&connection)?;
+	let purged_songs = purged_songs.iter().collect::<Vec<_>>();
+	let mut count = 0;
+	connection.transaction::<_, diesel::result::Error, _>(|| {
+		for chunk in purged_songs.chunks(buffer_sizes.clean) {
+			count += delete_songs(&connection, chunk, None)?;
+		}
+		Ok(())
+	})?;
+	Ok(count)
+}
+
+// Removes the songs and directories indexed from a mount point, returning how many. A mount point
+// which is still configured is indexed again from scratch by the next update. Mount points removed
+// from the configuration are forgotten when it is amended.
+pub fn forget_mount(db: &DB, mount_name: &str) -> Result<usize, IndexError> {
+	let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+	fill_mount_names(db, &vfs)?;
+	let settings: MiscSettings = {
+		let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+		misc_settings::table.get_result(&connection)?
+	};
+	let buffer_sizes = BufferSizes::new(&settings)?;
+
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let song_paths: Vec<String> = songs::table
+		.select(songs::path)
+		.filter(songs::mount_name.eq(mount_name))
+		.load(&connection)?;
+	let directory_paths: Vec<String> = directories::table
+		.select(directories::path)
+		.filter(directories::mount_name.eq(mount_name))
+		.load(&connection)?;
+	let song_paths = song_paths.iter().collect::<Vec<_>>();
+	let directory_paths = directory_paths.iter().collect::<Vec<_>>();
+	let mut count = 0;
+	connection.transaction::<_, diesel::result::Error, _>(|| {
+		for chunk in song_paths.chunks(buffer_sizes.clean) {
+			count += delete_songs(&connection, chunk, None)?;
+		}
+		for chunk in directory_paths.chunks(buffer_sizes.clean) {
+			count += diesel::delete(directories::table.filter(directories::path.eq_any(chunk)))
+				.execute(&connection)?;
+			delete_playlists(&connection, chunk)?;
+		}
+		group_albums(&connection, &settings)
+	})?;
+	Ok(count)
+}
+
+#[allow(dead_code)]
+#[cfg_attr(feature = "profile-index", flame)]
+pub fn populate(
+	db: &DB,
+	force_full: bool,
+	options: &UpdateOptions,
+) -> Result<UpdateSummary, IndexError> {
+	populate_mounts(db, force_full, false, options)
+}
+
+// Counts the song files an update would read, without reading their tags, so that progress can be
+// shown against a total. Files are sorted out by the same filters as a populate pass, and archives
+// are listed to count the songs within them. Cue sheets are not read, so a file they split into
+// tracks counts as a single song. Directories which cannot be read are skipped.
+pub fn count_indexable(db: &DB, options: &UpdateOptions) -> Result<usize, IndexError> {
+	let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+	let settings: MiscSettings = {
+		let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+		misc_settings::table.get_result(&connection)?
+	};
+	let file_filters = get_file_filters(&settings, &vfs, get_mount_include_patterns(db)?)?;
+	let ignore_marker = get_ignore_marker(&settings);
+	let max_depth = get_max_depth(&settings);
+	let directory_filter = options.directory_filter.as_deref();
+	let ignore_warning = |_: &Path, _| ();
+
+	let mut count = 0;
+	let mut visited_directories = HashSet::new();
+	let mut pending: Vec<(Option<PathBuf>, PathBuf)> = vfs
+		.get_mount_points()
+		.values()
+		.map(|path| (None, path.clone()))
+		.collect();
+	while let Some((parent, path)) = pending.pop() {
+		options.check_cancellation()?;
+		if directory_filter.map_or(false, |filter| {
+			!filter.should_index(&path, parent.as_deref())
+		}) {
+			continue;
+		}
+		if ignore_marker
+			.as_ref()
+			.map_or(false, |marker| has_ignore_marker(&path, marker))
+		{
+			continue;
+		}
+		// Directories reachable through several paths (via symlinks) are only counted once
+		match fs::canonicalize(utils::fs_path(&path)) {
+			Ok(canonical_path) => {
+				if !visited_directories.insert(canonical_path) {
+					continue;
+				}
+			}
+			Err(_) => continue,
+		}
+		let file_names = match fs::read_dir(utils::fs_path(&path)) {
+			Ok(files) => files
+				.filter_map(|f| f.ok())
+				.map(|f| f.file_name())
+				.collect(),
+			Err(_) => continue,
+		};
+		let files = file_filters.classify(&vfs, &path, file_names, false, &ignore_warning);
+		count += files.songs.len();
+		count += files::list_archive_songs(&files.archives, &ignore_warning).len();
+		let below_max_depth = max_depth.map_or(true, |max_depth| {
+			get_depth(&vfs, &path).map_or(true, |depth| depth < max_depth)
+		});
+		if below_max_depth {
+			pending.extend(
+				files
+					.sub_directories
+					.into_iter()
+					.map(|sub_directory| (Some(path.clone()), sub_directory)),
+			);
+		}
+	}
+	Ok(count)
+}
+
+// Updates the index with a single traversal instead of a clean pass followed by a populate pass.
+// Entries which were not found during the traversal are removed afterwards. Updates run this way
+// when `index_merge_updates` is set.
+#[allow(dead_code)]
+pub fn update_merge(db: &DB, options: &UpdateOptions) -> Result<UpdateSummary, IndexError> {
+	let start = time::Instant::now();
+	let mut summary = populate_mounts(db, false, true, options)?;
+	summary.stats.elapsed = start.elapsed();
+	Ok(summary)
+}
+
+fn populate_mounts(
+	db: &DB,
+	force_full: bool,
+	merge: bool,
+	options: &UpdateOptions,
+) -> Result<UpdateSummary, IndexError> {
+	let (targets, indexed_directories) = get_mount_targets(db, force_full, options)?;
+	let summary = populate_targets(db, targets, indexed_directories, merge, true, options)?;
+	clear_checkpoint(db, options)?;
+	Ok(summary)
+}
+
+// Mount points to traverse, along with what previous updates recorded about their directories
+fn get_mount_targets(
+	db: &DB,
+	force_full: bool,
+	options: &UpdateOptions,
+) -> Result<(Vec<PopulateTarget>, HashMap<String, IndexedDirectory>), IndexError> {
+	let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+	let settings: MiscSettings = {
+		let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+		misc_settings::table.get_result(&connection)?
+	};
+	check_mount_points(&vfs, &settings)?;
+	convert_stored_paths(db, &vfs)?;
+	fill_directory_ancestry(db, &vfs)?;
+	fill_mount_names(db, &vfs)?;
+	let mount_points = vfs.get_mount_points();
+	let mut mount_album_art_patterns = get_mount_album_art_patterns(db)?;
+
+	// Full updates still reuse the artwork found by previous updates, unless asked not to
+	let mut indexed_directories = if force_full && options.rescan_artwork {
+		HashMap::new()
+	} else {
+		let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+		directories::table
+			.select((
+				directories::path,
+				directories::last_indexed,
+				directories::dir_fingerprint,
+				directories::artwork,
+			))
+			.load::<(String, Option<i32>, Option<String>, Option<String>)>(&connection)?
+			.into_iter()
+			.map(|(path, last_indexed, fingerprint, artwork)| {
+				(
+					path,
+					IndexedDirectory {
+						last_indexed: last_indexed.filter(|_| !force_full),
+						fingerprint: fingerprint.filter(|_| !force_full),
+						artwork: artwork.filter(|_| !options.rescan_artwork),
+						checkpointed: false,
+					},
+				)
+			})
+			.collect()
+	};
+
+	let targets = mount_points
+		.iter()
+		.map(|(name, target)| PopulateTarget {
+			parent: None,
+			path: target.to_owned(),
+			album_art_patterns: mount_album_art_patterns.remove(name),
+			mount_name: name.to_owned(),
+		})
+		.collect();
+	for path in get_checkpoint(db)? {
+		indexed_directories
+			.entry(path)
+			.or_insert(IndexedDirectory {
+				last_indexed: None,
+				fingerprint: None,
+				artwork: None,
+				checkpointed: false,
+			})
+			.checkpointed = true;
+	}
+	Ok((targets, indexed_directories))
+}
+
+// The checkpoint only serves the update which follows an interrupted one
+fn clear_checkpoint(db: &DB, options: &UpdateOptions) -> Result<(), IndexError> {
+	if !options.is_cancelled() && !options.dry_run {
+		let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+		diesel::delete(index_checkpoint::table).execute(&connection)?;
+		// Removed songs which were not found again were not renamed
+		diesel::delete(index_renames::table).execute(&connection)?;
+	}
+	Ok(())
+}
+
+// Directories of which both the songs and the directory itself were written
+fn get_checkpoint(db: &DB) -> Result<Vec<String>, IndexError> {
+	let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+	let rows = index_checkpoint::table
+		.select((index_checkpoint::path, index_checkpoint::kind))
+		.load::<(String, String)>(&connection)?;
+	let mut parts: HashMap<String, (bool, bool)> = HashMap::new();
+	for (path, kind) in rows {
+		let entry = parts.entry(path).or_default();
+		match kind.as_str() {
+			CHECKPOINT_SONGS => entry.0 = true,
+			CHECKPOINT_DIRECTORY => entry.1 = true,
+			_ => (),
+		}
+	}
+	Ok(parts
+		.into_iter()
+		.filter(|(_, (songs, directory))| *songs && *directory)
+		.map(|(path, _)| path)
+		.collect())
+}
+
+// What the previous update recorded about a directory
+struct IndexedDirectory {
+	last_indexed: Option<i32>,
+	fingerprint: Option<String>,
+	artwork: Option<String>,
+	checkpointed: bool, // Fully written by an update which was interrupted
 }
 
-impl IndexUpdater {
-	#[cfg_attr(feature = "profile-index", flame)]
-	fn new(
-		album_art_pattern: Regex,
-		directory_sender: Sender<NewDirectory>,
-		song_sender: Sender<NewSong>,
-	) -> Result<IndexUpdater> {
-		Ok(IndexUpdater {
-			directory_sender,
-			song_sender,
-			album_art_pattern,
-		})
-	}
+// A directory to traverse, along with its parent directory in the index
+struct PopulateTarget {
+	parent: Option<PathBuf>,
+	path: PathBuf,
+	album_art_patterns: Option<Vec<Regex>>, // Falls back to the global patterns
+	mount_name: String,
+}
 
-	#[cfg_attr(feature = "profile-index", flame)]
-	fn push_song(&self, song: NewSong) -> Result<()> {
-		self.song_sender.send(song).map_err(Error::new)
+impl PopulateTarget {
+	fn get_album_art_patterns<'a>(&'a self, updater: &'a IndexUpdater) -> &'a [Regex] {
+		self.album_art_patterns
+			.as_deref()
+			.unwrap_or(&updater.album_art_patterns)
 	}
+}
 
-	#[cfg_attr(feature = "profile-index", flame)]
-	fn push_directory(&self, directory: NewDirectory) -> Result<()> {
-		self.directory_sender.send(directory).map_err(Error::new)
-	}
+type DirectoryInsertion = (
+	NewDirectory,
+	Vec<NewIndexedPlaylist>,
+	Vec<NewIndexedPlaylistSong>,
+);
 
-	fn get_artwork(&self, dir: &Path) -> Result<Option<String>> {
-		for file in fs::read_dir(dir)? {
-			let file = file?;
-			if let Some(name_string) = file.file_name().to_str() {
-				if self.album_art_pattern.is_match(name_string) {
-					return Ok(file.path().to_str().map(|p| p.to_owned()));
-				}
-			}
-		}
-		Ok(None)
-	}
+// A directory waiting for a stepped traversal to visit it
+struct PendingDirectory {
+	parent: Option<PathBuf>,
+	path: PathBuf,
+	target: usize,   // Index of the target the directory belongs to
+	is_target: bool, // Errors of targets interrupt the traversal, others become warnings
+}
 
-	fn populate_directory(&self, parent: Option<&Path>, path: &Path) -> Result<()> {
-		#[cfg(feature = "profile-index")]
-		let _guard = flame::start_guard(format!(
-			"dir: {}",
-			path.file_name()
-				.map(|s| { s.to_string_lossy().into_owned() })
-				.unwrap_or("Unknown".to_owned())
-		));
+// A populate pass, from the connections it writes with to the directories it has yet to visit.
+// `populate_targets` hands the writers to insertion threads and traverses targets in parallel,
+// while an `Indexer` steps through directories and batches one at a time.
+struct Population {
+	settings: MiscSettings,
+	fast_writes: bool,
+	targets: Vec<PopulateTarget>,
+	updater: IndexUpdater,
+	directory_receiver: Receiver<DirectoryInsertion>,
+	song_receiver: Receiver<SongInsertion>,
+	seen_receiver: Option<Receiver<SeenPath>>, // Along with a recorder during merge updates
+	directory_writer: DirectoryWriter,
+	song_writer: SongWriter,
+	seen_recorder: Option<SeenPathRecorder>,
+	atomic_connection: Option<InsertionConnection>,
+	pending: Vec<PendingDirectory>,
+	traversal: Result<(), IndexError>,
+}
 
-		// Find artwork
-		let mut directory_artwork = {
-			#[cfg(feature = "profile-index")]
-			let _guard = flame::start_guard("artwork");
-			self.get_artwork(path).unwrap_or(None)
+impl Population {
+	// Queues are bounded for threaded traversals, so that they wait for insertions to catch up. A
+	// stepped traversal takes rows off its queues itself, and queues a single directory at a time.
+	fn new(
+		db: &DB,
+		targets: Vec<PopulateTarget>,
+		indexed_directories: HashMap<String, IndexedDirectory>,
+		merge: bool,
+		checkpoints: bool,
+		threaded: bool,
+		options: &UpdateOptions,
+	) -> Result<Population, IndexError> {
+		let settings: MiscSettings = {
+			let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+			misc_settings::table.get_result(&connection)?
+		};
+		let buffer_sizes = BufferSizes::new(&settings)?;
+		let write_settings = WriteSettings {
+			checkpoints,
+			..WriteSettings::new(&settings)
 		};
 
-		// Extract path and parent path
-		let parent_string = parent.and_then(|p| p.to_str()).map(|s| s.to_owned());
-		let path_string = path.to_str().ok_or(anyhow!("Invalid directory path"))?;
+		let (existing_songs, existing_directories) = {
+			let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+			let songs: HashSet<String> = songs::table
+				.select(songs::path)
+				.filter(songs::deleted_at.is_null())
+				.load::<String>(&connection)?
+				.into_iter()
+				.collect();
+			let directories: HashSet<String> = directories::table
+				.select(directories::path)
+				.load::<String>(&connection)?
+				.into_iter()
+				.collect();
+			(songs, directories)
+		};
 
-		// Find date added
-		let metadata = {
-			#[cfg(feature = "profile-index")]
-			let _guard = flame::start_guard("metadata");
-			fs::metadata(path_string)?
+		let low_memory = settings.index_low_memory != 0;
+		let ((directory_sender, directory_receiver), (song_sender, song_receiver)) = if threaded {
+			(
+				insertion_channel(low_memory, buffer_sizes),
+				insertion_channel(low_memory, buffer_sizes),
+			)
+		} else {
+			(
+				crossbeam_channel::unbounded(),
+				crossbeam_channel::unbounded(),
+			)
 		};
-		let created = {
-			#[cfg(feature = "profile-index")]
-			let _guard = flame::start_guard("created_date");
-			metadata
-				.created()
-				.or_else(|_| metadata.modified())?
-				.duration_since(time::UNIX_EPOCH)?
-				.as_secs() as i32
+
+		let (seen_sender, seen_receiver, seen_recorder) = if merge {
+			let recorder =
+				SeenPathRecorder::new(db, buffer_sizes).map_err(IndexError::DatabaseConnection)?;
+			let (seen_sender, seen_receiver) = crossbeam_channel::unbounded();
+			(Some(seen_sender), Some(seen_receiver), Some(recorder))
+		} else {
+			(None, None, None)
 		};
 
-		let mut directory_album = None;
-		let mut directory_year = None;
-		let mut directory_artist = None;
-		let mut inconsistent_directory_album = false;
-		let mut inconsistent_directory_year = false;
-		let mut inconsistent_directory_artist = false;
+		// The journal mode is switched before insertions start, as it waits for the writes of other
+		// connections, which an atomic update only commits at its end. Mount points, and the song
+		// files progress is reported against, are read before, since pooled connections opened in
+		// WAL mode would keep the update from leaving it.
+		let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+		let include_patterns = get_mount_include_patterns(db)?;
+		let progress = match options.progress.clone() {
+			Some(sender) => {
+				let songs_total = count_indexable(db, options)?;
+				Some(Arc::new(ProgressReporter::new(sender, songs_total)))
+			}
+			None => None,
+		};
+		if write_settings.fast_writes {
+			let connection = db
+				.connect_unpooled()
+				.map_err(IndexError::DatabaseConnection)?;
+			db::set_wal_journal(&connection, true)?;
+		}
 
-		// Sub directories
-		let mut sub_directories = Vec::new();
-		let mut song_files = Vec::new();
+		let connect =
+			|| InsertionConnection::new(db, write_settings).map_err(IndexError::DatabaseConnection);
+		let atomic_connection = if write_settings.atomic {
+			Some(connect()?)
+		} else {
+			None
+		};
+		let (songs_connection, directories_connection) = match &atomic_connection {
+			Some(connection) => (connection.clone(), connection.clone()),
+			None => (connect()?, connect()?),
+		};
+		let directory_writer = DirectoryWriter::new(
+			directories_connection,
+			buffer_sizes,
+			existing_directories,
+			write_settings,
+			options.clone(),
+		);
+		let song_writer = SongWriter::new(
+			songs_connection,
+			buffer_sizes,
+			existing_songs,
+			progress.clone(),
+			write_settings,
+			options.clone(),
+		);
 
-		let files = match fs::read_dir(path) {
-			Ok(files) => files,
-			Err(e) => {
-				error!("Directory read error for `{}`: {}", path.display(), e);
-				return Err(e.into());
-			}
+		let senders = UpdateSenders {
+			directories: directory_sender,
+			songs: song_sender,
+			seen: seen_sender,
 		};
+		let updater = IndexUpdater::new(
+			&settings,
+			vfs,
+			include_patterns,
+			indexed_directories,
+			senders,
+			progress,
+			options,
+		)?;
 
-		// Insert content
-		for file in files {
-			let file_path = match file {
-				Ok(ref f) => f.path(),
-				Err(e) => {
-					error!("File read error within `{}`: {}", path_string, e);
-					break;
-				}
-			};
+		let pending = targets
+			.iter()
+			.enumerate()
+			.rev()
+			.map(|(index, target)| PendingDirectory {
+				parent: target.parent.clone(),
+				path: target.path.clone(),
+				target: index,
+				is_target: true,
+			})
+			.collect();
 
-			#[cfg(feature = "profile-index")]
-			let _guard = flame::start_guard(format!(
-				"file: {}",
-				file_path
-					.as_path()
-					.file_name()
-					.map(|s| { s.to_string_lossy().into_owned() })
-					.unwrap_or("Unknown".to_owned())
-			));
-
-			if file_path.is_dir() {
-				sub_directories.push(file_path.to_path_buf());
-				continue;
-			}
+		Ok(Population {
+			fast_writes: write_settings.fast_writes,
+			settings,
+			targets,
+			updater,
+			directory_receiver,
+			song_receiver,
+			seen_receiver,
+			directory_writer,
+			song_writer,
+			seen_recorder,
+			atomic_connection,
+			pending,
+			traversal: Ok(()),
+		})
+	}
 
-			song_files.push(file_path);
+	// The traversal only goes on while no batch is waiting to be written
+	fn phase(&self) -> IndexPhase {
+		let seen_full = self.seen_recorder.as_ref().map_or(false, |r| r.is_full());
+		if self.pending.is_empty()
+			|| self.song_writer.is_full()
+			|| self.directory_writer.is_full()
+			|| seen_full
+		{
+			IndexPhase::Writing
+		} else {
+			IndexPhase::Scanning
 		}
+	}
 
-		let song_metadata = |path: PathBuf| -> Option<(String, SongTags)> {
-			#[cfg(feature = "profile-index")]
-			let _guard = flame::start_guard("song_metadata");
+	fn is_written(&self) -> bool {
+		self.pending.is_empty()
+			&& self.song_receiver.is_empty()
+			&& self.directory_receiver.is_empty()
+			&& self.seen_receiver.as_ref().map_or(true, |r| r.is_empty())
+			&& self.song_writer.is_empty()
+			&& self.directory_writer.is_empty()
+			&& self.seen_recorder.as_ref().map_or(true, |r| r.is_empty())
+	}
 
-			path.to_str().and_then(|file_path_string| {
-				metadata::read(&path).map(|m| (file_path_string.to_owned(), m))
-			})
-		};
-		let song_tags = song_files
-			.into_par_iter()
-			.filter_map(song_metadata)
-			.collect::<Vec<_>>();
+	fn songs_added(&self) -> usize {
+		self.song_writer.added().count
+	}
 
-		if directory_artwork.is_none() {
-			directory_artwork = song_tags
-				.iter()
-				.find(|(_, t)| t.has_artwork)
-				.map(|(p, _)| p.to_owned());
-		}
+	fn directories_added(&self) -> usize {
+		self.directory_writer.added().count
+	}
 
-		for (file_path_string, tags) in song_tags {
-			if tags.year.is_some() {
-				inconsistent_directory_year |=
-					directory_year.is_some() && directory_year != tags.year;
-				directory_year = tags.year;
+	// Visits the next pending directory, whose sub-directories are visited before its siblings
+	fn scan_directory(&mut self) {
+		let directory = match self.pending.pop() {
+			Some(directory) => directory,
+			None => return,
+		};
+		let updater = &self.updater;
+		let target = &self.targets[directory.target];
+		let album_art_patterns = target.get_album_art_patterns(updater);
+		let index = || {
+			updater.index_directory(
+				directory.parent.as_deref(),
+				&directory.path,
+				album_art_patterns,
+				&target.mount_name,
+			)
+		};
+		let result = if directory.is_target {
+			updater
+				.check_mount_point(&directory.path)
+				.and_then(|_| index().map_err(IndexError::from))
+		} else {
+			updater
+				.check_sub_directory(&directory.path, index())
+				.map(Option::unwrap_or_default)
+				.map_err(IndexError::from)
+		};
+		match result {
+			Ok(sub_directories) => {
+				self.pending
+					.extend(
+						sub_directories
+							.into_iter()
+							.rev()
+							.map(|path| PendingDirectory {
+								parent: Some(directory.path.clone()),
+								path,
+								target: directory.target,
+								is_target: false,
+							}),
+					)
 			}
-
-			if tags.album.is_some() {
-				inconsistent_directory_album |=
-					directory_album.is_some() && directory_album != tags.album;
-				directory_album = tags.album.as_ref().cloned();
+			Err(e) => {
+				self.pending.clear();
+				self.traversal = Err(e);
 			}
+		}
+		self.fill_batches();
+	}
 
-			if tags.album_artist.is_some() {
-				inconsistent_directory_artist |=
-					directory_artist.is_some() && directory_artist != tags.album_artist;
-				directory_artist = tags.album_artist.as_ref().cloned();
-			} else if tags.artist.is_some() {
-				inconsistent_directory_artist |=
-					directory_artist.is_some() && directory_artist != tags.artist;
-				directory_artist = tags.artist.as_ref().cloned();
+	// Writes a full batch, or any batch once the traversal is over
+	fn write_batch(&mut self) {
+		let scanned = self.pending.is_empty();
+		if self.song_writer.is_full() || (scanned && !self.song_writer.is_empty()) {
+			self.song_writer.flush();
+		} else if self.directory_writer.is_full() || (scanned && !self.directory_writer.is_empty())
+		{
+			self.directory_writer.flush();
+		} else if let Some(recorder) = &mut self.seen_recorder {
+			if recorder.is_full() || (scanned && !recorder.is_empty()) {
+				recorder.flush();
 			}
-
-			let artwork_path = if tags.has_artwork {
-				Some(file_path_string.to_owned())
-			} else {
-				directory_artwork.as_ref().cloned()
-			};
-
-			let song = NewSong {
-				path: file_path_string.to_owned(),
-				parent: path_string.to_owned(),
-				disc_number: tags.disc_number.map(|n| n as i32),
-				track_number: tags.track_number.map(|n| n as i32),
-				title: tags.title,
-				duration: tags.duration.map(|n| n as i32),
-				artist: tags.artist,
-				album_artist: tags.album_artist,
-				album: tags.album,
-				year: tags.year,
-				artwork: artwork_path,
-			};
-
-			self.push_song(song)?;
 		}
+		self.fill_batches();
+	}
 
-		// Insert directory
-		let directory = {
-			if inconsistent_directory_year {
-				directory_year = None;
+	// Rows are taken off the queues until a batch is full, the others wait for the next batch
+	fn fill_batches(&mut self) {
+		while !self.song_writer.is_full() {
+			match self.song_receiver.try_recv() {
+				Ok(insertion) => self.song_writer.push(insertion),
+				Err(_) => break,
 			}
-			if inconsistent_directory_album {
-				directory_album = None;
+		}
+		while !self.directory_writer.is_full() {
+			match self.directory_receiver.try_recv() {
+				Ok((directory, playlists, playlist_songs)) => {
+					self.directory_writer
+						.push(directory, playlists, playlist_songs)
+				}
+				Err(_) => break,
 			}
-			if inconsistent_directory_artist {
-				directory_artist = None;
+		}
+		if let (Some(receiver), Some(recorder)) = (&self.seen_receiver, &mut self.seen_recorder) {
+			while !recorder.is_full() {
+				match receiver.try_recv() {
+					Ok(seen_path) => recorder.push(seen_path),
+					Err(_) => break,
+				}
 			}
+		}
+	}
 
-			NewDirectory {
-				path: path_string.to_owned(),
-				parent: parent_string,
-				artwork: directory_artwork,
-				album: directory_album,
-				artist: directory_artist,
-				year: directory_year,
-				date_added: created,
-			}
+	// Ends the insertions of a stepped traversal, once everything was written
+	fn finish(self, options: &UpdateOptions) -> Result<(UpdateSummary, PopulationEnd), IndexError> {
+		let mut summary = self.updater.into_summary();
+		let complete = self.traversal.is_ok() && !options.is_cancelled();
+		end_insertions(
+			&mut summary,
+			self.directory_writer.finish(),
+			self.song_writer.finish(),
+			self.atomic_connection,
+			complete,
+		)?;
+		let seen_connection = self
+			.seen_recorder
+			.and_then(|recorder| match recorder.finish() {
+				Ok(connection) => Some(connection),
+				Err(e) => {
+					error!("Could not record seen paths: {}", e);
+					None
+				}
+			});
+		let end = PopulationEnd {
+			settings: self.settings,
+			fast_writes: self.fast_writes,
+			traversal: self.traversal,
+			seen_connection,
 		};
+		Ok((summary, end))
+	}
+}
 
-		self.push_directory(directory)?;
-
-		// Populate subdirectories
-		sub_directories
-			.into_par_iter()
-			.map(|sub_directory| self.populate_directory(Some(path), &sub_directory))
-			.collect() // propagate an error to the caller if one of them failed
+// Records the rows written by a populate pass. Nothing from an atomic update is kept unless it was
+// fully written.
+fn end_insertions(
+	summary: &mut UpdateSummary,
+	(directories_added, directories_inserted): (PathChanges, usize),
+	(songs_added, songs_inserted): (PathChanges, usize),
+	atomic_connection: Option<InsertionConnection>,
+	complete: bool,
+) -> Result<(), IndexError> {
+	summary.directories_added = directories_added;
+	summary.stats.directories_inserted = directories_inserted;
+	summary.songs_added = songs_added;
+	summary.stats.songs_inserted = songs_inserted;
+	if let Some(atomic_connection) = atomic_connection {
+		if !atomic_connection.end_atomic(complete)? {
+			summary.songs_added = PathChanges::default();
+			summary.directories_added = PathChanges::default();
+			summary.stats.songs_inserted = 0;
+			summary.stats.directories_inserted = 0;
+			if complete {
+				return Err(IndexError::RolledBack);
+			}
+		}
 	}
+	Ok(())
 }
 
-#[cfg_attr(feature = "profile-index", flame)]
-pub fn clean(db: &DB) -> Result<()> {
-	let vfs = db.get_vfs()?;
+// What remains of a populate pass once its insertions ended
+struct PopulationEnd {
+	settings: MiscSettings,
+	fast_writes: bool,
+	traversal: Result<(), IndexError>,
+	seen_connection: Option<SqliteConnection>, // Holding the paths seen by a merge update
+}
 
-	{
-		let all_songs: Vec<String>;
-		{
-			let connection = db.connect()?;
-			all_songs = songs::table.select(songs::path).load(&connection)?;
+impl PopulationEnd {
+	// Entries are only removed after a complete traversal, as they would otherwise not all be seen
+	fn take_unseen_removal(
+		&mut self,
+		db: &DB,
+		options: &UpdateOptions,
+	) -> Result<Option<UnseenRemoval>, IndexError> {
+		match self.seen_connection.take() {
+			Some(connection) if self.traversal.is_ok() && !options.is_cancelled() => Ok(Some(
+				UnseenRemoval::new(db, connection, &self.settings, options)?,
+			)),
+			_ => Ok(None),
 		}
+	}
 
-		let missing_songs = all_songs
-			.par_iter()
-			.filter(|ref song_path| {
-				let path = Path::new(&song_path);
-				!path.exists() || vfs.real_to_virtual(path).is_err()
-			})
-			.collect::<Vec<_>>();
+	fn complete(
+		mut self,
+		db: &DB,
+		summary: &mut UpdateSummary,
+		options: &UpdateOptions,
+	) -> Result<(), IndexError> {
+		if let Some(mut removal) = self.take_unseen_removal(db, options)? {
+			while removal.step(db, &self.settings, summary, options)? {}
+		}
 
-		{
-			let connection = db.connect()?;
-			for chunk in missing_songs[..].chunks(INDEX_BUILDING_CLEAN_BUFFER_SIZE) {
-				diesel::delete(songs::table.filter(songs::path.eq_any(chunk)))
-					.execute(&connection)?;
+		// Leaving WAL mode fails while other connections still use it, in which case the next
+		// update tries again
+		if self.fast_writes {
+			let connection = db
+				.connect_unpooled()
+				.map_err(IndexError::DatabaseConnection)?;
+			if let Err(e) = db::set_wal_journal(&connection, false) {
+				error!("Could not restore database journal mode: {}", e);
 			}
 		}
-	}
 
-	{
-		let all_directories: Vec<String>;
-		{
-			let connection = db.connect()?;
-			all_directories = directories::table
-				.select(directories::path)
-				.load(&connection)?;
+		if !options.dry_run {
+			let connection = db.connect().map_err(IndexError::DatabaseConnection)?;
+			flag_missing_playlist_songs(&connection)?;
+			merge_disc_directories(&connection, &self.settings)?;
+			classify_artist_directories(&connection)?;
+			group_albums(&connection, &self.settings)?;
 		}
 
-		let missing_directories = all_directories
-			.par_iter()
-			.filter(|ref directory_path| {
-				let path = Path::new(&directory_path);
-				!path.exists() || vfs.real_to_virtual(path).is_err()
+		// Traversal errors caused by a cancellation are reported as such
+		options.check_cancellation()?;
+		self.traversal
+	}
+}
+
+fn populate_targets(
+	db: &DB,
+	targets: Vec<PopulateTarget>,
+	indexed_directories: HashMap<String, IndexedDirectory>,
+	merge: bool,
+	checkpoints: bool,
+	options: &UpdateOptions,
+) -> Result<UpdateSummary, IndexError> {
+	let Population {
+		settings,
+		fast_writes,
+		targets,
+		updater,
+		directory_receiver,
+		song_receiver,
+		seen_receiver,
+		directory_writer,
+		song_writer,
+		seen_recorder,
+		atomic_connection,
+		..
+	} = Population::new(
+		db,
+		targets,
+		indexed_directories,
+		merge,
+		checkpoints,
+		true,
+		options,
+	)?;
+
+	// A thread count of zero lets rayon pick one thread per logical CPU
+	let thread_count = settings.index_thread_count.unwrap_or(0).max(0) as usize;
+	let thread_pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(thread_count)
+		.build()
+		.map_err(Error::new)?;
+
+	let directories_thread =
+		std::thread::spawn(move || insert_directories(directory_receiver, directory_writer));
+	let songs_thread = std::thread::spawn(move || insert_songs(song_receiver, song_writer));
+	let seen_thread = match (seen_receiver, seen_recorder) {
+		(Some(receiver), Some(recorder)) => Some(std::thread::spawn(move || {
+			record_seen_paths(receiver, recorder)
+		})),
+		_ => None,
+	};
+
+	let traversal = thread_pool.install(|| {
+		targets
+			.iter()
+			.par_bridge()
+			.map(|target| {
+				updater.check_mount_point(&target.path)?;
+				updater
+					.populate_directory(
+						target.parent.as_deref(),
+						&target.path,
+						target.get_album_art_patterns(&updater),
+						&target.mount_name,
+					)
+					.map_err(IndexError::from)
 			})
-			.collect::<Vec<_>>();
+			.collect::<Result<(), IndexError>>()
+	});
+	// The insertion threads stop once the updater drops its senders
+	let mut summary = updater.into_summary();
 
-		{
-			let connection = db.connect()?;
-			for chunk in missing_directories[..].chunks(INDEX_BUILDING_CLEAN_BUFFER_SIZE) {
-				diesel::delete(directories::table.filter(directories::path.eq_any(chunk)))
-					.execute(&connection)?;
-			}
+	let directories = directories_thread.join().unwrap_or_else(|e| {
+		error!(
+			"Error while waiting for directory insertions to complete: {:?}",
+			e
+		);
+		Default::default()
+	});
+	let songs = songs_thread.join().unwrap_or_else(|e| {
+		error!(
+			"Error while waiting for song insertions to complete: {:?}",
+			e
+		);
+		Default::default()
+	});
+	let complete = traversal.is_ok() && !options.is_cancelled();
+	end_insertions(
+		&mut summary,
+		directories,
+		songs,
+		atomic_connection,
+		complete,
+	)?;
+
+	let seen_connection = seen_thread.and_then(|seen_thread| match seen_thread.join() {
+		Err(e) => {
+			error!("Error while waiting for seen paths to be recorded: {:?}", e);
+			None
 		}
-	}
+		Ok(Err(e)) => {
+			error!("Could not record seen paths: {}", e);
+			None
+		}
+		Ok(Ok(connection)) => Some(connection),
+	});
+	let end = PopulationEnd {
+		settings,
+		fast_writes,
+		traversal,
+		seen_connection,
+	};
+	end.complete(db, &mut summary, options)?;
+	Ok(summary)
+}
 
-	Ok(())
+// Temporary tables only exist for the connection which created them
+table! {
+	seen_songs (path) {
+		path -> Text,
+		read -> Bool,
+	}
 }
 
-#[cfg_attr(feature = "profile-index", flame)]
-pub fn populate(db: &DB) -> Result<()> {
-	let vfs = db.get_vfs()?;
-	let mount_points = vfs.get_mount_points();
+table! {
+	seen_directories (path) {
+		path -> Text,
+	}
+}
 
-	let album_art_pattern = {
-		let connection = db.connect()?;
-		let settings: MiscSettings = misc_settings::table.get_result(&connection)?;
-		Regex::new(&settings.index_album_art_pattern)?
-	};
+fn connect_for_seen_paths(db: &DB) -> Result<SqliteConnection> {
+	let connection = db.connect_unpooled()?;
+	connection.batch_execute(
+		"CREATE TEMPORARY TABLE seen_songs (path TEXT PRIMARY KEY NOT NULL, read BOOLEAN NOT NULL);
+		CREATE TEMPORARY TABLE seen_directories (path TEXT PRIMARY KEY NOT NULL);",
+	)?;
+	Ok(connection)
+}
 
-	let (directory_sender, directory_receiver) = crossbeam_channel::unbounded();
-	let (song_sender, song_receiver) = crossbeam_channel::unbounded();
+fn flush_seen_paths(
+	connection: &SqliteConnection,
+	songs: &[(String, bool)],
+	directories: &[String],
+) -> QueryResult<()> {
+	connection.transaction(|| {
+		diesel::insert_or_ignore_into(seen_songs::table)
+			.values(
+				songs
+					.iter()
+					.map(|(p, read)| (seen_songs::path.eq(p), seen_songs::read.eq(read)))
+					.collect::<Vec<_>>(),
+			)
+			.execute(connection)?;
+		diesel::insert_or_ignore_into(seen_directories::table)
+			.values(
+				directories
+					.iter()
+					.map(|p| seen_directories::path.eq(p))
+					.collect::<Vec<_>>(),
+			)
+			.execute(connection)?;
+		Ok(())
+	})
+}
 
-	let songs_db = db.clone();
-	let directories_db = db.clone();
+// Seen paths are written to the database rather than kept in memory, so that merge updates scale
+// to libraries of any size. Paths received after a failed write are dropped, as the set of seen
+// paths is incomplete by then.
+struct SeenPathRecorder {
+	connection: SqliteConnection,
+	buffer_sizes: BufferSizes,
+	songs: Vec<(String, bool)>,
+	directories: Vec<String>,
+	result: QueryResult<()>,
+}
 
-	let directories_thread = std::thread::spawn(move || {
-		insert_directories(directory_receiver, directories_db);
-	});
+impl SeenPathRecorder {
+	fn new(db: &DB, buffer_sizes: BufferSizes) -> Result<SeenPathRecorder> {
+		Ok(SeenPathRecorder {
+			connection: connect_for_seen_paths(db)?,
+			buffer_sizes,
+			songs: Vec::new(),
+			directories: Vec::new(),
+			result: Ok(()),
+		})
+	}
 
-	let songs_thread = std::thread::spawn(move || {
-		insert_songs(song_receiver, songs_db);
-	});
+	fn push(&mut self, seen_path: SeenPath) {
+		match seen_path {
+			SeenPath::Song(path) => self.songs.push((path, false)),
+			SeenPath::ReadSong(path) => self.songs.push((path, true)),
+			SeenPath::Directory(path) => self.directories.push(path),
+		}
+	}
 
-	{
-		let updater = IndexUpdater::new(album_art_pattern, directory_sender, song_sender)?;
-		let mount_points = mount_points.values().collect::<Vec<_>>();
-		mount_points
-			.iter()
-			.par_bridge()
-			.map(|target| updater.populate_directory(None, target.as_path()))
-			.collect::<Result<()>>()?;
+	fn is_full(&self) -> bool {
+		self.songs.len() + self.directories.len() >= self.buffer_sizes.insert
 	}
 
-	match directories_thread.join() {
-		Err(e) => error!(
-			"Error while waiting for directory insertions to complete: {:?}",
-			e
-		),
-		_ => (),
+	fn is_empty(&self) -> bool {
+		self.songs.is_empty() && self.directories.is_empty()
 	}
 
-	match songs_thread.join() {
-		Err(e) => error!(
-			"Error while waiting for song insertions to complete: {:?}",
-			e
-		),
-		_ => (),
+	fn flush(&mut self) {
+		if self.result.is_ok() {
+			self.result = flush_seen_paths(&self.connection, &self.songs, &self.directories);
+		}
+		self.songs.clear();
+		self.directories.clear();
 	}
 
-	Ok(())
+	// Returns the connection holding the seen paths
+	fn finish(mut self) -> Result<SqliteConnection> {
+		self.flush();
+		let connection = self.connection;
+		self.result.map(|_| connection).map_err(Error::new)
+	}
 }
 
-fn flush_directories(db: &DB, entries: &Vec<NewDirectory>) {
-	if db
-		.connect()
-		.and_then(|connection| {
-			diesel::insert_into(directories::table)
-				.values(entries)
-				.execute(&*connection) // TODO https://github.com/diesel-rs/diesel/issues/1822
-				.map_err(Error::new)
-		})
-		.is_err()
-	{
-		error!("Could not insert new directories in database");
+fn record_seen_paths(
+	receiver: Receiver<SeenPath>,
+	mut recorder: SeenPathRecorder,
+) -> Result<SqliteConnection> {
+	for seen_path in receiver {
+		recorder.push(seen_path);
+		if recorder.is_full() {
+			recorder.flush();
+		}
 	}
+	recorder.finish()
+}
+
+// Unseen entries are compared against the file system in chunks, on the connection holding the
+// seen paths. Entries which still exist, such as the contents of a directory which could not be
+// read, are kept like a clean pass would.
+struct UnseenRemoval {
+	connection: SqliteConnection,
+	vfs: VFS,
+	buffer_sizes: BufferSizes,
+	deleted_at: Option<i32>,
+	marked_directories: HashSet<PathBuf>,
+	stage: UnseenStage,
+	songs_deleted: usize,
 }
 
-fn flush_songs(db: &DB, entries: &Vec<NewSong>) {
-	if db
-		.connect()
-		.and_then(|connection| {
-			diesel::insert_into(songs::table)
-				.values(entries)
-				.execute(&*connection) // TODO https://github.com/diesel-rs/diesel/issues/1822
-				.map_err(Error::new)
+// Songs are removed first, then claimed by the songs read in their place, then directories are
+// removed. Each stage resumes after the last entry it went through.
+enum UnseenStage {
+	Songs(i32),
+	Renames(String),
+	Directories(i32),
+	Done,
+}
+
+impl UnseenRemoval {
+	fn new(
+		db: &DB,
+		connection: SqliteConnection,
+		settings: &MiscSettings,
+		options: &UpdateOptions,
+	) -> Result<UnseenRemoval, IndexError> {
+		let vfs = db.get_vfs().map_err(IndexError::VFS)?;
+		// Marked and filtered directories were skipped during the traversal, so they are among the
+		// unseen ones
+		let marked_directories =
+			if get_ignore_marker(settings).is_some() || options.directory_filter.is_some() {
+				let unseen_directories: Vec<String> = directories::table
+					.select(directories::path)
+					.filter(
+						directories::path
+							.ne_all(seen_directories::table.select(seen_directories::path)),
+					)
+					.load(&connection)?;
+				find_excluded_directories(&vfs, settings, options, &unseen_directories)
+			} else {
+				HashSet::new()
+			};
+		Ok(UnseenRemoval {
+			connection,
+			vfs,
+			buffer_sizes: BufferSizes::new(settings)?,
+			deleted_at: get_soft_delete_time(settings)?,
+			marked_directories,
+			stage: UnseenStage::Songs(i32::MIN),
+			songs_deleted: 0,
 		})
-		.is_err()
-	{
-		error!("Could not insert new songs in database");
 	}
-}
 
-fn insert_directories(receiver: Receiver<NewDirectory>, db: DB) {
-	let mut new_entries = Vec::new();
-	new_entries.reserve_exact(INDEX_BUILDING_INSERT_BUFFER_SIZE);
+	// Goes through the next chunk of entries, and returns whether entries remain
+	fn step(
+		&mut self,
+		db: &DB,
+		settings: &MiscSettings,
+		summary: &mut UpdateSummary,
+		options: &UpdateOptions,
+	) -> Result<bool, IndexError> {
+		options.check_cancellation()?;
+		let path_existence: &dyn PathExistence = match &options.path_existence {
+			Some(path_existence) => path_existence.as_ref(),
+			None => &FileSystemExistence,
+		};
+		let retention = Retention::new(
+			db,
+			&self.vfs,
+			settings,
+			&self.marked_directories,
+			path_existence,
+		)?;
+		let connection = &self.connection;
+		let chunk_size = self.buffer_sizes.clean as i64;
 
-	loop {
-		match receiver.recv() {
-			Ok(s) => {
-				new_entries.push(s);
-				if new_entries.len() >= INDEX_BUILDING_INSERT_BUFFER_SIZE {
-					flush_directories(&db, &new_entries);
-					new_entries.clear();
+		self.stage = match &self.stage {
+			UnseenStage::Songs(last_id) => {
+				// Resolved songs of unchanged directories are seen without their target being
+				// checked
+				let candidate_songs: Vec<(i32, String, Option<String>)> = songs::table
+					.select((songs::id, songs::path, songs::link_target))
+					.filter(songs::id.gt(last_id))
+					.filter(songs::deleted_at.is_null())
+					.filter(
+						songs::path
+							.ne_all(seen_songs::table.select(seen_songs::path))
+							.or(songs::link_target.is_not_null()),
+					)
+					.order(songs::id)
+					.limit(chunk_size)
+					.load(connection)?;
+				match candidate_songs.last() {
+					Some((id, _, _)) => {
+						let missing_songs = candidate_songs
+							.iter()
+							.filter(|(_, path, link_target)| {
+								retention.is_song_missing(Path::new(path), link_target.as_deref())
+							})
+							.map(|(_, path, _)| path)
+							.collect::<Vec<_>>();
+						for song_path in &missing_songs {
+							summary.songs_removed.record(song_path, options.dry_run);
+						}
+						if !options.dry_run {
+							let deleted = remove_songs(
+								connection,
+								settings,
+								&missing_songs,
+								self.deleted_at,
+							)?;
+							summary.stats.songs_deleted += deleted;
+							self.songs_deleted += deleted;
+						}
+						UnseenStage::Songs(*id)
+					}
+					// The songs read during the traversal were written before the removed ones
+					// were kept aside
+					None if self.songs_deleted > 0 && settings.index_detect_renames != 0 => {
+						UnseenStage::Renames(String::new())
+					}
+					None => UnseenStage::Directories(i32::MIN),
 				}
 			}
-			Err(_) => break,
-		}
+			UnseenStage::Renames(last_path) => {
+				let read_songs: Vec<String> = seen_songs::table
+					.select(seen_songs::path)
+					.filter(seen_songs::read.eq(true))
+					.filter(seen_songs::path.gt(last_path))
+					.order(seen_songs::path)
+					.limit(chunk_size)
+					.load(connection)?;
+				match read_songs.last() {
+					Some(path) => {
+						claim_removed_songs_at(connection, &read_songs)?;
+						UnseenStage::Renames(path.clone())
+					}
+					None => UnseenStage::Directories(i32::MIN),
+				}
+			}
+			UnseenStage::Directories(last_id) => {
+				let unseen_directories: Vec<(i32, String)> = directories::table
+					.select((directories::id, directories::path))
+					.filter(directories::id.gt(last_id))
+					.filter(
+						directories::path
+							.ne_all(seen_directories::table.select(seen_directories::path)),
+					)
+					.order(directories::id)
+					.limit(chunk_size)
+					.load(connection)?;
+				match unseen_directories.last() {
+					Some((id, _)) => {
+						let missing_directories = unseen_directories
+							.iter()
+							.map(|(_, path)| path)
+							.filter(|path| retention.is_directory_missing(Path::new(path)))
+							.collect::<Vec<_>>();
+						for directory_path in &missing_directories {
+							summary
+								.directories_removed
+								.record(directory_path, options.dry_run);
+						}
+						if !options.dry_run {
+							summary.stats.directories_deleted += diesel::delete(
+								directories::table
+									.filter(directories::path.eq_any(&missing_directories)),
+							)
+							.execute(connection)?;
+							delete_playlists(connection, &missing_directories)?;
+						}
+						UnseenStage::Directories(*id)
+					}
+					None => UnseenStage::Done,
+				}
+			}
+			UnseenStage::Done => UnseenStage::Done,
+		};
+		Ok(!matches!(self.stage, UnseenStage::Done))
 	}
+}
 
-	if new_entries.len() > 0 {
-		flush_directories(&db, &new_entries);
+fn get_soft_delete_time(settings: &MiscSettings) -> Result<Option<i32>, IndexError> {
+	if settings.index_soft_delete == 0 {
+		return Ok(None);
 	}
+	let now = time::SystemTime::now()
+		.duration_since(time::UNIX_EPOCH)
+		.map_err(Error::new)?;
+	Ok(Some(now.as_secs() as i32))
 }
 
-fn insert_songs(receiver: Receiver<NewSong>, db: DB) {
-	let mut new_entries = Vec::new();
-	new_entries.reserve_exact(INDEX_BUILDING_INSERT_BUFFER_SIZE);
+#[derive(Queryable)]
+struct DiscSong {
+	album: Option<String>,
+	album_artist: Option<String>,
+	artist: Option<String>,
+	year: Option<i32>,
+	original_date: Option<String>,
+	genre: Option<String>,
+	composer: Option<String>,
+	duration: Option<i32>,
+}
 
-	loop {
-		match receiver.recv() {
-			Ok(s) => {
-				new_entries.push(s);
-				if new_entries.len() >= INDEX_BUILDING_INSERT_BUFFER_SIZE {
-					flush_songs(&db, &new_entries);
-					new_entries.clear();
-				}
-			}
-			Err(_) => break,
+// Value shared by all the given values, compared without case and displayed with the most common
+// casing
+fn get_consistent_value<'a, I>(values: I) -> Option<String>
+where
+	I: Iterator<Item = &'a String>,
+{
+	let mut casings = CasingTally::default();
+	let mut key: Option<String> = None;
+	for value in values {
+		let value_key = value.to_lowercase();
+		if key.as_ref().map_or(false, |k| *k != value_key) {
+			return None;
 		}
+		key = Some(value_key);
+		casings.add(value);
 	}
+	casings.most_common()
+}
 
-	if new_entries.len() > 0 {
-		flush_songs(&db, &new_entries);
+// Albums split into disc sub-directories are listed once, through the directory holding the discs.
+// It is described by the songs of all its discs, which no longer count as albums on their own.
+// This is computed again from the songs after every update, as discs can be indexed on their own.
+fn merge_disc_directories(
+	connection: &SqliteConnection,
+	settings: &MiscSettings,
+) -> Result<(), IndexError> {
+	let disc_folder_pattern = match get_disc_folder_pattern(settings)? {
+		Some(pattern) => pattern,
+		None => return Ok(()),
+	};
+	let sort_articles = get_sort_articles(settings);
+
+	let mut discs: BTreeMap<String, Vec<(String, Option<String>)>> = BTreeMap::new();
+	for (path, parent, artwork) in directories::table
+		.select((directories::path, directories::parent, directories::artwork))
+		.filter(directories::parent.is_not_null())
+		.order(directories::path)
+		.load::<(String, Option<String>, Option<String>)>(connection)?
+	{
+		let is_disc = Path::new(&path)
+			.file_name()
+			.and_then(|n| n.to_str())
+			.map_or(false, |n| disc_folder_pattern.is_match(n));
+		if let (true, Some(parent)) = (is_disc, parent) {
+			discs.entry(parent).or_default().push((path, artwork));
+		}
 	}
+	if discs.is_empty() {
+		return Ok(());
+	}
+
+	connection.transaction::<_, diesel::result::Error, _>(|| {
+		for (parent, discs) in &discs {
+			let disc_paths = discs.iter().map(|(path, _)| path).collect::<Vec<_>>();
+			let songs: Vec<DiscSong> = songs::table
+				.select((
+					songs::album,
+					songs::album_artist,
+					songs::artist,
+					songs::year,
+					songs::original_date,
+					songs::genre,
+					songs::composer,
+					songs::duration,
+				))
+				.filter(
+					songs::parent
+						.eq(parent)
+						.or(songs::parent.eq_any(&disc_paths)),
+				)
+				.filter(songs::deleted_at.is_null())
+				.load(connection)?;
+
+			let album = get_consistent_value(songs.iter().filter_map(|s| s.album.as_ref()));
+			let artist = get_consistent_value(
+				songs
+					.iter()
+					.filter_map(|s| s.album_artist.as_ref().or_else(|| s.artist.as_ref())),
+			);
+			let mut years = songs.iter().filter_map(|s| s.year);
+			let first_year = years.next();
+			let year = first_year.filter(|y| years.all(|other| other == *y));
+			let mut dates = songs.iter().filter_map(|s| s.original_date.as_deref());
+			let original_date = dates
+				.next()
+				.and_then(|first| dates.try_fold(first, most_precise_date))
+				.map(|d| d.to_owned());
+			let genre = get_consistent_value(songs.iter().filter_map(|s| s.genre.as_ref()));
+			let composer = get_consistent_value(songs.iter().filter_map(|s| s.composer.as_ref()));
+			let total_duration: i32 = songs.iter().filter_map(|s| s.duration).sum();
+			let sort_album = album
+				.as_deref()
+				.map(|a| metadata::sort_key(a, &sort_articles));
+			let sort_artist = artist
+				.as_deref()
+				.map(|a| metadata::sort_key(a, &sort_articles));
+			let album_key = metadata::album_key(artist.as_deref(), album.as_deref());
+			let disc_artwork = discs.iter().find_map(|(_, artwork)| artwork.clone());
+			let kind = if !songs.is_empty() && album.is_some() {
+				DIRECTORY_KIND_ALBUM
+			} else {
+				DIRECTORY_KIND_OTHER
+			};
+
+			diesel::update(directories::table.filter(directories::path.eq(parent)))
+				.set((
+					directories::album.eq(album),
+					directories::sort_album.eq(sort_album),
+					directories::artist.eq(artist),
+					directories::sort_artist.eq(sort_artist),
+					directories::year.eq(year),
+					directories::original_date.eq(original_date),
+					directories::genre.eq(genre),
+					directories::composer.eq(composer),
+					directories::song_count.eq(songs.len() as i32),
+					directories::total_duration.eq(total_duration),
+					directories::kind.eq(kind),
+					directories::album_key.eq(album_key),
+				))
+				.execute(connection)?;
+			diesel::update(
+				directories::table
+					.filter(directories::path.eq(parent))
+					.filter(directories::artwork.is_null()),
+			)
+			.set(directories::artwork.eq(disc_artwork))
+			.execute(connection)?;
+			diesel::update(directories::table.filter(directories::path.eq_any(&disc_paths)))
+				.set((
+					directories::album.eq(None::<String>),
+					directories::sort_album.eq(None::<String>),
+					directories::kind.eq(DIRECTORY_KIND_OTHER),
+					directories::album_key.eq(None::<String>),
+				))
+				.execute(connection)?;
+		}
+		Ok(())
+	})?;
+	Ok(())
+}
+
+// Directories without songs of their own are artists when all of their subdirectories are albums.
+// Every update re-evaluates them since any subdirectory may have changed.
+fn classify_artist_directories(connection: &SqliteConnection) -> diesel::QueryResult<()> {
+	connection.transaction(|| {
+		diesel::update(directories::table.filter(directories::kind.eq(DIRECTORY_KIND_ARTIST)))
+			.set(directories::kind.eq(DIRECTORY_KIND_OTHER))
+			.execute(connection)?;
+		diesel::sql_query(format!(
+			"UPDATE directories SET kind = '{artist}' WHERE song_count = 0 \
+			AND EXISTS (SELECT 1 FROM directories AS child WHERE child.parent = directories.path) \
+			AND NOT EXISTS (SELECT 1 FROM directories AS child WHERE child.parent = directories.path \
+			AND (child.kind IS NULL OR child.kind != '{album}'))",
+			artist = DIRECTORY_KIND_ARTIST,
+			album = DIRECTORY_KIND_ALBUM,
+		))
+		.execute(connection)?;
+		Ok(())
+	})
+}
+
+#[derive(Debug, Queryable)]
+struct AlbumSong {
+	parent: String,
+	musicbrainz_album_id: Option<String>,
+	album: Option<String>,
+	album_artist: Option<String>,
+	artist: Option<String>,
+	year: Option<i32>,
+	artwork: Option<String>,
+	duration: Option<i32>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "albums"]
+struct NewAlbum {
+	musicbrainz_album_id: Option<String>,
+	directory: Option<String>,
+	name: Option<String>,
+	artist: Option<String>,
+	year: Option<i32>,
+	artwork: Option<String>,
+	song_count: i32,
+	total_duration: i32,
+}
+
+// Songs tagged with a MusicBrainz release belong to its album, whichever directories hold them.
+// Other songs with an album name are grouped by directory.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum AlbumKey {
+	MusicBrainz(String),
+	Directory(String),
+}
+
+// Albums are listed again after every update, or removed when grouping is disabled
+fn group_albums(connection: &SqliteConnection, settings: &MiscSettings) -> QueryResult<()> {
+	connection.transaction(|| {
+		diesel::delete(albums::table).execute(connection)?;
+		if settings.index_group_albums == 0 {
+			return Ok(());
+		}
+
+		let songs: Vec<AlbumSong> = songs::table
+			.select((
+				songs::parent,
+				songs::musicbrainz_album_id,
+				songs::album,
+				songs::album_artist,
+				songs::artist,
+				songs::year,
+				songs::artwork,
+				songs::duration,
+			))
+			.filter(songs::deleted_at.is_null())
+			.order(songs::path)
+			.load(connection)?;
+		let mut groups: BTreeMap<AlbumKey, Vec<AlbumSong>> = BTreeMap::new();
+		for song in songs {
+			let key = match (&song.musicbrainz_album_id, &song.album) {
+				(Some(id), _) => AlbumKey::MusicBrainz(id.clone()),
+				(None, Some(_)) => AlbumKey::Directory(song.parent.clone()),
+				(None, None) => continue,
+			};
+			groups.entry(key).or_default().push(song);
+		}
+
+		let new_albums = groups
+			.into_iter()
+			.map(|(key, songs)| {
+				let mut years = songs.iter().filter_map(|s| s.year);
+				let first_year = years.next();
+				let (musicbrainz_album_id, directory) = match key {
+					AlbumKey::MusicBrainz(id) => (Some(id), None),
+					AlbumKey::Directory(path) => (None, Some(path)),
+				};
+				NewAlbum {
+					musicbrainz_album_id,
+					directory,
+					name: get_consistent_value(songs.iter().filter_map(|s| s.album.as_ref())),
+					artist: get_consistent_value(
+						songs
+							.iter()
+							.filter_map(|s| s.album_artist.as_ref().or_else(|| s.artist.as_ref())),
+					),
+					year: first_year.filter(|y| years.all(|other| other == *y)),
+					artwork: songs.iter().find_map(|s| s.artwork.clone()),
+					song_count: songs.len() as i32,
+					total_duration: songs.iter().filter_map(|s| s.duration).sum(),
+				}
+			})
+			.collect::<Vec<_>>();
+		diesel::insert_into(albums::table)
+			.values(&new_albums)
+			.execute(connection)?;
+		Ok(())
+	})
+}
+
+// Playlist entries are flagged as missing when they do not reference an indexed song
+fn flag_missing_playlist_songs(connection: &SqliteConnection) -> diesel::QueryResult<()> {
+	let song_paths = songs::table
+		.select(songs::path)
+		.filter(songs::deleted_at.is_null());
+	diesel::update(
+		indexed_playlist_songs::table.filter(indexed_playlist_songs::path.eq_any(song_paths)),
+	)
+	.set(indexed_playlist_songs::missing.eq(0))
+	.execute(connection)?;
+	diesel::update(
+		indexed_playlist_songs::table.filter(indexed_playlist_songs::path.ne_all(song_paths)),
+	)
+	.set(indexed_playlist_songs::missing.eq(1))
+	.execute(connection)?;
+	Ok(())
 }
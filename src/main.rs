@@ -216,13 +216,13 @@ fn main() -> Result<()> {
 	info!("Swagger files location is {}", swagger_dir_path.display());
 
 	// Initialize thumbnails manager
-	let mut thumbnails_path = PathBuf::from(
+	let cache_path = PathBuf::from(
 		matches
 			.opt_str("cache")
 			.or(option_env!("POLARIS_CACHE_DIR").map(String::from))
 			.unwrap_or(".".to_owned()),
 	);
-	thumbnails_path.push("thumbnails");
+	let thumbnails_path = cache_path.join("thumbnails");
 	fs::create_dir_all(&thumbnails_path)?;
 	info!("Thumbnails location is {}", thumbnails_path.display());
 	let thumbnails_manager = thumbnails::ThumbnailsManager::new(&thumbnails_path);
@@ -236,7 +236,15 @@ fn main() -> Result<()> {
 	info!("Mounting swagger files on {}", swagger_url);
 
 	// Init index
-	let index = index::builder(db.clone()).periodic_updates(true).build();
+	let artwork_cache_path = cache_path.join("artwork");
+	info!(
+		"Embedded artwork cache location is {}",
+		artwork_cache_path.display()
+	);
+	let index = index::builder(db.clone())
+		.periodic_updates(true)
+		.artwork_cache(&artwork_cache_path)
+		.build();
 
 	// Start DDNS updates
 	let db_ddns = db.clone();
@@ -111,7 +111,7 @@ pub fn save_playlist(playlist_name: &str, owner: &str, content: &[String], db: &
 	for (i, path) in content.iter().enumerate() {
 		let virtual_path = Path::new(&path);
 		if let Some(real_path) = vfs
-			.virtual_to_real(virtual_path)
+			.virtual_to_stored(virtual_path)
 			.ok()
 			.and_then(|p| p.to_str().map(|s| s.to_owned()))
 		{
@@ -171,10 +171,10 @@ pub fn read_playlist(playlist_name: &str, owner: &str, db: &DB) -> Result<Vec<So
 		// Select songs. Not using Diesel because we need to LEFT JOIN using a custom column
 		let query = diesel::sql_query(
 			r#"
-			SELECT s.id, s.path, s.parent, s.track_number, s.disc_number, s.title, s.artist, s.album_artist, s.year, s.album, s.artwork, s.duration
+			SELECT s.id, s.path, s.parent, s.track_number, s.disc_number, s.title, s.artist, s.album_artist, s.year, s.album, s.artwork, s.duration, s.genre, s.file_size, s.mtime, s.replay_gain_track_gain, s.replay_gain_track_peak, s.replay_gain_album_gain, s.replay_gain_album_peak, s.track_total, s.disc_total, s.search_title, s.search_artist, s.start_time, s.composer, s.conductor, s.content_hash, s.sort_artist, s.sort_album, s.sort_title, s.format, s.bitrate, s.sample_rate, s.bits_per_sample, s.channels, s.deleted_at, s.bpm, s.musical_key, s.mount_name, s.rating, s.tagged_play_count, s.disc_subtitle, s.created, s.isrc, s.musicbrainz_track_id, s.musicbrainz_album_id, s.musicbrainz_artist_id, s.encoder_delay, s.encoder_padding, s.original_date, s.link_target, s.album_key, s.lossless, s.bitrate_mode, s.work, s.movement_name, s.movement_number
 			FROM playlist_songs ps
 			LEFT JOIN songs s ON ps.path = s.path
-			WHERE ps.playlist = ?
+			WHERE ps.playlist = ? AND s.deleted_at IS NULL
 			ORDER BY ps.ordering
 		"#,
 		);
@@ -252,7 +252,7 @@ fn test_fill_playlist() {
 	use crate::index;
 
 	let db = db::get_test_db("fill_playlist.sqlite");
-	index::update(&db).unwrap();
+	index::update(&db, &index::UpdateOptions::default()).unwrap();
 
 	let mut playlist_content: Vec<String> = index::flatten(&db, Path::new("root"))
 		.unwrap()
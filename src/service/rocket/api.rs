@@ -35,6 +35,8 @@ pub fn get_routes() -> Vec<rocket::Route> {
 		get_preferences,
 		put_preferences,
 		trigger_index,
+		trigger_index_directory,
+		verify_index,
 		auth,
 		browse_root,
 		browse,
@@ -46,6 +48,8 @@ pub fn get_routes() -> Vec<rocket::Route> {
 		search,
 		audio,
 		thumbnail,
+		lyrics,
+		ancestors,
 		list_playlists,
 		save_playlist,
 		read_playlist,
@@ -255,6 +259,24 @@ fn trigger_index(index: State<'_, Index>, _admin_rights: AdminRights) -> Result<
 	Ok(())
 }
 
+#[post("/trigger_index/<path>")]
+fn trigger_index_directory(
+	index: State<'_, Index>,
+	_admin_rights: AdminRights,
+	path: VFSPathBuf,
+) -> Result<()> {
+	index.update_directory(&path.into() as &PathBuf)?;
+	Ok(())
+}
+
+#[get("/verify_index")]
+fn verify_index(
+	index: State<'_, Index>,
+	_admin_rights: AdminRights,
+) -> Result<Json<index::VerifyReport>> {
+	Ok(Json(index.verify()?))
+}
+
 #[post("/auth", data = "<credentials>")]
 fn auth(
 	db: State<'_, DB>,
@@ -350,6 +372,22 @@ fn thumbnail(
 	Ok(file)
 }
 
+#[get("/lyrics/<path>")]
+fn lyrics(db: State<'_, DB>, _auth: Auth, path: VFSPathBuf) -> Result<Option<Json<index::Lyrics>>> {
+	let result = index::get_lyrics(db.deref().deref(), &path.into() as &PathBuf)?;
+	Ok(result.map(Json))
+}
+
+#[get("/ancestors/<path>")]
+fn ancestors(
+	db: State<'_, DB>,
+	_auth: Auth,
+	path: VFSPathBuf,
+) -> Result<Json<Vec<index::Directory>>> {
+	let result = index::get_ancestors(db.deref().deref(), &path.into() as &PathBuf)?;
+	Ok(Json(result))
+}
+
 #[get("/playlists")]
 fn list_playlists(db: State<'_, DB>, auth: Auth) -> Result<Json<Vec<dto::ListPlaylistsEntry>>> {
 	let playlist_names = playlist::list_playlists(&auth.username, db.deref().deref())?;
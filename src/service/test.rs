@@ -43,6 +43,8 @@ pub trait TestService {
 			mount_dirs: Some(vec![vfs::MountPoint {
 				name: TEST_MOUNT_NAME.into(),
 				source: TEST_MOUNT_SOURCE.into(),
+				album_art_pattern: None,
+				include_patterns: None,
 			}]),
 		};
 		self.put_json("/api/settings", &configuration);
@@ -196,6 +198,52 @@ fn test_service_trigger_index() {
 	assert_eq!(entries.len(), 3);
 }
 
+#[test]
+fn test_service_trigger_index_directory() {
+	let mut service = ServiceType::new(&format!("{}{}", TEST_DB_PREFIX, line!()));
+	service.complete_initial_setup();
+	service.login();
+
+	let mut path = PathBuf::new();
+	path.push("collection");
+	path.push("Khemmis");
+	let uri = format!(
+		"/api/trigger_index/{}",
+		percent_encode(path.to_string_lossy().as_ref().as_bytes(), NON_ALPHANUMERIC)
+	);
+	let response = service.post(&uri);
+	assert_eq!(response.status(), StatusCode::OK);
+
+	// Only the given directory is indexed, before the request completes
+	let response = service.get_json::<Vec<index::Song>>("/api/flatten");
+	let entries = response.body();
+	assert_eq!(entries.len(), 5);
+	assert!(entries
+		.iter()
+		.all(|s| PathBuf::from(&s.path).starts_with(&path)));
+}
+
+#[test]
+fn test_service_verify_index() {
+	let mut service = ServiceType::new(&format!("{}{}", TEST_DB_PREFIX, line!()));
+	service.complete_initial_setup();
+	service.login();
+
+	let response = service.get_json::<index::VerifyReport>("/api/verify_index");
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.body().unindexed_songs.len(), 13);
+
+	// Indexing carries on in the background after the first songs show up
+	service.index();
+	loop {
+		let response = service.get_json::<index::VerifyReport>("/api/verify_index");
+		if response.body() == &index::VerifyReport::default() {
+			break;
+		}
+		std::thread::sleep(Duration::from_secs(1));
+	}
+}
+
 #[test]
 fn test_service_auth() {
 	let mut service = ServiceType::new(&format!("{}{}", TEST_DB_PREFIX, line!()));
@@ -395,6 +443,73 @@ fn test_service_playlists() {
 	assert_eq!(playlists.len(), 0);
 }
 
+#[test]
+fn test_service_lyrics() {
+	let mut service = ServiceType::new(&format!("{}{}", TEST_DB_PREFIX, line!()));
+	service.complete_initial_setup();
+	service.login();
+	service.index();
+
+	let mut path = PathBuf::new();
+	path.push("collection");
+	path.push("Khemmis");
+	path.push("Hunted");
+	path.push("02 - Candlelight.mp3");
+	let uri = format!(
+		"/api/lyrics/{}",
+		percent_encode(path.to_string_lossy().as_ref().as_bytes(), NON_ALPHANUMERIC)
+	);
+
+	let response = service.get_json::<index::Lyrics>(&uri);
+	assert_eq!(response.status(), StatusCode::OK);
+	let lyrics = response.body();
+	assert!(lyrics.has_synced_lyrics);
+	assert!(lyrics.lyrics.contains("[00:01.00]First placeholder line"));
+
+	path.set_file_name("01 - Above The Water.mp3");
+	let uri = format!(
+		"/api/lyrics/{}",
+		percent_encode(path.to_string_lossy().as_ref().as_bytes(), NON_ALPHANUMERIC)
+	);
+	let response = service.get(&uri);
+	assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_service_ancestors() {
+	let mut service = ServiceType::new(&format!("{}{}", TEST_DB_PREFIX, line!()));
+	service.complete_initial_setup();
+	service.login();
+	service.index();
+
+	let mut path = PathBuf::new();
+	path.push("collection");
+	path.push("Khemmis");
+	path.push("Hunted");
+	let uri = format!(
+		"/api/ancestors/{}",
+		percent_encode(path.to_string_lossy().as_ref().as_bytes(), NON_ALPHANUMERIC)
+	);
+
+	let response = service.get_json::<Vec<index::Directory>>(&uri);
+	assert_eq!(response.status(), StatusCode::OK);
+	let ancestors = response.body();
+	assert_eq!(
+		ancestors.iter().map(|d| d.path.clone()).collect::<Vec<_>>(),
+		vec![
+			"collection".to_owned(),
+			PathBuf::from("collection")
+				.join("Khemmis")
+				.to_string_lossy()
+				.into_owned(),
+		]
+	);
+	assert_eq!(
+		ancestors.iter().map(|d| d.depth).collect::<Vec<_>>(),
+		vec![Some(0), Some(1)]
+	);
+}
+
 #[test]
 fn test_service_thumbnail() {
 	let mut service = ServiceType::new(&format!("{}{}", TEST_DB_PREFIX, line!()));
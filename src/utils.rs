@@ -1,4 +1,11 @@
-use std::path::Path;
+use log::warn;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 
 #[macro_export]
 macro_rules! match_ignore_case {
@@ -21,6 +28,7 @@ pub enum AudioFormat {
 	MPC,
 	OGG,
 	OPUS,
+	WAVPACK,
 }
 
 #[cfg_attr(feature = "profile-index", flame)]
@@ -41,10 +49,165 @@ pub fn get_audio_format(path: &Path) -> Option<AudioFormat> {
 		"mpc" => Some(AudioFormat::MPC),
 		"ogg" => Some(AudioFormat::OGG),
 		"opus" => Some(AudioFormat::OPUS),
+		"wv" => Some(AudioFormat::WAVPACK),
 		_ => None,
 	}
 }
 
+// Resolves `.` and `..` components without touching the file system, so the path may not exist
+pub fn normalize_path(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => (),
+			Component::ParentDir => match normalized.components().next_back() {
+				Some(Component::Normal(_)) => {
+					normalized.pop();
+				}
+				Some(Component::RootDir) | Some(Component::Prefix(_)) => (),
+				_ => normalized.push(component),
+			},
+			_ => normalized.push(component),
+		}
+	}
+	normalized
+}
+
+// Paths handed to file system operations on Windows use the extended-length prefix, so deeply
+// nested files are not limited to MAX_PATH. Paths stored in the index keep their normal form.
+#[cfg(windows)]
+pub fn fs_path(path: &Path) -> Cow<'_, Path> {
+	const PREFIX: &str = r"\\?\";
+	match path.to_str() {
+		Some(s) if !s.starts_with(PREFIX) => (),
+		_ => return Cow::Borrowed(path),
+	};
+	// Extended-length paths are not normalized by Windows, which rejects `/`, `.` and `..`
+	let absolute_path = if path.is_absolute() {
+		normalize_path(path)
+	} else {
+		match std::env::current_dir() {
+			Ok(current_dir) => normalize_path(&current_dir.join(path)),
+			Err(_) => return Cow::Borrowed(path),
+		}
+	};
+	let absolute_string = absolute_path.to_string_lossy();
+	let extended_path = match absolute_string.strip_prefix(r"\\") {
+		Some(unc_path) => format!(r"{}UNC\{}", PREFIX, unc_path),
+		None => format!("{}{}", PREFIX, absolute_string),
+	};
+	Cow::Owned(PathBuf::from(extended_path))
+}
+
+#[cfg(not(windows))]
+pub fn fs_path(path: &Path) -> Cow<'_, Path> {
+	Cow::Borrowed(path)
+}
+
+// Paths returned by the file system, such as canonical paths on Windows, are given back their normal
+// form before they are stored
+#[cfg(windows)]
+pub fn strip_fs_prefix(path: PathBuf) -> PathBuf {
+	let path_string = match path.to_str() {
+		Some(s) => s.to_owned(),
+		None => return path,
+	};
+	if let Some(unc_path) = path_string.strip_prefix(r"\\?\UNC\") {
+		return PathBuf::from(format!(r"\\{}", unc_path));
+	}
+	match path_string.strip_prefix(r"\\?\") {
+		Some(local_path) => PathBuf::from(local_path),
+		None => path,
+	}
+}
+
+#[cfg(not(windows))]
+pub fn strip_fs_prefix(path: PathBuf) -> PathBuf {
+	path
+}
+
+const RETRY_IO_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// I/O errors and stale file handles, as returned by network shares whose server went away
+#[cfg(target_os = "linux")]
+const TRANSIENT_OS_ERRORS: &[i32] = &[5, 116];
+#[cfg(target_os = "macos")]
+const TRANSIENT_OS_ERRORS: &[i32] = &[5, 70];
+#[cfg(windows)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[59, 64, 121]; // Unexpected network error, lost share, timeout
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const TRANSIENT_OS_ERRORS: &[i32] = &[];
+
+// Errors which go away on their own. Others, such as missing paths or denied permissions, would
+// only fail again.
+fn is_transient(error: &io::Error) -> bool {
+	match error.kind() {
+		io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => true,
+		_ => error
+			.raw_os_error()
+			.map_or(false, |code| TRANSIENT_OS_ERRORS.contains(&code)),
+	}
+}
+
+// File system operations which fail transiently, as happens when a network share briefly drops,
+// are attempted again, waiting twice as long before each new attempt
+pub fn retry_io<T, F>(path: &Path, retries: u32, delay: Duration, mut operation: F) -> io::Result<T>
+where
+	F: FnMut() -> io::Result<T>,
+{
+	let mut delay = delay;
+	let mut attempt = 0;
+	loop {
+		match operation() {
+			Err(e) if attempt < retries && is_transient(&e) => {
+				warn!(
+					"Could not read `{}` ({}), retrying in {:?}",
+					path.display(),
+					e,
+					delay
+				);
+				std::thread::sleep(delay);
+				delay = (delay * 2).min(RETRY_IO_MAX_DELAY);
+				attempt += 1;
+			}
+			result => return result,
+		}
+	}
+}
+
+// Whether the file system holding a directory ignores case, as is common on macOS and Windows. An
+// entry of the directory is looked up with its case swapped, which only finds something when case
+// is ignored, unless the directory also lists that other name.
+pub fn is_case_insensitive(directory: &Path) -> bool {
+	let names = match fs::read_dir(fs_path(directory)) {
+		Ok(read_dir) => read_dir
+			.filter_map(|entry| entry.ok())
+			.map(|entry| entry.file_name())
+			.collect::<HashSet<_>>(),
+		Err(_) => return false,
+	};
+	let swap_case = |name: &str| -> String {
+		name.chars()
+			.flat_map(|c| {
+				if c.is_lowercase() {
+					c.to_uppercase().collect::<Vec<_>>()
+				} else {
+					c.to_lowercase().collect::<Vec<_>>()
+				}
+			})
+			.collect()
+	};
+	let probe = names.iter().find_map(|name| {
+		let name = name.to_str()?;
+		let swapped = swap_case(name);
+		if swapped == name || names.contains(OsStr::new(&swapped)) {
+			return None;
+		}
+		Some(directory.join(swapped))
+	});
+	probe.map_or(false, |p| fs_path(&p).exists())
+}
+
 #[test]
 fn test_get_audio_format() {
 	assert_eq!(get_audio_format(Path::new("animals/🐷/my🐖file.jpg")), None);
@@ -52,4 +215,124 @@ fn test_get_audio_format() {
 		get_audio_format(Path::new("animals/🐷/my🐖file.flac")),
 		Some(AudioFormat::FLAC)
 	);
+	assert_eq!(
+		get_audio_format(Path::new("animals/🐷/my🐖file.WV")),
+		Some(AudioFormat::WAVPACK)
+	);
+}
+
+#[test]
+fn test_normalize_path() {
+	assert_eq!(
+		normalize_path(Path::new("music/Album/./../Other/01.mp3")),
+		PathBuf::from("music/Other/01.mp3")
+	);
+	assert_eq!(
+		normalize_path(Path::new("../music/01.mp3")),
+		PathBuf::from("../music/01.mp3")
+	);
+	assert_eq!(
+		normalize_path(Path::new("/../music/01.mp3")),
+		PathBuf::from("/music/01.mp3")
+	);
+}
+
+#[cfg(windows)]
+#[test]
+fn test_fs_path() {
+	assert_eq!(
+		fs_path(Path::new(r"C:\music\Album\..\01.mp3")),
+		Path::new(r"\\?\C:\music\01.mp3")
+	);
+	assert_eq!(
+		fs_path(Path::new(r"\\server\share\01.mp3")),
+		Path::new(r"\\?\UNC\server\share\01.mp3")
+	);
+	assert_eq!(
+		fs_path(Path::new(r"\\?\C:\music\01.mp3")),
+		Path::new(r"\\?\C:\music\01.mp3")
+	);
+}
+
+#[cfg(windows)]
+#[test]
+fn test_strip_fs_prefix() {
+	let paths = [r"C:\music\01.mp3", r"\\server\share\01.mp3"];
+	for path in paths.iter() {
+		let path = Path::new(path);
+		assert_eq!(strip_fs_prefix(fs_path(path).into_owned()), path);
+	}
+	assert_eq!(
+		strip_fs_prefix(PathBuf::from(r"C:\music\01.mp3")),
+		Path::new(r"C:\music\01.mp3")
+	);
+}
+
+#[test]
+fn test_retry_io() {
+	let path = Path::new("music");
+	let mut attempts = 0;
+	let result = retry_io(path, 3, Duration::from_millis(1), || {
+		attempts += 1;
+		match attempts {
+			1 | 2 => Err(io::Error::new(io::ErrorKind::TimedOut, "Host is down")),
+			_ => Ok(attempts),
+		}
+	});
+	assert_eq!(result.unwrap(), 3);
+
+	attempts = 0;
+	let result: io::Result<()> = retry_io(path, 2, Duration::from_millis(1), || {
+		attempts += 1;
+		Err(io::Error::new(io::ErrorKind::TimedOut, "Host is down"))
+	});
+	assert!(result.is_err());
+	assert_eq!(attempts, 3);
+
+	attempts = 0;
+	let result: io::Result<()> = retry_io(path, 2, Duration::from_millis(1), || {
+		attempts += 1;
+		Err(io::Error::new(io::ErrorKind::NotFound, "No such file"))
+	});
+	assert!(result.is_err());
+	assert_eq!(attempts, 1);
+
+	attempts = 0;
+	let result: io::Result<()> = retry_io(path, 2, Duration::from_millis(1), || {
+		attempts += 1;
+		Err(io::Error::new(
+			io::ErrorKind::PermissionDenied,
+			"Permission denied",
+		))
+	});
+	assert!(result.is_err());
+	assert_eq!(attempts, 1);
+
+	#[cfg(target_os = "linux")]
+	{
+		attempts = 0;
+		let result: io::Result<()> = retry_io(path, 2, Duration::from_millis(1), || {
+			attempts += 1;
+			Err(io::Error::from_raw_os_error(116))
+		});
+		assert!(result.is_err());
+		assert_eq!(attempts, 3);
+	}
+}
+
+#[test]
+fn test_is_case_insensitive() {
+	let directory = Path::new("test-output/case-probe");
+	if directory.exists() {
+		fs::remove_dir_all(directory).unwrap();
+	}
+	fs::create_dir_all(directory.join("Probe")).unwrap();
+	let ignores_case = directory.join("PROBE").exists();
+	assert_eq!(is_case_insensitive(directory), ignores_case);
+
+	// Entries which only differ by case exist side by side when case matters
+	if !ignores_case {
+		fs::create_dir_all(directory.join("pROBE")).unwrap();
+		assert!(!is_case_insensitive(directory));
+	}
 }
@@ -5,8 +5,8 @@ use std::collections::HashMap;
 use std::path::Path;
 use std::path::PathBuf;
 
-use crate::db::mount_points;
 use crate::db::DB;
+use crate::db::{misc_settings, mount_points};
 
 pub trait VFSSource {
 	fn get_vfs(&self) -> Result<VFS>;
@@ -18,11 +18,15 @@ impl VFSSource for DB {
 		let mut vfs = VFS::new();
 		let connection = self.connect()?;
 		let points: Vec<MountPoint> = mount_points
-			.select((source, name))
+			.select((source, name, album_art_pattern, include_patterns))
 			.get_results(&connection)?;
 		for point in points {
 			vfs.mount(&Path::new(&point.source), &point.name)?;
 		}
+		let store_relative_paths: i32 = misc_settings::table
+			.select(misc_settings::index_store_relative_paths)
+			.get_result(&connection)?;
+		vfs.set_store_relative_paths(store_relative_paths != 0);
 		Ok(vfs)
 	}
 }
@@ -32,37 +36,63 @@ impl VFSSource for DB {
 pub struct MountPoint {
 	pub source: String,
 	pub name: String,
+	// Overrides the global album art pattern for directories within this mount point
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub album_art_pattern: Option<String>,
+	// Comma separated globs, relative to the mount point, of the only files and directories to index
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub include_patterns: Option<String>,
 }
 
 pub struct VFS {
 	mount_points: HashMap<String, PathBuf>,
+	store_relative_paths: bool,
 }
 
 impl VFS {
 	pub fn new() -> VFS {
 		VFS {
 			mount_points: HashMap::new(),
+			store_relative_paths: false,
 		}
 	}
 
+	// The index stores paths either as real paths, or relative to their mount point (prefixed
+	// with the mount name) so that it does not depend on where the collection lives
+	pub fn set_store_relative_paths(&mut self, store_relative_paths: bool) {
+		self.store_relative_paths = store_relative_paths;
+	}
+
 	pub fn mount(&mut self, real_path: &Path, name: &str) -> Result<()> {
 		self.mount_points
 			.insert(name.to_owned(), real_path.to_path_buf());
 		Ok(())
 	}
 
+	// Mount points can be nested within one another, in which case the innermost one applies
 	pub fn real_to_virtual<P: AsRef<Path>>(&self, real_path: P) -> Result<PathBuf> {
-		for (name, target) in &self.mount_points {
-			if let Ok(p) = real_path.as_ref().strip_prefix(target) {
+		let best_match = self
+			.mount_points
+			.iter()
+			.filter_map(|(name, target)| {
+				real_path
+					.as_ref()
+					.strip_prefix(target)
+					.ok()
+					.map(|p| (target.components().count(), name, p))
+			})
+			.max_by_key(|(depth, _, _)| *depth);
+		match best_match {
+			Some((_, name, p)) => {
 				let mount_path = Path::new(&name);
-				return if p.components().count() == 0 {
+				if p.components().count() == 0 {
 					Ok(mount_path.to_path_buf())
 				} else {
 					Ok(mount_path.join(p))
-				};
+				}
 			}
+			None => bail!("Real path has no match in VFS"),
 		}
-		bail!("Real path has no match in VFS")
 	}
 
 	pub fn virtual_to_real<P: AsRef<Path>>(&self, virtual_path: P) -> Result<PathBuf> {
@@ -79,6 +109,46 @@ impl VFS {
 		bail!("Virtual path has no match in VFS")
 	}
 
+	// Paths outside of the mount points (such as cached artwork) are always stored as they are
+	pub fn real_to_stored<P: AsRef<Path>>(&self, real_path: P) -> PathBuf {
+		let real_path = real_path.as_ref();
+		if self.store_relative_paths {
+			self.real_to_virtual(real_path)
+				.unwrap_or_else(|_| real_path.to_owned())
+		} else {
+			real_path.to_owned()
+		}
+	}
+
+	pub fn stored_to_real<P: AsRef<Path>>(&self, stored_path: P) -> PathBuf {
+		let stored_path = stored_path.as_ref();
+		if self.store_relative_paths {
+			self.virtual_to_real(stored_path)
+				.unwrap_or_else(|_| stored_path.to_owned())
+		} else {
+			stored_path.to_owned()
+		}
+	}
+
+	pub fn virtual_to_stored<P: AsRef<Path>>(&self, virtual_path: P) -> Result<PathBuf> {
+		let real_path = self.virtual_to_real(virtual_path)?;
+		Ok(self.real_to_stored(real_path))
+	}
+
+	pub fn stored_to_virtual<P: AsRef<Path>>(&self, stored_path: P) -> Result<PathBuf> {
+		self.real_to_virtual(self.stored_to_real(stored_path))
+	}
+
+	// Relative stored paths are only mounted when they start with the name of a mount point, and
+	// are never mistaken for real paths
+	pub fn is_mounted<P: AsRef<Path>>(&self, stored_path: P) -> bool {
+		if self.store_relative_paths {
+			self.virtual_to_real(stored_path).is_ok()
+		} else {
+			self.real_to_virtual(stored_path).is_ok()
+		}
+	}
+
 	pub fn get_mount_points(&self) -> &HashMap<String, PathBuf> {
 		&self.mount_points
 	}
@@ -130,3 +200,72 @@ fn test_real_to_virtual() {
 	let found_path = vfs.real_to_virtual(real_path.as_path()).unwrap();
 	assert!(found_path == correct_path);
 }
+
+#[test]
+fn test_real_to_virtual_nested() {
+	let mut vfs = VFS::new();
+	vfs.mount(Path::new("music"), "root").unwrap();
+	vfs.mount(&Path::new("music").join("classical"), "classical")
+		.unwrap();
+	vfs.mount(&Path::new("music").join("classical").join("opera"), "opera")
+		.unwrap();
+
+	let real_path = Path::new("music")
+		.join("classical")
+		.join("Bach")
+		.join("a.flac");
+	assert_eq!(
+		vfs.real_to_virtual(&real_path).unwrap(),
+		Path::new("classical").join("Bach").join("a.flac")
+	);
+	assert_eq!(
+		vfs.real_to_virtual(Path::new("music").join("classical").join("opera"))
+			.unwrap(),
+		Path::new("opera")
+	);
+	assert_eq!(
+		vfs.real_to_virtual(Path::new("music").join("jazz"))
+			.unwrap(),
+		Path::new("root").join("jazz")
+	);
+}
+
+#[test]
+fn test_stored_paths() {
+	let mut vfs = VFS::new();
+	vfs.mount(Path::new("test_dir"), "root").unwrap();
+	let real_path = Path::new("test_dir")
+		.join("somewhere")
+		.join("something.png");
+	let virtual_path = Path::new("root").join("somewhere").join("something.png");
+	let outside_path = Path::new("cache").join("something.png");
+
+	assert_eq!(vfs.real_to_stored(&real_path), real_path);
+	assert_eq!(vfs.virtual_to_stored(&virtual_path).unwrap(), real_path);
+
+	vfs.set_store_relative_paths(true);
+	assert_eq!(vfs.real_to_stored(&real_path), virtual_path);
+	assert_eq!(vfs.stored_to_real(&virtual_path), real_path);
+	assert_eq!(vfs.virtual_to_stored(&virtual_path).unwrap(), virtual_path);
+	assert_eq!(vfs.stored_to_virtual(&virtual_path).unwrap(), virtual_path);
+	assert_eq!(vfs.real_to_stored(&outside_path), outside_path);
+	assert_eq!(vfs.stored_to_real(&outside_path), outside_path);
+}
+
+#[test]
+fn test_is_mounted() {
+	let mut vfs = VFS::new();
+	vfs.mount(Path::new("test_dir"), "root").unwrap();
+	let real_path = Path::new("test_dir").join("something.png");
+	let virtual_path = Path::new("root").join("something.png");
+	assert!(vfs.is_mounted(&real_path));
+	assert!(!vfs.is_mounted(&virtual_path));
+	assert!(!vfs.is_mounted(Path::new("other_dir").join("something.png")));
+
+	// Relative paths are not compared with mount sources, which could also be relative
+	vfs.mount(Path::new("root"), "other").unwrap();
+	vfs.set_store_relative_paths(true);
+	assert!(vfs.is_mounted(&virtual_path));
+	assert!(!vfs.is_mounted(&real_path));
+	assert!(!vfs.is_mounted(Path::new("removed").join("something.png")));
+}